@@ -6,6 +6,8 @@ use crate::config::ServerSection;
 const API_VERSION: u32 = 1;
 const HTTP_CONNECT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
 const WS_CONNECT_ATTEMPT_CAP: std::time::Duration = std::time::Duration::from_secs(2);
+/// How often a running create or restore is asked for progress.
+const OPERATION_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
 
 /// Sort socket addresses so IPv4 comes before IPv6.
 /// Avoids timeouts on networks with broken IPv6 transit.
@@ -66,6 +68,58 @@ pub fn normalize_server_url(url: &str) -> Result<String> {
     Ok(normalized)
 }
 
+/// Size and hex SHA-256 of a local file.
+fn sha256_file(path: &std::path::Path) -> Result<(u64, String)> {
+    use sha2::{Digest, Sha256};
+    use std::io::Read;
+
+    let mut f =
+        std::fs::File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    let mut size = 0u64;
+    let mut buf = vec![0u8; 1024 * 1024];
+    loop {
+        let n = f
+            .read(&mut buf)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        size += n as u64;
+    }
+    let hex = hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect();
+    Ok((size, hex))
+}
+
+/// Collects the `data:` lines of Server-Sent Events.
+#[derive(Default)]
+struct SseParser {
+    data: Vec<String>,
+}
+
+impl SseParser {
+    /// Feed one line; returns an event's data when a blank line ends it.
+    /// Comments and fields other than `data` are skipped.
+    fn line(&mut self, line: &str) -> Option<String> {
+        if line.is_empty() {
+            if self.data.is_empty() {
+                return None;
+            }
+            return Some(std::mem::take(&mut self.data).join("\n"));
+        }
+        if let Some(data) = line.strip_prefix("data:") {
+            self.data
+                .push(data.strip_prefix(' ').unwrap_or(data).to_string());
+        }
+        None
+    }
+}
+
 pub struct ApiClient {
     base_url: String,
     auth_header: String,
@@ -138,6 +192,80 @@ impl ApiClient {
         Ok(resp)
     }
 
+    /// POST asking the server to run the request in the background
+    /// (`Prefer: respond-async`). Servers without background operations
+    /// answer it as usual.
+    fn post_async(&self, path: &str, body: &impl serde::Serialize) -> Result<ureq::Response> {
+        let url = format!("{}{path}", self.base_url);
+        let resp = self
+            .agent
+            .post(&url)
+            .set("Authorization", &self.auth_header)
+            .set("Prefer", "respond-async")
+            .send_json(body)
+            .map_err(|e| self.handle_error(e))?;
+        self.check_api_version(&resp)?;
+        Ok(resp)
+    }
+
+    /// The VM of a `post_async` response: polls the operation the server
+    /// started, passing each state of it to `on_progress`, until it ends.
+    fn await_operation(
+        &self,
+        resp: ureq::Response,
+        what: &str,
+        mut on_progress: impl FnMut(&OperationInfo),
+    ) -> Result<VmInfo> {
+        if resp.status() != 202 {
+            return resp
+                .into_json()
+                .with_context(|| format!("failed to parse {what} response"));
+        }
+        let mut op: OperationInfo = resp
+            .into_json()
+            .with_context(|| format!("failed to parse {what} operation"))?;
+        loop {
+            on_progress(&op);
+            if op.is_finished() {
+                break;
+            }
+            std::thread::sleep(OPERATION_POLL_INTERVAL);
+            op = self.operation(&op.id)?;
+        }
+        match op.result {
+            Some(vm) => Ok(vm),
+            None => anyhow::bail!(
+                "server error ({}): {}",
+                op.error_status.unwrap_or(500),
+                op.error.unwrap_or_else(|| format!("{what} failed"))
+            ),
+        }
+    }
+
+    fn patch(&self, path: &str, body: &impl serde::Serialize) -> Result<ureq::Response> {
+        let url = format!("{}{path}", self.base_url);
+        let resp = self
+            .agent
+            .request("PATCH", &url)
+            .set("Authorization", &self.auth_header)
+            .send_json(body)
+            .map_err(|e| self.handle_error(e))?;
+        self.check_api_version(&resp)?;
+        Ok(resp)
+    }
+
+    fn put(&self, path: &str, body: &impl serde::Serialize) -> Result<ureq::Response> {
+        let url = format!("{}{path}", self.base_url);
+        let resp = self
+            .agent
+            .put(&url)
+            .set("Authorization", &self.auth_header)
+            .send_json(body)
+            .map_err(|e| self.handle_error(e))?;
+        self.check_api_version(&resp)?;
+        Ok(resp)
+    }
+
     fn delete(&self, path: &str) -> Result<ureq::Response> {
         let url = format!("{}{path}", self.base_url);
         let resp = self
@@ -187,22 +315,98 @@ impl ApiClient {
         resp.into_json().context("failed to parse whoami response")
     }
 
-    pub fn create_vm(&self, name: &str, cpus: u32, mem_mib: u32) -> Result<VmInfo> {
-        let name = Self::validate_name(name)?;
-        let req = CreateVmRequest {
-            name: name.to_string(),
-            cpus,
-            mem_mib,
-        };
-        let resp = self.post("/v1/vms", &req)?;
-        resp.into_json().context("failed to parse create response")
+    /// Create a VM, passing the progress of the server's create operation
+    /// to `on_progress` as it goes.
+    pub fn create_vm(
+        &self,
+        req: &CreateVmRequest,
+        on_progress: impl FnMut(&OperationInfo),
+    ) -> Result<VmInfo> {
+        Self::validate_name(&req.name)?;
+        let resp = self.post_async("/v1/vms", req)?;
+        self.await_operation(resp, "create", on_progress)
+    }
+
+    pub fn operation(&self, id: &str) -> Result<OperationInfo> {
+        let resp = self.get(&format!("/v1/operations/{id}"))?;
+        resp.into_json().context("failed to parse operation")
     }
 
-    pub fn list_vms(&self) -> Result<Vec<VmInfo>> {
-        let resp = self.get("/v1/vms")?;
+    /// VMs with all the labels in `selector`; every VM if it is empty.
+    pub fn list_vms(&self, selector: &[(String, String)]) -> Result<Vec<VmInfo>> {
+        let url = format!("{}/v1/vms", self.base_url);
+        let mut req = self
+            .agent
+            .get(&url)
+            .set("Authorization", &self.auth_header);
+        for (key, value) in selector {
+            req = req.query("label", &format!("{key}={value}"));
+        }
+        let resp = req.call().map_err(|e| self.handle_error(e))?;
+        self.check_api_version(&resp)?;
         resp.into_json().context("failed to parse list response")
     }
 
+    pub fn usage(&self) -> Result<UsageSummary> {
+        let resp = self.get("/v1/usage")?;
+        resp.into_json().context("failed to parse usage response")
+    }
+
+    pub fn list_images(&self) -> Result<Vec<ImageInfo>> {
+        let resp = self.get("/v1/images")?;
+        resp.into_json().context("failed to parse images response")
+    }
+
+    pub fn list_artifacts(&self, kind: ArtifactKind) -> Result<Vec<ArtifactInfo>> {
+        let resp = self.get(&format!("/v1/admin/{}", kind.collection()))?;
+        resp.into_json()
+            .with_context(|| format!("failed to parse {kind} list response"))
+    }
+
+    /// Upload `file` to the image registry. The body is streamed; its
+    /// SHA-256 is sent along so the server can reject a corrupted transfer.
+    pub fn upload_artifact(
+        &self,
+        kind: ArtifactKind,
+        name: &str,
+        file: &std::path::Path,
+    ) -> Result<ArtifactInfo> {
+        let (size, sha256) = sha256_file(file)?;
+        let body = std::fs::File::open(file)
+            .with_context(|| format!("failed to open {}", file.display()))?;
+        let url = format!("{}/v1/admin/{}/{name}", self.base_url, kind.collection());
+        let resp = self
+            .agent
+            .put(&url)
+            .set("Authorization", &self.auth_header)
+            .set("Content-Length", &size.to_string())
+            .set("X-Content-Sha256", &sha256)
+            .send(body)
+            .map_err(|e| self.handle_error(e))?;
+        self.check_api_version(&resp)?;
+        resp.into_json().context("failed to parse upload response")
+    }
+
+    pub fn delete_artifact(&self, kind: ArtifactKind, name: &str) -> Result<()> {
+        self.delete(&format!("/v1/admin/{}/{name}", kind.collection()))?;
+        Ok(())
+    }
+
+    /// Promote a checkpoint to a golden image. Copying the snapshot files can
+    /// take a while, so this allows much longer than other requests.
+    pub fn promote_golden(&self, req: &PromoteGoldenRequest) -> Result<GoldenInfo> {
+        let url = format!("{}/v1/admin/golden/promote", self.base_url);
+        let resp = self
+            .agent
+            .post(&url)
+            .set("Authorization", &self.auth_header)
+            .timeout(std::time::Duration::from_secs(600))
+            .send_json(req)
+            .map_err(|e| self.handle_error(e))?;
+        self.check_api_version(&resp)?;
+        resp.into_json().context("failed to parse promote response")
+    }
+
     pub fn get_vm(&self, name: &str) -> Result<VmInfo> {
         let name = Self::validate_name(name)?;
         let resp = self.get(&format!("/v1/vms/{name}"))?;
@@ -215,6 +419,117 @@ impl ApiClient {
         Ok(())
     }
 
+    pub fn update_vm(&self, name: &str, req: &UpdateVmRequest) -> Result<VmInfo> {
+        let name = Self::validate_name(name)?;
+        let resp = self.patch(&format!("/v1/vms/{name}"), req)?;
+        resp.into_json().context("failed to parse update response")
+    }
+
+    pub fn rename_vm(&self, name: &str, new_name: &str) -> Result<VmInfo> {
+        let new_name = Self::validate_name(new_name)?.to_string();
+        self.update_vm(
+            name,
+            &UpdateVmRequest {
+                name: Some(new_name),
+                ..Default::default()
+            },
+        )
+    }
+
+    fn events_request(&self, vm: Option<&str>, since_secs: Option<u64>) -> Result<ureq::Request> {
+        let url = format!("{}/v1/events", self.base_url);
+        let mut req = self
+            .agent
+            .get(&url)
+            .set("Authorization", &self.auth_header);
+        if let Some(vm) = vm {
+            req = req.query("vm", Self::validate_name(vm)?);
+        }
+        if let Some(secs) = since_secs {
+            req = req.query("since", &secs.to_string());
+        }
+        Ok(req)
+    }
+
+    /// Recorded events of all VMs, or only `vm`, oldest first.
+    pub fn events(&self, vm: Option<&str>, since_secs: Option<u64>) -> Result<Vec<VmEvent>> {
+        let resp = self
+            .events_request(vm, since_secs)?
+            .call()
+            .map_err(|e| self.handle_error(e))?;
+        self.check_api_version(&resp)?;
+        resp.into_json().context("failed to parse events")
+    }
+
+    /// Like `events`, then keep passing new events to `on_event` as they
+    /// happen. Only returns on error.
+    pub fn follow_events(
+        &self,
+        vm: Option<&str>,
+        since_secs: Option<u64>,
+        mut on_event: impl FnMut(&VmEvent),
+    ) -> Result<()> {
+        use std::io::BufRead;
+
+        let resp = self
+            .events_request(vm, since_secs)?
+            .query("after", "0")
+            .set("Accept", "text/event-stream")
+            .call()
+            .map_err(|e| self.handle_error(e))?;
+        self.check_api_version(&resp)?;
+        let mut parser = SseParser::default();
+        for line in std::io::BufReader::new(resp.into_reader()).lines() {
+            let line = line.context("event stream interrupted")?;
+            if let Some(data) = parser.line(&line) {
+                let event: VmEvent =
+                    serde_json::from_str(&data).context("failed to parse event")?;
+                on_event(&event);
+            }
+        }
+        anyhow::bail!("event stream closed by the server")
+    }
+
+    pub fn init_log(&self, name: &str) -> Result<InitScriptInfo> {
+        let name = Self::validate_name(name)?;
+        let resp = self.get(&format!("/v1/vms/{name}/init-log"))?;
+        resp.into_json().context("failed to parse init log")
+    }
+
+    pub fn balloon(&self, name: &str) -> Result<BalloonInfo> {
+        let name = Self::validate_name(name)?;
+        let resp = self.get(&format!("/v1/vms/{name}/balloon"))?;
+        resp.into_json().context("failed to parse balloon response")
+    }
+
+    pub fn set_balloon(&self, name: &str, target_mib: u32) -> Result<BalloonInfo> {
+        let name = Self::validate_name(name)?;
+        let resp = self.put(
+            &format!("/v1/vms/{name}/balloon"),
+            &BalloonRequest { target_mib },
+        )?;
+        resp.into_json().context("failed to parse balloon response")
+    }
+
+    pub fn stop_vm(&self, name: &str) -> Result<VmInfo> {
+        self.vm_action(name, "stop")
+    }
+
+    pub fn start_vm(&self, name: &str) -> Result<VmInfo> {
+        self.vm_action(name, "start")
+    }
+
+    pub fn reboot_vm(&self, name: &str) -> Result<VmInfo> {
+        self.vm_action(name, "reboot")
+    }
+
+    fn vm_action(&self, name: &str, action: &str) -> Result<VmInfo> {
+        let name = Self::validate_name(name)?;
+        let resp = self.post(&format!("/v1/vms/{name}/{action}"), &serde_json::json!({}))?;
+        resp.into_json()
+            .with_context(|| format!("failed to parse {action} response"))
+    }
+
     pub fn exec_vm(&self, name: &str, command: &[String], env: &[String]) -> Result<ExecResponse> {
         let name = Self::validate_name(name)?;
         let req = ExecRequest {
@@ -243,11 +558,28 @@ impl ApiClient {
             .context("failed to parse checkpoints response")
     }
 
+    pub fn verify_checkpoint(
+        &self,
+        name: &str,
+        checkpoint_id: &str,
+    ) -> Result<CheckpointVerifyResult> {
+        let name = Self::validate_name(name)?;
+        let resp = self.post(
+            &format!("/v1/vms/{name}/checkpoints/{checkpoint_id}/verify"),
+            &serde_json::json!({}),
+        )?;
+        resp.into_json()
+            .context("failed to parse verify response")
+    }
+
     pub fn restore_vm(
         &self,
         name: &str,
         checkpoint_id: &str,
         new_name: Option<&str>,
+        user_data: Option<String>,
+        verify: bool,
+        on_progress: impl FnMut(&OperationInfo),
     ) -> Result<VmInfo> {
         let name = Self::validate_name(name)?;
         if let Some(n) = new_name {
@@ -256,9 +588,11 @@ impl ApiClient {
         let req = RestoreRequest {
             checkpoint_id: checkpoint_id.to_string(),
             new_name: new_name.map(|s| s.to_string()),
+            user_data,
+            verify,
         };
-        let resp = self.post(&format!("/v1/vms/{name}/restore"), &req)?;
-        resp.into_json().context("failed to parse restore response")
+        let resp = self.post_async(&format!("/v1/vms/{name}/restore"), &req)?;
+        self.await_operation(resp, "restore", on_progress)
     }
 
     /// Return the WebSocket URL for a given path (replaces http(s) with ws(s)).
@@ -441,6 +775,14 @@ mod tests {
     use super::*;
     use crate::config::ServerSection;
 
+    #[test]
+    fn sse_parser_joins_data_lines() {
+        let mut parser = SseParser::default();
+        let stream = ": keepalive\n\nid: 1\nevent: vm.died\ndata: {\"a\":\ndata:1}\n\n";
+        let events: Vec<String> = stream.lines().filter_map(|l| parser.line(l)).collect();
+        assert_eq!(events, vec!["{\"a\":\n1}".to_string()]);
+    }
+
     #[test]
     fn normalize_server_url_trims_and_strips_trailing_slash() {
         let normalized = normalize_server_url("  https://noid.example.com/ ").unwrap();
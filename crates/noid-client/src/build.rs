@@ -0,0 +1,537 @@
+//! `noid build`: turn a Noidfile into a named golden image.
+//!
+//! A Noidfile is TOML:
+//!
+//! ```toml
+//! base = "node20"        # image to start from; the default golden image if unset
+//! cpus = 2
+//! mem_mib = 4096
+//!
+//! [env]                  # set for every `run` step
+//! DEBIAN_FRONTEND = "noninteractive"
+//!
+//! [[step]]
+//! run = "apt-get update && apt-get install -y git"
+//!
+//! [[step]]
+//! copy = "gitconfig"     # relative to the Noidfile
+//! dest = "/root/.gitconfig"
+//! ```
+//!
+//! Steps run in a VM named `_build-<image>`, which is checkpointed after each
+//! step. Every checkpoint is labelled with a hash of the Noidfile up to that
+//! step, so a rebuild restores the longest unchanged prefix and only runs the
+//! steps after it. The last checkpoint is promoted to the image through the
+//! admin API.
+
+use anyhow::{bail, Context, Result};
+use base64::Engine;
+use noid_types::{CheckpointInfo, CreateVmRequest, PromoteGoldenRequest};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::api::ApiClient;
+use crate::exec;
+
+/// Files copied in are sent through the serial console, so keep them small.
+const MAX_COPY_BYTES: usize = 256 * 1024;
+/// Base64 characters per exec; stays well below the guest tty's line limit.
+const COPY_CHUNK: usize = 2048;
+const LABEL_PREFIX: &str = "build:";
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawNoidfile {
+    base: Option<String>,
+    #[serde(default = "default_cpus")]
+    cpus: u32,
+    #[serde(default = "default_mem_mib")]
+    mem_mib: u32,
+    #[serde(default)]
+    env: BTreeMap<String, String>,
+    #[serde(default)]
+    step: Vec<RawStep>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawStep {
+    run: Option<String>,
+    copy: Option<PathBuf>,
+    dest: Option<String>,
+}
+
+fn default_cpus() -> u32 {
+    1
+}
+
+fn default_mem_mib() -> u32 {
+    2048
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+    Run(String),
+    Copy {
+        src: PathBuf,
+        dest: String,
+        data: Vec<u8>,
+        mode: u32,
+    },
+}
+
+impl std::fmt::Display for Step {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Step::Run(cmd) => write!(f, "run {cmd}"),
+            Step::Copy { src, dest, .. } => write!(f, "copy {} -> {dest}", src.display()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Noidfile {
+    pub base: Option<String>,
+    pub cpus: u32,
+    pub mem_mib: u32,
+    /// `KEY=VALUE` pairs, sorted by key.
+    pub env: Vec<String>,
+    pub steps: Vec<Step>,
+}
+
+impl Noidfile {
+    /// Read and validate a Noidfile, including the files it copies.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let dir = path.parent().unwrap_or(Path::new("."));
+        Self::parse(&text, dir).with_context(|| format!("invalid Noidfile {}", path.display()))
+    }
+
+    fn parse(text: &str, dir: &Path) -> Result<Self> {
+        let raw: RawNoidfile = toml::from_str(text)?;
+        let env: Vec<String> = raw.env.iter().map(|(k, v)| format!("{k}={v}")).collect();
+        noid_types::validate_env_vars(&env).map_err(|e| anyhow::anyhow!("{e}"))?;
+
+        let steps = raw
+            .step
+            .into_iter()
+            .enumerate()
+            .map(|(i, step)| load_step(step, dir).with_context(|| format!("step {}", i + 1)))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Noidfile {
+            base: raw.base,
+            cpus: raw.cpus,
+            mem_mib: raw.mem_mib,
+            env,
+            steps,
+        })
+    }
+
+    /// Cache key of each layer: the freshly created VM, then the VM after
+    /// each step. A key covers everything before it, so equal keys mean
+    /// equal prefixes. `base_version` is the base image's version from
+    /// `GET /v1/images`, so replacing the base invalidates every layer.
+    pub fn layer_keys(&self, base_version: Option<&str>) -> Vec<String> {
+        let mut hasher = Sha256::new();
+        hasher.update(b"noid-build-v1\0");
+        hasher.update(self.base.as_deref().unwrap_or("").as_bytes());
+        if let Some(version) = base_version {
+            hasher.update(format!("@{version}"));
+        }
+        hasher.update(format!("\0{}x{}\0", self.cpus, self.mem_mib));
+        for var in &self.env {
+            hasher.update(var.as_bytes());
+            hasher.update(b"\0");
+        }
+        let mut keys = vec![hex(&hasher.clone().finalize())];
+        for step in &self.steps {
+            match step {
+                Step::Run(cmd) => {
+                    hasher.update(b"run\0");
+                    hasher.update(cmd.as_bytes());
+                }
+                Step::Copy {
+                    dest, data, mode, ..
+                } => {
+                    hasher.update(format!("copy\0{dest}\0{mode:o}\0"));
+                    hasher.update(Sha256::digest(data));
+                }
+            }
+            hasher.update(b"\0");
+            keys.push(hex(&hasher.clone().finalize()));
+        }
+        keys
+    }
+}
+
+fn load_step(step: RawStep, dir: &Path) -> Result<Step> {
+    match step {
+        RawStep {
+            run: Some(cmd),
+            copy: None,
+            dest: None,
+        } => Ok(Step::Run(cmd)),
+        RawStep {
+            run: None,
+            copy: Some(src),
+            dest: Some(dest),
+        } => {
+            if !dest.starts_with('/') {
+                bail!("dest must be an absolute path: {dest}");
+            }
+            let path = dir.join(&src);
+            let data = std::fs::read(&path)
+                .with_context(|| format!("failed to read {}", path.display()))?;
+            if data.len() > MAX_COPY_BYTES {
+                bail!(
+                    "{} is too large to copy ({} bytes, max {MAX_COPY_BYTES}); \
+                     download it in a run step instead",
+                    src.display(),
+                    data.len()
+                );
+            }
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path)?.permissions().mode() & 0o777;
+            Ok(Step::Copy {
+                src,
+                dest,
+                data,
+                mode,
+            })
+        }
+        RawStep {
+            copy: Some(_),
+            dest: None,
+            ..
+        } => bail!("copy needs a dest"),
+        _ => bail!("expected either run = \"...\" or copy = \"...\" with dest = \"...\""),
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn label(key: &str) -> String {
+    format!("{LABEL_PREFIX}{}", &key[..16])
+}
+
+/// The deepest layer with a usable checkpoint, as (layer index, checkpoint id).
+fn cached_layer(keys: &[String], checkpoints: &[CheckpointInfo]) -> Option<(usize, String)> {
+    keys.iter().enumerate().rev().find_map(|(layer, key)| {
+        let label = label(key);
+        checkpoints
+            .iter()
+            .rev()
+            .find(|cp| !cp.corrupt && cp.label.as_deref() == Some(label.as_str()))
+            .map(|cp| (layer, cp.id.clone()))
+    })
+}
+
+/// Longest VM name the server accepts.
+const MAX_VM_NAME: usize = 64;
+
+/// Name of the VM that builds, and caches the layers of, `image`. Names of
+/// images too long to fit are shortened and suffixed with a hash of the full
+/// name, so distinct images keep distinct build VMs.
+pub fn build_vm_name(image: &str) -> String {
+    let name = format!("_build-{image}");
+    if name.len() <= MAX_VM_NAME {
+        return name;
+    }
+    let suffix = &hex(&Sha256::digest(image.as_bytes()))[..8];
+    // Image names are ASCII, so any byte index is a char boundary.
+    format!("{}-{suffix}", &name[..MAX_VM_NAME - suffix.len() - 1])
+}
+
+/// Build `image` from `noidfile` and promote the result.
+pub fn run(api: &ApiClient, noidfile: &Noidfile, image: &str, no_cache: bool) -> Result<()> {
+    let vm = build_vm_name(image);
+    // The default golden image is not listed, so its builds are keyed by
+    // name alone.
+    let base_version = match &noidfile.base {
+        Some(base) => api
+            .list_images()?
+            .into_iter()
+            .find(|i| &i.name == base)
+            .and_then(|i| i.version),
+        None => None,
+    };
+    let keys = noidfile.layer_keys(base_version.as_deref());
+    let exists = api.list_vms(&[])?.iter().any(|v| v.name == vm);
+
+    let cached = if exists && !no_cache {
+        cached_layer(&keys, &api.list_checkpoints(&vm)?)
+    } else {
+        None
+    };
+    let total = noidfile.steps.len();
+    let (start, mut checkpoint) = match cached {
+        Some((layer, id)) => {
+            for (i, step) in noidfile.steps.iter().take(layer).enumerate() {
+                println!("Step {}/{total} (cached): {step}", i + 1);
+            }
+            if layer < total {
+                api.restore_vm(&vm, &id, None, None, false, |_| {})?;
+            }
+            (layer, id)
+        }
+        None => {
+            if exists {
+                api.destroy_vm(&vm)?;
+            }
+            println!("Creating build VM '{vm}'");
+            api.create_vm(
+                &CreateVmRequest {
+                    name: vm.clone(),
+                    cpus: noidfile.cpus,
+                    mem_mib: noidfile.mem_mib,
+                    image: noidfile.base.clone(),
+                    kernel: None,
+                    rootfs: None,
+                    boot_args: None,
+                    init: None,
+                    restart: Default::default(),
+                    restart_from: Default::default(),
+                    ttl_secs: None,
+                    stop_on_expiry: false,
+                    labels: Default::default(),
+                    user_data: None,
+                    rate_limits: Default::default(),
+                },
+                |_| {},
+            )?;
+            wait_for_guest(api, &vm)?;
+            let cp = api.create_checkpoint(&vm, Some(&label(&keys[0])))?;
+            (0, cp.id)
+        }
+    };
+
+    for (i, step) in noidfile.steps.iter().enumerate().skip(start) {
+        println!("Step {}/{total}: {step}", i + 1);
+        run_step(api, &vm, step, &noidfile.env).with_context(|| {
+            format!(
+                "step {} failed; build VM '{vm}' is left running for inspection",
+                i + 1
+            )
+        })?;
+        checkpoint = api.create_checkpoint(&vm, Some(&label(&keys[i + 1])))?.id;
+    }
+
+    let user = api.whoami()?.name;
+    let info = api.promote_golden(&PromoteGoldenRequest {
+        user,
+        vm: vm.clone(),
+        checkpoint,
+        image: Some(image.to_string()),
+    })?;
+    println!(
+        "Built image '{image}' ({}); build VM '{vm}' kept as the layer cache",
+        info.shape
+    );
+    Ok(())
+}
+
+fn wait_for_guest(api: &ApiClient, vm: &str) -> Result<()> {
+    let probe = ["echo".to_string(), "ready".to_string()];
+    for _ in 0..60 {
+        if let Ok(resp) = api.exec_vm(vm, &probe, &[]) {
+            if resp.exit_code == Some(0) && resp.stdout.contains("ready") {
+                return Ok(());
+            }
+        }
+        std::thread::sleep(std::time::Duration::from_secs(2));
+    }
+    bail!("build VM '{vm}' did not become ready")
+}
+
+fn run_step(api: &ApiClient, vm: &str, step: &Step, env: &[String]) -> Result<()> {
+    match step {
+        Step::Run(cmd) => {
+            let command = ["sh".to_string(), "-c".to_string(), cmd.clone()];
+            let code = exec::exec_ws(api, vm, &command, env)?;
+            if code != 0 {
+                bail!("command exited with status {code}");
+            }
+            Ok(())
+        }
+        Step::Copy {
+            dest, data, mode, ..
+        } => {
+            let staging = format!("{dest}.noid-build");
+            sh(
+                api,
+                vm,
+                r#"mkdir -p "$(dirname "$1")" && : > "$2""#,
+                &[dest, &staging],
+            )?;
+            let encoded = base64::engine::general_purpose::STANDARD.encode(data);
+            for chunk in encoded.as_bytes().chunks(COPY_CHUNK) {
+                let chunk = std::str::from_utf8(chunk).expect("base64 is ASCII");
+                sh(api, vm, r#"printf %s "$1" >> "$2""#, &[chunk, &staging])?;
+            }
+            sh(
+                api,
+                vm,
+                r#"base64 -d "$2" > "$1" && rm -f "$2" && chmod "$3" "$1""#,
+                &[dest, &staging, &format!("{mode:o}")],
+            )
+        }
+    }
+}
+
+/// Run a shell script in the guest with positional arguments, failing on a
+/// nonzero exit.
+fn sh(api: &ApiClient, vm: &str, script: &str, args: &[&str]) -> Result<()> {
+    let mut command = vec!["sh".to_string(), "-c".to_string(), script.to_string()];
+    command.push("sh".to_string());
+    command.extend(args.iter().map(|a| a.to_string()));
+    let resp = api.exec_vm(vm, &command, &[])?;
+    match resp.exit_code {
+        Some(0) => Ok(()),
+        _ if resp.timed_out => bail!("timed out"),
+        code => bail!(
+            "exited with status {}: {}",
+            code.map_or("unknown".to_string(), |c| c.to_string()),
+            resp.stdout.trim()
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Result<Noidfile> {
+        Noidfile::parse(text, Path::new("."))
+    }
+
+    fn checkpoint(id: &str, label: &str) -> CheckpointInfo {
+        CheckpointInfo {
+            id: id.into(),
+            vm_name: "_build-web".into(),
+            label: Some(label.into()),
+            created_at: String::new(),
+            corrupt: false,
+            disk: None,
+            firecracker_version: None,
+        }
+    }
+
+    #[test]
+    fn parse_noidfile() {
+        let nf = parse(
+            r#"
+            base = "node20"
+            cpus = 2
+
+            [env]
+            B = "2"
+            A = "1"
+
+            [[step]]
+            run = "apt-get update"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(nf.base.as_deref(), Some("node20"));
+        assert_eq!((nf.cpus, nf.mem_mib), (2, 2048));
+        assert_eq!(nf.env, vec!["A=1", "B=2"]);
+        assert_eq!(nf.steps, vec![Step::Run("apt-get update".into())]);
+    }
+
+    #[test]
+    fn build_vm_names_fit_the_name_limit() {
+        assert_eq!(build_vm_name("web"), "_build-web");
+        let fits = "a".repeat(MAX_VM_NAME - "_build-".len());
+        assert_eq!(build_vm_name(&fits), format!("_build-{fits}"));
+
+        let long = build_vm_name(&format!("{fits}b"));
+        let other = build_vm_name(&format!("{fits}c"));
+        assert_eq!(long.len(), MAX_VM_NAME);
+        assert!(long.starts_with("_build-aaaa"));
+        assert_ne!(long, other);
+        assert_eq!(long, build_vm_name(&format!("{fits}b")));
+    }
+
+    #[test]
+    fn example_noidfile_parses() {
+        let nf = parse(include_str!("../../../Noidfile.example")).unwrap();
+        assert!(nf.base.is_none());
+        assert_eq!(nf.steps.len(), 2);
+    }
+
+    #[test]
+    fn parse_rejects_bad_steps() {
+        let err = parse("[[step]]\ncopy = \"x\"\n").unwrap_err();
+        assert!(format!("{err:#}").contains("copy needs a dest"));
+        let err = parse("[[step]]\nrun = \"a\"\ncopy = \"x\"\ndest = \"/x\"\n").unwrap_err();
+        assert!(format!("{err:#}").contains("expected either"));
+        let err = parse("[[step]]\ncopy = \"x\"\ndest = \"relative\"\n").unwrap_err();
+        assert!(format!("{err:#}").contains("absolute"));
+        assert!(parse("[[step]]\nrun = \"a\"\nuser = \"root\"\n").is_err());
+        assert!(parse("[env]\n\"1BAD\" = \"x\"\n").is_err());
+    }
+
+    #[test]
+    fn copy_reads_file_relative_to_noidfile() {
+        let dir = std::env::temp_dir().join(format!("noid-build-copy-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("gitconfig"), b"[user]\n").unwrap();
+        let nf = Noidfile::parse(
+            "[[step]]\ncopy = \"gitconfig\"\ndest = \"/root/.gitconfig\"\n",
+            &dir,
+        )
+        .unwrap();
+        match &nf.steps[0] {
+            Step::Copy { data, dest, .. } => {
+                assert_eq!(data, b"[user]\n");
+                assert_eq!(dest, "/root/.gitconfig");
+            }
+            other => panic!("unexpected step {other:?}"),
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn layer_keys_share_unchanged_prefix() {
+        let a = parse("[[step]]\nrun = \"one\"\n[[step]]\nrun = \"two\"\n").unwrap();
+        let b = parse("[[step]]\nrun = \"one\"\n[[step]]\nrun = \"three\"\n").unwrap();
+        let c = parse("cpus = 2\n[[step]]\nrun = \"one\"\n").unwrap();
+        let (ka, kb, kc) = (a.layer_keys(None), b.layer_keys(None), c.layer_keys(None));
+        assert_eq!(ka.len(), 3);
+        assert_eq!(ka[..2], kb[..2]);
+        assert_ne!(ka[2], kb[2]);
+        assert_ne!(ka[0], kc[0]);
+        assert_ne!(ka[1], kc[1]);
+    }
+
+    #[test]
+    fn layer_keys_change_with_base_version() {
+        let nf = parse("base = \"node20\"\n[[step]]\nrun = \"one\"\n").unwrap();
+        let (old, new) = (nf.layer_keys(Some("1")), nf.layer_keys(Some("2")));
+        assert_eq!(old, nf.layer_keys(Some("1")));
+        assert_ne!(old[0], new[0]);
+        assert_ne!(old[1], new[1]);
+    }
+
+    #[test]
+    fn cached_layer_picks_deepest_match() {
+        let nf = parse("[[step]]\nrun = \"one\"\n[[step]]\nrun = \"two\"\n").unwrap();
+        let keys = nf.layer_keys(None);
+        assert_eq!(cached_layer(&keys, &[]), None);
+
+        let mut cps = vec![
+            checkpoint("base", &label(&keys[0])),
+            checkpoint("one", &label(&keys[1])),
+            checkpoint("other", "build:0000000000000000"),
+        ];
+        assert_eq!(cached_layer(&keys, &cps), Some((1, "one".into())));
+
+        cps[1].corrupt = true;
+        assert_eq!(cached_layer(&keys, &cps), Some((0, "base".into())));
+    }
+}
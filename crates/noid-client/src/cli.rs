@@ -1,4 +1,5 @@
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
+use noid_types::{IoLimit, RateLimits, RestartFrom, RestartPolicy};
 
 #[derive(Parser)]
 #[command(
@@ -37,14 +38,145 @@ pub enum Command {
         /// Memory in MiB
         #[arg(long, default_value = "2048")]
         mem: u32,
+        /// Golden image to create from (see `noid images`)
+        #[arg(long)]
+        image: Option<String>,
+        /// Kernel from the server's image registry (see `noid kernel list`)
+        #[arg(long)]
+        kernel: Option<String>,
+        /// Rootfs from the server's image registry (see `noid rootfs list`)
+        #[arg(long, conflicts_with = "image")]
+        rootfs: Option<String>,
+        /// Extra kernel command-line parameters, e.g. "mitigations=off"
+        #[arg(long)]
+        boot_args: Option<String>,
+        /// Alternative init to boot, e.g. /sbin/myinit
+        #[arg(long)]
+        init: Option<String>,
+        /// Restart after the VM exits on its own: never, on-failure or always
+        #[arg(long, default_value = "never")]
+        restart: RestartPolicy,
+        /// How to restart: boot (cold boot its disk) or checkpoint (restore the latest checkpoint)
+        #[arg(long, default_value = "boot")]
+        restart_from: RestartFrom,
+        /// Destroy the VM after this long, e.g. 30m, 2h or 1d
+        #[arg(long, value_parser = noid_types::parse_duration_secs)]
+        ttl: Option<u64>,
+        /// When the TTL runs out, stop the VM after a final checkpoint and keep it instead of destroying it
+        #[arg(long, requires = "ttl")]
+        stop_on_expiry: bool,
+        /// Label the VM, e.g. team=ml (repeatable)
+        #[arg(short = 'l', long = "label", value_name = "KEY=VALUE", value_parser = noid_types::parse_label)]
+        labels: Vec<(String, String)>,
+        /// Shell script to run inside the VM once it is up (see `noid init-log`)
+        #[arg(long, value_name = "FILE")]
+        init_script: Option<std::path::PathBuf>,
+        #[command(flatten)]
+        limits: RateLimitArgs,
     },
-    /// Destroy a microVM
+    /// Destroy a microVM, or every microVM matching --label selectors
     Destroy {
+        /// VM name (optional if .noid-vm file exists)
+        #[arg(conflicts_with = "selector")]
+        name: Option<String>,
+        /// Destroy all VMs with this label (repeatable; all must match)
+        #[arg(short = 'l', long = "label", value_name = "KEY=VALUE", value_parser = noid_types::parse_label)]
+        selector: Vec<(String, String)>,
+    },
+    /// Rename a microVM, along with its checkpoints
+    Rename {
+        /// Current VM name
+        name: String,
+        /// New VM name
+        new_name: String,
+    },
+    /// Shut down a microVM, keeping its disk
+    Stop {
         /// VM name (optional if .noid-vm file exists)
         name: Option<String>,
     },
+    /// Boot a stopped microVM
+    Start {
+        /// VM name (optional if .noid-vm file exists)
+        name: Option<String>,
+    },
+    /// Shut down and boot a microVM again
+    Reboot {
+        /// VM name (optional if .noid-vm file exists)
+        name: Option<String>,
+    },
+    /// Show or change when a microVM expires
+    Ttl {
+        /// VM name (optional if .noid-vm file exists)
+        name: Option<String>,
+        /// Expire this long from now, e.g. 30m, 2h or 1d
+        #[arg(long, value_parser = noid_types::parse_duration_secs, conflicts_with_all = ["extend", "clear"])]
+        set: Option<u64>,
+        /// Push the expiry back by this long
+        #[arg(long, value_parser = noid_types::parse_duration_secs, conflicts_with = "clear")]
+        extend: Option<u64>,
+        /// Never expire
+        #[arg(long)]
+        clear: bool,
+        /// On expiry, stop the VM after a final checkpoint and keep it (true) or destroy it (false)
+        #[arg(long)]
+        stop_on_expiry: Option<bool>,
+    },
+    /// Show or change how much memory a microVM's guest may use
+    Mem {
+        /// VM name (optional if .noid-vm file exists)
+        name: Option<String>,
+        /// Inflate or deflate the balloon to leave the guest this many MiB
+        #[arg(long)]
+        target: Option<u32>,
+    },
+    /// Show or change a microVM's disk and network rate limits
+    Limits {
+        /// VM name (optional if .noid-vm file exists)
+        name: Option<String>,
+        #[command(flatten)]
+        limits: RateLimitArgs,
+    },
+    /// Show the status and output of a microVM's init script
+    InitLog {
+        /// VM name (optional if .noid-vm file exists)
+        name: Option<String>,
+        /// Wait for the script to finish and exit with its status
+        #[arg(long)]
+        wait: bool,
+    },
+    /// Show a microVM's lifecycle events: creation, exec, checkpoints, crashes and restarts
+    Events {
+        /// VM name (optional if .noid-vm file exists)
+        #[arg(conflicts_with = "all")]
+        name: Option<String>,
+        /// Include exit status and log tails
+        #[arg(long)]
+        detail: bool,
+        /// Only events from this long ago on, e.g. 30m, 1h or 2d
+        #[arg(long, value_parser = noid_types::parse_duration_secs)]
+        since: Option<u64>,
+        /// Events of all your VMs
+        #[arg(long)]
+        all: bool,
+        /// Keep printing new events as they happen
+        #[arg(short, long)]
+        follow: bool,
+    },
     /// List all microVMs
-    List,
+    List {
+        /// Only VMs with this label (repeatable; all must match)
+        #[arg(short = 'l', long = "label", value_name = "KEY=VALUE", value_parser = noid_types::parse_label)]
+        selector: Vec<(String, String)>,
+    },
+    /// Show, set or remove a microVM's labels
+    Label {
+        /// VM name
+        name: String,
+        /// KEY=VALUE to set a label, KEY- to remove one
+        #[arg(value_parser = parse_label_change)]
+        changes: Vec<(String, Option<String>)>,
+    },
     /// Show info about a microVM
     Info {
         /// VM name (optional if .noid-vm file exists)
@@ -82,6 +214,39 @@ pub enum Command {
         /// VM name (optional if .noid-vm file exists)
         name: Option<String>,
     },
+    /// Verify a checkpoint's files against its recorded checksums
+    Verify {
+        /// VM name
+        name: String,
+        /// Checkpoint ID
+        checkpoint_id: String,
+    },
+    /// Show disk usage of your VMs and checkpoints
+    Usage,
+    /// List golden images available on the server
+    Images,
+    /// Manage kernels in the server's image registry (admin)
+    Kernel {
+        #[command(subcommand)]
+        action: RegistryAction,
+    },
+    /// Manage rootfs images in the server's image registry (admin)
+    Rootfs {
+        #[command(subcommand)]
+        action: RegistryAction,
+    },
+    /// Build a golden image from a Noidfile (admin)
+    Build {
+        /// Path to the Noidfile
+        #[arg(short = 'f', long = "file", default_value = "Noidfile")]
+        file: std::path::PathBuf,
+        /// Name of the image to build (replaces an existing image)
+        #[arg(long)]
+        name: String,
+        /// Ignore cached layers and run every step
+        #[arg(long)]
+        no_cache: bool,
+    },
     /// Update noid to the latest release
     Update,
     /// Restore a microVM from a checkpoint
@@ -93,6 +258,12 @@ pub enum Command {
         /// Create as a new VM with this name
         #[arg(long = "as")]
         new_name: Option<String>,
+        /// Shell script to run inside the restored VM (see `noid init-log`)
+        #[arg(long, value_name = "FILE")]
+        init_script: Option<std::path::PathBuf>,
+        /// Verify the checkpoint's files against their checksums first
+        #[arg(long)]
+        verify: bool,
     },
 }
 
@@ -108,3 +279,64 @@ pub enum AuthAction {
         token: String,
     },
 }
+
+#[derive(Subcommand)]
+pub enum RegistryAction {
+    /// Upload a file under a name
+    Upload {
+        /// Name to store it under, e.g. 6.12 or ubuntu-25.04
+        name: String,
+        /// Local file to upload
+        file: std::path::PathBuf,
+    },
+    /// List uploaded files
+    List,
+    /// Delete an uploaded file
+    Rm {
+        /// Name
+        name: String,
+    },
+}
+
+/// Rate limit flags of `noid create` and `noid limits`. With `noid limits`,
+/// 0 removes a limit.
+#[derive(Args)]
+pub struct RateLimitArgs {
+    /// Disk bandwidth per second, e.g. 50M
+    #[arg(long, value_name = "RATE", value_parser = noid_types::parse_bandwidth)]
+    pub disk_bw: Option<u64>,
+    /// Disk operations per second
+    #[arg(long, value_name = "N")]
+    pub disk_iops: Option<u64>,
+    /// Network bandwidth per second in each direction, e.g. 10M
+    #[arg(long, value_name = "RATE", value_parser = noid_types::parse_bandwidth)]
+    pub net_bw: Option<u64>,
+    /// Network packets per second in each direction
+    #[arg(long, value_name = "N")]
+    pub net_pps: Option<u64>,
+}
+
+impl RateLimitArgs {
+    pub fn limits(&self) -> RateLimits {
+        RateLimits {
+            disk: IoLimit {
+                bandwidth: self.disk_bw,
+                ops: self.disk_iops,
+            },
+            net: IoLimit {
+                bandwidth: self.net_bw,
+                ops: self.net_pps,
+            },
+        }
+    }
+}
+
+/// Parse a `noid label` argument: `key=value` sets a label, `key-` removes it.
+fn parse_label_change(s: &str) -> Result<(String, Option<String>), String> {
+    if let Some(key) = s.strip_suffix('-').filter(|_| !s.contains('=')) {
+        noid_types::validate_label(key, "")?;
+        return Ok((key.to_string(), None));
+    }
+    let (key, value) = noid_types::parse_label(s)?;
+    Ok((key, Some(value)))
+}
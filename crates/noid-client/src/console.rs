@@ -142,43 +142,41 @@ pub fn attach_console(api: &ApiClient, vm_name: &str, env: &[String]) -> Result<
         // Check for keyboard input (non-blocking)
         if event::poll(Duration::from_millis(10))? {
             match event::read()? {
-                Event::Paste(text) => {
+                Event::Paste(text) if !text.is_empty() => {
                     // Bracketed paste: send entire pasted text as one frame.
                     // Translate newlines to CR (what real terminals send for Enter).
                     // Normalize \r\n first to avoid double-CR.
-                    if !text.is_empty() {
-                        let translated = text.replace("\r\n", "\r").replace('\n', "\r");
+                    let translated = text.replace("\r\n", "\r").replace('\n', "\r");
 
-                        // Check if any line in the pasted text is "exit"
-                        let lines: Vec<&str> = translated.split('\r').collect();
-                        for (i, line) in lines.iter().enumerate() {
-                            let is_last = i == lines.len() - 1;
-                            if !is_last && line.trim() == "exit" {
-                                // Send Ctrl+U to clear the VM's input line, then detach
-                                let _ = send_stdin(&mut ws, b"\x15");
-                                set_ws_nonblocking(&mut ws, false);
-                                let _ = ws.send(Message::Close(None));
-                                let _ = ws.close(None);
-                                let _ = crossterm::execute!(
-                                    stdout,
-                                    crossterm::event::DisableBracketedPaste
-                                );
-                                terminal::disable_raw_mode()?;
-                                println!("\r\n--- Detached ---");
-                                return Ok(());
-                            }
+                    // Check if any line in the pasted text is "exit"
+                    let lines: Vec<&str> = translated.split('\r').collect();
+                    for (i, line) in lines.iter().enumerate() {
+                        let is_last = i == lines.len() - 1;
+                        if !is_last && line.trim() == "exit" {
+                            // Send Ctrl+U to clear the VM's input line, then detach
+                            let _ = send_stdin(&mut ws, b"\x15");
+                            set_ws_nonblocking(&mut ws, false);
+                            let _ = ws.send(Message::Close(None));
+                            let _ = ws.close(None);
+                            let _ = crossterm::execute!(
+                                stdout,
+                                crossterm::event::DisableBracketedPaste
+                            );
+                            terminal::disable_raw_mode()?;
+                            println!("\r\n--- Detached ---");
+                            return Ok(());
                         }
+                    }
 
-                        if !send_stdin(&mut ws, translated.as_bytes()) {
-                            break;
-                        }
-                        // Update line_buffer with the last incomplete line
-                        if let Some(last) = lines.last() {
-                            if translated.ends_with('\r') {
-                                line_buffer.clear();
-                            } else {
-                                line_buffer = last.to_string();
-                            }
+                    if !send_stdin(&mut ws, translated.as_bytes()) {
+                        break;
+                    }
+                    // Update line_buffer with the last incomplete line
+                    if let Some(last) = lines.last() {
+                        if translated.ends_with('\r') {
+                            line_buffer.clear();
+                        } else {
+                            line_buffer = last.to_string();
                         }
                     }
                 }
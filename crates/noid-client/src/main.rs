@@ -1,14 +1,15 @@
 mod api;
+mod build;
 mod cli;
 mod config;
 mod console;
 mod exec;
 mod update;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use clap::Parser;
 
-use cli::{AuthAction, Cli, Command};
+use cli::{AuthAction, Cli, Command, RegistryAction};
 use config::{ClientConfig, ServerSection};
 
 fn main() -> Result<()> {
@@ -34,17 +35,123 @@ fn main() -> Result<()> {
             cmd_whoami()?;
             0
         }
-        Command::Create { name, cpus, mem } => {
-            cmd_create(&name, cpus, mem)?;
+        Command::Create {
+            name,
+            cpus,
+            mem,
+            image,
+            kernel,
+            rootfs,
+            boot_args,
+            init,
+            restart,
+            restart_from,
+            ttl,
+            stop_on_expiry,
+            labels,
+            init_script,
+            limits,
+        } => {
+            cmd_create(noid_types::CreateVmRequest {
+                name,
+                cpus,
+                mem_mib: mem,
+                image,
+                kernel,
+                rootfs,
+                boot_args,
+                init,
+                restart,
+                restart_from,
+                ttl_secs: ttl,
+                stop_on_expiry,
+                labels: labels.into_iter().collect(),
+                user_data: read_init_script(init_script.as_deref())?,
+                // 0 means unlimited, as with `noid limits`
+                rate_limits: noid_types::RateLimits::default().updated(&limits.limits()),
+            })?;
+            0
+        }
+        Command::Destroy { name, selector } => {
+            if selector.is_empty() {
+                let name = config::resolve_vm_name(name.as_deref())?;
+                cmd_destroy(&name)?;
+            } else {
+                cmd_destroy_matching(&selector)?;
+            }
+            0
+        }
+        Command::Rename { name, new_name } => {
+            cmd_rename(&name, &new_name)?;
             0
         }
-        Command::Destroy { name } => {
+        Command::Stop { name } => {
             let name = config::resolve_vm_name(name.as_deref())?;
-            cmd_destroy(&name)?;
+            cmd_stop(&name)?;
+            0
+        }
+        Command::Start { name } => {
+            let name = config::resolve_vm_name(name.as_deref())?;
+            cmd_start(&name)?;
+            0
+        }
+        Command::Reboot { name } => {
+            let name = config::resolve_vm_name(name.as_deref())?;
+            cmd_reboot(&name)?;
+            0
+        }
+        Command::Ttl {
+            name,
+            set,
+            extend,
+            clear,
+            stop_on_expiry,
+        } => {
+            let name = config::resolve_vm_name(name.as_deref())?;
+            let req = noid_types::UpdateVmRequest {
+                ttl_secs: if clear { Some(0) } else { set },
+                extend_secs: extend,
+                stop_on_expiry,
+                ..Default::default()
+            };
+            cmd_ttl(&name, &req)?;
+            0
+        }
+        Command::Mem { name, target } => {
+            let name = config::resolve_vm_name(name.as_deref())?;
+            cmd_mem(&name, target)?;
+            0
+        }
+        Command::Limits { name, limits } => {
+            let name = config::resolve_vm_name(name.as_deref())?;
+            cmd_limits(&name, &limits.limits())?;
+            0
+        }
+        Command::InitLog { name, wait } => {
+            let name = config::resolve_vm_name(name.as_deref())?;
+            cmd_init_log(&name, wait)?
+        }
+        Command::Events {
+            name,
+            detail,
+            since,
+            all,
+            follow,
+        } => {
+            let name = if all {
+                None
+            } else {
+                Some(config::resolve_vm_name(name.as_deref())?)
+            };
+            cmd_events(name.as_deref(), since, detail, follow)?;
+            0
+        }
+        Command::List { selector } => {
+            cmd_list(&selector)?;
             0
         }
-        Command::List => {
-            cmd_list()?;
+        Command::Label { name, changes } => {
+            cmd_label(&name, changes)?;
             0
         }
         Command::Info { name } => {
@@ -81,6 +188,34 @@ fn main() -> Result<()> {
             cmd_checkpoints(&name)?;
             0
         }
+        Command::Verify {
+            name,
+            checkpoint_id,
+        } => cmd_checkpoint_verify(&name, &checkpoint_id)?,
+        Command::Images => {
+            cmd_images()?;
+            0
+        }
+        Command::Kernel { action } => {
+            cmd_registry(noid_types::ArtifactKind::Kernel, action)?;
+            0
+        }
+        Command::Rootfs { action } => {
+            cmd_registry(noid_types::ArtifactKind::Rootfs, action)?;
+            0
+        }
+        Command::Usage => {
+            cmd_usage()?;
+            0
+        }
+        Command::Build {
+            file,
+            name,
+            no_cache,
+        } => {
+            cmd_build(&file, &name, no_cache)?;
+            0
+        }
         Command::Update => {
             update::self_update()?;
             0
@@ -89,9 +224,12 @@ fn main() -> Result<()> {
             name,
             checkpoint_id,
             new_name,
+            init_script,
+            verify,
         } => {
             let name = config::resolve_vm_name(name.as_deref())?;
-            cmd_restore(&name, &checkpoint_id, new_name.as_deref())?;
+            let user_data = read_init_script(init_script.as_deref())?;
+            cmd_restore(&name, &checkpoint_id, new_name.as_deref(), user_data, verify)?;
             0
         }
     };
@@ -159,13 +297,66 @@ fn cmd_whoami() -> Result<()> {
     Ok(())
 }
 
-fn cmd_create(name: &str, cpus: u32, mem: u32) -> Result<()> {
+fn cmd_create(req: noid_types::CreateVmRequest) -> Result<()> {
     let api = api_client()?;
-    let info = api.create_vm(name, cpus, mem)?;
+    let mut progress = ProgressLine::new(format!("Creating VM '{}'", req.name));
+    let info = api.create_vm(&req, |op| progress.update(op));
+    progress.clear();
+    let info = info?;
     println!("VM '{}' created (state: {})", info.name, info.state);
     Ok(())
 }
 
+/// The phase of a running create or restore, on one line of stderr that is
+/// rewritten as the operation moves on. Only shown on a terminal.
+struct ProgressLine {
+    label: String,
+    start: std::time::Instant,
+    enabled: bool,
+    shown: bool,
+}
+
+impl ProgressLine {
+    fn new(label: String) -> Self {
+        use std::io::IsTerminal;
+        Self {
+            label,
+            start: std::time::Instant::now(),
+            enabled: std::io::stderr().is_terminal(),
+            shown: false,
+        }
+    }
+
+    fn update(&mut self, op: &noid_types::OperationInfo) {
+        use std::io::Write;
+        let Some(phase) = op.phase().filter(|_| self.enabled && !op.is_finished()) else {
+            return;
+        };
+        let secs = self.start.elapsed().as_secs();
+        eprint!("\r\x1b[K{}: {phase}... ({secs}s)", self.label);
+        let _ = std::io::stderr().flush();
+        self.shown = true;
+    }
+
+    fn clear(&mut self) {
+        if std::mem::take(&mut self.shown) {
+            eprint!("\r\x1b[K");
+        }
+    }
+}
+
+/// Read an `--init-script` file for the `user_data` of a request.
+fn read_init_script(path: Option<&std::path::Path>) -> Result<Option<String>> {
+    let Some(path) = path else {
+        return Ok(None);
+    };
+    let script = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read init script {}", path.display()))?;
+    noid_types::validate_user_data(&script)
+        .map_err(|e| anyhow::anyhow!("{}: {e}", path.display()))?;
+    Ok(Some(script))
+}
+
 fn cmd_destroy(name: &str) -> Result<()> {
     let api = api_client()?;
     api.destroy_vm(name)?;
@@ -173,9 +364,311 @@ fn cmd_destroy(name: &str) -> Result<()> {
     Ok(())
 }
 
-fn cmd_list() -> Result<()> {
+fn cmd_rename(name: &str, new_name: &str) -> Result<()> {
+    let api = api_client()?;
+    api.rename_vm(name, new_name)?;
+    println!("VM '{name}' renamed to '{new_name}'");
+    if config::read_active_vm().as_deref() == Some(name) {
+        config::write_active_vm(new_name)?;
+        println!("Active VM set to '{new_name}'");
+    }
+    Ok(())
+}
+
+fn cmd_destroy_matching(selector: &[(String, String)]) -> Result<()> {
+    let api = api_client()?;
+    let vms = api.list_vms(selector)?;
+    if vms.is_empty() {
+        println!("No VMs match.");
+        return Ok(());
+    }
+    let mut failed = 0;
+    for vm in &vms {
+        match api.destroy_vm(&vm.name) {
+            Ok(()) => println!("VM '{}' destroyed", vm.name),
+            Err(e) => {
+                eprintln!("Failed to destroy VM '{}': {e:#}", vm.name);
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        anyhow::bail!("{failed} of {} VMs could not be destroyed", vms.len());
+    }
+    Ok(())
+}
+
+fn cmd_label(name: &str, changes: Vec<(String, Option<String>)>) -> Result<()> {
+    let api = api_client()?;
+    let info = if changes.is_empty() {
+        api.get_vm(name)?
+    } else {
+        let req = noid_types::UpdateVmRequest {
+            labels: changes.into_iter().collect(),
+            ..Default::default()
+        };
+        api.update_vm(name, &req)?
+    };
+    if info.labels.is_empty() {
+        println!("VM '{name}' has no labels");
+    }
+    for (key, value) in &info.labels {
+        println!("{key}={value}");
+    }
+    Ok(())
+}
+
+/// `key=value` pairs joined by `sep`, or `-` if there are none.
+fn format_labels(labels: &std::collections::BTreeMap<String, String>, sep: &str) -> String {
+    if labels.is_empty() {
+        return "-".into();
+    }
+    labels
+        .iter()
+        .map(|(k, v)| format!("{k}={v}"))
+        .collect::<Vec<_>>()
+        .join(sep)
+}
+
+fn cmd_stop(name: &str) -> Result<()> {
+    let api = api_client()?;
+    api.stop_vm(name)?;
+    println!("VM '{name}' stopped");
+    Ok(())
+}
+
+fn cmd_start(name: &str) -> Result<()> {
+    let api = api_client()?;
+    api.start_vm(name)?;
+    println!("VM '{name}' started");
+    Ok(())
+}
+
+fn cmd_reboot(name: &str) -> Result<()> {
+    let api = api_client()?;
+    api.reboot_vm(name)?;
+    println!("VM '{name}' rebooted");
+    Ok(())
+}
+
+fn cmd_ttl(name: &str, req: &noid_types::UpdateVmRequest) -> Result<()> {
+    let api = api_client()?;
+    let changed =
+        req.ttl_secs.is_some() || req.extend_secs.is_some() || req.stop_on_expiry.is_some();
+    let info = if changed {
+        api.update_vm(name, req)?
+    } else {
+        api.get_vm(name)?
+    };
+    println!("VM '{}' expires: {}", info.name, format_expiry(&info));
+    Ok(())
+}
+
+/// When a VM expires and what happens then, e.g.
+/// "in 1h 5m (2026-02-12 12:00:00 UTC), then destroyed".
+fn format_expiry(info: &noid_types::VmInfo) -> String {
+    let (Some(at), Some(left)) = (&info.expires_at, info.expires_in_secs) else {
+        return "never".into();
+    };
+    let then = if info.stop_on_expiry {
+        "checkpointed and stopped"
+    } else {
+        "destroyed"
+    };
+    format!(
+        "in {} ({at} UTC), then {then}",
+        noid_types::format_duration_secs(left)
+    )
+}
+
+fn cmd_mem(name: &str, target: Option<u32>) -> Result<()> {
+    let api = api_client()?;
+    let balloon = match target {
+        Some(target) => api.set_balloon(name, target)?,
+        None => api.balloon(name)?,
+    };
+    let info = api.get_vm(name)?;
+    println!("Memory:  {} MiB", info.mem_mib);
+    print_balloon(&balloon);
+    Ok(())
+}
+
+fn cmd_limits(name: &str, changes: &noid_types::RateLimits) -> Result<()> {
+    let api = api_client()?;
+    if !changes.is_unlimited() {
+        let req = noid_types::UpdateVmRequest {
+            rate_limits: Some(*changes),
+            ..Default::default()
+        };
+        api.update_vm(name, &req)?;
+    }
+    let info = api.get_vm(name)?;
+    let limits = info.details.map(|d| d.rate_limits).unwrap_or_default();
+    println!("Disk:    {}", format_limit(&limits.disk, "IOPS"));
+    println!("Network: {}", format_limit(&limits.net, "packets/s"));
+    Ok(())
+}
+
+/// A rate limit as shown by `noid info` and `noid limits`.
+fn format_limit(limit: &noid_types::IoLimit, ops_unit: &str) -> String {
+    let mut parts = Vec::new();
+    if let Some(bandwidth) = limit.bandwidth {
+        parts.push(format!("{}/s", format_bytes(bandwidth)));
+    }
+    if let Some(ops) = limit.ops {
+        parts.push(format!("{ops} {ops_unit}"));
+    }
+    if parts.is_empty() {
+        "unlimited".to_string()
+    } else {
+        parts.join(", ")
+    }
+}
+
+/// Process, network and provenance lines of `noid info`.
+fn print_details(details: &noid_types::VmDetails) {
+    if let Some(uptime) = details.uptime_secs {
+        println!("Uptime:  {}", noid_types::format_duration_secs(uptime));
+    }
+    if let Some(pid) = details.pid {
+        println!("PID:     {pid}");
+    }
+    if let (Some(cpu), Some(rss)) = (details.host_cpu_secs, details.host_rss_bytes) {
+        println!("Host:    {cpu:.1}s CPU, {} RSS", format_bytes(rss));
+    }
+    if let Some(ip) = &details.guest_ip {
+        let mut extra = Vec::new();
+        if let Some(mac) = &details.guest_mac {
+            extra.push(format!("MAC {mac}"));
+        }
+        if let Some(tap) = &details.tap_name {
+            extra.push(format!("TAP {tap}"));
+        }
+        if extra.is_empty() {
+            println!("Network: {ip}");
+        } else {
+            println!("Network: {ip} ({})", extra.join(", "));
+        }
+    }
+    if !details.kernel.is_empty() {
+        println!("Kernel:  {}", details.kernel);
+    }
+    if let Some(rootfs) = &details.source_rootfs {
+        println!("Source:  {rootfs}");
+    }
+    let checkpoints = match details.checkpoint_count {
+        1 => "1 checkpoint".to_string(),
+        n => format!("{n} checkpoints"),
+    };
+    match &details.parent_checkpoint {
+        Some(parent) => println!("History: {checkpoints}; restored from {parent}"),
+        None => println!("History: {checkpoints}"),
+    }
+    if let Some(init) = &details.init_script {
+        println!("Init:    {}", format_init_script(init));
+    }
+    let limits = &details.rate_limits;
+    if !limits.is_unlimited() {
+        println!(
+            "Limits:  disk {}; network {}",
+            format_limit(&limits.disk, "IOPS"),
+            format_limit(&limits.net, "packets/s")
+        );
+    }
+}
+
+/// Balloon and guest memory lines of `noid info` and `noid mem`.
+fn print_balloon(balloon: &noid_types::BalloonInfo) {
+    println!(
+        "Balloon: {} MiB reclaimed, guest target {} MiB",
+        balloon.balloon_mib, balloon.target_mib
+    );
+    let size = |bytes: Option<u64>| bytes.map_or("-".to_string(), format_bytes);
+    if balloon.total_bytes.is_some() {
+        println!(
+            "Guest:   {} total, {} free, {} available",
+            size(balloon.total_bytes),
+            size(balloon.free_bytes),
+            size(balloon.available_bytes)
+        );
+    }
+    if let (Some(major), Some(minor)) = (balloon.major_faults, balloon.minor_faults) {
+        println!("Faults:  {major} major, {minor} minor");
+    }
+}
+
+fn format_init_script(init: &noid_types::InitScriptInfo) -> String {
+    let mut line = init.state.clone();
+    if let Some(code) = init.exit_code {
+        line.push_str(&format!(" (exit {code})"));
+    }
+    if let Some(at) = &init.finished_at {
+        line.push_str(&format!(" at {at} UTC"));
+    }
+    line
+}
+
+/// Print a VM's init script status and output. With `wait`, poll until the
+/// script finishes and return its exit status.
+fn cmd_init_log(name: &str, wait: bool) -> Result<i32> {
+    let api = api_client()?;
+    let mut log = api.init_log(name)?;
+    while wait && log.finished_at.is_none() {
+        std::thread::sleep(std::time::Duration::from_secs(2));
+        log = api.init_log(name)?;
+    }
+    eprintln!("Init script: {}", format_init_script(&log));
+    if let Some(output) = &log.output {
+        print!("{output}");
+    }
+    Ok(match log.exit_code {
+        Some(code) if wait => code,
+        None if wait => 1,
+        _ => 0,
+    })
+}
+
+fn print_event(event: &noid_types::VmEvent, with_vm: bool, detail: bool) {
+    if with_vm {
+        println!(
+            "{}  {:<16} {:<18} {}",
+            event.created_at, event.vm_name, event.kind, event.message
+        );
+    } else {
+        println!("{}  {:<18} {}", event.created_at, event.kind, event.message);
+    }
+    if let Some(text) = event.detail.as_deref().filter(|_| detail) {
+        for line in text.lines() {
+            println!("    {line}");
+        }
+    }
+}
+
+/// Print the events of one VM, or all of them when `name` is `None`. With
+/// `follow`, keep printing new ones until interrupted.
+fn cmd_events(name: Option<&str>, since: Option<u64>, detail: bool, follow: bool) -> Result<()> {
     let api = api_client()?;
-    let vms = api.list_vms()?;
+    let with_vm = name.is_none();
+    if follow {
+        return api.follow_events(name, since, |event| print_event(event, with_vm, detail));
+    }
+    let events = api.events(name, since)?;
+    if events.is_empty() {
+        match name {
+            Some(name) => println!("No events for VM '{name}'."),
+            None => println!("No events."),
+        }
+        return Ok(());
+    }
+    for event in &events {
+        print_event(event, with_vm, detail);
+    }
+    Ok(())
+}
+
+fn cmd_list(selector: &[(String, String)]) -> Result<()> {
+    let api = api_client()?;
+    let vms = api.list_vms(selector)?;
     if vms.is_empty() {
         println!("No VMs found.");
         return Ok(());
@@ -191,6 +684,8 @@ fn cmd_list() -> Result<()> {
         #[tabled(rename = "mem (MiB)")]
         mem: u32,
         created: String,
+        expires: String,
+        labels: String,
     }
 
     let rows: Vec<VmRow> = vms
@@ -201,6 +696,11 @@ fn cmd_list() -> Result<()> {
             cpus: vm.cpus,
             mem: vm.mem_mib,
             created: vm.created_at.clone(),
+            expires: vm
+                .expires_in_secs
+                .map(|left| format!("in {}", noid_types::format_duration_secs(left)))
+                .unwrap_or_else(|| "-".into()),
+            labels: format_labels(&vm.labels, ","),
         })
         .collect();
 
@@ -208,6 +708,100 @@ fn cmd_list() -> Result<()> {
     Ok(())
 }
 
+fn cmd_images() -> Result<()> {
+    let api = api_client()?;
+    let images = api.list_images()?;
+    if images.is_empty() {
+        println!("No named images installed.");
+        return Ok(());
+    }
+
+    use tabled::{Table, Tabled};
+
+    #[derive(Tabled)]
+    struct ImageRow {
+        name: String,
+        #[tabled(rename = "fast-path shapes (cpus x MiB)")]
+        shapes: String,
+        kernel: String,
+    }
+
+    let rows: Vec<ImageRow> = images
+        .iter()
+        .map(|img| ImageRow {
+            name: img.name.clone(),
+            shapes: if img.shapes.is_empty() {
+                "-".into()
+            } else {
+                img.shapes
+                    .iter()
+                    .map(|s| s.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            },
+            kernel: if img.custom_kernel { "image" } else { "server" }.into(),
+        })
+        .collect();
+
+    println!("{}", Table::new(rows));
+    Ok(())
+}
+
+fn cmd_registry(kind: noid_types::ArtifactKind, action: RegistryAction) -> Result<()> {
+    let api = api_client()?;
+    match action {
+        RegistryAction::Upload { name, file } => {
+            let info = api.upload_artifact(kind, &name, &file)?;
+            println!(
+                "Uploaded {kind} '{}' ({}, sha256 {})",
+                info.name,
+                format_bytes(info.size_bytes),
+                info.sha256
+            );
+        }
+        RegistryAction::Rm { name } => {
+            api.delete_artifact(kind, &name)?;
+            println!("Deleted {kind} '{name}'");
+        }
+        RegistryAction::List => {
+            let artifacts = api.list_artifacts(kind)?;
+            if artifacts.is_empty() {
+                println!("No {kind} images uploaded.");
+                return Ok(());
+            }
+
+            use tabled::{Table, Tabled};
+
+            #[derive(Tabled)]
+            struct ArtifactRow {
+                name: String,
+                size: String,
+                sha256: String,
+                uploaded: String,
+            }
+
+            let rows: Vec<ArtifactRow> = artifacts
+                .iter()
+                .map(|a| ArtifactRow {
+                    name: a.name.clone(),
+                    size: format_bytes(a.size_bytes),
+                    sha256: a.sha256.clone(),
+                    uploaded: a.uploaded_at.clone(),
+                })
+                .collect();
+
+            println!("{}", Table::new(rows));
+        }
+    }
+    Ok(())
+}
+
+fn cmd_build(file: &std::path::Path, image: &str, no_cache: bool) -> Result<()> {
+    let noidfile = build::Noidfile::load(file)?;
+    let api = api_client()?;
+    build::run(&api, &noidfile, image, no_cache)
+}
+
 fn cmd_info(name: &str) -> Result<()> {
     let api = api_client()?;
     let info = api.get_vm(name)?;
@@ -216,9 +810,82 @@ fn cmd_info(name: &str) -> Result<()> {
     println!("CPUs:    {}", info.cpus);
     println!("Memory:  {} MiB", info.mem_mib);
     println!("Created: {}", info.created_at);
+    if let Some(details) = &info.details {
+        print_details(details);
+    }
+    if info.expires_at.is_some() {
+        println!("Expires: {}", format_expiry(&info));
+    }
+    if !info.labels.is_empty() {
+        println!("Labels:  {}", format_labels(&info.labels, ", "));
+    }
+    if let Some(disk) = &info.disk {
+        println!("Disk:    {}", format_disk_usage(disk));
+    }
+    if let Some(balloon) = &info.balloon {
+        print_balloon(balloon);
+    }
     Ok(())
 }
 
+fn cmd_usage() -> Result<()> {
+    let api = api_client()?;
+    let usage = api.usage()?;
+
+    use tabled::{Table, Tabled};
+
+    #[derive(Tabled)]
+    struct UsageRow {
+        kind: String,
+        count: String,
+        logical: String,
+        exclusive: String,
+        shared: String,
+    }
+
+    let row = |kind: &str, count: Option<usize>, disk: &noid_types::DiskUsage| UsageRow {
+        kind: kind.into(),
+        count: count.map(|c| c.to_string()).unwrap_or("-".into()),
+        logical: format_bytes(disk.logical_bytes),
+        exclusive: format_bytes(disk.exclusive_bytes),
+        shared: format_bytes(disk.shared_bytes),
+    };
+    let rows = vec![
+        row("vms", Some(usage.vm_count), &usage.vms),
+        row("checkpoints", Some(usage.checkpoint_count), &usage.checkpoints),
+        row("artifacts", None, &usage.artifacts),
+        row("total", None, &usage.total),
+    ];
+
+    println!("{}", Table::new(rows));
+    Ok(())
+}
+
+fn format_disk_usage(disk: &noid_types::DiskUsage) -> String {
+    format!(
+        "{} logical, {} exclusive, {} shared",
+        format_bytes(disk.logical_bytes),
+        format_bytes(disk.exclusive_bytes),
+        format_bytes(disk.shared_bytes)
+    )
+}
+
+/// Human-readable size using binary units, e.g. `1.5 GiB`.
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: &[&str] = &["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
+
 fn cmd_exec(name: &str, command: &[String], env: &[String]) -> Result<i32> {
     let api = api_client()?;
 
@@ -274,6 +941,10 @@ fn cmd_checkpoints(name: &str) -> Result<()> {
         id: String,
         label: String,
         created: String,
+        size: String,
+        exclusive: String,
+        firecracker: String,
+        status: String,
     }
 
     let rows: Vec<CpRow> = checkpoints
@@ -282,6 +953,16 @@ fn cmd_checkpoints(name: &str) -> Result<()> {
             id: cp.id.clone(),
             label: cp.label.clone().unwrap_or("-".into()),
             created: cp.created_at.clone(),
+            size: cp
+                .disk
+                .map(|d| format_bytes(d.logical_bytes))
+                .unwrap_or("-".into()),
+            exclusive: cp
+                .disk
+                .map(|d| format_bytes(d.exclusive_bytes))
+                .unwrap_or("-".into()),
+            firecracker: cp.firecracker_version.clone().unwrap_or("-".into()),
+            status: if cp.corrupt { "corrupt" } else { "ok" }.into(),
         })
         .collect();
 
@@ -289,12 +970,67 @@ fn cmd_checkpoints(name: &str) -> Result<()> {
     Ok(())
 }
 
-fn cmd_restore(name: &str, checkpoint_id: &str, new_name: Option<&str>) -> Result<()> {
+fn cmd_checkpoint_verify(name: &str, checkpoint_id: &str) -> Result<i32> {
+    let api = api_client()?;
+    let result = api.verify_checkpoint(name, checkpoint_id)?;
+    if result.ok {
+        println!("Checkpoint '{}' is intact", result.id);
+    } else {
+        println!("Checkpoint '{}' is CORRUPT:", result.id);
+        for problem in &result.problems {
+            println!("  {problem}");
+        }
+    }
+    if !result.checksums_recorded {
+        eprintln!("note: checkpoint predates checksums; only file presence was checked");
+    }
+    Ok(if result.ok { 0 } else { 1 })
+}
+
+fn cmd_restore(
+    name: &str,
+    checkpoint_id: &str,
+    new_name: Option<&str>,
+    user_data: Option<String>,
+    verify: bool,
+) -> Result<()> {
     let api = api_client()?;
-    let info = api.restore_vm(name, checkpoint_id, new_name)?;
+    let target = new_name.unwrap_or(name);
+    let mut progress = ProgressLine::new(format!("Restoring VM '{target}'"));
+    let info = api.restore_vm(name, checkpoint_id, new_name, user_data, verify, |op| {
+        progress.update(op)
+    });
+    progress.clear();
+    let info = info?;
     println!(
         "VM '{}' restored from checkpoint '{checkpoint_id}'",
         info.name
     );
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_limit_lists_set_limits() {
+        let limit = noid_types::IoLimit {
+            bandwidth: Some(50 << 20),
+            ops: Some(1000),
+        };
+        assert_eq!(format_limit(&limit, "IOPS"), "50.0 MiB/s, 1000 IOPS");
+        assert_eq!(
+            format_limit(&noid_types::IoLimit::default(), "IOPS"),
+            "unlimited"
+        );
+    }
+
+    #[test]
+    fn format_bytes_uses_binary_units() {
+        assert_eq!(format_bytes(0), "0 B");
+        assert_eq!(format_bytes(1023), "1023 B");
+        assert_eq!(format_bytes(1024), "1.0 KiB");
+        assert_eq!(format_bytes(1536 * 1024 * 1024), "1.5 GiB");
+    }
+}
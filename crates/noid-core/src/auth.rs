@@ -60,7 +60,7 @@ pub fn token_rate_key(token: &str) -> String {
     after_prefix.chars().take(16).collect()
 }
 
-fn hex_encode(bytes: &[u8]) -> String {
+pub(crate) fn hex_encode(bytes: &[u8]) -> String {
     bytes.iter().map(|b| format!("{b:02x}")).collect()
 }
 
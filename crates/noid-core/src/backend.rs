@@ -1,24 +1,69 @@
-use anyhow::{bail, Result};
-use noid_types::{CheckpointInfo, ExecResult, VmInfo};
-use std::collections::HashMap;
+use anyhow::{bail, Context, Result};
+use noid_types::{
+    ArtifactKind, BalloonInfo, CheckpointInfo, CheckpointVerifyResult, CreateVmRequest, DiskUsage,
+    ExecResult, ImageInfo, InitScriptInfo, OperationPhase, RateLimits, RestartFrom,
+    UpdateVmRequest, UsageSummary, VmDetails, VmEvent, VmInfo, VmShape,
+};
+use std::collections::{HashMap, HashSet};
 use std::io::Seek;
-use std::path::PathBuf;
-use std::sync::{Arc, Mutex};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, Weak};
 
 /// Per-VM lock map: keyed by (user_id, vm_name), value is a shared mutex.
 type VmLockMap = Mutex<HashMap<(String, String), Arc<Mutex<()>>>>;
 
-use crate::{db, exec, network, storage, vm};
+/// Storage namespace for template VMs booted to build golden snapshots.
+/// Not a real user, so these VMs never appear in the database.
+const TEMPLATE_USER: &str = "_templates";
+
+/// How long `stop` waits for the guest to shut down before killing it.
+const STOP_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// How often the watchdog looks for dead VM processes it did not spawn.
+const WATCHDOG_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// How often the reaper looks for expired VMs.
+const REAPER_INTERVAL: std::time::Duration = std::time::Duration::from_secs(15);
+
+/// Events older than this are pruned by the reaper.
+const EVENT_MAX_AGE_DAYS: u32 = 30;
+
+/// Events kept per user at most; the reaper prunes the oldest beyond it.
+const MAX_EVENTS_PER_USER: u32 = 10_000;
+
+/// Format of `created_at` and `expires_at` in the database (UTC).
+const DB_TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// Least memory the balloon may leave a guest with.
+const MIN_BALLOON_TARGET_MIB: u32 = 64;
+
+use crate::boot::{BootConfig, Manifest};
+use crate::firecracker::{self, Firecrackers};
+use crate::idle::{self, ActivityTracker};
+use crate::image::{Image, ImageConfig};
+use crate::jail::{JailSpec, JailerConfig};
+use crate::pool::{self, PoolKey, PoolSpec, WarmPool, WarmVm, POOL_USER};
+use crate::registry::Artifact;
+use crate::watchdog::{self, RestartTracker};
+use crate::{db, exec, init_script, integrity, network, storage, vm};
 
 /// Handle for an attached console session.
 pub struct ConsoleHandle {
     pub serial_log: PathBuf,
     pub vm_dir: PathBuf,
+    /// Keeps the VM from being hibernated while the session lasts.
+    _session: Arc<()>,
 }
 
 /// Trait abstracting VM operations.
 pub trait VmBackend: Send + Sync {
-    fn create(&self, user_id: &str, name: &str, cpus: u32, mem_mib: u32) -> Result<VmInfo>;
+    /// Create a VM, telling `progress` about each phase as it starts.
+    fn create(
+        &self,
+        user_id: &str,
+        req: &CreateVmRequest,
+        progress: &dyn Fn(OperationPhase),
+    ) -> Result<VmInfo>;
     fn destroy(&self, user_id: &str, name: &str) -> Result<()>;
     fn get(&self, user_id: &str, name: &str) -> Result<Option<VmInfo>>;
     fn list(&self, user_id: &str) -> Result<Vec<VmInfo>>;
@@ -31,36 +76,128 @@ pub trait VmBackend: Send + Sync {
     ) -> Result<(String, ExecResult)>;
     fn checkpoint(&self, user_id: &str, name: &str, label: Option<&str>) -> Result<CheckpointInfo>;
     fn list_checkpoints(&self, user_id: &str, name: &str) -> Result<Vec<CheckpointInfo>>;
+    fn verify_checkpoint(
+        &self,
+        user_id: &str,
+        name: &str,
+        checkpoint_id: &str,
+    ) -> Result<CheckpointVerifyResult>;
+    /// Replace VM `name` with checkpoint `checkpoint_id`, or clone it as
+    /// `new_name`, telling `progress` about each phase as it starts. The
+    /// checkpoint's files are checked for presence and size first; with
+    /// `verify`, also against their checksums.
     fn restore(
         &self,
         user_id: &str,
         name: &str,
         checkpoint_id: &str,
         new_name: Option<&str>,
+        verify: bool,
+        progress: &dyn Fn(OperationPhase),
     ) -> Result<VmInfo>;
+    /// Shut a VM down, keeping its disk and checkpoints.
+    fn stop(&self, user_id: &str, name: &str) -> Result<VmInfo>;
+    /// Cold boot a stopped or dead VM from its own disk.
+    fn start(&self, user_id: &str, name: &str) -> Result<VmInfo>;
+    fn reboot(&self, user_id: &str, name: &str) -> Result<VmInfo>;
+    /// Give a VM a new name, moving its storage and checkpoints along. A
+    /// running VM keeps running.
+    fn rename(&self, user_id: &str, name: &str, new_name: &str) -> Result<VmInfo>;
+    /// Change a VM's expiry, labels or rate limits.
+    fn update(&self, user_id: &str, name: &str, req: &UpdateVmRequest) -> Result<VmInfo>;
+    /// Balloon state and guest memory statistics of a running VM.
+    fn balloon(&self, user_id: &str, name: &str) -> Result<BalloonInfo>;
+    /// Inflate or deflate a VM's balloon so the guest is left with
+    /// `target_mib` of its memory.
+    fn set_balloon(&self, user_id: &str, name: &str, target_mib: u32) -> Result<BalloonInfo>;
+    /// Run `script` in the VM's guest in the background once the guest is
+    /// reachable, replacing any earlier init log.
+    fn start_init_script(&self, user_id: &str, name: &str, script: &str) -> Result<()>;
+    /// State of the VM's init script, with its output once it finished.
+    fn init_log(&self, user_id: &str, name: &str) -> Result<InitScriptInfo>;
+    /// Lifecycle events of a VM, oldest first.
+    fn events(&self, user_id: &str, name: &str) -> Result<Vec<VmEvent>>;
+    /// Events of all the user's VMs, or only `vm_name`, oldest first: those
+    /// after event `after_id` and, with `since_secs`, no older than that.
+    fn user_events(
+        &self,
+        user_id: &str,
+        vm_name: Option<&str>,
+        after_id: i64,
+        since_secs: Option<u64>,
+    ) -> Result<Vec<VmEvent>>;
+    /// Id of the newest event so far, for following events from now on.
+    fn latest_event_id(&self) -> Result<i64>;
     fn console_attach(&self, user_id: &str, name: &str) -> Result<ConsoleHandle>;
+    fn usage(&self, user_id: &str) -> Result<UsageSummary>;
+    fn list_images(&self) -> Result<Vec<ImageInfo>>;
+    /// Shapes the default image can create from a golden snapshot.
+    fn fast_path_shapes(&self) -> Vec<VmShape>;
 }
 
 pub struct FirecrackerBackend {
+    /// Handle to ourselves for background work (golden shape builds).
+    this: Weak<FirecrackerBackend>,
     db: Mutex<db::Db>,
     kernel: String,
     rootfs: String,
     exec_timeout_secs: u64,
     vm_locks: VmLockMap,
-    golden_dir: PathBuf,
+    storage: Box<dyn storage::StorageDriver>,
+    /// Shapes to keep golden snapshots for, built after the first cold boot.
+    golden_shapes: Vec<VmShape>,
+    /// Shape snapshot directories currently being built.
+    shape_builds: Mutex<HashSet<PathBuf>>,
+    /// Network indices held by template VMs and warm VMs being booted or
+    /// handed out, which have no `vms` row.
+    reserved_net_indices: Mutex<Vec<u32>>,
+    /// Pre-warmed VMs ready to be handed out by `create`.
+    pool: WarmPool,
+    /// Restart attempts of crashed VMs, for backoff.
+    restarts: RestartTracker,
+    /// When each VM was last used, for hibernating idle ones.
+    activity: ActivityTracker,
+    /// Run VMs through Firecracker's jailer, if set.
+    jailer: Option<JailerConfig>,
+    /// Limits every VM runs within; a VM's own limits can only be tighter.
+    rate_limits: RateLimits,
+    /// Firecracker binaries: new VMs run on the main one, snapshots are
+    /// loaded by one of their release line.
+    firecracker: Firecrackers,
 }
 
 impl FirecrackerBackend {
-    pub fn new(db: db::Db, kernel: String, rootfs: String, exec_timeout_secs: u64) -> Self {
-        let golden_dir = storage::golden_dir();
-        Self {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        db: db::Db,
+        kernel: String,
+        rootfs: String,
+        exec_timeout_secs: u64,
+        storage: Box<dyn storage::StorageDriver>,
+        golden_shapes: Vec<VmShape>,
+        warm_pools: Vec<PoolSpec>,
+        jailer: Option<JailerConfig>,
+        rate_limits: RateLimits,
+        firecracker: Firecrackers,
+    ) -> Arc<Self> {
+        Arc::new_cyclic(|this| Self {
+            this: this.clone(),
             db: Mutex::new(db),
             kernel,
             rootfs,
             exec_timeout_secs,
             vm_locks: Mutex::new(HashMap::new()),
-            golden_dir,
-        }
+            storage,
+            golden_shapes,
+            shape_builds: Mutex::new(HashSet::new()),
+            reserved_net_indices: Mutex::new(Vec::new()),
+            pool: WarmPool::new(warm_pools),
+            restarts: RestartTracker::default(),
+            activity: ActivityTracker::default(),
+            jailer,
+            rate_limits,
+            firecracker,
+        })
     }
 
     fn db(&self) -> std::sync::MutexGuard<'_, db::Db> {
@@ -80,25 +217,110 @@ impl FirecrackerBackend {
         locks.remove(&(user_id.to_string(), name.to_string()));
     }
 
-    /// Cold-boot create: configure + start a fresh VM from kernel/rootfs.
+    /// Network indices in use by VMs, template VMs and warm VMs.
+    fn used_net_indices(&self) -> Result<Vec<u32>> {
+        let mut used = self.db().list_used_net_indices()?;
+        used.extend(
+            self.reserved_net_indices
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .iter(),
+        );
+        used.extend(self.pool.net_indices());
+        Ok(used)
+    }
+
+    /// Kernel and rootfs a cold boot of `image` uses. Named images boot their
+    /// own rootfs (and kernel, if they ship one); the default image boots the
+    /// server's.
+    fn boot_files(&self, image: &Image) -> (String, String) {
+        let kernel = image
+            .kernel()
+            .map(|k| k.to_string_lossy().to_string())
+            .unwrap_or_else(|| self.kernel.clone());
+        match image.name {
+            Some(_) => (kernel, image.rootfs().to_string_lossy().to_string()),
+            None => (kernel, self.rootfs.clone()),
+        }
+    }
+
+    /// Limits a VM with its own limits `own` runs with.
+    fn effective_limits(&self, own: &RateLimits) -> RateLimits {
+        own.tighter(&self.rate_limits)
+    }
+
+    /// UID (and GID) VMs of `user_id` run as under the jailer. Template and
+    /// warm VMs share the first one.
+    fn jail_uid(&self, config: &JailerConfig, user_id: &str) -> Result<u32> {
+        if user_id == TEMPLATE_USER || user_id == POOL_USER {
+            return Ok(config.uid_base);
+        }
+        let index = self.db().user_jail_index(user_id)?;
+        Ok(config.uid_base + 1 + index)
+    }
+
+    /// What the network of a VM of `user_id` needs for the jailer.
+    fn net_isolation(&self, user_id: &str) -> Result<network::NetworkIsolation> {
+        let Some(ref config) = self.jailer else {
+            return Ok(network::NetworkIsolation::default());
+        };
+        Ok(network::NetworkIsolation {
+            owner: Some(self.jail_uid(config, user_id)?),
+            netns: config.netns,
+        })
+    }
+
+    /// Spawn `firecracker` for a VM of `user_id` in `subvol`, jailed if the
+    /// jailer is on. `kernel` is given for cold boots.
+    #[allow(clippy::too_many_arguments)]
+    fn spawn_fc(
+        &self,
+        user_id: &str,
+        subvol: &Path,
+        shape: VmShape,
+        kernel: Option<&str>,
+        net: Option<&network::NetworkConfig>,
+        firecracker: &firecracker::Binary,
+    ) -> Result<(u32, String)> {
+        let spec = match self.jailer {
+            Some(ref config) => Some(JailSpec {
+                config: config.clone(),
+                uid: self.jail_uid(config, user_id)?,
+                cpus: shape.cpus,
+                mem_mib: shape.mem_mib,
+                netns: net.and_then(|nc| nc.netns.clone()),
+                expose: kernel.map(PathBuf::from).into_iter().collect(),
+            }),
+            None => None,
+        };
+        vm::spawn_fc(subvol, &firecracker.path, spec.as_ref())
+    }
+
+    /// Cold-boot create: configure + start a fresh VM from kernel/rootfs,
+    /// with its rate limits in force from the start.
+    #[allow(clippy::too_many_arguments)]
     fn create_cold_boot(
         &self,
         user_id: &str,
         name: &str,
+        boot: &BootConfig,
+        rootfs: &str,
         cpus: u32,
         mem_mib: u32,
+        settings: &db::VmSettings,
+        progress: &dyn Fn(OperationPhase),
     ) -> Result<VmInfo> {
-        if !std::path::Path::new(&self.kernel).exists() {
-            bail!("kernel not found: {}", self.kernel);
+        if !std::path::Path::new(&boot.kernel).exists() {
+            bail!("kernel not found: {}", boot.kernel);
         }
-        if !std::path::Path::new(&self.rootfs).exists() {
-            bail!("rootfs not found: {}", self.rootfs);
+        if !std::path::Path::new(&rootfs).exists() {
+            bail!("rootfs not found: {rootfs}");
         }
 
         let net_config = match (|| -> Result<_> {
-            let used = self.db().list_used_net_indices()?;
+            let used = self.used_net_indices()?;
             let index = network::allocate_index(&used)?;
-            network::setup_vm_network(index)
+            network::setup_vm_network(index, self.net_isolation(user_id)?)
         })() {
             Ok(cfg) => Some(cfg),
             Err(e) => {
@@ -107,64 +329,90 @@ impl FirecrackerBackend {
             }
         };
 
-        let subvol = storage::create_vm_subvolume(user_id, name)?;
-        let vm_rootfs = match storage::reflink_rootfs(user_id, name, &self.rootfs) {
+        progress(OperationPhase::CloningStorage);
+        let subvol = storage::create_vm_subvolume(self.storage.as_ref(), user_id, name)?;
+        let vm_rootfs = match storage::copy_rootfs(self.storage.as_ref(), user_id, name, rootfs) {
             Ok(r) => r,
             Err(e) => {
                 if let Some(ref nc) = net_config {
                     let _ = network::teardown_vm_network(&nc.tap_name);
                 }
-                let _ = storage::delete_subvolume(user_id, name);
+                let _ = storage::delete_subvolume(self.storage.as_ref(), user_id, name);
                 return Err(e);
             }
         };
 
-        let (pid, sock) = match vm::spawn_fc(&subvol) {
+        progress(OperationPhase::Spawning);
+        let firecracker = self.firecracker.main();
+        let (pid, sock) = match self.spawn_fc(
+            user_id,
+            &subvol,
+            VmShape { cpus, mem_mib },
+            Some(&boot.kernel),
+            net_config.as_ref(),
+            firecracker,
+        ) {
             Ok(r) => r,
             Err(e) => {
                 if let Some(ref nc) = net_config {
                     let _ = network::teardown_vm_network(&nc.tap_name);
                 }
-                let _ = storage::delete_subvolume(user_id, name);
+                let _ = storage::delete_subvolume(self.storage.as_ref(), user_id, name);
                 return Err(e);
             }
         };
 
+        progress(OperationPhase::Booting);
         if let Err(e) = vm::configure_and_start_vm(
             &sock,
-            &self.kernel,
+            boot,
             &vm_rootfs.to_string_lossy(),
             cpus,
             mem_mib,
             net_config.as_ref(),
+            &self.effective_limits(&settings.rate_limits),
         ) {
             vm::kill_vm_process(pid as i64);
             if let Some(ref nc) = net_config {
                 let _ = network::teardown_vm_network(&nc.tap_name);
             }
-            let _ = storage::delete_subvolume(user_id, name);
+            let _ = storage::delete_subvolume(self.storage.as_ref(), user_id, name);
             return Err(e);
         }
 
-        self.insert_vm_record(user_id, name, pid, sock, cpus, mem_mib, net_config.as_ref())
+        self.insert_vm_record(
+            user_id,
+            name,
+            pid,
+            sock,
+            boot.clone(),
+            rootfs,
+            cpus,
+            mem_mib,
+            net_config.as_ref(),
+            firecracker.version_string(),
+            settings.clone(),
+        )
     }
 
     /// Fast create: restore from golden snapshot, reconfigure network.
+    #[allow(clippy::too_many_arguments)]
     fn create_from_golden(
         &self,
         user_id: &str,
         name: &str,
+        image: &Image,
+        snapshot_dir: &Path,
         cpus: u32,
         mem_mib: u32,
+        settings: &db::VmSettings,
+        progress: &dyn Fn(OperationPhase),
     ) -> Result<VmInfo> {
-        // Clone golden snapshot files into VM dir
-        let subvol = storage::clone_golden(user_id, name)?;
-
         // Allocate network
         let net_config = match (|| -> Result<_> {
-            let used = self.db().list_used_net_indices()?;
+            let used = self.used_net_indices()?;
             let index = network::allocate_index(&used)?;
-            network::setup_vm_network(index)
+            network::setup_vm_network(index, self.net_isolation(user_id)?)
         })() {
             Ok(cfg) => Some(cfg),
             Err(e) => {
@@ -173,22 +421,95 @@ impl FirecrackerBackend {
             }
         };
 
-        // Spawn FC process (creates new FIFO + serial.log)
-        let (pid, sock) = match vm::spawn_fc(&subvol) {
-            Ok(r) => r,
+        let (pid, sock, firecracker_version) = self.resume_golden(
+            user_id,
+            name,
+            snapshot_dir,
+            VmShape { cpus, mem_mib },
+            net_config.as_ref(),
+            &self.effective_limits(&settings.rate_limits),
+            progress,
+        )?;
+        let (kernel, rootfs) = self.boot_files(image);
+        self.insert_vm_record(
+            user_id,
+            name,
+            pid,
+            sock,
+            BootConfig::kernel(kernel),
+            &rootfs,
+            cpus,
+            mem_mib,
+            net_config.as_ref(),
+            firecracker_version,
+            settings.clone(),
+        )
+    }
+
+    /// Clone a golden snapshot into `user_id`/`name`, resume it with I/O
+    /// limited to `limits` and reconfigure the guest network. Returns (pid,
+    /// socket_path, Firecracker version). On failure the network and storage
+    /// are torn down.
+    #[allow(clippy::too_many_arguments)]
+    fn resume_golden(
+        &self,
+        user_id: &str,
+        name: &str,
+        snapshot_dir: &Path,
+        shape: VmShape,
+        net_config: Option<&network::NetworkConfig>,
+        limits: &RateLimits,
+        progress: &dyn Fn(OperationPhase),
+    ) -> Result<(u32, String, Option<String>)> {
+        let config = ImageConfig::load(snapshot_dir);
+        let firecracker = match config {
+            Ok(ref c) => self
+                .firecracker
+                .for_snapshot(c.firecracker_version.as_deref()),
+            Err(_) => Ok(self.firecracker.main()),
+        };
+        let firecracker = match firecracker {
+            Ok(f) => f,
             Err(e) => {
-                if let Some(ref nc) = net_config {
+                if let Some(nc) = net_config {
+                    let _ = network::teardown_vm_network(&nc.tap_name);
+                }
+                let dir = snapshot_dir.display();
+                return Err(e.context(format!("golden snapshot {dir} needs rebuilding")));
+            }
+        };
+
+        // Clone golden snapshot files into VM dir
+        progress(OperationPhase::CloningStorage);
+        let subvol = match storage::clone_golden(self.storage.as_ref(), snapshot_dir, user_id, name)
+        {
+            Ok(subvol) => subvol,
+            Err(e) => {
+                if let Some(nc) = net_config {
                     let _ = network::teardown_vm_network(&nc.tap_name);
                 }
-                let _ = storage::delete_subvolume(user_id, name);
                 return Err(e);
             }
         };
 
+        // Spawn FC process (creates new FIFO + serial.log)
+        progress(OperationPhase::Spawning);
+        let (pid, sock) =
+            match self.spawn_fc(user_id, &subvol, shape, None, net_config, firecracker) {
+                Ok(r) => r,
+                Err(e) => {
+                    if let Some(nc) = net_config {
+                        let _ = network::teardown_vm_network(&nc.tap_name);
+                    }
+                    let _ = storage::delete_subvolume(self.storage.as_ref(), user_id, name);
+                    return Err(e);
+                }
+            };
+
         // Load snapshot, patch drive/network to point at new resources, resume
         let rootfs_path = subvol.join("rootfs.ext4");
-        let snapshot_rootfs_hint = match storage::golden_snapshot_rootfs_path() {
-            Ok(v) => v,
+        let snapshot_rootfs_hint = match config {
+            Ok(c) => c.snapshot_rootfs_path,
             Err(e) => {
                 eprintln!("warning: failed to read golden snapshot rootfs path: {e:#}");
                 None
@@ -196,7 +517,7 @@ impl FirecrackerBackend {
         }
         .or_else(|| vm::extract_rootfs_path_from_vmstate(&subvol));
         let rootfs_alias = snapshot_rootfs_hint.as_deref().and_then(|p| {
-            match vm::ensure_snapshot_rootfs_path(p, &rootfs_path.to_string_lossy()) {
+            match vm::ensure_snapshot_rootfs_path(pid, p, &rootfs_path.to_string_lossy()) {
                 Ok(v) => v,
                 Err(e) => {
                     eprintln!("warning: failed to create snapshot rootfs alias: {e:#}");
@@ -204,20 +525,22 @@ impl FirecrackerBackend {
                 }
             }
         });
+        progress(OperationPhase::LoadingSnapshot);
         if let Err(e) = vm::load_and_restore_snapshot(
             &sock,
             &subvol,
             &rootfs_path.to_string_lossy(),
-            net_config.as_ref(),
+            net_config,
+            limits,
         ) {
             if let Some(alias) = rootfs_alias.as_ref() {
                 let _ = std::fs::remove_file(alias);
             }
             vm::kill_vm_process(pid as i64);
-            if let Some(ref nc) = net_config {
+            if let Some(nc) = net_config {
                 let _ = network::teardown_vm_network(&nc.tap_name);
             }
-            let _ = storage::delete_subvolume(user_id, name);
+            let _ = storage::delete_subvolume(self.storage.as_ref(), user_id, name);
             return Err(e);
         }
         if let Some(alias) = rootfs_alias.as_ref() {
@@ -226,7 +549,8 @@ impl FirecrackerBackend {
 
         // Reconfigure guest network (snapshot has old template IP).
         // Brief delay lets the guest kernel stabilize after resume.
-        if let Some(ref nc) = net_config {
+        if let Some(nc) = net_config {
+            progress(OperationPhase::ConfiguringNetwork);
             std::thread::sleep(std::time::Duration::from_secs(1));
             if let Err(e) = self.reconfigure_guest_network(&subvol, nc) {
                 eprintln!("warning: failed to reconfigure guest network: {e:#}");
@@ -237,174 +561,1501 @@ impl FirecrackerBackend {
             }
         }
 
-        self.insert_vm_record(user_id, name, pid, sock, cpus, mem_mib, net_config.as_ref())
+        Ok((pid, sock, firecracker.version_string()))
     }
 
-    /// Insert VM record into DB and return VmInfo. Rolls back on failure.
-    #[allow(clippy::too_many_arguments)]
-    fn insert_vm_record(
-        &self,
-        user_id: &str,
-        name: &str,
-        pid: u32,
-        socket_path: String,
-        cpus: u32,
-        mem_mib: u32,
-        net_config: Option<&network::NetworkConfig>,
-    ) -> Result<VmInfo> {
-        let rootfs_path = storage::vm_dir(user_id, name)
-            .join("rootfs.ext4")
-            .to_string_lossy()
-            .to_string();
+    /// Stop warm VMs left by a previous run and start filling the pools
+    /// configured in `warm_pool`.
+    pub fn start_warm_pools(&self) {
+        pool::cleanup_stale(self.storage.as_ref());
+        for spec in self.pool.specs() {
+            self.refill_pool(spec);
+        }
+    }
 
-        if let Err(e) = self.db().insert_vm(
-            user_id,
-            name,
-            db::VmInsertData {
-                pid,
-                socket_path,
-                kernel: self.kernel.clone(),
-                rootfs: rootfs_path,
-                cpus,
-                mem_mib,
-                net_index: net_config.map(|c| c.index),
-                tap_name: net_config.map(|c| c.tap_name.clone()),
-                guest_ip: net_config.map(|c| c.guest_ip.clone()),
-            },
-        ) {
-            vm::kill_vm_process(pid as i64);
-            if let Some(nc) = net_config {
-                let _ = network::teardown_vm_network(&nc.tap_name);
+    /// Watch VM processes for exits nobody asked for. Processes spawned by
+    /// this server are reported by their reaper thread as soon as they exit;
+    /// ones that survived a server restart are polled.
+    pub fn start_watchdog(&self) {
+        let this = self.this.clone();
+        vm::set_exit_handler(Box::new(move |exit| {
+            if let Some(this) = this.upgrade() {
+                this.handle_vm_exit(exit.pid, exit.status);
             }
-            let _ = storage::delete_subvolume(user_id, name);
-            return Err(e);
-        }
+        }));
 
-        Ok(VmInfo {
-            name: name.to_string(),
-            state: "running".to_string(),
-            cpus,
-            mem_mib,
-            created_at: chrono::Utc::now().format("%Y-%m-%d %H:%M:%S").to_string(),
-        })
+        let this = self.this.clone();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(WATCHDOG_INTERVAL);
+            let Some(this) = this.upgrade() else {
+                return;
+            };
+            let vms = match this.db().list_running_vms() {
+                Ok(vms) => vms,
+                Err(e) => {
+                    eprintln!("warning: watchdog failed to list VMs: {e:#}");
+                    continue;
+                }
+            };
+            for rec in vms {
+                let Some(pid) = rec.pid.map(|p| p as u32) else {
+                    continue;
+                };
+                if vm::is_child(pid) || vm::is_process_alive(pid as i32) {
+                    continue;
+                }
+                let this = this.clone();
+                std::thread::spawn(move || this.handle_vm_exit(pid, None));
+            }
+        });
     }
 
-    /// After restoring from snapshot, reconfigure the guest's network interface.
-    /// The snapshot has the template's old IP; we flush and assign the new one.
-    /// Uses a bounded timeout since this is best-effort (VM is usable without it).
-    fn reconfigure_guest_network(
-        &self,
-        vm_dir: &std::path::Path,
-        net_config: &network::NetworkConfig,
-    ) -> Result<()> {
-        // Guest auto-logins as noid (unprivileged) with passwordless sudo.
-        // After snapshot restore the guest clock is stale (baked from golden snapshot),
-        // which breaks TLS cert validation. Set it from the host before anything else.
-        let host_epoch = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_secs();
-        let cmd_str = format!(
-            "sudo date -s @{} >/dev/null 2>&1; \
-             sudo ip addr flush dev eth0 && \
-             sudo ip addr add {}/30 dev eth0 && \
-             sudo ip link set eth0 up && \
-             sudo ip route replace default via {}",
-            host_epoch, net_config.guest_ip, net_config.host_ip
-        );
-        let cmd = vec!["sh".to_string(), "-c".to_string(), cmd_str];
-        let timeout = self.exec_timeout_secs.max(15);
-        let (_, exit_code, timed_out, _) = exec::exec_via_serial(vm_dir, &cmd, timeout, &[])?;
-        if timed_out {
-            bail!("network reconfiguration timed out");
+    /// A Firecracker process exited. If it belonged to a running VM, mark
+    /// the VM dead, record why, and restart it if its policy says so.
+    fn handle_vm_exit(&self, pid: u32, status: Option<std::process::ExitStatus>) {
+        let rec = match self.db().list_running_vms() {
+            Ok(vms) => vms.into_iter().find(|r| r.pid == Some(pid as i64)),
+            Err(e) => {
+                eprintln!("warning: watchdog failed to list VMs: {e:#}");
+                return;
+            }
+        };
+        // Stopped, destroyed or restarted on purpose
+        let Some(rec) = rec else {
+            return;
+        };
+        let (user_id, name) = (rec.user_id.clone(), rec.name.clone());
+
+        let report = {
+            let lock = self.vm_lock(&user_id, &name);
+            let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+            // Whoever held the lock may have replaced the process meanwhile
+            match self.db().get_vm(&user_id, &name) {
+                Ok(Some(cur)) if cur.state == "running" && cur.pid == Some(pid as i64) => {}
+                _ => return,
+            }
+            let report = watchdog::crash_report(&storage::vm_dir(&user_id, &name), status);
+            if let Some(ref tap) = rec.tap_name {
+                if let Err(e) = network::teardown_vm_network(tap) {
+                    eprintln!("warning: failed to teardown TAP {tap}: {e:#}");
+                }
+            }
+            let recorded = {
+                let db = self.db();
+                db.set_vm_dead(&user_id, &name).and_then(|()| {
+                    db.insert_event(
+                        &user_id,
+                        &name,
+                        "vm.died",
+                        &report.message,
+                        Some(&report.detail),
+                    )
+                })
+            };
+            if let Err(e) = recorded {
+                eprintln!("warning: failed to record death of VM {name}: {e:#}");
+            }
+            report
+        };
+        eprintln!("VM {name} (user {user_id}) died: {}", report.message);
+
+        if !watchdog::should_restart(rec.restart, report.failure) {
+            return;
         }
-        if exit_code != Some(0) {
-            bail!(
-                "network reconfiguration failed (exit code: {:?})",
-                exit_code
+        let Some(attempt) = self.restarts.next_attempt(&user_id, &name) else {
+            let message = format!(
+                "gave up after {} restarts; start it with `noid start`",
+                watchdog::MAX_RESTARTS
             );
-        }
-        Ok(())
-    }
+            let _ = self
+                .db()
+                .insert_event(&user_id, &name, "vm.restart_failed", &message, None);
+            return;
+        };
+        std::thread::sleep(watchdog::restart_delay(attempt));
 
-    fn vm_to_info(rec: &db::VmRecord) -> VmInfo {
-        let alive = rec.pid.is_some_and(|pid| vm::is_process_alive(pid as i32));
-        let state = if alive {
-            rec.state.clone()
-        } else {
-            "dead".to_string()
+        let (kind, message) = match self.restart_after_crash(&user_id, &rec) {
+            Ok(Some(how)) => ("vm.restarted", how),
+            // Started, stopped or destroyed by someone else during the backoff
+            Ok(None) => return,
+            Err(e) => ("vm.restart_failed", format!("{e:#}")),
         };
-        VmInfo {
-            name: rec.name.clone(),
-            state,
-            cpus: rec.cpus,
-            mem_mib: rec.mem_mib,
-            created_at: rec.created_at.clone(),
-        }
+        eprintln!("VM {name} (user {user_id}): {message}");
+        let _ = self
+            .db()
+            .insert_event(&user_id, &name, kind, &message, None);
     }
-}
 
-impl VmBackend for FirecrackerBackend {
-    fn create(&self, user_id: &str, name: &str, cpus: u32, mem_mib: u32) -> Result<VmInfo> {
-        storage::validate_name(name, "VM")?;
+    /// Bring a dead VM back as its `restart_from` setting says. Returns how
+    /// it was restarted, or `None` if it is no longer dead.
+    fn restart_after_crash(&self, user_id: &str, rec: &db::VmRecord) -> Result<Option<String>> {
+        let name = &rec.name;
+        let still_dead = || -> Result<bool> {
+            Ok(self
+                .db()
+                .get_vm(user_id, name)?
+                .is_some_and(|cur| cur.state == "dead"))
+        };
 
-        if self.db().get_vm(user_id, name)?.is_some() {
-            bail!("VM '{name}' already exists");
+        if rec.restart_from == RestartFrom::Checkpoint {
+            let latest = self
+                .db()
+                .list_checkpoints(user_id, name)?
+                .into_iter()
+                .rev()
+                .find(|cp| !cp.corrupt);
+            if let Some(cp) = latest {
+                if !still_dead()? {
+                    return Ok(None);
+                }
+                let err = match self.restore(user_id, name, &cp.id, None, false, &|_| {}) {
+                    Ok(_) => return Ok(Some(format!("restored from checkpoint {}", cp.id))),
+                    Err(e) => e,
+                };
+                eprintln!(
+                    "warning: failed to restore VM '{name}' from checkpoint {}: {err:#}",
+                    cp.id
+                );
+                // A failed restore leaves the VM stopped on its own disk.
+                let lock = self.vm_lock(user_id, name);
+                let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+                let Some(cur) = self.db().get_vm(user_id, name)? else {
+                    return Ok(None);
+                };
+                if cur.state != "dead" && cur.state != "stopped" {
+                    return Ok(None);
+                }
+                self.boot_stopped(user_id, &cur)?;
+                return Ok(Some(format!(
+                    "rebooted from disk (restoring checkpoint {} failed: {err:#})",
+                    cp.id
+                )));
+            }
+        }
+
+        let lock = self.vm_lock(user_id, name);
+        let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+        if !still_dead()? {
+            return Ok(None);
         }
+        self.boot_stopped(user_id, rec)?;
+        let how = if rec.restart_from == RestartFrom::Checkpoint {
+            "rebooted from disk (no checkpoint to restore)"
+        } else {
+            "rebooted from disk"
+        };
+        Ok(Some(how.to_string()))
+    }
 
-        // Check if we can use the golden snapshot (fast path)
-        let use_golden = self.golden_dir.join("memory.snap").exists()
-            && match storage::golden_config() {
-                Ok((gc, gm)) => gc == cpus && gm == mem_mib,
-                Err(_) => false,
+    /// Hibernate VMs that have not been used through exec, the console or
+    /// their network for `idle_after`.
+    pub fn start_hibernator(&self, idle_after: std::time::Duration) {
+        let interval = (idle_after / 4).clamp(
+            std::time::Duration::from_secs(5),
+            std::time::Duration::from_secs(60),
+        );
+        let this = self.this.clone();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(interval);
+            let Some(this) = this.upgrade() else {
+                return;
+            };
+            let vms = match this.db().list_running_vms() {
+                Ok(vms) => vms,
+                Err(e) => {
+                    eprintln!("warning: hibernator failed to list VMs: {e:#}");
+                    continue;
+                }
             };
+            for rec in vms {
+                let (user_id, name) = (&rec.user_id, &rec.name);
+                let idle = this.activity.idle_for(
+                    user_id,
+                    name,
+                    rec.tap_name.as_deref().and_then(idle::tap_bytes),
+                    idle::input_age(&storage::vm_dir(user_id, name)),
+                );
+                if idle < idle_after {
+                    continue;
+                }
+                match this.hibernate(user_id, name) {
+                    Ok(true) => eprintln!(
+                        "VM {name} (user {user_id}) hibernated after {}s idle",
+                        idle.as_secs()
+                    ),
+                    Ok(false) => {}
+                    Err(e) => eprintln!("warning: failed to hibernate VM {name}: {e:#}"),
+                }
+            }
+        });
+    }
 
-        if use_golden {
-            match self.create_from_golden(user_id, name, cpus, mem_mib) {
-                Ok(info) => Ok(info),
+    /// Remove VMs whose TTL has run out and prune old events, checking
+    /// every `REAPER_INTERVAL`.
+    pub fn start_reaper(&self) {
+        let this = self.this.clone();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(REAPER_INTERVAL);
+            let Some(this) = this.upgrade() else {
+                return;
+            };
+            if let Err(e) = this
+                .db()
+                .prune_events(EVENT_MAX_AGE_DAYS, MAX_EVENTS_PER_USER)
+            {
+                eprintln!("warning: failed to prune events: {e:#}");
+            }
+            let expired = match this.db().list_expired_vms() {
+                Ok(vms) => vms,
                 Err(e) => {
-                    eprintln!("warning: golden snapshot failed ({e:#}), falling back to cold boot");
-                    let _ = storage::delete_subvolume(user_id, name);
-                    self.create_cold_boot(user_id, name, cpus, mem_mib)
+                    eprintln!("warning: reaper failed to list VMs: {e:#}");
+                    continue;
+                }
+            };
+            for rec in expired {
+                if let Err(e) = this.expire_vm(&rec) {
+                    eprintln!(
+                        "warning: failed to expire VM {} (user {}): {e:#}",
+                        rec.name, rec.user_id
+                    );
                 }
             }
-        } else {
-            self.create_cold_boot(user_id, name, cpus, mem_mib)
-        }
+        });
     }
 
-    fn destroy(&self, user_id: &str, name: &str) -> Result<()> {
-        let lock = self.vm_lock(user_id, name);
-        let guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+    /// Pick up init scripts that were waiting or running when the server
+    /// last stopped. Scripts run in the guest, so most have kept going.
+    pub fn start_init_scripts(&self) {
+        let vms = match self.db().list_unfinished_init_scripts() {
+            Ok(vms) => vms,
+            Err(e) => {
+                eprintln!("warning: failed to list unfinished init scripts: {e:#}");
+                return;
+            }
+        };
+        for rec in vms {
+            self.spawn_init_script(&rec.user_id, rec.id);
+        }
+    }
 
-        let vm_rec = self
-            .db()
-            .get_vm(user_id, name)?
-            .ok_or_else(|| anyhow::anyhow!("VM '{name}' not found"))?;
+    /// Drive the init script of VM `id` in a background thread.
+    fn spawn_init_script(&self, user_id: &str, id: i64) {
+        let Some(this) = self.this.upgrade() else {
+            return;
+        };
+        let user_id = user_id.to_string();
+        std::thread::spawn(move || {
+            if let Err(e) = this.run_init_script(&user_id, id) {
+                eprintln!("warning: init script of VM {id} (user {user_id}): {e:#}");
+            }
+        });
+    }
 
-        if let Some(pid) = vm_rec.pid {
-            vm::kill_vm_process(pid);
+    /// Drive the init script of VM `id` from its recorded state to a finished
+    /// one: wait for the guest, start the script, then wait for it to exit.
+    /// The VM is followed by its row id, so it is kept track of across
+    /// renames. Returns early if the VM goes away or is replaced by a restore.
+    fn run_init_script(&self, user_id: &str, id: i64) -> Result<()> {
+        let Some(rec) = self.db().get_vm_by_id(user_id, id)? else {
+            return Ok(());
+        };
+        let dir = storage::vm_dir(user_id, &rec.name);
+        // The guest announces the script's exit on its console; look for that
+        // from here on rather than asking it over and over.
+        let mut serial_pos = init_script::serial_pos(&dir);
+        // A script that was already running when the server started may have
+        // exited while it was down, so ask right away.
+        let mut ask = rec.init_script_state.as_deref() == Some(init_script::STATE_RUNNING);
+        if rec.init_script_state.as_deref() == Some(init_script::STATE_PENDING) {
+            let script = std::fs::read_to_string(init_script::user_data_path(&dir))
+                .context("failed to read init script")?;
+            if let Err(e) = self.launch_init_script(user_id, &rec.name, &script) {
+                return self.finish_init_script(
+                    user_id,
+                    &rec.name,
+                    init_script::STATE_FAILED,
+                    None,
+                    &format!("noid: failed to start init script: {e:#}\n"),
+                );
+            }
+            self.db()
+                .set_vm_init_script(user_id, &rec.name, init_script::STATE_RUNNING, None)?;
         }
 
-        // Teardown TAP device if networking was configured
+        let mut deadline = std::time::Instant::now() + init_script::TIMEOUT;
+        let mut looks = 0;
+        loop {
+            let delay = if looks == 0 && ask {
+                std::time::Duration::ZERO
+            } else {
+                init_script::poll_delay(looks)
+            };
+            looks += 1;
+            std::thread::sleep(delay);
+            let Some(rec) = self.db().get_vm_by_id(user_id, id)? else {
+                return Ok(());
+            };
+            let name = rec.name.as_str();
+            match rec.state.as_str() {
+                "running" => {}
+                // The guest is frozen; leave it asleep, and the script's
+                // time with it.
+                "hibernated" => {
+                    deadline += delay;
+                    continue;
+                }
+                _ => {
+                    return self.finish_init_script(
+                        user_id,
+                        name,
+                        init_script::STATE_FAILED,
+                        None,
+                        "noid: VM stopped before the init script finished\n",
+                    )
+                }
+            }
+            ask |= init_script::reported_done(&storage::vm_dir(user_id, name), &mut serial_pos);
+            let timed_out = std::time::Instant::now() >= deadline;
+            if !ask && !timed_out {
+                continue;
+            }
+            // Keep asking, as the backoff allows, until the guest answers.
+            let poll = match self.guest_command(user_id, name, &init_script::poll_command()) {
+                Ok(stdout) => init_script::parse_poll(&stdout),
+                Err(_) if !timed_out => continue,
+                Err(e) => {
+                    return self.finish_init_script(
+                        user_id,
+                        name,
+                        init_script::STATE_TIMED_OUT,
+                        None,
+                        &format!("noid: the guest did not answer: {e:#}\n"),
+                    )
+                }
+            };
+            let (state, exit_code) = match poll {
+                init_script::Poll::Exited(0) => (init_script::STATE_SUCCEEDED, Some(0)),
+                init_script::Poll::Exited(code) => (init_script::STATE_FAILED, Some(code)),
+                init_script::Poll::Lost => {
+                    return self.finish_init_script(
+                        user_id,
+                        name,
+                        init_script::STATE_FAILED,
+                        None,
+                        "noid: the guest lost the init script (was the VM rebooted?)\n",
+                    )
+                }
+                init_script::Poll::Running if timed_out => {
+                    let _ = self.guest_command(user_id, name, &init_script::kill_command());
+                    (init_script::STATE_TIMED_OUT, None)
+                }
+                init_script::Poll::Running => {
+                    ask = false;
+                    continue;
+                }
+            };
+            let output = self
+                .guest_command(user_id, name, &init_script::output_command())
+                .unwrap_or_else(|e| format!("noid: failed to read init script output: {e:#}\n"));
+            return self.finish_init_script(user_id, name, state, exit_code, &output);
+        }
+    }
+
+    /// Wait for the guest to answer, then copy the script in and start it.
+    fn launch_init_script(&self, user_id: &str, name: &str, script: &str) -> Result<()> {
+        let probe = ["echo".to_string(), "ready".to_string()];
+        let deadline = std::time::Instant::now() + init_script::READY_TIMEOUT;
+        while !self
+            .guest_command(user_id, name, &probe)
+            .is_ok_and(|out| out.contains("ready"))
+        {
+            if std::time::Instant::now() >= deadline {
+                bail!("guest did not become reachable");
+            }
+            std::thread::sleep(init_script::POLL_INTERVAL);
+        }
+        for command in init_script::launch_commands(script) {
+            self.guest_command(user_id, name, &command)?;
+        }
+        Ok(())
+    }
+
+    /// Run a short command in a VM's guest, failing unless it exits 0. Unlike
+    /// the user's own commands, this neither wakes a hibernated VM nor counts
+    /// as activity that keeps it from hibernating.
+    fn guest_command(&self, user_id: &str, name: &str, command: &[String]) -> Result<String> {
+        let lock = self.vm_lock(user_id, name);
+        let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+        if self.running_vm(user_id, name)?.state != "running" {
+            bail!("VM '{name}' is not running");
+        }
+        let dir = storage::vm_dir(user_id, name);
+        match exec::exec_via_serial(&dir, command, self.exec_timeout_secs, &[])? {
+            (stdout, Some(0), false, _) => Ok(stdout),
+            (_, _, true, _) => bail!("timed out"),
+            (stdout, code, _, _) => bail!(
+                "exited with status {}: {}",
+                code.map_or("unknown".to_string(), |c| c.to_string()),
+                stdout.trim()
+            ),
+        }
+    }
+
+    fn finish_init_script(
+        &self,
+        user_id: &str,
+        name: &str,
+        state: &str,
+        exit_code: Option<i32>,
+        output: &str,
+    ) -> Result<()> {
+        let dir = storage::vm_dir(user_id, name);
+        std::fs::write(init_script::log_path(&dir), output).context("failed to write init log")?;
+        self.db()
+            .set_vm_init_script(user_id, name, state, exit_code)
+    }
+
+    /// Destroy an expired VM, or checkpoint and stop it if it asked for that.
+    fn expire_vm(&self, rec: &db::VmRecord) -> Result<()> {
+        let (user_id, name) = (rec.user_id.as_str(), rec.name.as_str());
+        // The TTL may have been extended since the VM was listed
+        let now = chrono::Utc::now().format(DB_TIME_FORMAT).to_string();
+        let still_expired = self
+            .db()
+            .get_vm(user_id, name)?
+            .and_then(|cur| cur.expires_at)
+            .is_some_and(|at| at <= now);
+        if !still_expired {
+            return Ok(());
+        }
+        if !rec.stop_on_expiry {
+            self.destroy(user_id, name)?;
+            eprintln!("VM {name} (user {user_id}) expired and was destroyed");
+            return self
+                .db()
+                .insert_event(user_id, name, "vm.expired", "destroyed", None);
+        }
+
+        // Only a running VM has state beyond its disk worth checkpointing
+        let mut message = if rec.state == "running" || rec.state == "hibernated" {
+            match self.checkpoint(user_id, name, Some("expired")) {
+                Ok(cp) => format!("checkpointed as {} and stopped", cp.id),
+                Err(e) => format!("stopped; final checkpoint failed: {e:#}"),
+            }
+        } else {
+            "left stopped; it was not running".to_string()
+        };
+        match self.stop(user_id, name) {
+            Ok(_) => {}
+            Err(e) if e.to_string().contains("is not running") => {}
+            Err(e) => message = format!("{message}; stop failed: {e:#}"),
+        }
+        self.db().set_vm_expiry(user_id, name, None, true)?;
+        eprintln!("VM {name} (user {user_id}) expired: {message}");
+        self.db()
+            .insert_event(user_id, name, "vm.expired", &message, None)
+    }
+
+    /// Top up the warm pool for `spec` on a background thread, unless it is
+    /// already being refilled.
+    fn refill_pool(&self, spec: &PoolSpec) {
+        let key = spec.key();
+        let Some(this) = self.this.upgrade() else {
+            return;
+        };
+        if self.pool.deficit(spec) == 0 || !self.pool.start_filling(&key) {
+            return;
+        }
+        let spec = spec.clone();
+        std::thread::spawn(move || {
+            while this.pool.deficit(&spec) > 0 {
+                match this.warm_up(&spec) {
+                    Ok(Some(warm)) => this.pool.add(spec.key(), warm),
+                    // No golden snapshot yet; refilled once it is built
+                    Ok(None) => break,
+                    Err(e) => {
+                        eprintln!("warning: failed to warm a VM for pool {spec:?}: {e:#}");
+                        break;
+                    }
+                }
+            }
+            this.pool.done_filling(&spec.key());
+        });
+    }
+
+    /// Boot one warm VM for `spec`. Returns `None` if the image has no
+    /// golden snapshot of that shape yet, after scheduling one to be built.
+    fn warm_up(&self, spec: &PoolSpec) -> Result<Option<WarmVm>> {
+        let key = spec.key();
+        let image = match spec.image.as_deref() {
+            Some(name) => Image::open(name)?,
+            None => Image::default_image(),
+        };
+        let Some(snapshot_dir) = image.snapshot_dir(key.shape) else {
+            self.schedule_shape_build(&image, key.shape);
+            return Ok(None);
+        };
+
+        let name = format!(
+            "{}-{}-{}",
+            image.name.as_deref().unwrap_or("default"),
+            key.shape,
+            &uuid::Uuid::new_v4().simple().to_string()[..8]
+        );
+        // Held here while booting, then by the pool itself.
+        let net_index = self.reserve_net_index()?;
+        let result = (|| -> Result<WarmVm> {
+            let net_config = match self
+                .net_isolation(POOL_USER)
+                .and_then(|isolation| network::setup_vm_network(net_index, isolation))
+            {
+                Ok(nc) => Some(nc),
+                Err(e) => {
+                    eprintln!("warning: VM networking unavailable: {e:#}");
+                    None
+                }
+            };
+            let (pid, _, firecracker_version) = self.resume_golden(
+                POOL_USER,
+                &name,
+                &snapshot_dir,
+                key.shape,
+                net_config.as_ref(),
+                &self.rate_limits,
+                &|_| {},
+            )?;
+            let (kernel, base_rootfs) = self.boot_files(&image);
+            let warm = WarmVm {
+                name: name.clone(),
+                pid,
+                net_config,
+                kernel,
+                base_rootfs,
+                firecracker_version,
+            };
+            if let Err(e) = pool::write_marker(&warm) {
+                pool::discard(self.storage.as_ref(), &warm);
+                return Err(e);
+            }
+            Ok(warm)
+        })();
+        self.unreserve_net_index(net_index);
+        result.map(Some)
+    }
+
+    /// Allocate a network index for a VM that has no `vms` row.
+    fn reserve_net_index(&self) -> Result<u32> {
+        let mut reserved = self
+            .reserved_net_indices
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let mut used = self.db().list_used_net_indices()?;
+        used.extend(reserved.iter());
+        used.extend(self.pool.net_indices());
+        let index = network::allocate_index(&used)?;
+        reserved.push(index);
+        Ok(index)
+    }
+
+    fn unreserve_net_index(&self, index: u32) {
+        self.reserved_net_indices
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|&i| i != index);
+    }
+
+    /// Hand a warm VM from the pool to `user_id` as `name`: move its
+    /// directory into the user's storage, point the root drive at the new
+    /// path and record it. Returns `None` if no usable warm VM was available.
+    fn create_from_pool(
+        &self,
+        user_id: &str,
+        name: &str,
+        key: &PoolKey,
+        settings: &db::VmSettings,
+    ) -> Option<VmInfo> {
+        while let Some(warm) = self.pool.take(key) {
+            if !vm::is_process_alive(warm.pid as i32) {
+                pool::discard(self.storage.as_ref(), &warm);
+                continue;
+            }
+            // Out of the pool but not yet in the DB: keep the index reserved.
+            let net_index = warm.net_config.as_ref().map(|nc| nc.index);
+            if let Some(index) = net_index {
+                self.reserved_net_indices
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .push(index);
+            }
+            let result = self.adopt_warm_vm(user_id, name, &warm, key.shape, settings);
+            if let Some(index) = net_index {
+                self.unreserve_net_index(index);
+            }
+            match result {
+                Ok(info) => return Some(info),
+                Err(e) => eprintln!("warning: failed to hand out warm VM {}: {e:#}", warm.name),
+            }
+        }
+        None
+    }
+
+    /// Hand warm VM `warm` to `user_id` as `name`. Warm VMs run with the
+    /// server's limits, so tighter ones in `settings` are applied before the
+    /// VM is recorded; if that fails the warm VM is discarded.
+    fn adopt_warm_vm(
+        &self,
+        user_id: &str,
+        name: &str,
+        warm: &WarmVm,
+        shape: VmShape,
+        settings: &db::VmSettings,
+    ) -> Result<VmInfo> {
+        let target = storage::vm_dir(user_id, name);
+        let limits = self.effective_limits(&settings.rate_limits);
+        let sock = target.join("firecracker.sock");
+        let moved = (|| -> Result<()> {
+            if let Some(parent) = target.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::rename(warm.dir(), &target)?;
+            let _ = std::fs::remove_file(target.join("pool.json"));
+            vm::set_rootfs_path(
+                &sock.to_string_lossy(),
+                &target.join("rootfs.ext4").to_string_lossy(),
+            )?;
+            if limits != self.rate_limits {
+                vm::set_rate_limits(&sock.to_string_lossy(), &limits, warm.net_config.is_some())
+                    .context("failed to apply rate limits")?;
+            }
+            Ok(())
+        })();
+        if let Err(e) = moved {
+            if target.exists() && !warm.dir().exists() {
+                let _ = std::fs::rename(&target, warm.dir());
+            }
+            pool::discard(self.storage.as_ref(), warm);
+            return Err(e);
+        }
+
+        // insert_vm_record cleans up the VM if the insert fails.
+        self.insert_vm_record(
+            user_id,
+            name,
+            warm.pid,
+            sock.to_string_lossy().to_string(),
+            BootConfig::kernel(warm.kernel.clone()),
+            &warm.base_rootfs,
+            shape.cpus,
+            shape.mem_mib,
+            warm.net_config.as_ref(),
+            warm.firecracker_version.clone(),
+            settings.clone(),
+        )
+    }
+
+    /// Build a golden snapshot of `shape` for `image` on a background
+    /// thread, unless one is already being built.
+    fn schedule_shape_build(&self, image: &Image, shape: VmShape) {
+        let target = image.shape_dir(shape);
+        let Some(this) = self.this.upgrade() else {
+            return;
+        };
+        if !self
+            .shape_builds
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(target.clone())
+        {
+            return;
+        }
+        let image = image.clone();
+        std::thread::spawn(move || {
+            eprintln!("building golden snapshot {}", target.display());
+            let result = this.build_shape_snapshot(&image, shape, &target);
+            this.shape_builds
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .remove(&target);
+            match result {
+                Ok(()) => {
+                    eprintln!("golden snapshot ready: {}", target.display());
+                    // Warm pools waiting on this snapshot can fill now.
+                    for spec in this.pool.specs() {
+                        this.refill_pool(spec);
+                    }
+                }
+                Err(e) => eprintln!(
+                    "warning: failed to build golden snapshot {}: {e:#}",
+                    target.display()
+                ),
+            }
+        });
+    }
+
+    /// Cold-boot a template VM of `shape`, wait for the guest to come up,
+    /// snapshot it and install the snapshot at `target`.
+    fn build_shape_snapshot(&self, image: &Image, shape: VmShape, target: &Path) -> Result<()> {
+        let (kernel, rootfs) = self.boot_files(image);
+        let name = format!("{}-{shape}", image.name.as_deref().unwrap_or("default"));
+        let driver = self.storage.as_ref();
+
+        // Leftovers from a build interrupted by a server restart.
+        let _ = storage::delete_subvolume(driver, TEMPLATE_USER, &name);
+        let subvol = storage::create_vm_subvolume(driver, TEMPLATE_USER, &name)?;
+
+        // Restores remap the snapshot's network device, so the template
+        // must have one.
+        let net_index = self.reserve_net_index()?;
+        let result = self
+            .net_isolation(TEMPLATE_USER)
+            .and_then(|isolation| network::setup_vm_network(net_index, isolation))
+            .and_then(|nc| {
+                let result = self.snapshot_template(&name, &subvol, &kernel, &rootfs, shape, &nc);
+                let _ = network::teardown_vm_network(&nc.tap_name);
+                result
+            });
+        self.unreserve_net_index(net_index);
+
+        let result = result.and_then(|()| self.install_shape_snapshot(&subvol, shape, target));
+        let _ = storage::delete_subvolume(driver, TEMPLATE_USER, &name);
+        result
+    }
+
+    fn snapshot_template(
+        &self,
+        name: &str,
+        subvol: &Path,
+        kernel: &str,
+        rootfs: &str,
+        shape: VmShape,
+        net_config: &network::NetworkConfig,
+    ) -> Result<()> {
+        let vm_rootfs = storage::copy_rootfs(self.storage.as_ref(), TEMPLATE_USER, name, rootfs)?;
+        let (pid, sock) = self.spawn_fc(
+            TEMPLATE_USER,
+            subvol,
+            shape,
+            Some(kernel),
+            Some(net_config),
+            self.firecracker.main(),
+        )?;
+        let result = (|| -> Result<()> {
+            vm::configure_and_start_vm(
+                &sock,
+                &BootConfig::kernel(kernel),
+                &vm_rootfs.to_string_lossy(),
+                shape.cpus,
+                shape.mem_mib,
+                Some(net_config),
+                &RateLimits::default(),
+            )?;
+            self.wait_for_guest(subvol)?;
+            vm::pause_vm(&sock)?;
+            vm::create_fc_snapshot(&sock, subvol)
+        })();
+        vm::kill_vm_process(pid as i64);
+        result
+    }
+
+    /// Poll the guest over serial until it runs a command.
+    fn wait_for_guest(&self, vm_dir: &Path) -> Result<()> {
+        const ATTEMPTS: u32 = 60;
+        let cmd = vec!["echo".to_string(), "ready".to_string()];
+        for _ in 0..ATTEMPTS {
+            if let Ok((stdout, Some(0), false, _)) = exec::exec_via_serial(vm_dir, &cmd, 5, &[]) {
+                if stdout.contains("ready") {
+                    return Ok(());
+                }
+            }
+            std::thread::sleep(std::time::Duration::from_secs(2));
+        }
+        bail!("template VM did not become ready");
+    }
+
+    /// Copy a template's snapshot into the pool. Files are staged next to
+    /// `target` and renamed into place so a partial copy is never used.
+    fn install_shape_snapshot(&self, subvol: &Path, shape: VmShape, target: &Path) -> Result<()> {
+        let staging = target.with_extension("tmp");
+        let _ = std::fs::remove_dir_all(&staging);
+        std::fs::create_dir_all(&staging)?;
+        for file in integrity::CHECKPOINT_FILES {
+            self.storage
+                .copy_file(&subvol.join(file), &staging.join(file))?;
+        }
+        let config = ImageConfig {
+            cpus: shape.cpus,
+            mem_mib: shape.mem_mib,
+            snapshot_rootfs_path: Some(subvol.join("rootfs.ext4").to_string_lossy().to_string()),
+            firecracker_version: self.firecracker.main().version_string(),
+        };
+        std::fs::write(staging.join("config.json"), serde_json::to_string(&config)?)?;
+        let _ = std::fs::remove_dir_all(target);
+        std::fs::rename(&staging, target)?;
+        Ok(())
+    }
+
+    /// Insert VM record into DB and return VmInfo. Rolls back on failure.
+    #[allow(clippy::too_many_arguments)]
+    fn insert_vm_record(
+        &self,
+        user_id: &str,
+        name: &str,
+        pid: u32,
+        socket_path: String,
+        boot: BootConfig,
+        base_rootfs: &str,
+        cpus: u32,
+        mem_mib: u32,
+        net_config: Option<&network::NetworkConfig>,
+        firecracker_version: Option<String>,
+        settings: db::VmSettings,
+    ) -> Result<VmInfo> {
+        let rootfs_path = storage::vm_dir(user_id, name)
+            .join("rootfs.ext4")
+            .to_string_lossy()
+            .to_string();
+
+        if let Err(e) = self.db().insert_vm(
+            user_id,
+            name,
+            db::VmInsertData {
+                pid,
+                socket_path,
+                boot,
+                rootfs: rootfs_path,
+                cpus,
+                mem_mib,
+                net_index: net_config.map(|c| c.index),
+                tap_name: net_config.map(|c| c.tap_name.clone()),
+                guest_ip: net_config.map(|c| c.guest_ip.clone()),
+                base_rootfs: Some(base_rootfs.to_string()),
+                firecracker_version,
+                settings: settings.clone(),
+            },
+        ) {
+            vm::kill_vm_process(pid as i64);
+            if let Some(nc) = net_config {
+                let _ = network::teardown_vm_network(&nc.tap_name);
+            }
+            let _ = storage::delete_subvolume(self.storage.as_ref(), user_id, name);
+            return Err(e);
+        }
+
+        Ok(VmInfo {
+            name: name.to_string(),
+            state: "running".to_string(),
+            cpus,
+            mem_mib,
+            created_at: chrono::Utc::now().format(DB_TIME_FORMAT).to_string(),
+            disk: None,
+            expires_in_secs: expires_in_secs(settings.expires_at.as_deref()),
+            expires_at: settings.expires_at,
+            stop_on_expiry: settings.stop_on_expiry,
+            balloon: None,
+            labels: settings.labels,
+            details: None,
+        })
+    }
+
+    /// Look up a VM whose Firecracker process is running.
+    fn running_vm(&self, user_id: &str, name: &str) -> Result<db::VmRecord> {
+        let rec = self
+            .db()
+            .get_vm(user_id, name)?
+            .ok_or_else(|| anyhow::anyhow!("VM '{name}' not found"))?;
+        if !rec.pid.is_some_and(|pid| vm::is_process_alive(pid as i32)) {
+            bail!("VM '{name}' is not running");
+        }
+        Ok(rec)
+    }
+
+    /// Read the balloon of a running VM. VMs booted before the balloon
+    /// device existed (or restored from such snapshots) don't have one.
+    fn balloon_info(rec: &db::VmRecord) -> Result<BalloonInfo> {
+        if !vm::has_balloon(&rec.socket_path) {
+            bail!(
+                "VM '{}' has no balloon device; reboot it to add one",
+                rec.name
+            );
+        }
+        let stats = vm::balloon_stats(&rec.socket_path)?;
+        let mib = |key: &str| stats[key].as_u64().unwrap_or(0) as u32;
+        let guest = |key: &str| stats[key].as_u64();
+        Ok(BalloonInfo {
+            target_mib: rec.mem_mib.saturating_sub(mib("target_mib")),
+            balloon_mib: mib("actual_mib"),
+            total_bytes: guest("total_memory"),
+            free_bytes: guest("free_memory"),
+            available_bytes: guest("available_memory"),
+            major_faults: guest("major_faults"),
+            minor_faults: guest("minor_faults"),
+        })
+    }
+
+    /// Like `running_vm`, but first resumes the VM if it is hibernated. The
+    /// caller holds the VM lock.
+    fn awake_vm(&self, user_id: &str, name: &str) -> Result<db::VmRecord> {
+        let rec = self
+            .db()
+            .get_vm(user_id, name)?
+            .ok_or_else(|| anyhow::anyhow!("VM '{name}' not found"))?;
+        if rec.state == "hibernated" {
+            self.resume_hibernated(user_id, &rec)?;
+        }
+        self.activity.touch(user_id, name);
+        self.running_vm(user_id, name)
+    }
+
+    /// Snapshot an idle VM's memory to its directory and stop its process.
+    /// Returns false without doing anything if the VM is busy (its lock is
+    /// held) or no longer running.
+    fn hibernate(&self, user_id: &str, name: &str) -> Result<bool> {
+        let lock = self.vm_lock(user_id, name);
+        let _guard = match lock.try_lock() {
+            Ok(guard) => guard,
+            Err(std::sync::TryLockError::Poisoned(e)) => e.into_inner(),
+            Err(std::sync::TryLockError::WouldBlock) => return Ok(false),
+        };
+        let rec = match self.running_vm(user_id, name) {
+            Ok(rec) if rec.state == "running" => rec,
+            _ => return Ok(false),
+        };
+
+        vm::pause_vm(&rec.socket_path)?;
+        let subvol = storage::vm_dir(user_id, name);
+        if let Err(e) = vm::create_fc_snapshot(&rec.socket_path, &subvol) {
+            let _ = vm::resume_vm(&rec.socket_path);
+            return Err(e);
+        }
+        if let Some(pid) = rec.pid {
+            vm::kill_vm_process(pid);
+        }
+        if let Some(ref tap) = rec.tap_name {
+            if let Err(e) = network::teardown_vm_network(tap) {
+                eprintln!("warning: failed to teardown TAP {tap}: {e:#}");
+            }
+        }
+        self.db().set_vm_hibernated(user_id, name)?;
+        Ok(true)
+    }
+
+    /// Drop the volume a failed restore cloned, moving back the volume it
+    /// was replacing, if any.
+    fn discard_restored_volume(&self, user_id: &str, name: &str, aside: Option<&Path>) {
+        let driver = self.storage.as_ref();
+        let result = match aside {
+            Some(aside) => storage::put_back_vm_volume(driver, user_id, name, aside),
+            None => storage::delete_subvolume(driver, user_id, name),
+        };
+        if let Err(e) = result {
+            eprintln!("warning: failed to clean up after restoring VM '{name}': {e:#}");
+        }
+    }
+
+    /// Make the rootfs path recorded in a snapshot (`hint`) resolve while it
+    /// loads, for snapshots taken under another VM name. Returns the alias to
+    /// remove once loaded, if one was needed.
+    fn snapshot_rootfs_alias(
+        pid: u32,
+        hint: Option<&str>,
+        rootfs: &std::path::Path,
+    ) -> Option<PathBuf> {
+        let hint = hint?;
+        match vm::ensure_snapshot_rootfs_path(pid, hint, &rootfs.to_string_lossy()) {
+            Ok(alias) => alias,
+            Err(e) => {
+                eprintln!("warning: failed to create snapshot rootfs alias: {e:#}");
+                None
+            }
+        }
+    }
+
+    /// Bring a hibernated VM back from the snapshot in its directory, with a
+    /// new network. The caller holds the VM lock. On failure the VM stays
+    /// hibernated.
+    fn resume_hibernated(&self, user_id: &str, rec: &db::VmRecord) -> Result<()> {
+        let name = &rec.name;
+        let firecracker = self
+            .firecracker
+            .for_snapshot(rec.firecracker_version.as_deref())
+            .with_context(|| format!("cannot resume hibernated VM '{name}'"))?;
+        let subvol = storage::vm_dir(user_id, name);
+        let rootfs_path = subvol.join("rootfs.ext4");
+        let rootfs = rootfs_path.to_string_lossy().to_string();
+        let net_config = self.setup_network(user_id);
+        let teardown_net = || {
+            if let Some(ref nc) = net_config {
+                let _ = network::teardown_vm_network(&nc.tap_name);
+            }
+        };
+
+        let shape = VmShape {
+            cpus: rec.cpus,
+            mem_mib: rec.mem_mib,
+        };
+        let (pid, sock) = match self.spawn_fc(
+            user_id,
+            &subvol,
+            shape,
+            None,
+            net_config.as_ref(),
+            firecracker,
+        ) {
+            Ok(r) => r,
+            Err(e) => {
+                teardown_net();
+                return Err(e);
+            }
+        };
+        // The VM may have been renamed since it hibernated
+        let rootfs_alias = Self::snapshot_rootfs_alias(
+            pid,
+            vm::extract_rootfs_path_from_vmstate(&subvol).as_deref(),
+            &rootfs_path,
+        );
+        let limits = self.effective_limits(&rec.rate_limits);
+        let resumed =
+            vm::load_and_restore_snapshot(&sock, &subvol, &rootfs, net_config.as_ref(), &limits)
+                .and_then(|()| {
+                    self.db().set_vm_running(
+                        user_id,
+                        name,
+                        pid,
+                        &sock,
+                        &rootfs,
+                        net_config.as_ref(),
+                        firecracker.version_string().as_deref(),
+                    )
+                });
+        if let Some(alias) = rootfs_alias {
+            let _ = std::fs::remove_file(alias);
+        }
+        if let Err(e) = resumed {
+            vm::kill_vm_process(pid as i64);
+            teardown_net();
+            return Err(e.context(format!("failed to resume hibernated VM '{name}'")));
+        }
+
+        // Same as after a restore: new address, stale clock
+        if let Some(ref nc) = net_config {
+            std::thread::sleep(std::time::Duration::from_secs(1));
+            if let Err(e) = self.reconfigure_guest_network(&subvol, nc) {
+                eprintln!("warning: failed to reconfigure guest network: {e:#}");
+                if e.to_string().contains("timed out") {
+                    let _ = vm::write_to_serial(&subvol, b"\x03\r");
+                }
+            }
+        }
+        eprintln!("VM {name} (user {user_id}) resumed from hibernation");
+        Ok(())
+    }
+
+    /// Set up a TAP device for a VM being booted or resumed. VMs still run
+    /// without networking, so failures are only logged.
+    fn setup_network(&self, user_id: &str) -> Option<network::NetworkConfig> {
+        match (|| -> Result<_> {
+            let used = self.used_net_indices()?;
+            let index = network::allocate_index(&used)?;
+            network::setup_vm_network(index, self.net_isolation(user_id)?)
+        })() {
+            Ok(cfg) => Some(cfg),
+            Err(e) => {
+                eprintln!("warning: VM networking unavailable: {e:#}");
+                None
+            }
+        }
+    }
+
+    /// Shut down a VM's process, if any, and release its network. The caller
+    /// holds the VM lock and records the new state.
+    fn halt(&self, rec: &db::VmRecord) {
+        if let Some(pid) = rec.pid {
+            if vm::is_process_alive(pid as i32) {
+                vm::shutdown_vm(pid, &rec.socket_path, STOP_TIMEOUT);
+            }
+        }
+        if let Some(ref tap) = rec.tap_name {
+            if let Err(e) = network::teardown_vm_network(tap) {
+                eprintln!("warning: failed to teardown TAP {tap}: {e:#}");
+            }
+        }
+    }
+
+    /// Cold boot a stopped VM from the `rootfs.ext4` in its directory, with
+    /// the kernel, command line and shape it was created with. The caller
+    /// holds the VM lock.
+    fn boot_stopped(&self, user_id: &str, rec: &db::VmRecord) -> Result<VmInfo> {
+        let name = &rec.name;
+        let boot = rec.boot_config();
+        if !Path::new(&boot.kernel).exists() {
+            bail!("kernel not found: {}", boot.kernel);
+        }
+        let subvol = storage::vm_dir(user_id, name);
+        let rootfs = subvol.join("rootfs.ext4");
+        if !rootfs.exists() {
+            bail!("disk of VM '{name}' not found: {}", rootfs.display());
+        }
+        let rootfs = rootfs.to_string_lossy().to_string();
+
+        let net_config = self.setup_network(user_id);
+        let teardown_net = || {
+            if let Some(ref nc) = net_config {
+                let _ = network::teardown_vm_network(&nc.tap_name);
+            }
+        };
+
+        let shape = VmShape {
+            cpus: rec.cpus,
+            mem_mib: rec.mem_mib,
+        };
+        let firecracker = self.firecracker.main();
+        let (pid, sock) = match self.spawn_fc(
+            user_id,
+            &subvol,
+            shape,
+            Some(&boot.kernel),
+            net_config.as_ref(),
+            firecracker,
+        ) {
+            Ok(r) => r,
+            Err(e) => {
+                teardown_net();
+                return Err(e);
+            }
+        };
+        let started = vm::configure_and_start_vm(
+            &sock,
+            &boot,
+            &rootfs,
+            rec.cpus,
+            rec.mem_mib,
+            net_config.as_ref(),
+            &self.effective_limits(&rec.rate_limits),
+        )
+        .and_then(|()| {
+            self.db().set_vm_running(
+                user_id,
+                name,
+                pid,
+                &sock,
+                &rootfs,
+                net_config.as_ref(),
+                firecracker.version_string().as_deref(),
+            )
+        });
+        if let Err(e) = started {
+            vm::kill_vm_process(pid as i64);
+            teardown_net();
+            return Err(e);
+        }
+
+        let rec = self
+            .db()
+            .get_vm(user_id, name)?
+            .ok_or_else(|| anyhow::anyhow!("VM '{name}' not found"))?;
+        Ok(Self::vm_to_info(&rec))
+    }
+
+    /// After restoring from snapshot, reconfigure the guest's network interface.
+    /// The snapshot has the template's old IP; we flush and assign the new one.
+    /// Uses a bounded timeout since this is best-effort (VM is usable without it).
+    fn reconfigure_guest_network(
+        &self,
+        vm_dir: &std::path::Path,
+        net_config: &network::NetworkConfig,
+    ) -> Result<()> {
+        // Guest auto-logins as noid (unprivileged) with passwordless sudo.
+        // After snapshot restore the guest clock is stale (baked from golden snapshot),
+        // which breaks TLS cert validation. Set it from the host before anything else.
+        let host_epoch = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let cmd_str = format!(
+            "sudo date -s @{} >/dev/null 2>&1; \
+             sudo ip addr flush dev eth0 && \
+             sudo ip addr add {}/30 dev eth0 && \
+             sudo ip link set eth0 up && \
+             sudo ip route replace default via {}",
+            host_epoch, net_config.guest_ip, net_config.host_ip
+        );
+        let cmd = vec!["sh".to_string(), "-c".to_string(), cmd_str];
+        let timeout = self.exec_timeout_secs.max(15);
+        let (_, exit_code, timed_out, _) = exec::exec_via_serial(vm_dir, &cmd, timeout, &[])?;
+        if timed_out {
+            bail!("network reconfiguration timed out");
+        }
+        if exit_code != Some(0) {
+            bail!(
+                "network reconfiguration failed (exit code: {:?})",
+                exit_code
+            );
+        }
+        Ok(())
+    }
+
+    /// Check a checkpoint's files against the checksums recorded when it was
+    /// taken, and persist the outcome in the checkpoint's `corrupt` flag.
+    /// Without `hash` only presence and sizes are checked, which can find a
+    /// checkpoint corrupt but not clear an earlier finding.
+    fn verify_checkpoint_record(
+        &self,
+        cp: &db::CheckpointRecord,
+        hash: bool,
+    ) -> Result<CheckpointVerifyResult> {
+        let expected: Option<Vec<integrity::FileChecksum>> = match cp.checksums.as_deref() {
+            Some(json) => Some(serde_json::from_str(json).map_err(|e| {
+                anyhow::anyhow!("invalid checksums recorded for checkpoint '{}': {e}", cp.id)
+            })?),
+            None => None,
+        };
+        let dir = std::path::Path::new(&cp.snapshot_path);
+        let problems = if hash {
+            integrity::verify_checksums(dir, expected.as_deref())?
+        } else {
+            integrity::verify_sizes(dir, expected.as_deref())?
+        };
+        let ok = problems.is_empty();
+        if ok == cp.corrupt && (hash || !ok) {
+            self.db().set_checkpoint_corrupt(&cp.user_id, &cp.id, !ok)?;
+        }
+        Ok(CheckpointVerifyResult {
+            id: cp.id.clone(),
+            vm_name: cp.vm_name.clone(),
+            ok,
+            checksums_recorded: expected.is_some(),
+            problems,
+        })
+    }
+
+    fn vm_to_info(rec: &db::VmRecord) -> VmInfo {
+        let alive = rec.pid.is_some_and(|pid| vm::is_process_alive(pid as i32));
+        let state = if alive || rec.state != "running" {
+            rec.state.clone()
+        } else {
+            "dead".to_string()
+        };
+        VmInfo {
+            name: rec.name.clone(),
+            state,
+            cpus: rec.cpus,
+            mem_mib: rec.mem_mib,
+            created_at: rec.created_at.clone(),
+            disk: None,
+            expires_at: rec.expires_at.clone(),
+            expires_in_secs: expires_in_secs(rec.expires_at.as_deref()),
+            stop_on_expiry: rec.stop_on_expiry,
+            balloon: None,
+            labels: rec.labels.clone(),
+            details: None,
+        }
+    }
+
+    /// Disk usage of a VM or checkpoint directory. Usage is informational,
+    /// so failures are logged and reported as unknown.
+    fn disk_usage(&self, path: &std::path::Path) -> Option<DiskUsage> {
+        match self.storage.usage(path) {
+            Ok(usage) => Some(usage),
+            Err(e) => {
+                eprintln!("warning: failed to measure {}: {e:#}", path.display());
+                None
+            }
+        }
+    }
+}
+
+impl FirecrackerBackend {
+    fn create_vm(
+        &self,
+        user_id: &str,
+        req: &CreateVmRequest,
+        progress: &dyn Fn(OperationPhase),
+    ) -> Result<VmInfo> {
+        let (name, cpus, mem_mib) = (req.name.as_str(), req.cpus, req.mem_mib);
+        storage::validate_name(name, "VM")?;
+
+        if self.db().get_vm(user_id, name)?.is_some() {
+            bail!("VM '{name}' already exists");
+        }
+
+        // An explicitly requested image must exist; only the default image
+        // may be absent.
+        let image = match req.image.as_deref() {
+            Some(image_name) => Image::open(image_name)?,
+            None => Image::default_image(),
+        };
+        let kernel = match req.kernel.as_deref() {
+            Some(n) => Some(Artifact::open(ArtifactKind::Kernel, n)?.path()),
+            None => None,
+        };
+        let rootfs = match req.rootfs.as_deref() {
+            Some(n) => Some(Artifact::open(ArtifactKind::Rootfs, n)?.path()),
+            None => None,
+        };
+        // Golden snapshots were booted from the image's own kernel and rootfs,
+        // with the standard command line.
+        let overridden =
+            kernel.is_some() || rootfs.is_some() || req.boot_args.is_some() || req.init.is_some();
+
+        // The VM is recorded with these in the same statement that lists it,
+        // and its limits apply from boot, so it never runs without them.
+        let settings = db::VmSettings {
+            restart: req.restart,
+            restart_from: req.restart_from,
+            expires_at: req
+                .ttl_secs
+                .map(|ttl| expiry_after(chrono::Utc::now().naive_utc(), ttl))
+                .transpose()?,
+            stop_on_expiry: req.stop_on_expiry,
+            labels: req.labels.clone(),
+            rate_limits: req.rate_limits,
+        };
+        let shape = VmShape { cpus, mem_mib };
+        let key = PoolKey {
+            image: image.name.clone(),
+            shape,
+        };
+        if !overridden && self.pool.is_pooled(&key) {
+            let warm = self.create_from_pool(user_id, name, &key, &settings);
+            for spec in self.pool.specs().iter().filter(|s| s.key() == key) {
+                self.refill_pool(spec);
+            }
+            if let Some(info) = warm {
+                return Ok(info);
+            }
+        }
+
+        // Check if we can use a golden snapshot (fast path)
+        if let Some(snapshot_dir) = image.snapshot_dir(shape).filter(|_| !overridden) {
+            match self.create_from_golden(
+                user_id,
+                name,
+                &image,
+                &snapshot_dir,
+                cpus,
+                mem_mib,
+                &settings,
+                progress,
+            ) {
+                Ok(info) => return Ok(info),
+                Err(e) => {
+                    eprintln!("warning: golden snapshot failed ({e:#}), falling back to cold boot");
+                    let _ = storage::delete_subvolume(self.storage.as_ref(), user_id, name);
+                }
+            }
+        }
+
+        let (image_kernel, image_rootfs) = self.boot_files(&image);
+        let boot = BootConfig {
+            kernel: kernel.map_or(image_kernel, |p| p.to_string_lossy().to_string()),
+            boot_args: req.boot_args.clone(),
+            init: req.init.clone(),
+        };
+        let rootfs = rootfs.map_or(image_rootfs, |p| p.to_string_lossy().to_string());
+        let info = self.create_cold_boot(
+            user_id, name, &boot, &rootfs, cpus, mem_mib, &settings, progress,
+        )?;
+        if !overridden && self.golden_shapes.contains(&shape) && image.snapshot_dir(shape).is_none()
+        {
+            self.schedule_shape_build(&image, shape);
+        }
+        Ok(info)
+    }
+}
+
+impl VmBackend for FirecrackerBackend {
+    fn create(
+        &self,
+        user_id: &str,
+        req: &CreateVmRequest,
+        progress: &dyn Fn(OperationPhase),
+    ) -> Result<VmInfo> {
+        let info = self.create_vm(user_id, req, progress)?;
+        if let Some(ref script) = req.user_data {
+            self.start_init_script(user_id, &req.name, script)?;
+        }
+        let message = format!("{} vCPU, {} MiB", info.cpus, info.mem_mib);
+        let _ = self
+            .db()
+            .insert_event(user_id, &req.name, "vm.created", &message, None);
+        if req.user_data.is_none() {
+            return Ok(info);
+        }
+        self.get(user_id, &req.name)?
+            .ok_or_else(|| anyhow::anyhow!("VM '{}' not found", req.name))
+    }
+
+    fn destroy(&self, user_id: &str, name: &str) -> Result<()> {
+        let lock = self.vm_lock(user_id, name);
+        let guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+
+        let vm_rec = self
+            .db()
+            .get_vm(user_id, name)?
+            .ok_or_else(|| anyhow::anyhow!("VM '{name}' not found"))?;
+
+        if let Some(pid) = vm_rec.pid {
+            vm::kill_vm_process(pid);
+        }
+
+        // Teardown TAP device if networking was configured
         if let Some(ref tap) = vm_rec.tap_name {
             if let Err(e) = network::teardown_vm_network(tap) {
                 eprintln!("warning: failed to teardown TAP {tap}: {e:#}");
             }
         }
 
-        storage::delete_subvolume(user_id, name)?;
-        self.db().delete_vm(user_id, name)?;
+        storage::delete_subvolume(self.storage.as_ref(), user_id, name)?;
+        {
+            let db = self.db();
+            db.delete_vm(user_id, name)?;
+            let _ = db.insert_event(user_id, name, "vm.destroyed", "destroyed", None);
+        }
 
         drop(guard);
         self.remove_vm_lock(user_id, name);
+        self.restarts.forget(user_id, name);
+        self.activity.forget(user_id, name);
 
         Ok(())
     }
 
     fn get(&self, user_id: &str, name: &str) -> Result<Option<VmInfo>> {
-        let rec = self.db().get_vm(user_id, name)?;
-        Ok(rec.as_ref().map(Self::vm_to_info))
+        let Some(rec) = self.db().get_vm(user_id, name)? else {
+            return Ok(None);
+        };
+        let mut info = Self::vm_to_info(&rec);
+        info.disk = self.disk_usage(&storage::vm_dir(user_id, name));
+        let running = info.state == "running";
+        if running {
+            info.balloon = Self::balloon_info(&rec).ok();
+        }
+
+        let checkpoint_count = self.db().list_checkpoints(user_id, name)?.len();
+        let pid = rec.pid.filter(|_| running).map(|pid| pid as u32);
+        let stats = pid.and_then(vm::process_stats);
+        info.details = Some(VmDetails {
+            version: noid_types::VM_DETAILS_VERSION,
+            pid,
+            uptime_secs: stats.as_ref().map(|s| s.uptime.as_secs()),
+            guest_ip: rec.guest_ip.clone().filter(|_| running),
+            guest_mac: running.then(|| vm::guest_mac(&rec.socket_path)).flatten(),
+            tap_name: rec.tap_name.clone().filter(|_| running),
+            kernel: rec.kernel.clone(),
+            source_rootfs: rec.base_rootfs.clone(),
+            parent_checkpoint: rec.parent_checkpoint.clone(),
+            checkpoint_count,
+            host_cpu_secs: stats.as_ref().map(|s| s.cpu_secs),
+            host_rss_bytes: stats.as_ref().map(|s| s.rss_bytes),
+            init_script: rec.init_script_state.clone().map(|state| InitScriptInfo {
+                state,
+                exit_code: rec.init_script_exit_code,
+                finished_at: rec.init_script_finished_at.clone(),
+                output: None,
+            }),
+            rate_limits: self.effective_limits(&rec.rate_limits),
+        });
+        Ok(Some(info))
     }
 
     fn list(&self, user_id: &str) -> Result<Vec<VmInfo>> {
@@ -419,17 +2070,25 @@ impl VmBackend for FirecrackerBackend {
         command: &[String],
         env: &[String],
     ) -> Result<(String, ExecResult)> {
-        self.db()
-            .get_vm(user_id, name)?
-            .ok_or_else(|| anyhow::anyhow!("VM '{name}' not found"))?;
-
         let lock = self.vm_lock(user_id, name);
         let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+        self.awake_vm(user_id, name)?;
 
         let dir = storage::vm_dir(user_id, name);
         let (stdout, exit_code, timed_out, truncated) =
             exec::exec_via_serial(&dir, command, self.exec_timeout_secs, env)?;
 
+        // Only the program name: arguments may carry secrets.
+        let program = command.first().map_or("", String::as_str);
+        let message = match exit_code {
+            _ if timed_out => format!("{program} timed out"),
+            Some(code) => format!("{program} exited with status {code}"),
+            None => format!("{program} exited with unknown status"),
+        };
+        let _ = self
+            .db()
+            .insert_event(user_id, name, "exec.finished", &message, None);
+
         Ok((
             stdout,
             ExecResult {
@@ -444,32 +2103,64 @@ impl VmBackend for FirecrackerBackend {
         let lock = self.vm_lock(user_id, name);
         let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
 
-        let rec = self
-            .db()
-            .get_vm(user_id, name)?
-            .ok_or_else(|| anyhow::anyhow!("VM '{name}' not found"))?;
+        let rec = self.awake_vm(user_id, name)?;
 
         let checkpoint_id = uuid::Uuid::new_v4().to_string().replace('-', "")[..16].to_string();
 
         vm::pause_vm(&rec.socket_path)?;
         let subvol = storage::vm_dir(user_id, name);
         vm::create_fc_snapshot(&rec.socket_path, &subvol)?;
-        let snap_path = storage::create_snapshot(user_id, name, &checkpoint_id)?;
+        let snap_path =
+            storage::create_snapshot(self.storage.as_ref(), user_id, name, &checkpoint_id)?;
         vm::resume_vm(&rec.socket_path)?;
 
-        self.db().insert_checkpoint(
-            &checkpoint_id,
-            name,
-            user_id,
-            label,
-            &snap_path.to_string_lossy(),
-        )?;
+        let manifest = Manifest {
+            boot: rec.boot_config(),
+            rootfs: rec.rootfs.clone(),
+            base_rootfs: rec.base_rootfs.clone(),
+            cpus: rec.cpus,
+            mem_mib: rec.mem_mib,
+            firecracker_version: rec.firecracker_version.clone(),
+        };
+        if let Err(e) = manifest.write(&snap_path) {
+            eprintln!("warning: failed to write manifest for checkpoint {checkpoint_id}: {e:#}");
+        }
+
+        // Hash the checkpoint copy after resuming so the VM isn't paused
+        // for the duration. Without checksums, restore still checks presence.
+        let checksums = match integrity::compute_checksums(&snap_path) {
+            Ok(sums) => Some(serde_json::to_string(&sums)?),
+            Err(e) => {
+                eprintln!("warning: failed to checksum checkpoint {checkpoint_id}: {e:#}");
+                None
+            }
+        };
+
+        {
+            let db = self.db();
+            db.insert_checkpoint(
+                &checkpoint_id,
+                name,
+                user_id,
+                label,
+                &snap_path.to_string_lossy(),
+                checksums.as_deref(),
+            )?;
+            let message = match label {
+                Some(label) => format!("{checkpoint_id} ({label})"),
+                None => checkpoint_id.clone(),
+            };
+            let _ = db.insert_event(user_id, name, "checkpoint.created", &message, None);
+        }
 
         Ok(CheckpointInfo {
             id: checkpoint_id,
             vm_name: name.to_string(),
             label: label.map(|s| s.to_string()),
             created_at: chrono::Utc::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+            corrupt: false,
+            disk: None,
+            firecracker_version: rec.firecracker_version.clone(),
         })
     }
 
@@ -478,35 +2169,111 @@ impl VmBackend for FirecrackerBackend {
         Ok(checkpoints
             .into_iter()
             .map(|cp| CheckpointInfo {
+                disk: self.disk_usage(std::path::Path::new(&cp.snapshot_path)),
+                firecracker_version: Manifest::read(Path::new(&cp.snapshot_path))
+                    .and_then(|m| m.firecracker_version),
                 id: cp.id,
                 vm_name: cp.vm_name,
                 label: cp.label,
                 created_at: cp.created_at,
+                corrupt: cp.corrupt,
             })
             .collect())
     }
 
+    fn verify_checkpoint(
+        &self,
+        user_id: &str,
+        name: &str,
+        checkpoint_id: &str,
+    ) -> Result<CheckpointVerifyResult> {
+        let checkpoint = self
+            .db()
+            .get_checkpoint(user_id, checkpoint_id)?
+            .filter(|cp| cp.vm_name == name)
+            .ok_or_else(|| anyhow::anyhow!("checkpoint '{checkpoint_id}' not found"))?;
+        self.verify_checkpoint_record(&checkpoint, true)
+    }
+
     fn restore(
         &self,
         user_id: &str,
         name: &str,
         checkpoint_id: &str,
         new_name: Option<&str>,
+        verify: bool,
+        progress: &dyn Fn(OperationPhase),
     ) -> Result<VmInfo> {
         let checkpoint = self
             .db()
             .get_checkpoint(user_id, checkpoint_id)?
             .ok_or_else(|| anyhow::anyhow!("checkpoint '{checkpoint_id}' not found"))?;
 
-        let orig_vm = self.db().get_vm(user_id, &checkpoint.vm_name)?;
+        // Missing and truncated files are found before anything is
+        // replaced. Hashing reads all of the memory file, so it is only done
+        // when asked for.
+        if !verify && checkpoint.corrupt {
+            bail!(
+                "checkpoint '{checkpoint_id}' failed integrity verification earlier; \
+                 verify it again to restore it"
+            );
+        }
+        if verify {
+            progress(OperationPhase::VerifyingCheckpoint);
+        }
+        let result = self.verify_checkpoint_record(&checkpoint, verify)?;
+        if !result.ok {
+            bail!(
+                "checkpoint '{checkpoint_id}' failed integrity verification: {}",
+                result.problems.join("; ")
+            );
+        }
+
+        // The checkpoint's manifest describes the VM as it was when the
+        // checkpoint was taken; older checkpoints fall back to the VM's row.
+        let manifest = Manifest::read(Path::new(&checkpoint.snapshot_path)).or_else(|| {
+            let orig = self.db().get_vm(user_id, &checkpoint.vm_name).ok()??;
+            Some(Manifest {
+                boot: orig.boot_config(),
+                rootfs: orig.rootfs,
+                base_rootfs: orig.base_rootfs,
+                cpus: orig.cpus,
+                mem_mib: orig.mem_mib,
+                firecracker_version: None,
+            })
+        });
+        // Refuse before replacing anything if no installed Firecracker can
+        // load the snapshot.
+        let firecracker = self
+            .firecracker
+            .for_snapshot(
+                manifest
+                    .as_ref()
+                    .and_then(|m| m.firecracker_version.as_deref()),
+            )
+            .with_context(|| format!("cannot restore checkpoint '{checkpoint_id}'"))?;
         let target_name = new_name.unwrap_or(name);
         storage::validate_name(target_name, "VM")?;
 
+        // Keeps the watchdog from acting on the process being replaced.
+        let lock = self.vm_lock(user_id, target_name);
+        let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+
+        // Restoring in place keeps the VM's settings: restart policy, expiry,
+        // labels and rate limits.
+        let mut replaced = None;
+        let mut aside = None;
+        progress(OperationPhase::CloningStorage);
         if new_name.is_some() {
             if self.db().get_vm(user_id, target_name)?.is_some() {
                 bail!("VM '{target_name}' already exists");
             }
-            storage::clone_snapshot(user_id, &checkpoint.snapshot_path, target_name)?;
+            storage::clone_snapshot(
+                self.storage.as_ref(),
+                user_id,
+                &checkpoint.snapshot_path,
+                target_name,
+            )?;
         } else {
             if let Some(rec) = self.db().get_vm(user_id, name)? {
                 if let Some(pid) = rec.pid {
@@ -516,17 +2283,30 @@ impl VmBackend for FirecrackerBackend {
                 if let Some(ref tap) = rec.tap_name {
                     let _ = network::teardown_vm_network(tap);
                 }
-                storage::delete_subvolume(user_id, name)?;
-                self.db().delete_vm(user_id, name)?;
+                // The row, and with it the VM's checkpoints, stays until the
+                // restored VM replaces it, and the old volume is only set
+                // aside; should the restore fail, the VM is left stopped on
+                // its own disk.
+                self.db().set_vm_stopped(user_id, name)?;
+                aside = storage::set_aside_vm_volume(self.storage.as_ref(), user_id, name)?;
+                replaced = Some(rec);
+            }
+            if let Err(e) = storage::clone_snapshot(
+                self.storage.as_ref(),
+                user_id,
+                &checkpoint.snapshot_path,
+                target_name,
+            ) {
+                self.discard_restored_volume(user_id, target_name, aside.as_deref());
+                return Err(e);
             }
-            storage::clone_snapshot(user_id, &checkpoint.snapshot_path, target_name)?;
         }
 
         // Allocate new TAP for restored VM
         let net_config = match (|| -> Result<_> {
-            let used = self.db().list_used_net_indices()?;
+            let used = self.used_net_indices()?;
             let index = network::allocate_index(&used)?;
-            network::setup_vm_network(index)
+            network::setup_vm_network(index, self.net_isolation(user_id)?)
         })() {
             Ok(cfg) => Some(cfg),
             Err(e) => {
@@ -536,37 +2316,57 @@ impl VmBackend for FirecrackerBackend {
         };
 
         let subvol = storage::vm_dir(user_id, target_name);
-        let (pid, socket_path) = match vm::spawn_fc(&subvol) {
+        progress(OperationPhase::Spawning);
+        let shape = manifest.as_ref().map_or(
+            VmShape {
+                cpus: 1,
+                mem_mib: 2048,
+            },
+            |m| VmShape {
+                cpus: m.cpus,
+                mem_mib: m.mem_mib,
+            },
+        );
+        let (pid, socket_path) = match self.spawn_fc(
+            user_id,
+            &subvol,
+            shape,
+            None,
+            net_config.as_ref(),
+            firecracker,
+        ) {
             Ok(r) => r,
             Err(e) => {
                 if let Some(ref nc) = net_config {
                     let _ = network::teardown_vm_network(&nc.tap_name);
                 }
-                let _ = storage::delete_subvolume(user_id, target_name);
+                self.discard_restored_volume(user_id, target_name, aside.as_deref());
                 return Err(e);
             }
         };
 
         // Load snapshot, patch drive/network to point at new resources, resume
         let rootfs_path_for_restore = subvol.join("rootfs.ext4");
-        let snapshot_rootfs_hint = orig_vm
+        let snapshot_rootfs_hint = manifest
             .as_ref()
-            .map(|rec| rec.rootfs.clone())
+            .map(|m| m.rootfs.clone())
             .or_else(|| vm::extract_rootfs_path_from_vmstate(&subvol));
-        let rootfs_alias = snapshot_rootfs_hint.as_deref().and_then(|p| {
-            match vm::ensure_snapshot_rootfs_path(p, &rootfs_path_for_restore.to_string_lossy()) {
-                Ok(v) => v,
-                Err(e) => {
-                    eprintln!("warning: failed to create snapshot rootfs alias: {e:#}");
-                    None
-                }
-            }
-        });
+        let rootfs_alias = Self::snapshot_rootfs_alias(
+            pid,
+            snapshot_rootfs_hint.as_deref(),
+            &rootfs_path_for_restore,
+        );
+        let own_limits = replaced
+            .as_ref()
+            .map(|old: &db::VmRecord| old.rate_limits)
+            .unwrap_or_default();
+        progress(OperationPhase::LoadingSnapshot);
         if let Err(e) = vm::load_and_restore_snapshot(
             &socket_path,
             &subvol,
             &rootfs_path_for_restore.to_string_lossy(),
             net_config.as_ref(),
+            &self.effective_limits(&own_limits),
         ) {
             if let Some(alias) = rootfs_alias.as_ref() {
                 let _ = std::fs::remove_file(alias);
@@ -575,7 +2375,7 @@ impl VmBackend for FirecrackerBackend {
             if let Some(ref nc) = net_config {
                 let _ = network::teardown_vm_network(&nc.tap_name);
             }
-            let _ = storage::delete_subvolume(user_id, target_name);
+            self.discard_restored_volume(user_id, target_name, aside.as_deref());
             return Err(e);
         }
         if let Some(alias) = rootfs_alias.as_ref() {
@@ -585,6 +2385,7 @@ impl VmBackend for FirecrackerBackend {
         // Reconfigure guest network (snapshot has old IP from original VM).
         // Brief delay lets the guest kernel stabilize after resume.
         if let Some(ref nc) = net_config {
+            progress(OperationPhase::ConfiguringNetwork);
             std::thread::sleep(std::time::Duration::from_secs(1));
             if let Err(e) = self.reconfigure_guest_network(&subvol, nc) {
                 eprintln!("warning: failed to reconfigure guest network: {e:#}");
@@ -595,57 +2396,384 @@ impl VmBackend for FirecrackerBackend {
             }
         }
 
-        let (kernel, rootfs_path, cpus, mem_mib) = if let Some(ref orig) = orig_vm {
-            (
-                orig.kernel.clone(),
-                orig.rootfs.clone(),
-                orig.cpus,
-                orig.mem_mib,
-            )
+        let manifest = manifest.unwrap_or_else(|| Manifest {
+            boot: BootConfig::kernel(self.kernel.clone()),
+            rootfs: subvol.join("rootfs.ext4").to_string_lossy().to_string(),
+            base_rootfs: None,
+            cpus: 1,
+            mem_mib: 2048,
+            firecracker_version: None,
+        });
+        let (cpus, mem_mib) = (manifest.cpus, manifest.mem_mib);
+
+        let data = db::VmInsertData {
+            pid,
+            socket_path,
+            boot: manifest.boot,
+            rootfs: manifest.rootfs,
+            cpus,
+            mem_mib,
+            net_index: net_config.as_ref().map(|c| c.index),
+            tap_name: net_config.as_ref().map(|c| c.tap_name.clone()),
+            guest_ip: net_config.as_ref().map(|c| c.guest_ip.clone()),
+            base_rootfs: manifest.base_rootfs,
+            firecracker_version: firecracker.version_string(),
+            settings: replaced
+                .as_ref()
+                .map(db::VmRecord::settings)
+                .unwrap_or_default(),
+        };
+        let inserted = if replaced.is_some() {
+            self.db().replace_vm(user_id, target_name, data)
         } else {
-            (
-                self.kernel.clone(),
-                subvol.join("rootfs.ext4").to_string_lossy().to_string(),
-                1,
-                2048,
-            )
+            self.db().insert_vm(user_id, target_name, data)
         };
-
-        if let Err(e) = self.db().insert_vm(
-            user_id,
-            target_name,
-            db::VmInsertData {
-                pid,
-                socket_path,
-                kernel,
-                rootfs: rootfs_path,
-                cpus,
-                mem_mib,
-                net_index: net_config.as_ref().map(|c| c.index),
-                tap_name: net_config.as_ref().map(|c| c.tap_name.clone()),
-                guest_ip: net_config.as_ref().map(|c| c.guest_ip.clone()),
-            },
-        ) {
+        if let Err(e) = inserted {
             vm::kill_vm_process(pid as i64);
             if let Some(ref nc) = net_config {
                 let _ = network::teardown_vm_network(&nc.tap_name);
             }
+            if aside.is_some() {
+                self.discard_restored_volume(user_id, target_name, aside.as_deref());
+            }
             return Err(e);
         }
+        if let Some(aside) = aside {
+            if let Err(e) = self.storage.delete_volume(&aside) {
+                eprintln!(
+                    "warning: failed to delete the replaced volume {}: {e:#}",
+                    aside.display()
+                );
+            }
+        }
+        {
+            let db = self.db();
+            db.set_vm_parent_checkpoint(user_id, target_name, checkpoint_id)?;
+            let message = format!("from checkpoint {checkpoint_id} of {name}");
+            let _ = db.insert_event(user_id, target_name, "restore.completed", &message, None);
+        }
 
-        Ok(VmInfo {
-            name: target_name.to_string(),
-            state: "running".to_string(),
-            cpus,
-            mem_mib,
-            created_at: chrono::Utc::now().format("%Y-%m-%d %H:%M:%S").to_string(),
-        })
+        let rec = self
+            .db()
+            .get_vm(user_id, target_name)?
+            .ok_or_else(|| anyhow::anyhow!("VM '{target_name}' not found"))?;
+        Ok(Self::vm_to_info(&rec))
     }
 
-    fn console_attach(&self, user_id: &str, name: &str) -> Result<ConsoleHandle> {
-        self.db()
+    fn stop(&self, user_id: &str, name: &str) -> Result<VmInfo> {
+        let lock = self.vm_lock(user_id, name);
+        let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+
+        let rec = self
+            .db()
+            .get_vm(user_id, name)?
+            .ok_or_else(|| anyhow::anyhow!("VM '{name}' not found"))?;
+        if rec.state == "stopped" {
+            bail!("VM '{name}' is not running");
+        }
+        self.halt(&rec);
+        self.db().set_vm_stopped(user_id, name)?;
+
+        let rec = self
+            .db()
+            .get_vm(user_id, name)?
+            .ok_or_else(|| anyhow::anyhow!("VM '{name}' not found"))?;
+        Ok(Self::vm_to_info(&rec))
+    }
+
+    fn start(&self, user_id: &str, name: &str) -> Result<VmInfo> {
+        let lock = self.vm_lock(user_id, name);
+        let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+
+        let rec = self
+            .db()
+            .get_vm(user_id, name)?
+            .ok_or_else(|| anyhow::anyhow!("VM '{name}' not found"))?;
+        if rec.pid.is_some_and(|pid| vm::is_process_alive(pid as i32)) {
+            bail!("VM '{name}' is already running");
+        }
+        if rec.state == "hibernated" {
+            self.resume_hibernated(user_id, &rec)?;
+            return self
+                .get(user_id, name)?
+                .ok_or_else(|| anyhow::anyhow!("VM '{name}' not found"));
+        }
+        // A dead VM still holds its TAP device and network index
+        self.halt(&rec);
+        self.db().set_vm_stopped(user_id, name)?;
+        self.boot_stopped(user_id, &rec)
+    }
+
+    fn reboot(&self, user_id: &str, name: &str) -> Result<VmInfo> {
+        let lock = self.vm_lock(user_id, name);
+        let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+
+        let rec = self
+            .db()
+            .get_vm(user_id, name)?
+            .ok_or_else(|| anyhow::anyhow!("VM '{name}' not found"))?;
+        if rec.state == "stopped" {
+            bail!("VM '{name}' is not running");
+        }
+        self.halt(&rec);
+        self.db().set_vm_stopped(user_id, name)?;
+        self.boot_stopped(user_id, &rec)
+    }
+
+    fn rename(&self, user_id: &str, name: &str, new_name: &str) -> Result<VmInfo> {
+        storage::validate_name(new_name, "VM")?;
+        // Hold both names' locks, taken in a fixed order so two renames in
+        // opposite directions can't deadlock.
+        let (first, second) = if name <= new_name {
+            (name, new_name)
+        } else {
+            (new_name, name)
+        };
+        let first_lock = self.vm_lock(user_id, first);
+        let first_guard = first_lock.lock().unwrap_or_else(|e| e.into_inner());
+        let second_lock = (first != second).then(|| self.vm_lock(user_id, second));
+        let second_guard = second_lock
+            .as_ref()
+            .map(|lock| lock.lock().unwrap_or_else(|e| e.into_inner()));
+
+        let db = self.db();
+        let rec = db
+            .get_vm(user_id, name)?
+            .ok_or_else(|| anyhow::anyhow!("VM '{name}' not found"))?;
+        if name == new_name {
+            return Ok(Self::vm_to_info(&rec));
+        }
+        if db.get_vm(user_id, new_name)?.is_some() {
+            bail!("VM '{new_name}' already exists");
+        }
+        // A jailed Firecracker sees the VM directory bind-mounted at the path
+        // it had when the jail was set up, so it could no longer write
+        // checkpoints or hibernate once the directory moves.
+        if self.jailer.is_some() && rec.pid.is_some_and(|pid| vm::is_process_alive(pid as i32)) {
+            bail!("VM '{name}' runs in a jail and cannot be renamed while running; stop it first");
+        }
+
+        let moved = storage::rename_vm_storage(user_id, name, new_name)?;
+        // A running VM's drive still has the old path, which its checkpoints
+        // would record; point it at the moved disk.
+        let running = rec.pid.is_some_and(|pid| vm::is_process_alive(pid as i32));
+        let repoint = |vm_name: &str| {
+            let dir = storage::vm_dir(user_id, vm_name);
+            vm::set_rootfs_path(
+                &dir.join("firecracker.sock").to_string_lossy(),
+                &dir.join("rootfs.ext4").to_string_lossy(),
+            )
+        };
+        if running {
+            if let Err(e) = repoint(new_name) {
+                storage::undo_renames(&moved);
+                return Err(e.context(format!("failed to rename VM '{name}'")));
+            }
+        }
+        let dirs: Vec<(String, String)> = moved
+            .iter()
+            .map(|(from, to)| {
+                (
+                    from.to_string_lossy().to_string(),
+                    to.to_string_lossy().to_string(),
+                )
+            })
+            .collect();
+        let dirs: Vec<(&str, &str)> = dirs.iter().map(|(f, t)| (f.as_str(), t.as_str())).collect();
+        if let Err(e) = db.rename_vm(user_id, name, new_name, &dirs) {
+            storage::undo_renames(&moved);
+            if running {
+                if let Err(e) = repoint(name) {
+                    eprintln!("warning: failed to point VM '{name}' back at its disk: {e:#}");
+                }
+            }
+            return Err(e);
+        }
+        let message = format!("renamed from '{name}'");
+        if let Err(e) = db.insert_event(user_id, new_name, "vm.renamed", &message, None) {
+            eprintln!("warning: failed to record rename of VM '{name}': {e:#}");
+        }
+        let rec = db
+            .get_vm(user_id, new_name)?
+            .ok_or_else(|| anyhow::anyhow!("VM '{new_name}' not found"))?;
+        drop(db);
+
+        drop(second_guard);
+        drop(first_guard);
+        self.remove_vm_lock(user_id, name);
+        self.restarts.forget(user_id, name);
+        self.activity.forget(user_id, name);
+        eprintln!("VM {name} (user {user_id}) renamed to {new_name}");
+        Ok(Self::vm_to_info(&rec))
+    }
+
+    fn update(&self, user_id: &str, name: &str, req: &UpdateVmRequest) -> Result<VmInfo> {
+        let lock = self.vm_lock(user_id, name);
+        let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+
+        let rec = self
+            .db()
+            .get_vm(user_id, name)?
+            .ok_or_else(|| anyhow::anyhow!("VM '{name}' not found"))?;
+        let now = chrono::Utc::now().naive_utc();
+        let mut settings = rec.settings();
+        match (req.ttl_secs, req.extend_secs) {
+            (Some(_), Some(_)) => bail!("ttl_secs and extend_secs cannot be combined"),
+            (Some(0), None) => settings.expires_at = None,
+            (Some(ttl), None) => settings.expires_at = Some(expiry_after(now, ttl)?),
+            (None, Some(extra)) => {
+                let current = settings
+                    .expires_at
+                    .as_deref()
+                    .and_then(|at| chrono::NaiveDateTime::parse_from_str(at, DB_TIME_FORMAT).ok())
+                    .filter(|at| *at > now)
+                    .unwrap_or(now);
+                settings.expires_at = Some(expiry_after(current, extra)?);
+            }
+            (None, None) => {}
+        }
+        if let Some(checkpoint) = req.stop_on_expiry {
+            settings.stop_on_expiry = checkpoint;
+        }
+        for (key, value) in &req.labels {
+            match value {
+                Some(value) => settings.labels.insert(key.clone(), value.clone()),
+                None => settings.labels.remove(key),
+            };
+        }
+        noid_types::validate_labels(&settings.labels).map_err(|e| anyhow::anyhow!(e))?;
+        if let Some(ref changes) = req.rate_limits {
+            settings.rate_limits = rec.rate_limits.updated(changes);
+        }
+
+        // A hibernated or stopped VM picks new limits up when it next runs.
+        let patch_limits = settings.rate_limits != rec.rate_limits && rec.state == "running";
+        if patch_limits {
+            vm::set_rate_limits(
+                &rec.socket_path,
+                &self.effective_limits(&settings.rate_limits),
+                rec.tap_name.is_some(),
+            )?;
+        }
+        let db = self.db();
+        if let Err(e) = db.set_vm_settings(user_id, name, &settings) {
+            if patch_limits {
+                let _ = vm::set_rate_limits(
+                    &rec.socket_path,
+                    &self.effective_limits(&rec.rate_limits),
+                    rec.tap_name.is_some(),
+                );
+            }
+            return Err(e);
+        }
+
+        let rec = db
+            .get_vm(user_id, name)?
+            .ok_or_else(|| anyhow::anyhow!("VM '{name}' not found"))?;
+        Ok(Self::vm_to_info(&rec))
+    }
+
+    fn balloon(&self, user_id: &str, name: &str) -> Result<BalloonInfo> {
+        let rec = self.running_vm(user_id, name)?;
+        Self::balloon_info(&rec)
+    }
+
+    fn set_balloon(&self, user_id: &str, name: &str, target_mib: u32) -> Result<BalloonInfo> {
+        let lock = self.vm_lock(user_id, name);
+        let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+
+        let rec = self.awake_vm(user_id, name)?;
+        if !(MIN_BALLOON_TARGET_MIB..=rec.mem_mib).contains(&target_mib) {
+            bail!(
+                "memory target {target_mib} MiB is out of range ({MIN_BALLOON_TARGET_MIB}..={} MiB)",
+                rec.mem_mib
+            );
+        }
+        if !vm::has_balloon(&rec.socket_path) {
+            bail!("VM '{name}' has no balloon device; reboot it to add one");
+        }
+        vm::set_balloon(&rec.socket_path, rec.mem_mib - target_mib)?;
+        Self::balloon_info(&rec)
+    }
+
+    fn start_init_script(&self, user_id: &str, name: &str, script: &str) -> Result<()> {
+        let dir = storage::vm_dir(user_id, name);
+        let id = {
+            let lock = self.vm_lock(user_id, name);
+            let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+            let db = self.db();
+            let rec = db
+                .get_vm(user_id, name)?
+                .ok_or_else(|| anyhow::anyhow!("VM '{name}' not found"))?;
+            std::fs::write(init_script::user_data_path(&dir), script)
+                .context("failed to save init script")?;
+            let _ = std::fs::remove_file(init_script::log_path(&dir));
+            db.set_vm_init_script(user_id, name, init_script::STATE_PENDING, None)?;
+            rec.id
+        };
+        self.spawn_init_script(user_id, id);
+        Ok(())
+    }
+
+    fn init_log(&self, user_id: &str, name: &str) -> Result<InitScriptInfo> {
+        let rec = self
+            .db()
             .get_vm(user_id, name)?
             .ok_or_else(|| anyhow::anyhow!("VM '{name}' not found"))?;
+        let state = rec
+            .init_script_state
+            .ok_or_else(|| anyhow::anyhow!("VM '{name}' has no init script"))?;
+        let output = rec.init_script_finished_at.is_some().then(|| {
+            let log = init_script::log_path(&storage::vm_dir(user_id, name));
+            std::fs::read(log)
+                .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+                .unwrap_or_default()
+        });
+        Ok(InitScriptInfo {
+            state,
+            exit_code: rec.init_script_exit_code,
+            finished_at: rec.init_script_finished_at,
+            output,
+        })
+    }
+
+    fn events(&self, user_id: &str, name: &str) -> Result<Vec<VmEvent>> {
+        let events = self.db().list_vm_events(user_id, name)?;
+        if events.is_empty() && self.db().get_vm(user_id, name)?.is_none() {
+            bail!("VM '{name}' not found");
+        }
+        Ok(events)
+    }
+
+    fn user_events(
+        &self,
+        user_id: &str,
+        vm_name: Option<&str>,
+        after_id: i64,
+        since_secs: Option<u64>,
+    ) -> Result<Vec<VmEvent>> {
+        // A window reaching back past what chrono can represent covers
+        // every event.
+        let since = since_secs.and_then(|secs| {
+            i64::try_from(secs)
+                .ok()
+                .and_then(chrono::Duration::try_seconds)
+                .and_then(|d| chrono::Utc::now().naive_utc().checked_sub_signed(d))
+                .map(|at| at.format(DB_TIME_FORMAT).to_string())
+        });
+        self.db()
+            .list_events(user_id, vm_name, after_id, since.as_deref())
+    }
+
+    fn latest_event_id(&self) -> Result<i64> {
+        self.db().latest_event_id()
+    }
+
+    fn console_attach(&self, user_id: &str, name: &str) -> Result<ConsoleHandle> {
+        let lock = self.vm_lock(user_id, name);
+        let guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+        self.awake_vm(user_id, name)?;
+        drop(guard);
 
         let dir = storage::vm_dir(user_id, name);
         let serial_log = vm::serial_log_path(&dir);
@@ -656,8 +2784,64 @@ impl VmBackend for FirecrackerBackend {
         Ok(ConsoleHandle {
             serial_log,
             vm_dir: dir,
+            _session: self.activity.console_session(user_id, name),
         })
     }
+
+    fn usage(&self, user_id: &str) -> Result<UsageSummary> {
+        let vms = self.db().list_vms(user_id)?;
+        let checkpoints = self.db().list_all_checkpoints(user_id)?;
+
+        let mut summary = UsageSummary {
+            vm_count: vms.len(),
+            checkpoint_count: checkpoints.len(),
+            ..Default::default()
+        };
+        for rec in &vms {
+            let dir = storage::vm_dir(user_id, &rec.name);
+            if let Some(u) = self.disk_usage(&dir) {
+                summary.vms.add(&u);
+            }
+        }
+        for cp in &checkpoints {
+            if let Some(u) = self.disk_usage(std::path::Path::new(&cp.snapshot_path)) {
+                summary.checkpoints.add(&u);
+            }
+        }
+
+        // Anything else under the user's storage directory (orphaned volumes,
+        // leftovers from failed operations) counts as artifacts.
+        let mut known: Vec<PathBuf> = vms
+            .iter()
+            .map(|rec| storage::vm_dir(user_id, &rec.name))
+            .collect();
+        known.extend(
+            checkpoints
+                .iter()
+                .map(|cp| PathBuf::from(&cp.snapshot_path)),
+        );
+        for path in storage::user_volume_paths(user_id)? {
+            if known.contains(&path) {
+                continue;
+            }
+            if let Some(u) = self.disk_usage(&path) {
+                summary.artifacts.add(&u);
+            }
+        }
+
+        summary.total.add(&summary.vms);
+        summary.total.add(&summary.checkpoints);
+        summary.total.add(&summary.artifacts);
+        Ok(summary)
+    }
+
+    fn list_images(&self) -> Result<Vec<ImageInfo>> {
+        Ok(Image::list()?.iter().map(Image::info).collect())
+    }
+
+    fn fast_path_shapes(&self) -> Vec<VmShape> {
+        Image::default_image().shapes()
+    }
 }
 
 /// Write bytes to a console handle's serial input.
@@ -675,3 +2859,177 @@ pub fn console_open_log(handle: &ConsoleHandle) -> Result<std::fs::File> {
     f.seek(std::io::SeekFrom::End(-(rewind as i64)))?;
     Ok(f)
 }
+
+/// `from` plus `secs`, formatted for the `expires_at` column.
+/// Seconds until `expires_at`, 0 once it has passed.
+fn expires_in_secs(expires_at: Option<&str>) -> Option<u64> {
+    expires_at.map(|at| {
+        chrono::NaiveDateTime::parse_from_str(at, DB_TIME_FORMAT)
+            .map(|at| (at - chrono::Utc::now().naive_utc()).num_seconds().max(0) as u64)
+            .unwrap_or(0)
+    })
+}
+
+fn expiry_after(from: chrono::NaiveDateTime, secs: u64) -> Result<String> {
+    let at = i64::try_from(secs)
+        .ok()
+        .and_then(chrono::Duration::try_seconds)
+        .and_then(|d| from.checked_add_signed(d))
+        .ok_or_else(|| anyhow::anyhow!("TTL of {secs} seconds is too long"))?;
+    Ok(at.format(DB_TIME_FORMAT).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::tests::vm_data;
+
+    fn test_backend(jailer: Option<JailerConfig>) -> Arc<FirecrackerBackend> {
+        let db = db::Db::open_in_memory().unwrap();
+        db.insert_user("u1", "alice", "hash").unwrap();
+        FirecrackerBackend::new(
+            db,
+            "/vmlinux".into(),
+            "/rootfs.ext4".into(),
+            30,
+            Box::new(storage::driver::CopyDriver),
+            Vec::new(),
+            Vec::new(),
+            jailer,
+            RateLimits::default(),
+            Firecrackers::new(
+                firecracker::Binary {
+                    path: PathBuf::from("/firecracker"),
+                    version: None,
+                },
+                Vec::new(),
+            ),
+        )
+    }
+
+    #[test]
+    fn corrupt_checkpoints_are_only_restored_after_verifying() {
+        let backend = test_backend(None);
+        {
+            let db = backend.db();
+            db.insert_vm("u1", "a", vm_data(std::process::id()))
+                .unwrap();
+            db.insert_checkpoint("c1", "a", "u1", None, "/nonexistent/c1", None)
+                .unwrap();
+            db.set_checkpoint_corrupt("u1", "c1", true).unwrap();
+        }
+        let err = backend
+            .restore("u1", "a", "c1", None, false, &|_| {})
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("failed integrity verification"), "{err}");
+        assert!(err.contains("verify it again"), "{err}");
+    }
+
+    #[test]
+    fn truncated_checkpoints_are_refused_without_verifying() {
+        let backend = test_backend(None);
+        let dir = crate::test_util::tempdir("truncated");
+        for file in integrity::CHECKPOINT_FILES {
+            std::fs::write(dir.join(file), b"contents").unwrap();
+        }
+        let sums = serde_json::to_string(&integrity::compute_checksums(&dir).unwrap()).unwrap();
+        std::fs::write(dir.join("memory.snap"), b"cont").unwrap();
+        {
+            let db = backend.db();
+            db.insert_vm("u1", "a", vm_data(std::process::id()))
+                .unwrap();
+            db.insert_checkpoint("c1", "a", "u1", None, &dir.to_string_lossy(), Some(&sums))
+                .unwrap();
+        }
+        let err = backend
+            .restore("u1", "a", "c1", None, false, &|_| {})
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("memory.snap: size mismatch"), "{err}");
+
+        let db = backend.db();
+        assert!(db.get_checkpoint("u1", "c1").unwrap().unwrap().corrupt);
+        // Nothing was replaced.
+        let rec = db.get_vm("u1", "a").unwrap().unwrap();
+        assert_eq!(rec.state, "running");
+        assert_eq!(rec.pid, Some(std::process::id() as i64));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn running_jailed_vms_are_not_renamed() {
+        let backend = test_backend(Some(JailerConfig::default()));
+        // Any live process stands in for the VM's jailed Firecracker.
+        backend
+            .db()
+            .insert_vm("u1", "a", vm_data(std::process::id()))
+            .unwrap();
+        let err = backend.rename("u1", "a", "b").unwrap_err().to_string();
+        assert!(err.contains("cannot be renamed while running"), "{err}");
+        assert!(backend.db().get_vm("u1", "a").unwrap().is_some());
+        assert!(backend.db().get_vm("u1", "b").unwrap().is_none());
+    }
+
+    #[test]
+    fn failed_updates_change_nothing() {
+        let backend = test_backend(None);
+        {
+            let db = backend.db();
+            db.insert_vm("u1", "a", vm_data(std::process::id()))
+                .unwrap();
+            db.set_vm_stopped("u1", "a").unwrap();
+        }
+        let mut req = UpdateVmRequest {
+            ttl_secs: Some(3600),
+            rate_limits: Some(RateLimits {
+                disk: noid_types::IoLimit {
+                    bandwidth: Some(1 << 20),
+                    ops: None,
+                },
+                ..RateLimits::default()
+            }),
+            ..UpdateVmRequest::default()
+        };
+        for i in 0..=noid_types::MAX_LABELS {
+            req.labels.insert(format!("k{i}"), Some("v".into()));
+        }
+        let err = backend.update("u1", "a", &req).unwrap_err().to_string();
+        assert!(err.contains("too many labels"), "{err}");
+        let rec = backend.db().get_vm("u1", "a").unwrap().unwrap();
+        assert_eq!(rec.settings(), db::VmSettings::default());
+
+        req.labels.clear();
+        backend.update("u1", "a", &req).unwrap();
+        let rec = backend.db().get_vm("u1", "a").unwrap().unwrap();
+        assert!(rec.expires_at.is_some());
+        assert_eq!(rec.rate_limits.disk.bandwidth, Some(1 << 20));
+    }
+
+    #[test]
+    fn stop_and_start_check_the_vm_state() {
+        let backend = test_backend(None);
+        // Any live process stands in for the VM's Firecracker.
+        backend
+            .db()
+            .insert_vm("u1", "a", vm_data(std::process::id()))
+            .unwrap();
+        let err = backend.start("u1", "a").unwrap_err().to_string();
+        assert!(err.contains("is already running"), "{err}");
+
+        backend.db().set_vm_stopped("u1", "a").unwrap();
+        let err = backend.stop("u1", "a").unwrap_err().to_string();
+        assert!(err.contains("is not running"), "{err}");
+
+        // A stopped VM gets as far as booting, which needs its kernel.
+        let err = backend.start("u1", "a").unwrap_err().to_string();
+        assert!(err.contains("kernel not found"), "{err}");
+        assert_eq!(
+            backend.db().get_vm("u1", "a").unwrap().unwrap().state,
+            "stopped"
+        );
+
+        let err = backend.stop("u1", "b").unwrap_err().to_string();
+        assert!(err.contains("not found"), "{err}");
+    }
+}
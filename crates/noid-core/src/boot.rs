@@ -0,0 +1,225 @@
+//! How cold-booted VMs start: kernel, command line and init.
+//!
+//! Every VM boots with [`BASE_BOOT_ARGS`]. Users may append their own
+//! parameters and pick another init, but not override the parameters noid
+//! depends on (see [`validate_boot_args`]).
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::network::{self, NetworkConfig};
+
+/// Kernel command line shared by all VMs. `net.ifnames=0 biosdevname=0` keep
+/// the NIC named eth0, which guest network reconfiguration depends on.
+pub const BASE_BOOT_ARGS: &str = "console=ttyS0 reboot=k panic=1 pci=off root=/dev/vda rw quiet \
+     loglevel=1 systemd.show_status=auto net.ifnames=0 biosdevname=0";
+
+/// Longest accepted `boot_args`. Leaves room for the base arguments and
+/// `ip=` within the kernel's command line limit.
+pub const MAX_BOOT_ARGS_LEN: usize = 1024;
+
+/// Parameters that cannot be passed in `boot_args`: the serial console exec
+/// runs over, the root device, networking and NIC naming, panic/reboot
+/// handling, and init (which has its own field).
+const DENIED_PARAMS: &[&str] = &[
+    "console",
+    "root",
+    "rootfstype",
+    "rootflags",
+    "ro",
+    "rw",
+    "init",
+    "rdinit",
+    "initrd",
+    "ip",
+    "net.ifnames",
+    "biosdevname",
+    "reboot",
+    "panic",
+    "pci",
+];
+
+/// Kernel and command-line customization of a VM.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BootConfig {
+    /// Host path of the kernel image.
+    pub kernel: String,
+    /// Appended to [`BASE_BOOT_ARGS`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub boot_args: Option<String>,
+    /// Passed as `init=`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub init: Option<String>,
+}
+
+impl BootConfig {
+    /// Boot `kernel` with the standard command line.
+    pub fn kernel(kernel: impl Into<String>) -> Self {
+        BootConfig {
+            kernel: kernel.into(),
+            boot_args: None,
+            init: None,
+        }
+    }
+
+    /// The full kernel command line, with the `ip=` parameter for `net`.
+    pub fn cmdline(&self, net: Option<&NetworkConfig>) -> String {
+        let mut args = BASE_BOOT_ARGS.to_string();
+        if let Some(net) = net {
+            args.push(' ');
+            args.push_str(&network::kernel_ip_param(net));
+        }
+        if let Some(init) = &self.init {
+            args.push_str(" init=");
+            args.push_str(init);
+        }
+        if let Some(extra) = &self.boot_args {
+            args.push(' ');
+            args.push_str(extra);
+        }
+        args
+    }
+}
+
+/// Check user-supplied kernel parameters against [`DENIED_PARAMS`].
+pub fn validate_boot_args(args: &str) -> Result<()> {
+    if args.len() > MAX_BOOT_ARGS_LEN {
+        bail!(
+            "boot_args too long ({} bytes, max {MAX_BOOT_ARGS_LEN})",
+            args.len()
+        );
+    }
+    if let Some(c) = args.chars().find(|c| !c.is_ascii_graphic() && *c != ' ') {
+        bail!("boot_args contains invalid character {c:?}");
+    }
+    // Anything after `--` goes to init, where the kernel no longer parses it.
+    for param in args.split(' ').take_while(|p| *p != "--") {
+        let key = param.split_once('=').map_or(param, |(key, _)| key);
+        // The kernel treats '-' and '_' in parameter names alike.
+        let key = key.replace('-', "_");
+        if DENIED_PARAMS.iter().any(|d| d.replace('-', "_") == key) {
+            bail!("boot_args may not set '{key}'");
+        }
+    }
+    Ok(())
+}
+
+/// An init must be an absolute guest path the command line can carry.
+pub fn validate_init(init: &str) -> Result<()> {
+    if !init.starts_with('/')
+        || init.len() > 256
+        || !init
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"/._-".contains(&b))
+    {
+        bail!("init must be an absolute path of letters, digits, '/', '.', '_' and '-'");
+    }
+    Ok(())
+}
+
+/// Contents of `vm.json`, written into every checkpoint so it can be
+/// restored with the VM's settings after the VM itself is gone.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    #[serde(flatten)]
+    pub boot: BootConfig,
+    /// The VM's drive path when the checkpoint was taken, which
+    /// Firecracker reopens on restore.
+    pub rootfs: String,
+    #[serde(default)]
+    pub base_rootfs: Option<String>,
+    pub cpus: u32,
+    pub mem_mib: u32,
+    /// Version of the Firecracker that took the checkpoint; `None` for
+    /// checkpoints taken before it was recorded, or if it was unknown.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub firecracker_version: Option<String>,
+}
+
+const MANIFEST_FILE: &str = "vm.json";
+
+impl Manifest {
+    pub fn write(&self, dir: &Path) -> Result<()> {
+        let path = dir.join(MANIFEST_FILE);
+        std::fs::write(&path, serde_json::to_string(self)?)
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    /// Read the manifest in `dir`. `None` for checkpoints taken before
+    /// manifests were written.
+    pub fn read(dir: &Path) -> Option<Self> {
+        let data = std::fs::read_to_string(dir.join(MANIFEST_FILE)).ok()?;
+        serde_json::from_str(&data).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cmdline_appends_init_and_extra_args() {
+        let boot = BootConfig {
+            kernel: "/vmlinux".into(),
+            boot_args: Some("mitigations=off sysctl.vm.swappiness=10".into()),
+            init: Some("/sbin/myinit".into()),
+        };
+        let cmdline = boot.cmdline(None);
+        assert!(cmdline.starts_with(BASE_BOOT_ARGS));
+        assert!(cmdline.ends_with(" init=/sbin/myinit mitigations=off sysctl.vm.swappiness=10"));
+        assert_eq!(BootConfig::kernel("/vmlinux").cmdline(None), BASE_BOOT_ARGS);
+    }
+
+    #[test]
+    fn boot_args_denylist() {
+        assert!(validate_boot_args("mitigations=off nokaslr").is_ok());
+        assert!(validate_boot_args("quiet -- console=ttyS1").is_ok());
+        for bad in [
+            "console=tty0",
+            "root=/dev/vdb",
+            "init=/bin/sh",
+            "rdinit=/bin/sh",
+            "ip=dhcp",
+            "net.ifnames=1",
+            "panic=0",
+            "ro",
+            "nokaslr\nconsole=tty0",
+        ] {
+            assert!(validate_boot_args(bad).is_err(), "{bad} accepted");
+        }
+        assert!(validate_boot_args(&"a".repeat(MAX_BOOT_ARGS_LEN + 1)).is_err());
+    }
+
+    #[test]
+    fn init_must_be_absolute_path() {
+        assert!(validate_init("/sbin/init").is_ok());
+        assert!(validate_init("/usr/lib/systemd/systemd").is_ok());
+        assert!(validate_init("sbin/init").is_err());
+        assert!(validate_init("/bin/sh -c x").is_err());
+        assert!(validate_init("/init;reboot").is_err());
+    }
+
+    #[test]
+    fn manifest_round_trip() {
+        let dir = crate::test_util::tempdir("manifest");
+        assert!(Manifest::read(&dir).is_none());
+        let manifest = Manifest {
+            boot: BootConfig {
+                kernel: "/k".into(),
+                boot_args: Some("nokaslr".into()),
+                init: None,
+            },
+            rootfs: "/vms/a/rootfs.ext4".into(),
+            base_rootfs: None,
+            cpus: 2,
+            mem_mib: 4096,
+            firecracker_version: Some("1.7.0".into()),
+        };
+        manifest.write(&dir).unwrap();
+        let read = Manifest::read(&dir).unwrap();
+        assert_eq!(read.boot, manifest.boot);
+        assert_eq!((read.cpus, read.mem_mib), (2, 4096));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -8,6 +8,11 @@ pub fn db_path() -> PathBuf {
     noid_dir().join("noid.db")
 }
 
+/// Locked by the running server, holding its pid.
+pub fn server_pid_path() -> PathBuf {
+    noid_dir().join("noid-server.pid")
+}
+
 fn dirs_home() -> PathBuf {
     std::env::var("HOME")
         .map(PathBuf::from)
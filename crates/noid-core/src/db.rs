@@ -1,6 +1,9 @@
 use anyhow::{Context, Result};
-use rusqlite::{params, Connection};
+use noid_types::{RateLimits, RestartFrom, RestartPolicy, VmEvent};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::collections::BTreeMap;
 
+use crate::boot::BootConfig;
 use crate::config;
 
 pub struct Db {
@@ -24,6 +27,103 @@ pub struct VmRecord {
     pub net_index: Option<u32>,
     pub tap_name: Option<String>,
     pub guest_ip: Option<String>,
+    /// Rootfs image the VM's disk was copied from. `None` for VMs created
+    /// before this was recorded.
+    pub base_rootfs: Option<String>,
+    /// Extra kernel parameters; see `boot::BootConfig`.
+    pub boot_args: Option<String>,
+    pub init: Option<String>,
+    pub restart: RestartPolicy,
+    pub restart_from: RestartFrom,
+    /// UTC time after which the reaper removes the VM.
+    pub expires_at: Option<String>,
+    /// Stop the VM after a final checkpoint on expiry instead of destroying
+    /// it. Stored in the `checkpoint_on_expiry` column.
+    pub stop_on_expiry: bool,
+    pub labels: BTreeMap<String, String>,
+    /// Checkpoint the VM was last restored from.
+    pub parent_checkpoint: Option<String>,
+    /// State of the VM's `user_data` init script; `None` if it has none.
+    pub init_script_state: Option<String>,
+    pub init_script_exit_code: Option<i32>,
+    pub init_script_finished_at: Option<String>,
+    /// The VM's own disk and network limits, before the server's apply.
+    pub rate_limits: RateLimits,
+    /// Version of the Firecracker the VM last ran on, which its snapshots
+    /// need; `None` if unknown.
+    pub firecracker_version: Option<String>,
+}
+
+impl VmRecord {
+    pub fn boot_config(&self) -> BootConfig {
+        BootConfig {
+            kernel: self.kernel.clone(),
+            boot_args: self.boot_args.clone(),
+            init: self.init.clone(),
+        }
+    }
+
+    pub fn settings(&self) -> VmSettings {
+        VmSettings {
+            restart: self.restart,
+            restart_from: self.restart_from,
+            expires_at: self.expires_at.clone(),
+            stop_on_expiry: self.stop_on_expiry,
+            labels: self.labels.clone(),
+            rate_limits: self.rate_limits,
+        }
+    }
+}
+
+/// What users choose about a VM beyond its shape, and may change later.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VmSettings {
+    pub restart: RestartPolicy,
+    pub restart_from: RestartFrom,
+    pub expires_at: Option<String>,
+    pub stop_on_expiry: bool,
+    pub labels: BTreeMap<String, String>,
+    pub rate_limits: RateLimits,
+}
+
+const VM_COLUMNS: &str = "id, user_id, name, pid, socket_path, kernel, rootfs, cpus, mem_mib, \
+     state, created_at, net_index, tap_name, guest_ip, base_rootfs, boot_args, init, \
+     restart_policy, restart_from, expires_at, checkpoint_on_expiry, labels, \
+     parent_checkpoint, init_script_state, init_script_exit_code, init_script_finished_at, \
+     rate_limits, firecracker_version";
+
+fn vm_from_row(row: &rusqlite::Row) -> rusqlite::Result<VmRecord> {
+    Ok(VmRecord {
+        id: row.get(0)?,
+        user_id: row.get(1)?,
+        name: row.get(2)?,
+        pid: row.get(3)?,
+        socket_path: row.get(4)?,
+        kernel: row.get(5)?,
+        rootfs: row.get(6)?,
+        cpus: row.get(7)?,
+        mem_mib: row.get(8)?,
+        state: row.get(9)?,
+        created_at: row.get(10)?,
+        net_index: row.get(11)?,
+        tap_name: row.get(12)?,
+        guest_ip: row.get(13)?,
+        base_rootfs: row.get(14)?,
+        boot_args: row.get(15)?,
+        init: row.get(16)?,
+        // Unknown values written by newer versions read as the defaults
+        restart: row.get::<_, String>(17)?.parse().unwrap_or_default(),
+        restart_from: row.get::<_, String>(18)?.parse().unwrap_or_default(),
+        expires_at: row.get(19)?,
+        stop_on_expiry: row.get(20)?,
+        labels: serde_json::from_str(&row.get::<_, String>(21)?).unwrap_or_default(),
+        parent_checkpoint: row.get(22)?,
+        init_script_state: row.get(23)?,
+        init_script_exit_code: row.get(24)?,
+        init_script_finished_at: row.get(25)?,
+        rate_limits: serde_json::from_str(&row.get::<_, String>(26)?).unwrap_or_default(),
+        firecracker_version: row.get(27)?,
+    })
 }
 
 #[derive(Debug)]
@@ -34,18 +134,97 @@ pub struct CheckpointRecord {
     pub label: Option<String>,
     pub snapshot_path: String,
     pub created_at: String,
+    /// JSON-encoded `integrity::FileChecksum` list recorded at checkpoint time.
+    /// `None` for checkpoints created before integrity verification existed.
+    pub checksums: Option<String>,
+    pub corrupt: bool,
+}
+
+const CHECKPOINT_COLUMNS: &str =
+    "id, vm_name, user_id, label, snapshot_path, created_at, checksums, corrupt";
+
+fn checkpoint_from_row(row: &rusqlite::Row) -> rusqlite::Result<CheckpointRecord> {
+    Ok(CheckpointRecord {
+        id: row.get(0)?,
+        vm_name: row.get(1)?,
+        user_id: row.get(2)?,
+        label: row.get(3)?,
+        snapshot_path: row.get(4)?,
+        created_at: row.get(5)?,
+        checksums: row.get(6)?,
+        corrupt: row.get(7)?,
+    })
 }
 
+/// Columns added after the initial schema: (table, column, definition).
+/// Applied with ALTER TABLE on databases created by older versions.
+const MIGRATIONS: &[(&str, &str, &str)] = &[
+    ("checkpoints", "checksums", "TEXT"),
+    ("checkpoints", "corrupt", "INTEGER NOT NULL DEFAULT 0"),
+    ("vms", "base_rootfs", "TEXT"),
+    ("vms", "boot_args", "TEXT"),
+    ("vms", "init", "TEXT"),
+    ("vms", "restart_policy", "TEXT NOT NULL DEFAULT 'never'"),
+    ("vms", "restart_from", "TEXT NOT NULL DEFAULT 'boot'"),
+    ("vms", "expires_at", "TEXT"),
+    ("vms", "checkpoint_on_expiry", "INTEGER NOT NULL DEFAULT 0"),
+    ("vms", "labels", "TEXT NOT NULL DEFAULT '{}'"),
+    ("vms", "parent_checkpoint", "TEXT"),
+    ("vms", "init_script_state", "TEXT"),
+    ("vms", "init_script_exit_code", "INTEGER"),
+    ("vms", "init_script_finished_at", "TEXT"),
+    ("users", "jail_index", "INTEGER"),
+    ("vms", "rate_limits", "TEXT NOT NULL DEFAULT '{}'"),
+    ("vms", "firecracker_version", "TEXT"),
+];
+
 pub struct VmInsertData {
     pub pid: u32,
     pub socket_path: String,
-    pub kernel: String,
+    pub boot: BootConfig,
     pub rootfs: String,
     pub cpus: u32,
     pub mem_mib: u32,
     pub net_index: Option<u32>,
     pub tap_name: Option<String>,
     pub guest_ip: Option<String>,
+    pub base_rootfs: Option<String>,
+    pub firecracker_version: Option<String>,
+    pub settings: VmSettings,
+}
+
+/// Insert a running VM's row through `conn`, which may be a transaction.
+fn insert_vm_row(conn: &Connection, user_id: &str, name: &str, data: VmInsertData) -> Result<()> {
+    conn.execute(
+        "INSERT INTO vms (user_id, name, pid, socket_path, kernel, rootfs, cpus, mem_mib, state, net_index, tap_name, guest_ip, base_rootfs, boot_args, init, firecracker_version,
+             restart_policy, restart_from, expires_at, checkpoint_on_expiry, labels, rate_limits)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, 'running', ?9, ?10, ?11, ?12, ?13, ?14, ?15,
+             ?16, ?17, ?18, ?19, ?20, ?21)",
+        params![
+            user_id,
+            name,
+            data.pid,
+            data.socket_path,
+            data.boot.kernel,
+            data.rootfs,
+            data.cpus,
+            data.mem_mib,
+            data.net_index,
+            data.tap_name,
+            data.guest_ip,
+            data.base_rootfs,
+            data.boot.boot_args,
+            data.boot.init,
+            data.firecracker_version,
+            data.settings.restart.as_str(),
+            data.settings.restart_from.as_str(),
+            data.settings.expires_at,
+            data.settings.stop_on_expiry,
+            serde_json::to_string(&data.settings.labels)?,
+            serde_json::to_string(&data.settings.rate_limits)?
+        ],
+    )?;
+    Ok(())
 }
 
 #[derive(Debug)]
@@ -69,6 +248,16 @@ impl Db {
         Ok(db)
     }
 
+    /// A fresh database in memory, for tests.
+    #[cfg(test)]
+    pub(crate) fn open_in_memory() -> Result<Self> {
+        let conn = Connection::open_in_memory()?;
+        conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+        let db = Self { conn };
+        db.init_schema()?;
+        Ok(db)
+    }
+
     fn init_schema(&self) -> Result<()> {
         self.conn.execute_batch(
             "CREATE TABLE IF NOT EXISTS users (
@@ -92,6 +281,20 @@ impl Db {
                 net_index INTEGER,
                 tap_name TEXT,
                 guest_ip TEXT,
+                base_rootfs TEXT,
+                boot_args TEXT,
+                init TEXT,
+                restart_policy TEXT NOT NULL DEFAULT 'never',
+                restart_from TEXT NOT NULL DEFAULT 'boot',
+                expires_at TEXT,
+                checkpoint_on_expiry INTEGER NOT NULL DEFAULT 0,
+                labels TEXT NOT NULL DEFAULT '{}',
+                parent_checkpoint TEXT,
+                init_script_state TEXT,
+                init_script_exit_code INTEGER,
+                init_script_finished_at TEXT,
+                rate_limits TEXT NOT NULL DEFAULT '{}',
+                firecracker_version TEXT,
                 UNIQUE(user_id, name)
             );
             CREATE TABLE IF NOT EXISTS checkpoints (
@@ -101,9 +304,37 @@ impl Db {
                 label TEXT,
                 snapshot_path TEXT NOT NULL,
                 created_at TEXT NOT NULL DEFAULT (datetime('now')),
+                checksums TEXT,
+                corrupt INTEGER NOT NULL DEFAULT 0,
                 FOREIGN KEY (user_id, vm_name) REFERENCES vms(user_id, name)
+            );
+            CREATE TABLE IF NOT EXISTS events (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                user_id TEXT NOT NULL,
+                vm_name TEXT NOT NULL,
+                kind TEXT NOT NULL,
+                message TEXT NOT NULL,
+                detail TEXT,
+                created_at TEXT NOT NULL DEFAULT (datetime('now'))
             );",
         )?;
+        self.migrate()
+    }
+
+    fn migrate(&self) -> Result<()> {
+        for (table, column, definition) in MIGRATIONS {
+            let mut stmt = self.conn.prepare(&format!("PRAGMA table_info({table})"))?;
+            let exists = stmt
+                .query_map([], |row| row.get::<_, String>(1))?
+                .collect::<Result<Vec<_>, _>>()?
+                .iter()
+                .any(|c| c == column);
+            if !exists {
+                self.conn.execute_batch(&format!(
+                    "ALTER TABLE {table} ADD COLUMN {column} {definition}"
+                ))?;
+            }
+        }
         Ok(())
     }
 
@@ -174,6 +405,27 @@ impl Db {
         }
     }
 
+    /// The user's index among users whose VMs have run in the jailer,
+    /// assigned on first use. It picks the user's jail UID.
+    pub fn user_jail_index(&self, user_id: &str) -> Result<u32> {
+        self.conn.execute(
+            "UPDATE users SET jail_index = \
+             (SELECT COALESCE(MAX(jail_index), -1) + 1 FROM users) \
+             WHERE id = ?1 AND jail_index IS NULL",
+            params![user_id],
+        )?;
+        let index: Option<u32> = self
+            .conn
+            .query_row(
+                "SELECT jail_index FROM users WHERE id = ?1",
+                params![user_id],
+                |row| row.get::<_, Option<u32>>(0),
+            )
+            .optional()?
+            .flatten();
+        index.ok_or_else(|| anyhow::anyhow!("user '{user_id}' not found"))
+    }
+
     pub fn list_users(&self) -> Result<Vec<UserRecord>> {
         let mut stmt = self
             .conn
@@ -204,7 +456,9 @@ impl Db {
             Some(u) => u.id,
             None => return Ok(None),
         };
-        // Delete checkpoints, then VMs, then user
+        // Delete events and checkpoints, then VMs, then user
+        self.conn
+            .execute("DELETE FROM events WHERE user_id = ?1", params![user_id])?;
         self.conn.execute(
             "DELETE FROM checkpoints WHERE user_id = ?1",
             params![user_id],
@@ -219,81 +473,50 @@ impl Db {
     // --- VM methods (user-scoped) ---
 
     pub fn insert_vm(&self, user_id: &str, name: &str, data: VmInsertData) -> Result<()> {
-        self.conn.execute(
-            "INSERT INTO vms (user_id, name, pid, socket_path, kernel, rootfs, cpus, mem_mib, state, net_index, tap_name, guest_ip)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, 'running', ?9, ?10, ?11)",
-            params![
-                user_id,
-                name,
-                data.pid,
-                data.socket_path,
-                data.kernel,
-                data.rootfs,
-                data.cpus,
-                data.mem_mib,
-                data.net_index,
-                data.tap_name,
-                data.guest_ip
-            ],
-        )?;
-        Ok(())
+        insert_vm_row(&self.conn, user_id, name, data)
     }
 
     pub fn get_vm(&self, user_id: &str, name: &str) -> Result<Option<VmRecord>> {
-        let mut stmt = self.conn.prepare(
-            "SELECT id, user_id, name, pid, socket_path, kernel, rootfs, cpus, mem_mib, state, created_at, net_index, tap_name, guest_ip
-             FROM vms WHERE user_id = ?1 AND name = ?2",
-        )?;
-        let mut rows = stmt.query_map(params![user_id, name], |row| {
-            Ok(VmRecord {
-                id: row.get(0)?,
-                user_id: row.get(1)?,
-                name: row.get(2)?,
-                pid: row.get(3)?,
-                socket_path: row.get(4)?,
-                kernel: row.get(5)?,
-                rootfs: row.get(6)?,
-                cpus: row.get(7)?,
-                mem_mib: row.get(8)?,
-                state: row.get(9)?,
-                created_at: row.get(10)?,
-                net_index: row.get(11)?,
-                tap_name: row.get(12)?,
-                guest_ip: row.get(13)?,
-            })
-        })?;
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {VM_COLUMNS} FROM vms WHERE user_id = ?1 AND name = ?2"
+        ))?;
+        let mut rows = stmt.query_map(params![user_id, name], vm_from_row)?;
         match rows.next() {
             Some(row) => Ok(Some(row?)),
             None => Ok(None),
         }
     }
 
+    /// A VM by its row id, which stays the same when it is renamed.
+    pub fn get_vm_by_id(&self, user_id: &str, id: i64) -> Result<Option<VmRecord>> {
+        self.conn
+            .query_row(
+                &format!("SELECT {VM_COLUMNS} FROM vms WHERE user_id = ?1 AND id = ?2"),
+                params![user_id, id],
+                vm_from_row,
+            )
+            .optional()
+            .map_err(Into::into)
+    }
+
     pub fn list_vms(&self, user_id: &str) -> Result<Vec<VmRecord>> {
-        let mut stmt = self.conn.prepare(
-            "SELECT id, user_id, name, pid, socket_path, kernel, rootfs, cpus, mem_mib, state, created_at, net_index, tap_name, guest_ip
-             FROM vms WHERE user_id = ?1 ORDER BY created_at",
-        )?;
-        let rows = stmt.query_map(params![user_id], |row| {
-            Ok(VmRecord {
-                id: row.get(0)?,
-                user_id: row.get(1)?,
-                name: row.get(2)?,
-                pid: row.get(3)?,
-                socket_path: row.get(4)?,
-                kernel: row.get(5)?,
-                rootfs: row.get(6)?,
-                cpus: row.get(7)?,
-                mem_mib: row.get(8)?,
-                state: row.get(9)?,
-                created_at: row.get(10)?,
-                net_index: row.get(11)?,
-                tap_name: row.get(12)?,
-                guest_ip: row.get(13)?,
-            })
-        })?;
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {VM_COLUMNS} FROM vms WHERE user_id = ?1 ORDER BY created_at"
+        ))?;
+        let rows = stmt.query_map(params![user_id], vm_from_row)?;
         rows.collect::<Result<Vec<_>, _>>().map_err(Into::into)
     }
 
+    /// Number of VMs, across all users, booted with `kernel`.
+    pub fn count_vms_with_kernel(&self, kernel: &str) -> Result<usize> {
+        let count: i64 = self.conn.query_row(
+            "SELECT COUNT(*) FROM vms WHERE kernel = ?1",
+            params![kernel],
+            |row| row.get(0),
+        )?;
+        Ok(count as usize)
+    }
+
     pub fn list_used_net_indices(&self) -> Result<Vec<u32>> {
         let mut stmt = self
             .conn
@@ -314,6 +537,303 @@ impl Db {
         Ok(())
     }
 
+    /// Replace a VM's row with a new one, for when a restore replaces the VM
+    /// in place. Its checkpoints, including the one restored, are kept.
+    pub fn replace_vm(&self, user_id: &str, name: &str, data: VmInsertData) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        // checkpoints(vm_name) references vms(name); check it at commit.
+        tx.execute_batch("PRAGMA defer_foreign_keys = ON")?;
+        tx.execute(
+            "DELETE FROM vms WHERE user_id = ?1 AND name = ?2",
+            params![user_id, name],
+        )?;
+        insert_vm_row(&tx, user_id, name, data)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Mark a VM stopped. It no longer has a process or network; its disk and
+    /// checkpoints are kept.
+    pub fn set_vm_stopped(&self, user_id: &str, name: &str) -> Result<()> {
+        self.set_vm_down(user_id, name, "stopped")
+    }
+
+    /// Mark a VM whose process exited on its own. Like a stopped VM, but
+    /// shown as dead until it is started again.
+    pub fn set_vm_dead(&self, user_id: &str, name: &str) -> Result<()> {
+        self.set_vm_down(user_id, name, "dead")
+    }
+
+    /// Mark a VM whose memory was snapshotted to its directory and whose
+    /// process was stopped because it sat idle.
+    pub fn set_vm_hibernated(&self, user_id: &str, name: &str) -> Result<()> {
+        self.set_vm_down(user_id, name, "hibernated")
+    }
+
+    fn set_vm_down(&self, user_id: &str, name: &str, state: &str) -> Result<()> {
+        self.conn.execute(
+            "UPDATE vms SET state = ?3, pid = NULL, net_index = NULL, tap_name = NULL, guest_ip = NULL
+             WHERE user_id = ?1 AND name = ?2",
+            params![user_id, name, state],
+        )?;
+        Ok(())
+    }
+
+    /// Set when a VM expires (`None` for never), in the `YYYY-MM-DD HH:MM:SS`
+    /// UTC format of `created_at`.
+    pub fn set_vm_expiry(
+        &self,
+        user_id: &str,
+        name: &str,
+        expires_at: Option<&str>,
+        stop_on_expiry: bool,
+    ) -> Result<()> {
+        self.conn.execute(
+            "UPDATE vms SET expires_at = ?3, checkpoint_on_expiry = ?4
+             WHERE user_id = ?1 AND name = ?2",
+            params![user_id, name, expires_at, stop_on_expiry],
+        )?;
+        Ok(())
+    }
+
+    /// Replace all of a VM's settings in one statement.
+    pub fn set_vm_settings(&self, user_id: &str, name: &str, settings: &VmSettings) -> Result<()> {
+        self.conn.execute(
+            "UPDATE vms SET restart_policy = ?3, restart_from = ?4, expires_at = ?5,
+                 checkpoint_on_expiry = ?6, labels = ?7, rate_limits = ?8
+             WHERE user_id = ?1 AND name = ?2",
+            params![
+                user_id,
+                name,
+                settings.restart.as_str(),
+                settings.restart_from.as_str(),
+                settings.expires_at,
+                settings.stop_on_expiry,
+                serde_json::to_string(&settings.labels)?,
+                serde_json::to_string(&settings.rate_limits)?
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Record the checkpoint a VM was restored from.
+    pub fn set_vm_parent_checkpoint(
+        &self,
+        user_id: &str,
+        name: &str,
+        checkpoint_id: &str,
+    ) -> Result<()> {
+        self.conn.execute(
+            "UPDATE vms SET parent_checkpoint = ?3 WHERE user_id = ?1 AND name = ?2",
+            params![user_id, name, checkpoint_id],
+        )?;
+        Ok(())
+    }
+
+    /// Record the progress of a VM's init script. Leaving `pending` or
+    /// `running` stamps the finish time.
+    pub fn set_vm_init_script(
+        &self,
+        user_id: &str,
+        name: &str,
+        state: &str,
+        exit_code: Option<i32>,
+    ) -> Result<()> {
+        self.conn.execute(
+            "UPDATE vms SET init_script_state = ?3, init_script_exit_code = ?4,
+                init_script_finished_at =
+                    CASE WHEN ?3 IN ('pending', 'running') THEN NULL ELSE datetime('now') END
+             WHERE user_id = ?1 AND name = ?2",
+            params![user_id, name, state, exit_code],
+        )?;
+        Ok(())
+    }
+
+    /// VMs of all users whose init script has not finished.
+    pub fn list_unfinished_init_scripts(&self) -> Result<Vec<VmRecord>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {VM_COLUMNS} FROM vms WHERE init_script_state IN ('pending', 'running')"
+        ))?;
+        let rows = stmt.query_map([], vm_from_row)?;
+        rows.collect::<Result<Vec<_>, _>>().map_err(Into::into)
+    }
+
+    /// Rename a VM along with its checkpoints and events, in one
+    /// transaction. Paths under `moved_dirs` (old, new) are rewritten to
+    /// the new directory.
+    pub fn rename_vm(
+        &self,
+        user_id: &str,
+        name: &str,
+        new_name: &str,
+        moved_dirs: &[(&str, &str)],
+    ) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        // checkpoints(vm_name) references vms(name); check it at commit.
+        tx.execute_batch("PRAGMA defer_foreign_keys = ON")?;
+        let renamed = tx.execute(
+            "UPDATE vms SET name = ?3 WHERE user_id = ?1 AND name = ?2",
+            params![user_id, name, new_name],
+        )?;
+        if renamed == 0 {
+            anyhow::bail!("VM '{name}' not found");
+        }
+        tx.execute(
+            "UPDATE checkpoints SET vm_name = ?3 WHERE user_id = ?1 AND vm_name = ?2",
+            params![user_id, name, new_name],
+        )?;
+        tx.execute(
+            "UPDATE events SET vm_name = ?3 WHERE user_id = ?1 AND vm_name = ?2",
+            params![user_id, name, new_name],
+        )?;
+        for (from, to) in moved_dirs {
+            for (table, column, owner) in [
+                ("vms", "socket_path", "name"),
+                ("vms", "rootfs", "name"),
+                ("checkpoints", "snapshot_path", "vm_name"),
+            ] {
+                tx.execute(
+                    &format!(
+                        "UPDATE {table} SET {column} = ?4 || substr({column}, length(?3) + 1)
+                         WHERE user_id = ?1 AND {owner} = ?2
+                           AND ({column} = ?3 OR substr({column}, 1, length(?3) + 1) = ?3 || '/')"
+                    ),
+                    params![user_id, new_name, from, to],
+                )?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// VMs of all users whose expiry has passed.
+    pub fn list_expired_vms(&self) -> Result<Vec<VmRecord>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {VM_COLUMNS} FROM vms
+             WHERE expires_at IS NOT NULL AND expires_at <= datetime('now')"
+        ))?;
+        let rows = stmt.query_map([], vm_from_row)?;
+        rows.collect::<Result<Vec<_>, _>>().map_err(Into::into)
+    }
+
+    /// VMs of all users that should have a Firecracker process.
+    pub fn list_running_vms(&self) -> Result<Vec<VmRecord>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {VM_COLUMNS} FROM vms WHERE state = 'running' AND pid IS NOT NULL"
+        ))?;
+        let rows = stmt.query_map([], vm_from_row)?;
+        rows.collect::<Result<Vec<_>, _>>().map_err(Into::into)
+    }
+
+    /// Record the new process and network of a VM booted again by `start`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn set_vm_running(
+        &self,
+        user_id: &str,
+        name: &str,
+        pid: u32,
+        socket_path: &str,
+        rootfs: &str,
+        net: Option<&crate::network::NetworkConfig>,
+        firecracker_version: Option<&str>,
+    ) -> Result<()> {
+        self.conn.execute(
+            "UPDATE vms SET state = 'running', pid = ?3, socket_path = ?4, rootfs = ?5,
+                 net_index = ?6, tap_name = ?7, guest_ip = ?8, firecracker_version = ?9
+             WHERE user_id = ?1 AND name = ?2",
+            params![
+                user_id,
+                name,
+                pid,
+                socket_path,
+                rootfs,
+                net.map(|n| n.index),
+                net.map(|n| n.tap_name.as_str()),
+                net.map(|n| n.guest_ip.as_str()),
+                firecracker_version
+            ],
+        )?;
+        Ok(())
+    }
+
+    // --- Event methods (user-scoped) ---
+
+    pub fn insert_event(
+        &self,
+        user_id: &str,
+        vm_name: &str,
+        kind: &str,
+        message: &str,
+        detail: Option<&str>,
+    ) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO events (user_id, vm_name, kind, message, detail) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![user_id, vm_name, kind, message, detail],
+        )?;
+        Ok(())
+    }
+
+    /// Events of one VM, oldest first. Events outlive the VM they are about.
+    pub fn list_vm_events(&self, user_id: &str, vm_name: &str) -> Result<Vec<VmEvent>> {
+        self.list_events(user_id, Some(vm_name), 0, None)
+    }
+
+    /// Events of a user's VMs (or just `vm_name`) with an id above
+    /// `after_id`, created at or after `since` (UTC), oldest first.
+    pub fn list_events(
+        &self,
+        user_id: &str,
+        vm_name: Option<&str>,
+        after_id: i64,
+        since: Option<&str>,
+    ) -> Result<Vec<VmEvent>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, vm_name, kind, message, detail, created_at FROM events
+             WHERE user_id = ?1 AND (?2 IS NULL OR vm_name = ?2) AND id > ?3
+                AND (?4 IS NULL OR created_at >= ?4)
+             ORDER BY id",
+        )?;
+        let rows = stmt.query_map(params![user_id, vm_name, after_id, since], |row| {
+            Ok(VmEvent {
+                id: row.get(0)?,
+                vm_name: row.get(1)?,
+                kind: row.get(2)?,
+                message: row.get(3)?,
+                detail: row.get(4)?,
+                created_at: row.get(5)?,
+            })
+        })?;
+        rows.collect::<Result<Vec<_>, _>>().map_err(Into::into)
+    }
+
+    /// Id of the newest event of any user, 0 if there are none.
+    pub fn latest_event_id(&self) -> Result<i64> {
+        self.conn
+            .query_row("SELECT COALESCE(MAX(id), 0) FROM events", [], |row| {
+                row.get(0)
+            })
+            .map_err(Into::into)
+    }
+
+    /// Delete events older than `max_age_days`, and all but the newest
+    /// `max_per_user` of each user. Returns how many were deleted.
+    pub fn prune_events(&self, max_age_days: u32, max_per_user: u32) -> Result<usize> {
+        let old = self.conn.execute(
+            "DELETE FROM events WHERE created_at < datetime('now', ?1)",
+            params![format!("-{max_age_days} days")],
+        )?;
+        let excess = self.conn.execute(
+            "DELETE FROM events WHERE id IN (
+                SELECT id FROM (
+                    SELECT id, ROW_NUMBER() OVER (PARTITION BY user_id ORDER BY id DESC) AS n
+                    FROM events
+                ) WHERE n > ?1
+             )",
+            params![max_per_user],
+        )?;
+        Ok(old + excess)
+    }
+
     // --- Checkpoint methods (user-scoped) ---
 
     pub fn insert_checkpoint(
@@ -323,11 +843,33 @@ impl Db {
         user_id: &str,
         label: Option<&str>,
         snapshot_path: &str,
+        checksums: Option<&str>,
     ) -> Result<()> {
         self.conn.execute(
-            "INSERT INTO checkpoints (id, vm_name, user_id, label, snapshot_path)
-             VALUES (?1, ?2, ?3, ?4, ?5)",
-            params![id, vm_name, user_id, label, snapshot_path],
+            "INSERT INTO checkpoints (id, vm_name, user_id, label, snapshot_path, checksums)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![id, vm_name, user_id, label, snapshot_path, checksums],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_checkpoint_corrupt(
+        &self,
+        user_id: &str,
+        checkpoint_id: &str,
+        corrupt: bool,
+    ) -> Result<()> {
+        self.conn.execute(
+            "UPDATE checkpoints SET corrupt = ?1 WHERE id = ?2 AND user_id = ?3",
+            params![corrupt, checkpoint_id, user_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn delete_checkpoint(&self, user_id: &str, checkpoint_id: &str) -> Result<()> {
+        self.conn.execute(
+            "DELETE FROM checkpoints WHERE id = ?1 AND user_id = ?2",
+            params![checkpoint_id, user_id],
         )?;
         Ok(())
     }
@@ -337,20 +879,10 @@ impl Db {
         user_id: &str,
         checkpoint_id: &str,
     ) -> Result<Option<CheckpointRecord>> {
-        let mut stmt = self.conn.prepare(
-            "SELECT id, vm_name, user_id, label, snapshot_path, created_at
-             FROM checkpoints WHERE id = ?1 AND user_id = ?2",
-        )?;
-        let mut rows = stmt.query_map(params![checkpoint_id, user_id], |row| {
-            Ok(CheckpointRecord {
-                id: row.get(0)?,
-                vm_name: row.get(1)?,
-                user_id: row.get(2)?,
-                label: row.get(3)?,
-                snapshot_path: row.get(4)?,
-                created_at: row.get(5)?,
-            })
-        })?;
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {CHECKPOINT_COLUMNS} FROM checkpoints WHERE id = ?1 AND user_id = ?2"
+        ))?;
+        let mut rows = stmt.query_map(params![checkpoint_id, user_id], checkpoint_from_row)?;
         match rows.next() {
             Some(row) => Ok(Some(row?)),
             None => Ok(None),
@@ -358,20 +890,201 @@ impl Db {
     }
 
     pub fn list_checkpoints(&self, user_id: &str, vm_name: &str) -> Result<Vec<CheckpointRecord>> {
-        let mut stmt = self.conn.prepare(
-            "SELECT id, vm_name, user_id, label, snapshot_path, created_at
-             FROM checkpoints WHERE user_id = ?1 AND vm_name = ?2 ORDER BY created_at",
-        )?;
-        let rows = stmt.query_map(params![user_id, vm_name], |row| {
-            Ok(CheckpointRecord {
-                id: row.get(0)?,
-                vm_name: row.get(1)?,
-                user_id: row.get(2)?,
-                label: row.get(3)?,
-                snapshot_path: row.get(4)?,
-                created_at: row.get(5)?,
-            })
-        })?;
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {CHECKPOINT_COLUMNS} FROM checkpoints
+             WHERE user_id = ?1 AND vm_name = ?2 ORDER BY created_at"
+        ))?;
+        let rows = stmt.query_map(params![user_id, vm_name], checkpoint_from_row)?;
+        rows.collect::<Result<Vec<_>, _>>().map_err(Into::into)
+    }
+
+    /// All of a user's checkpoints, across VMs.
+    pub fn list_all_checkpoints(&self, user_id: &str) -> Result<Vec<CheckpointRecord>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {CHECKPOINT_COLUMNS} FROM checkpoints
+             WHERE user_id = ?1 ORDER BY created_at"
+        ))?;
+        let rows = stmt.query_map(params![user_id], checkpoint_from_row)?;
         rows.collect::<Result<Vec<_>, _>>().map_err(Into::into)
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    fn test_db() -> Db {
+        let db = Db::open_in_memory().unwrap();
+        db.insert_user("u1", "alice", "hash").unwrap();
+        db
+    }
+
+    pub(crate) fn vm_data(pid: u32) -> VmInsertData {
+        VmInsertData {
+            pid,
+            socket_path: format!("/vms/a/{pid}.sock"),
+            boot: BootConfig::kernel("/vmlinux"),
+            rootfs: "/vms/a/rootfs.ext4".into(),
+            cpus: 1,
+            mem_mib: 512,
+            net_index: Some(0),
+            tap_name: Some("noid0".into()),
+            guest_ip: Some("172.16.0.2".into()),
+            base_rootfs: None,
+            firecracker_version: None,
+            settings: VmSettings::default(),
+        }
+    }
+
+    #[test]
+    fn vm_id_survives_rename() {
+        let db = test_db();
+        db.insert_vm("u1", "a", vm_data(10)).unwrap();
+        let id = db.get_vm("u1", "a").unwrap().unwrap().id;
+        db.rename_vm("u1", "a", "b", &[]).unwrap();
+        assert_eq!(db.get_vm_by_id("u1", id).unwrap().unwrap().name, "b");
+        assert!(db.get_vm_by_id("u2", id).unwrap().is_none());
+    }
+
+    #[test]
+    fn settings_are_recorded_with_the_vm() {
+        let db = test_db();
+        let settings = VmSettings {
+            restart: RestartPolicy::OnFailure,
+            restart_from: RestartFrom::Checkpoint,
+            expires_at: Some("2030-01-01 00:00:00".into()),
+            stop_on_expiry: true,
+            labels: BTreeMap::from([("ci".to_string(), "true".to_string())]),
+            rate_limits: RateLimits {
+                disk: noid_types::IoLimit {
+                    bandwidth: Some(1 << 20),
+                    ops: Some(100),
+                },
+                ..RateLimits::default()
+            },
+        };
+        db.insert_vm(
+            "u1",
+            "a",
+            VmInsertData {
+                settings: settings.clone(),
+                ..vm_data(10)
+            },
+        )
+        .unwrap();
+        assert_eq!(db.get_vm("u1", "a").unwrap().unwrap().settings(), settings);
+
+        let mut changed = settings.clone();
+        changed.expires_at = None;
+        changed.labels.clear();
+        db.set_vm_settings("u1", "a", &changed).unwrap();
+        assert_eq!(db.get_vm("u1", "a").unwrap().unwrap().settings(), changed);
+    }
+
+    #[test]
+    fn replace_vm_keeps_checkpoints() {
+        let db = test_db();
+        db.insert_vm("u1", "a", vm_data(10)).unwrap();
+        db.insert_checkpoint("c1", "a", "u1", None, "/cp/c1", None)
+            .unwrap();
+        db.insert_checkpoint("c2", "a", "u1", Some("later"), "/cp/c2", None)
+            .unwrap();
+
+        // As a restore in place does: stop the VM, then replace its row.
+        db.set_vm_stopped("u1", "a").unwrap();
+        db.replace_vm("u1", "a", vm_data(20)).unwrap();
+
+        let rec = db.get_vm("u1", "a").unwrap().unwrap();
+        assert_eq!(rec.pid, Some(20));
+        assert_eq!(rec.state, "running");
+        let ids: Vec<_> = db
+            .list_checkpoints("u1", "a")
+            .unwrap()
+            .into_iter()
+            .map(|c| c.id)
+            .collect();
+        assert_eq!(ids, ["c1", "c2"]);
+
+        // A plain delete is still refused while checkpoints reference the VM.
+        assert!(db
+            .conn
+            .execute("DELETE FROM vms WHERE user_id = 'u1' AND name = 'a'", [])
+            .is_err());
+    }
+
+    #[test]
+    fn stopped_vms_release_their_process_and_network() {
+        let db = test_db();
+        db.insert_vm("u1", "a", vm_data(10)).unwrap();
+
+        db.set_vm_stopped("u1", "a").unwrap();
+        let rec = db.get_vm("u1", "a").unwrap().unwrap();
+        assert_eq!(rec.state, "stopped");
+        assert_eq!(rec.pid, None);
+        assert_eq!(rec.net_index, None);
+        assert_eq!(rec.tap_name, None);
+        assert_eq!(rec.guest_ip, None);
+        assert!(db.list_running_vms().unwrap().is_empty());
+
+        let net = crate::network::NetworkConfig {
+            index: 3,
+            tap_name: "noid3".into(),
+            host_ip: "172.16.0.13".into(),
+            guest_ip: "172.16.0.14".into(),
+            guest_mac: "06:00:ac:10:00:0e".into(),
+            netns: None,
+        };
+        db.set_vm_running(
+            "u1",
+            "a",
+            20,
+            "/vms/a/20.sock",
+            "/vms/a/rootfs.ext4",
+            Some(&net),
+            Some("1.7.0"),
+        )
+        .unwrap();
+        let rec = db.get_vm("u1", "a").unwrap().unwrap();
+        assert_eq!(rec.state, "running");
+        assert_eq!(rec.pid, Some(20));
+        assert_eq!(rec.socket_path, "/vms/a/20.sock");
+        assert_eq!(rec.net_index, Some(3));
+        assert_eq!(rec.tap_name.as_deref(), Some("noid3"));
+        assert_eq!(rec.firecracker_version.as_deref(), Some("1.7.0"));
+        assert_eq!(db.list_running_vms().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn prune_events_drops_old_and_excess_events() {
+        let db = test_db();
+        db.insert_user("u2", "bob", "hash").unwrap();
+        for i in 0..5 {
+            db.insert_event("u1", "a", "vm.created", &format!("event {i}"), None)
+                .unwrap();
+        }
+        db.insert_event("u2", "b", "vm.created", "recent", None)
+            .unwrap();
+        db.insert_event("u2", "b", "vm.created", "ancient", None)
+            .unwrap();
+        db.conn
+            .execute(
+                "UPDATE events SET created_at = datetime('now', '-40 days')
+                 WHERE message = 'ancient'",
+                [],
+            )
+            .unwrap();
+
+        // One of bob's events is too old; alice has two more than allowed.
+        assert_eq!(db.prune_events(30, 3).unwrap(), 3);
+        let messages = |user| -> Vec<String> {
+            db.list_events(user, None, 0, None)
+                .unwrap()
+                .into_iter()
+                .map(|e| e.message)
+                .collect()
+        };
+        assert_eq!(messages("u1"), ["event 2", "event 3", "event 4"]);
+        assert_eq!(messages("u2"), ["recent"]);
+        assert_eq!(db.prune_events(30, 3).unwrap(), 0);
+    }
+}
@@ -0,0 +1,238 @@
+//! Installed Firecracker binaries and the snapshots each can load.
+//!
+//! Snapshot formats change between Firecracker releases, so a snapshot is
+//! loaded by a binary of the release line (major.minor) that took it. The
+//! server runs new VMs on its main binary; older ones can be installed
+//! alongside to keep restoring checkpoints taken before an upgrade. A VM
+//! restored on an older binary keeps running on it, so its own checkpoints
+//! and hibernation need the same release line.
+
+use anyhow::{anyhow, bail, Context, Result};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Where the server looks for Firecracker unless configured otherwise.
+pub const DEFAULT_BINARY: &str = "/usr/local/bin/firecracker";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl Version {
+    /// Parse `1.7.0` or `v1.7.0`.
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim().trim_start_matches('v');
+        let mut parts = s.splitn(3, '.');
+        let major = parts.next()?.parse().ok()?;
+        let minor = parts.next()?.parse().ok()?;
+        // Pre-release and build suffixes, as in 1.8.0-dev, are ignored.
+        let patch = parts
+            .next()?
+            .split(|c: char| !c.is_ascii_digit())
+            .next()?
+            .parse()
+            .ok()?;
+        Some(Self {
+            major,
+            minor,
+            patch,
+        })
+    }
+
+    /// Whether this release loads snapshots taken by `snapshot`: only
+    /// releases of the same line do.
+    pub fn can_load(&self, snapshot: &Version) -> bool {
+        self.major == snapshot.major && self.minor == snapshot.minor
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// Version in the output of `firecracker --version`, whose first line reads
+/// `Firecracker v1.7.0`.
+fn parse_version_output(output: &str) -> Option<Version> {
+    output
+        .lines()
+        .find(|line| line.starts_with("Firecracker"))?
+        .split_whitespace()
+        .find_map(Version::parse)
+}
+
+/// A Firecracker binary. `version` is `None` if it could not be detected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Binary {
+    pub path: PathBuf,
+    pub version: Option<Version>,
+}
+
+impl Binary {
+    /// Run `path --version` to find the binary's version.
+    pub fn detect(path: &Path) -> Result<Self> {
+        let output = Command::new(path)
+            .arg("--version")
+            .output()
+            .with_context(|| format!("failed to run {}", path.display()))?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let version = parse_version_output(&stdout).ok_or_else(|| {
+            anyhow!(
+                "unrecognized output from {} --version: {}",
+                path.display(),
+                stdout.lines().next().unwrap_or_default()
+            )
+        })?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            version: Some(version),
+        })
+    }
+
+    /// Version as recorded with snapshots.
+    pub fn version_string(&self) -> Option<String> {
+        self.version.map(|v| v.to_string())
+    }
+}
+
+/// The server's main Firecracker and older ones kept for restores.
+#[derive(Debug, Clone)]
+pub struct Firecrackers {
+    main: Binary,
+    extra: Vec<Binary>,
+}
+
+impl Firecrackers {
+    pub fn new(main: Binary, extra: Vec<Binary>) -> Self {
+        Self { main, extra }
+    }
+
+    /// Detect the versions of `main` and `extra`. The main binary may be
+    /// missing or unrecognizable, in which case snapshots are not checked;
+    /// extra binaries are only of use with a known version.
+    pub fn detect(main: &Path, extra: &[PathBuf]) -> Result<Self> {
+        let main = Binary::detect(main).unwrap_or_else(|e| {
+            eprintln!("warning: Firecracker version unknown, snapshots will not be checked: {e:#}");
+            Binary {
+                path: main.to_path_buf(),
+                version: None,
+            }
+        });
+        let extra = extra
+            .iter()
+            .map(|path| Binary::detect(path))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self::new(main, extra))
+    }
+
+    /// Binary new VMs run on.
+    pub fn main(&self) -> &Binary {
+        &self.main
+    }
+
+    pub fn all(&self) -> impl Iterator<Item = &Binary> {
+        std::iter::once(&self.main).chain(&self.extra)
+    }
+
+    /// Binary to load a snapshot taken by Firecracker `version` with: the
+    /// main one if it can, else the newest extra one that can. Snapshots
+    /// without a recorded version predate the check and get the main one.
+    pub fn for_snapshot(&self, version: Option<&str>) -> Result<&Binary> {
+        let Some(recorded) = version else {
+            return Ok(&self.main);
+        };
+        let version = Version::parse(recorded)
+            .ok_or_else(|| anyhow!("invalid Firecracker version '{recorded}'"))?;
+        let Some(main_version) = self.main.version else {
+            return Ok(&self.main);
+        };
+        if main_version.can_load(&version) {
+            return Ok(&self.main);
+        }
+        if let Some(binary) = self
+            .extra
+            .iter()
+            .filter(|b| b.version.is_some_and(|v| v.can_load(&version)))
+            .max_by_key(|b| b.version)
+        {
+            return Ok(binary);
+        }
+        bail!(
+            "snapshot was taken with Firecracker {version}, which Firecracker {main_version} \
+             cannot load; install a {}.{}.x release and add it to extra_firecrackers",
+            version.major,
+            version.minor
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn binary(path: &str, version: &str) -> Binary {
+        Binary {
+            path: PathBuf::from(path),
+            version: Version::parse(version),
+        }
+    }
+
+    #[test]
+    fn parses_version_output() {
+        let output = "Firecracker v1.7.0\n\nSupported snapshot data format versions: v1.0.0\n";
+        assert_eq!(parse_version_output(output), Version::parse("1.7.0"));
+        assert_eq!(
+            parse_version_output("Firecracker v1.8.0-dev\n"),
+            Some(Version {
+                major: 1,
+                minor: 8,
+                patch: 0
+            })
+        );
+        assert_eq!(parse_version_output("jailer v1.7.0\n"), None);
+        assert_eq!(Version::parse("v1.10.2").unwrap().to_string(), "1.10.2");
+        assert!(Version::parse("1.7").is_none());
+    }
+
+    #[test]
+    fn snapshots_load_on_their_release_line() {
+        let fcs = Firecrackers::new(
+            binary("/fc-1.10", "1.10.1"),
+            vec![
+                binary("/fc-1.7.0", "1.7.0"),
+                binary("/fc-1.7.1", "1.7.1"),
+                binary("/fc-1.9", "1.9.0"),
+            ],
+        );
+        assert_eq!(fcs.for_snapshot(None).unwrap().path, Path::new("/fc-1.10"));
+        assert_eq!(
+            fcs.for_snapshot(Some("1.10.0")).unwrap().path,
+            Path::new("/fc-1.10")
+        );
+        assert_eq!(
+            fcs.for_snapshot(Some("1.7.0")).unwrap().path,
+            Path::new("/fc-1.7.1")
+        );
+        let err = fcs.for_snapshot(Some("1.6.0")).unwrap_err().to_string();
+        assert!(err.contains("Firecracker 1.6.0"), "{err}");
+        assert!(err.contains("1.6.x"), "{err}");
+        assert!(fcs.for_snapshot(Some("latest")).is_err());
+
+        let unknown = Firecrackers::new(
+            Binary {
+                path: PathBuf::from("/fc"),
+                version: None,
+            },
+            Vec::new(),
+        );
+        assert_eq!(
+            unknown.for_snapshot(Some("1.6.0")).unwrap().path,
+            Path::new("/fc")
+        );
+    }
+}
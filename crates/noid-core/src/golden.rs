@@ -0,0 +1,357 @@
+//! Promoting a checkpoint into a golden image, with one level of rollback.
+//!
+//! The new version is staged in a hidden sibling of the image directory,
+//! verified, and exchanged with the current one in a single rename, so
+//! `create` never sees a partially written image or none at all. The
+//! replaced version is kept as `.<name>.previous` next to it until the next
+//! promotion. Swaps hold `.<name>.lock`, so the server and the admin CLI
+//! never swap the same image at once.
+
+use anyhow::{anyhow, bail, Context, Result};
+use nix::fcntl::{renameat2, RenameFlags};
+use noid_types::GoldenInfo;
+use std::path::{Path, PathBuf};
+
+use crate::boot::Manifest;
+use crate::db::{self, CheckpointRecord, VmRecord};
+use crate::image::{Image, ImageConfig};
+use crate::lockfile::LockFile;
+use crate::storage::driver::CopyDriver;
+use crate::storage::StorageDriver;
+use crate::{integrity, storage, vm};
+
+/// Look up a user's checkpoint and the VM it was taken from.
+pub fn find_checkpoint(
+    db: &db::Db,
+    user_name: &str,
+    vm_name: &str,
+    checkpoint_id: &str,
+) -> Result<(VmRecord, CheckpointRecord)> {
+    let user = db
+        .get_user_by_name(user_name)?
+        .ok_or_else(|| anyhow!("user '{user_name}' not found"))?;
+    let vm = db
+        .get_vm(&user.id, vm_name)?
+        .ok_or_else(|| anyhow!("VM '{vm_name}' not found"))?;
+    let checkpoint = db
+        .get_checkpoint(&user.id, checkpoint_id)?
+        .filter(|cp| cp.vm_name == vm_name)
+        .ok_or_else(|| anyhow!("checkpoint '{checkpoint_id}' not found"))?;
+    Ok((vm, checkpoint))
+}
+
+/// Replace `image` with the contents of `checkpoint`, keeping the current
+/// version for [`rollback`]. The image's own kernel, if any, is carried over.
+pub fn promote(image: &Image, vm: &VmRecord, checkpoint: &CheckpointRecord) -> Result<GoldenInfo> {
+    let source = Path::new(&checkpoint.snapshot_path);
+    let expected: Option<Vec<integrity::FileChecksum>> = match checkpoint.checksums.as_deref() {
+        Some(json) => Some(serde_json::from_str(json).map_err(|e| {
+            anyhow!(
+                "invalid checksums recorded for checkpoint '{}': {e}",
+                checkpoint.id
+            )
+        })?),
+        None => None,
+    };
+    let problems = integrity::verify_checksums(source, expected.as_deref())?;
+    if !problems.is_empty() {
+        bail!(
+            "checkpoint '{}' failed integrity verification: {}",
+            checkpoint.id,
+            problems.join("; ")
+        );
+    }
+
+    // Firecracker reopens the rootfs path recorded in vmstate.snap when the
+    // snapshot is loaded; see vm::ensure_snapshot_rootfs_path.
+    let snapshot_rootfs_path =
+        vm::extract_rootfs_path_from_vmstate(source).unwrap_or_else(|| vm.rootfs.clone());
+    if !Path::new(&snapshot_rootfs_path).starts_with(storage::storage_dir()) {
+        bail!("snapshot rootfs path is outside noid storage: {snapshot_rootfs_path}");
+    }
+    let config = ImageConfig {
+        cpus: vm.cpus,
+        mem_mib: vm.mem_mib,
+        snapshot_rootfs_path: Some(snapshot_rootfs_path),
+        firecracker_version: Manifest::read(source).and_then(|m| m.firecracker_version),
+    };
+    promote_snapshot(image, source, expected.as_deref(), &config)
+}
+
+fn promote_snapshot(
+    image: &Image,
+    source: &Path,
+    expected: Option<&[integrity::FileChecksum]>,
+    config: &ImageConfig,
+) -> Result<GoldenInfo> {
+    if let Some(parent) = image.dir.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let _lock = lock(image)?;
+
+    let staging = sibling(&image.dir, "staging");
+    let _ = std::fs::remove_dir_all(&staging);
+    if let Err(e) = stage(
+        source,
+        &staging,
+        expected,
+        config,
+        image.kernel().as_deref(),
+    ) {
+        let _ = std::fs::remove_dir_all(&staging);
+        return Err(e);
+    }
+
+    if let Err(e) = swap_in(&staging, &image.dir) {
+        let _ = std::fs::remove_dir_all(&staging);
+        return Err(e).with_context(|| format!("failed to install {}", image.dir.display()));
+    }
+    // `staging` now holds the replaced version, if there was one.
+    if staging.exists() {
+        let previous = sibling(&image.dir, "previous");
+        let _ = std::fs::remove_dir_all(&previous);
+        std::fs::rename(&staging, &previous)
+            .with_context(|| format!("failed to keep {}", previous.display()))?;
+    }
+    current(image)
+}
+
+/// Put directory `new` in place of `dir` in one step, leaving what `dir`
+/// held, if anything, at `new`.
+fn swap_in(new: &Path, dir: &Path) -> Result<()> {
+    if dir.exists() {
+        renameat2(None, new, None, dir, RenameFlags::RENAME_EXCHANGE)?;
+    } else {
+        std::fs::rename(new, dir)?;
+    }
+    Ok(())
+}
+
+/// Take the lock guarding swaps of `image`, waiting for another swap.
+fn lock(image: &Image) -> Result<LockFile> {
+    LockFile::lock(&sibling(&image.dir, "lock"))
+}
+
+/// Copy the snapshot files into `staging` and check the copies, so a copy
+/// cut short (e.g. by a full disk) is never swapped in.
+fn stage(
+    source: &Path,
+    staging: &Path,
+    expected: Option<&[integrity::FileChecksum]>,
+    config: &ImageConfig,
+    kernel: Option<&Path>,
+) -> Result<()> {
+    std::fs::create_dir_all(staging)?;
+    for file in integrity::CHECKPOINT_FILES {
+        CopyDriver.copy_file(&source.join(file), &staging.join(file))?;
+    }
+    if let Some(kernel) = kernel {
+        CopyDriver.copy_file(kernel, &staging.join("vmlinux"))?;
+    }
+    let problems = integrity::verify_checksums(staging, expected)?;
+    if !problems.is_empty() {
+        bail!(
+            "copied golden files are incomplete: {}",
+            problems.join("; ")
+        );
+    }
+    std::fs::write(staging.join("config.json"), serde_json::to_string(config)?)?;
+    Ok(())
+}
+
+/// Swap `image` with its previous version. Rolling back twice restores the
+/// version that was current before the first rollback.
+pub fn rollback(image: &Image) -> Result<GoldenInfo> {
+    let previous = sibling(&image.dir, "previous");
+    if !previous.exists() {
+        bail!("previous version of {} not found", describe(image));
+    }
+    let _lock = lock(image)?;
+    // Another process may have rolled back to it meanwhile.
+    if !previous.exists() {
+        bail!("previous version of {} not found", describe(image));
+    }
+    swap_in(&previous, &image.dir)
+        .with_context(|| format!("failed to restore {}", previous.display()))?;
+    current(image)
+}
+
+fn current(image: &Image) -> Result<GoldenInfo> {
+    Ok(GoldenInfo {
+        image: image.name.clone(),
+        shape: ImageConfig::load(&image.dir)?.shape(),
+        has_previous: sibling(&image.dir, "previous").exists(),
+    })
+}
+
+/// Hidden directory next to `dir`, e.g. `~/.noid/.golden.staging`. The
+/// leading dot keeps it out of `Image::list`.
+fn sibling(dir: &Path, tag: &str) -> PathBuf {
+    let name = dir
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    dir.with_file_name(format!(".{name}.{tag}"))
+}
+
+fn describe(image: &Image) -> String {
+    match &image.name {
+        Some(name) => format!("image '{name}'"),
+        None => "the default golden image".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::tempdir;
+    use noid_types::VmShape;
+
+    fn checkpoint(root: &Path, name: &str, memory: &[u8]) -> PathBuf {
+        let dir = root.join(name);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("memory.snap"), memory).unwrap();
+        std::fs::write(dir.join("vmstate.snap"), b"vmstate").unwrap();
+        std::fs::write(dir.join("rootfs.ext4"), b"rootfs").unwrap();
+        dir
+    }
+
+    fn config(cpus: u32) -> ImageConfig {
+        ImageConfig {
+            cpus,
+            mem_mib: 2048,
+            snapshot_rootfs_path: None,
+            firecracker_version: None,
+        }
+    }
+
+    fn image(root: &Path) -> Image {
+        Image {
+            name: Some("node20".into()),
+            dir: root.join("images").join("node20"),
+        }
+    }
+
+    #[test]
+    fn promote_then_rollback_swaps_versions() {
+        let root = tempdir("golden-swap");
+        let image = image(&root);
+        let first = checkpoint(&root, "cp1", b"first");
+        let second = checkpoint(&root, "cp2", b"second");
+
+        let info = promote_snapshot(&image, &first, None, &config(1)).unwrap();
+        assert!(!info.has_previous);
+        std::fs::write(image.dir.join("vmlinux"), b"kernel").unwrap();
+        let first_version = image.version();
+
+        let info = promote_snapshot(&image, &second, None, &config(2)).unwrap();
+        assert_ne!(image.version(), first_version);
+        assert_eq!(
+            info.shape,
+            VmShape {
+                cpus: 2,
+                mem_mib: 2048
+            }
+        );
+        assert!(info.has_previous);
+        assert!(!sibling(&image.dir, "staging").exists());
+        assert_eq!(
+            std::fs::read(image.dir.join("memory.snap")).unwrap(),
+            b"second"
+        );
+        assert_eq!(std::fs::read(image.dir.join("vmlinux")).unwrap(), b"kernel");
+
+        let info = rollback(&image).unwrap();
+        assert_eq!(info.shape.cpus, 1);
+        assert_eq!(image.version(), first_version);
+        assert_eq!(
+            std::fs::read(image.dir.join("memory.snap")).unwrap(),
+            b"first"
+        );
+
+        // Rolling back again returns to the promoted version
+        rollback(&image).unwrap();
+        assert_eq!(
+            std::fs::read(image.dir.join("memory.snap")).unwrap(),
+            b"second"
+        );
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn failed_promotion_leaves_current_image() {
+        let root = tempdir("golden-failed");
+        let image = image(&root);
+        let good = checkpoint(&root, "good", b"good");
+        promote_snapshot(&image, &good, None, &config(1)).unwrap();
+
+        let broken = checkpoint(&root, "broken", b"");
+        std::fs::remove_file(broken.join("rootfs.ext4")).unwrap();
+        assert!(promote_snapshot(&image, &broken, None, &config(2)).is_err());
+
+        assert_eq!(
+            std::fs::read(image.dir.join("memory.snap")).unwrap(),
+            b"good"
+        );
+        assert!(!sibling(&image.dir, "staging").exists());
+        assert!(!sibling(&image.dir, "previous").exists());
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn swaps_wait_for_other_processes() {
+        let root = tempdir("golden-lock");
+        let image = image(&root);
+        promote_snapshot(
+            &image,
+            &checkpoint(&root, "cp1", b"first"),
+            None,
+            &config(1),
+        )
+        .unwrap();
+        promote_snapshot(
+            &image,
+            &checkpoint(&root, "cp2", b"second"),
+            None,
+            &config(1),
+        )
+        .unwrap();
+
+        // A lock taken through another open file stands in for a second
+        // process, as flock locks belong to open files.
+        let held = LockFile::lock(&sibling(&image.dir, "lock")).unwrap();
+        let swapping = {
+            let image = image.clone();
+            std::thread::spawn(move || rollback(&image).map(|_| ()))
+        };
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        assert!(!swapping.is_finished());
+        assert_eq!(
+            std::fs::read(image.dir.join("memory.snap")).unwrap(),
+            b"second"
+        );
+        drop(held);
+        swapping.join().unwrap().unwrap();
+        assert_eq!(
+            std::fs::read(image.dir.join("memory.snap")).unwrap(),
+            b"first"
+        );
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn rollback_without_previous_is_not_found() {
+        let root = tempdir("golden-noprev");
+        let err = rollback(&image(&root)).unwrap_err();
+        assert!(err.to_string().contains("not found"));
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn staging_dirs_are_hidden() {
+        assert_eq!(
+            sibling(Path::new("/x/.noid/golden"), "previous"),
+            PathBuf::from("/x/.noid/.golden.previous")
+        );
+    }
+}
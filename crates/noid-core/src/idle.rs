@@ -0,0 +1,165 @@
+//! Idle detection for hibernation: how long ago a VM was last used through
+//! exec, the console or its network.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Last known use of one VM.
+struct Activity {
+    last: Instant,
+    /// TAP byte counters when last sampled.
+    net_bytes: Option<u64>,
+    /// Cloned into every console session; more than one strong reference
+    /// means someone is attached.
+    console: Arc<()>,
+}
+
+impl Activity {
+    fn new(now: Instant) -> Self {
+        Activity {
+            last: now,
+            net_bytes: None,
+            console: Arc::new(()),
+        }
+    }
+}
+
+/// Tracks VM activity, keyed by (user_id, vm name).
+#[derive(Default)]
+pub struct ActivityTracker {
+    vms: Mutex<HashMap<(String, String), Activity>>,
+}
+
+impl ActivityTracker {
+    fn with<R>(&self, user_id: &str, name: &str, f: impl FnOnce(&mut Activity) -> R) -> R {
+        let mut vms = self.vms.lock().unwrap_or_else(|e| e.into_inner());
+        let activity = vms
+            .entry((user_id.to_string(), name.to_string()))
+            .or_insert_with(|| Activity::new(Instant::now()));
+        f(activity)
+    }
+
+    /// Record that a VM is being used now.
+    pub fn touch(&self, user_id: &str, name: &str) {
+        self.with(user_id, name, |a| a.last = Instant::now());
+    }
+
+    /// A token held for the length of a console session. The VM does not
+    /// count as idle while any token is alive.
+    pub fn console_session(&self, user_id: &str, name: &str) -> Arc<()> {
+        self.with(user_id, name, |a| {
+            a.last = Instant::now();
+            a.console.clone()
+        })
+    }
+
+    /// How long a VM has gone unused. `net_bytes` is the current TAP byte
+    /// count and `input_age` the time since anything was written to its
+    /// serial console; either counts as use when it changed. A VM seen for
+    /// the first time counts as just used.
+    pub fn idle_for(
+        &self,
+        user_id: &str,
+        name: &str,
+        net_bytes: Option<u64>,
+        input_age: Option<Duration>,
+    ) -> Duration {
+        let now = Instant::now();
+        self.with(user_id, name, |a| {
+            if Arc::strong_count(&a.console) > 1 {
+                a.last = now;
+            }
+            if net_bytes != a.net_bytes {
+                a.last = now;
+                a.net_bytes = net_bytes;
+            }
+            let idle = now.duration_since(a.last);
+            input_age.map_or(idle, |age| idle.min(age))
+        })
+    }
+
+    /// Drop the record of a VM, e.g. when it is destroyed.
+    pub fn forget(&self, user_id: &str, name: &str) {
+        let mut vms = self.vms.lock().unwrap_or_else(|e| e.into_inner());
+        vms.remove(&(user_id.to_string(), name.to_string()));
+    }
+}
+
+/// Bytes sent and received on a TAP device so far.
+pub fn tap_bytes(tap_name: &str) -> Option<u64> {
+    let stats = Path::new("/sys/class/net")
+        .join(tap_name)
+        .join("statistics");
+    let read = |file: &str| -> Option<u64> {
+        std::fs::read_to_string(stats.join(file))
+            .ok()?
+            .trim()
+            .parse()
+            .ok()
+    };
+    Some(read("rx_bytes")? + read("tx_bytes")?)
+}
+
+/// Time since the VM in `vm_dir` last had input on its serial console,
+/// which carries both exec commands and console keystrokes.
+pub fn input_age(vm_dir: &Path) -> Option<Duration> {
+    let modified = std::fs::metadata(vm_dir.join("serial.in"))
+        .ok()?
+        .modified()
+        .ok()?;
+    Some(
+        SystemTime::now()
+            .duration_since(modified)
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set_last_use(tracker: &ActivityTracker, name: &str, secs_ago: u64) {
+        let last = Instant::now()
+            .checked_sub(Duration::from_secs(secs_ago))
+            .unwrap();
+        tracker.with("u", name, |a| a.last = last);
+    }
+
+    #[test]
+    fn activity_resets_idle_time() {
+        let tracker = ActivityTracker::default();
+        set_last_use(&tracker, "vm", 60);
+
+        // Same (absent) traffic counters: idle since a minute ago
+        assert!(tracker.idle_for("u", "vm", None, None) >= Duration::from_secs(60));
+        // Recent serial input wins
+        assert_eq!(
+            tracker.idle_for("u", "vm", None, Some(Duration::from_secs(5))),
+            Duration::from_secs(5)
+        );
+        // Traffic counters moved
+        assert!(tracker.idle_for("u", "vm", Some(100), None) < Duration::from_secs(1));
+
+        // First sighting counts as just used
+        assert!(tracker.idle_for("u", "new", None, None) < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn console_session_keeps_vm_busy() {
+        let tracker = ActivityTracker::default();
+        let session = tracker.console_session("u", "vm");
+        set_last_use(&tracker, "vm", 60);
+        assert!(tracker.idle_for("u", "vm", None, None) < Duration::from_secs(1));
+
+        drop(session);
+        set_last_use(&tracker, "vm", 60);
+        assert!(tracker.idle_for("u", "vm", None, None) >= Duration::from_secs(60));
+    }
+
+    #[test]
+    fn tap_bytes_of_missing_device_is_none() {
+        assert_eq!(tap_bytes("noid-no-such-tap"), None);
+    }
+}
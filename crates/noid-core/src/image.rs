@@ -0,0 +1,294 @@
+//! Golden images: a template rootfs plus an optional pre-booted snapshot that
+//! `create` clones instead of cold-booting.
+//!
+//! The default image lives in `~/.noid/golden/`. Named images live in
+//! `~/.noid/images/<name>/` with the same layout:
+//!
+//! ```text
+//! rootfs.ext4     template disk (also used to cold-boot other shapes)
+//! vmlinux         optional kernel; the server's kernel is used otherwise
+//! memory.snap     snapshot of the booted template
+//! vmstate.snap
+//! config.json     {"cpus": 1, "mem_mib": 2048, "snapshot_rootfs_path": "..."}
+//! shapes/<cpus>x<mem_mib>/   snapshots of other shapes (same files as above)
+//! ```
+//!
+//! Shape snapshots are built by the server in the background for the shapes
+//! listed in `golden_shapes`; see `FirecrackerBackend`.
+
+use anyhow::{bail, Context, Result};
+use noid_types::{ImageInfo, VmShape};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::{config, storage};
+
+/// Contents of a snapshot's `config.json`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageConfig {
+    pub cpus: u32,
+    pub mem_mib: u32,
+    /// Backing rootfs path captured in vmstate.snap when the snapshot was taken.
+    #[serde(default)]
+    pub snapshot_rootfs_path: Option<String>,
+    /// Version of the Firecracker that took the snapshot, if known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub firecracker_version: Option<String>,
+}
+
+impl ImageConfig {
+    pub fn shape(&self) -> VmShape {
+        VmShape {
+            cpus: self.cpus,
+            mem_mib: self.mem_mib,
+        }
+    }
+
+    /// Read `config.json` from a snapshot directory.
+    pub fn load(dir: &Path) -> Result<Self> {
+        let path = dir.join("config.json");
+        let data = std::fs::read_to_string(&path)
+            .with_context(|| format!("failed to read image config: {}", path.display()))?;
+        serde_json::from_str(&data)
+            .with_context(|| format!("failed to parse image config: {}", path.display()))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Image {
+    /// `None` for the default image.
+    pub name: Option<String>,
+    pub dir: PathBuf,
+}
+
+/// Directory holding named images.
+pub fn images_dir() -> PathBuf {
+    config::noid_dir().join("images")
+}
+
+impl Image {
+    /// The unnamed image in `~/.noid/golden/`. It may not exist, in which
+    /// case `create` cold-boots from the server's kernel and rootfs.
+    pub fn default_image() -> Self {
+        Image {
+            name: None,
+            dir: storage::golden_dir(),
+        }
+    }
+
+    /// A named image, which need not be installed yet.
+    pub fn named(name: &str) -> Result<Self> {
+        storage::validate_name(name, "Image")?;
+        Ok(Image {
+            name: Some(name.to_string()),
+            dir: images_dir().join(name),
+        })
+    }
+
+    /// Open a named image. Fails if the image is not installed.
+    pub fn open(name: &str) -> Result<Self> {
+        Self::open_in(&images_dir(), name)
+    }
+
+    fn open_in(root: &Path, name: &str) -> Result<Self> {
+        storage::validate_name(name, "Image")?;
+        let image = Image {
+            name: Some(name.to_string()),
+            dir: root.join(name),
+        };
+        if !image.rootfs().exists() {
+            bail!("image '{name}' not found");
+        }
+        Ok(image)
+    }
+
+    /// All installed named images, sorted by name.
+    pub fn list() -> Result<Vec<Image>> {
+        Self::list_in(&images_dir())
+    }
+
+    fn list_in(root: &Path) -> Result<Vec<Image>> {
+        let entries = match std::fs::read_dir(root) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e).with_context(|| format!("failed to read {}", root.display())),
+        };
+        let mut images: Vec<Image> = entries
+            .filter_map(|e| e.ok())
+            .filter_map(|e| e.file_name().into_string().ok())
+            .filter_map(|name| Self::open_in(root, &name).ok())
+            .collect();
+        images.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(images)
+    }
+
+    pub fn rootfs(&self) -> PathBuf {
+        self.dir.join("rootfs.ext4")
+    }
+
+    /// The image's own kernel, if it ships one.
+    pub fn kernel(&self) -> Option<PathBuf> {
+        Some(self.dir.join("vmlinux")).filter(|p| p.exists())
+    }
+
+    /// Where a snapshot of `shape` lives in the pool (whether or not it exists).
+    pub fn shape_dir(&self, shape: VmShape) -> PathBuf {
+        self.dir.join("shapes").join(shape.to_string())
+    }
+
+    /// Directory holding a snapshot for `shape`: the image's own snapshot if
+    /// it has that shape, otherwise one from the shape pool.
+    pub fn snapshot_dir(&self, shape: VmShape) -> Option<PathBuf> {
+        [self.dir.clone(), self.shape_dir(shape)]
+            .into_iter()
+            .find(|dir| snapshot_shape(dir) == Some(shape))
+    }
+
+    /// All shapes that have a snapshot, sorted.
+    pub fn shapes(&self) -> Vec<VmShape> {
+        let mut shapes: Vec<VmShape> = snapshot_shape(&self.dir).into_iter().collect();
+        if let Ok(entries) = std::fs::read_dir(self.dir.join("shapes")) {
+            shapes.extend(
+                entries
+                    .filter_map(|e| e.ok())
+                    .filter(|e| e.file_name().to_str().and_then(parse_shape).is_some())
+                    .filter_map(|e| snapshot_shape(&e.path())),
+            );
+        }
+        shapes.sort();
+        shapes.dedup();
+        shapes
+    }
+
+    pub fn info(&self) -> ImageInfo {
+        ImageInfo {
+            name: self.name.clone().unwrap_or_default(),
+            shapes: self.shapes(),
+            custom_kernel: self.kernel().is_some(),
+            version: self.version(),
+        }
+    }
+
+    /// Modification time, in nanoseconds, of the image's `config.json`, which
+    /// every promotion writes anew and rollbacks swap along with the rest of
+    /// the image; images without one fall back to their rootfs.
+    pub fn version(&self) -> Option<String> {
+        [self.dir.join("config.json"), self.rootfs()]
+            .iter()
+            .find_map(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok())
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|d| d.as_nanos().to_string())
+    }
+}
+
+/// Shape of the snapshot in `dir`, if it holds a usable one.
+fn snapshot_shape(dir: &Path) -> Option<VmShape> {
+    if !dir.join("memory.snap").exists() {
+        return None;
+    }
+    ImageConfig::load(dir).ok().map(|c| c.shape())
+}
+
+/// Parse a shape directory name such as `4x8192`.
+fn parse_shape(name: &str) -> Option<VmShape> {
+    let (cpus, mem_mib) = name.split_once('x')?;
+    Some(VmShape {
+        cpus: cpus.parse().ok()?,
+        mem_mib: mem_mib.parse().ok()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::tempdir;
+
+    fn install(root: &Path, name: &str, config: Option<&str>) {
+        let dir = root.join(name);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("rootfs.ext4"), b"rootfs").unwrap();
+        if let Some(config) = config {
+            std::fs::write(dir.join("memory.snap"), b"mem").unwrap();
+            std::fs::write(dir.join("vmstate.snap"), b"state").unwrap();
+            std::fs::write(dir.join("config.json"), config).unwrap();
+        }
+    }
+
+    #[test]
+    fn open_missing_image_is_not_found() {
+        let root = tempdir("images-missing");
+        let err = Image::open_in(&root, "node20").unwrap_err();
+        assert_eq!(err.to_string(), "image 'node20' not found");
+        assert!(Image::open_in(&root, "../etc").is_err());
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn list_skips_incomplete_images() {
+        let root = tempdir("images-list");
+        install(&root, "python", None);
+        install(&root, "node20", Some(r#"{"cpus": 2, "mem_mib": 4096}"#));
+        std::fs::create_dir_all(root.join("empty")).unwrap();
+        let names: Vec<_> = Image::list_in(&root)
+            .unwrap()
+            .into_iter()
+            .map(|i| i.name.unwrap())
+            .collect();
+        assert_eq!(names, vec!["node20", "python"]);
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    fn shape(cpus: u32, mem_mib: u32) -> VmShape {
+        VmShape { cpus, mem_mib }
+    }
+
+    #[test]
+    fn snapshot_dir_requires_exact_shape() {
+        let root = tempdir("images-shape");
+        install(&root, "node20", Some(r#"{"cpus": 2, "mem_mib": 4096}"#));
+        install(&root, "cold", None);
+        let node = Image::open_in(&root, "node20").unwrap();
+        assert_eq!(node.snapshot_dir(shape(2, 4096)), Some(node.dir.clone()));
+        assert_eq!(node.snapshot_dir(shape(1, 4096)), None);
+        let info = node.info();
+        assert_eq!(info.shapes, vec![shape(2, 4096)]);
+        assert!(!info.custom_kernel);
+
+        let cold = Image::open_in(&root, "cold").unwrap();
+        assert_eq!(cold.snapshot_dir(shape(1, 2048)), None);
+        assert!(cold.info().shapes.is_empty());
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn snapshot_dir_uses_shape_pool() {
+        let root = tempdir("images-pool");
+        install(&root, "node20", Some(r#"{"cpus": 1, "mem_mib": 2048}"#));
+        let node = Image::open_in(&root, "node20").unwrap();
+        let big = node.shape_dir(shape(4, 8192));
+        std::fs::create_dir_all(&big).unwrap();
+        std::fs::write(big.join("memory.snap"), b"mem").unwrap();
+        std::fs::write(big.join("config.json"), r#"{"cpus": 4, "mem_mib": 8192}"#).unwrap();
+        // A half-built shape (staging dir) is never offered
+        let staging = node.dir.join("shapes").join("2x4096.tmp");
+        std::fs::create_dir_all(&staging).unwrap();
+        std::fs::write(staging.join("memory.snap"), b"mem").unwrap();
+        std::fs::write(
+            staging.join("config.json"),
+            r#"{"cpus": 2, "mem_mib": 4096}"#,
+        )
+        .unwrap();
+
+        assert_eq!(node.snapshot_dir(shape(4, 8192)), Some(big));
+        assert_eq!(node.snapshot_dir(shape(2, 4096)), None);
+        assert_eq!(node.shapes(), vec![shape(1, 2048), shape(4, 8192)]);
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn parse_shape_dir_names() {
+        assert_eq!(parse_shape("4x8192"), Some(shape(4, 8192)));
+        assert_eq!(parse_shape("2x4096.tmp"), None);
+        assert_eq!(parse_shape("big"), None);
+    }
+}
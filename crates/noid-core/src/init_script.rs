@@ -0,0 +1,217 @@
+//! Init scripts (`user_data`): a shell script copied into the guest over the
+//! serial console once it is reachable and run in the background there. When
+//! it exits the guest prints a notice to its console; the server watches the
+//! serial log for it, then asks the guest for the exit status and keeps the
+//! script's output next to the VM's disk.
+
+use base64::Engine;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::vm;
+
+pub const STATE_PENDING: &str = "pending";
+pub const STATE_RUNNING: &str = "running";
+pub const STATE_SUCCEEDED: &str = "succeeded";
+pub const STATE_FAILED: &str = "failed";
+pub const STATE_TIMED_OUT: &str = "timed-out";
+
+/// How long a script may run before it is killed and marked timed out.
+pub const TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+/// How long to wait for a new VM's guest to answer on the serial console.
+pub const READY_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// How often the guest is probed while waiting for it to become reachable,
+/// and the first delay between looks at the serial log.
+pub const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Longest delay between looks at the serial log.
+const MAX_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Guest directory holding the script, its pid, output and exit status.
+/// Cleared before every run.
+const GUEST_DIR: &str = "/tmp/noid-init";
+
+/// Base64 characters copied per serial command, well under the tty's line
+/// length limit.
+const CHUNK: usize = 2048;
+
+/// What the poll command prints when the guest has no trace of the script,
+/// e.g. because the VM was rebooted.
+const LOST: &str = "lost";
+
+/// What the guest prints to its console once the script has exited. The
+/// launch command spells it in two parts, so its echo doesn't match.
+const DONE_NOTICE: &str = "NOID_INIT_DONE";
+
+/// The script as given on create or restore, kept for runs that resume
+/// after a server restart.
+pub fn user_data_path(vm_dir: &Path) -> PathBuf {
+    vm_dir.join("user-data")
+}
+
+/// Output of the last finished run.
+pub fn log_path(vm_dir: &Path) -> PathBuf {
+    vm_dir.join("init.log")
+}
+
+fn sh(script: &str, args: &[&str]) -> Vec<String> {
+    let mut command = vec!["sh".to_string(), "-c".to_string(), script.to_string()];
+    command.push("sh".to_string());
+    command.extend(args.iter().map(|a| a.to_string()));
+    command
+}
+
+/// Guest commands that copy `script` in and start it in the background,
+/// to be run in order. Each must exit 0.
+pub fn launch_commands(script: &str) -> Vec<Vec<String>> {
+    let dir = GUEST_DIR;
+    let mut commands = vec![sh(r#"rm -rf "$1" && mkdir -p "$1""#, &[dir])];
+    let encoded = base64::engine::general_purpose::STANDARD.encode(script);
+    for chunk in encoded.as_bytes().chunks(CHUNK) {
+        let chunk = std::str::from_utf8(chunk).expect("base64 is ASCII");
+        commands.push(sh(r#"printf %s "$1" >> "$2/script.b64""#, &[chunk, dir]));
+    }
+    // The subshell outlives this command; the pid file is what the poll
+    // checks to tell a running script from one lost to a reboot.
+    commands.push(sh(
+        r#"d="$1"; base64 -d "$d/script.b64" > "$d/script" && chmod 700 "$d/script" || exit 1; ( "$d/script" < /dev/null > "$d/output" 2>&1 & echo $! > "$d/pid"; wait $!; echo $? > "$d/exit.tmp"; mv "$d/exit.tmp" "$d/exit"; printf '%s%s\n' NOID_INIT_ DONE > /dev/console ) > /dev/null 2>&1 &"#,
+        &[dir],
+    ));
+    commands
+}
+
+/// Guest command printing the script's exit status once it has exited.
+pub fn poll_command() -> Vec<String> {
+    sh(
+        &format!(r#"cat "$1/exit" 2>/dev/null || test -e "$1/pid" || echo {LOST}"#),
+        &[GUEST_DIR],
+    )
+}
+
+/// Guest command printing the script's output so far.
+pub fn output_command() -> Vec<String> {
+    sh(r#"cat "$1/output" 2>/dev/null"#, &[GUEST_DIR])
+}
+
+/// Guest command killing a script that ran too long.
+pub fn kill_command() -> Vec<String> {
+    sh(r#"kill "$(cat "$1/pid")" 2>/dev/null; true"#, &[GUEST_DIR])
+}
+
+/// What the output of `poll_command` says about the script.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Poll {
+    Running,
+    Exited(i32),
+    /// The guest has no record of the script.
+    Lost,
+}
+
+pub fn parse_poll(stdout: &str) -> Poll {
+    let out = stdout.trim();
+    if out == LOST {
+        return Poll::Lost;
+    }
+    out.parse().map_or(Poll::Running, Poll::Exited)
+}
+
+/// Delay before the `n`th look at the serial log: doubling from
+/// `POLL_INTERVAL` up to `MAX_POLL_INTERVAL`.
+pub fn poll_delay(n: u32) -> Duration {
+    POLL_INTERVAL
+        .saturating_mul(1 << n.min(16))
+        .min(MAX_POLL_INTERVAL)
+}
+
+/// Length of the serial log of the VM in `vm_dir`: where to start watching
+/// for the done notice.
+pub fn serial_pos(vm_dir: &Path) -> u64 {
+    std::fs::metadata(vm::serial_log_path(vm_dir)).map_or(0, |m| m.len())
+}
+
+/// Whether the serial log of the VM in `vm_dir` shows the done notice after
+/// byte `pos`, which is moved on past what was read.
+pub fn reported_done(vm_dir: &Path, pos: &mut u64) -> bool {
+    let Ok(mut file) = std::fs::File::open(vm::serial_log_path(vm_dir)) else {
+        return false;
+    };
+    let len = file.metadata().map_or(0, |m| m.len());
+    if len < *pos {
+        // The log was started over.
+        *pos = 0;
+    }
+    let mut tail = Vec::new();
+    if file.seek(SeekFrom::Start(*pos)).is_err() || file.read_to_end(&mut tail).is_err() {
+        return false;
+    }
+    // Read the end again next time, in case the notice is being written.
+    *pos += tail.len().saturating_sub(DONE_NOTICE.len()) as u64;
+    tail.windows(DONE_NOTICE.len())
+        .any(|w| w == DONE_NOTICE.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn launch_commands_chunk_the_script() {
+        let script = "#!/bin/sh\necho 'hello world'\n".repeat(200);
+        let commands = launch_commands(&script);
+        let encoded: String = commands[1..commands.len() - 1]
+            .iter()
+            .map(|c| {
+                assert!(c[4].len() <= CHUNK);
+                c[4].as_str()
+            })
+            .collect();
+        let decoded = base64::engine::general_purpose::STANDARD
+            .decode(encoded)
+            .unwrap();
+        assert_eq!(decoded, script.as_bytes());
+        // Nothing multi-line goes over the serial console.
+        assert!(commands.iter().flatten().all(|arg| !arg.contains('\n')));
+    }
+
+    #[test]
+    fn done_notice_is_read_from_the_serial_log() {
+        let dir = crate::test_util::tempdir("init");
+        let log = vm::serial_log_path(&dir);
+        // The launch command's echo on the console is not the notice.
+        let launch = launch_commands("true").concat().join(" ");
+        assert!(!launch.contains(DONE_NOTICE));
+        std::fs::write(&log, format!("NOID_INIT_DONE\r\n$ {launch}\r\n")).unwrap();
+
+        let mut pos = serial_pos(&dir);
+        assert!(!reported_done(&dir, &mut pos));
+        let mut f = std::fs::OpenOptions::new().append(true).open(&log).unwrap();
+        f.write_all(b"login: NOID_INIT_").unwrap();
+        assert!(!reported_done(&dir, &mut pos));
+        f.write_all(b"DONE\r\n").unwrap();
+        assert!(reported_done(&dir, &mut pos));
+        // A VM restart starts the log over.
+        std::fs::write(&log, b"NOID_INIT_DONE\r\n").unwrap();
+        assert!(reported_done(&dir, &mut pos));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn poll_delay_backs_off() {
+        assert_eq!(poll_delay(0), POLL_INTERVAL);
+        assert_eq!(poll_delay(1), POLL_INTERVAL * 2);
+        assert_eq!(poll_delay(4), MAX_POLL_INTERVAL);
+        assert_eq!(poll_delay(u32::MAX), MAX_POLL_INTERVAL);
+    }
+
+    #[test]
+    fn poll_output() {
+        assert_eq!(parse_poll(""), Poll::Running);
+        assert_eq!(parse_poll("0\r\n"), Poll::Exited(0));
+        assert_eq!(parse_poll("127\n"), Poll::Exited(127));
+        assert_eq!(parse_poll("lost\n"), Poll::Lost);
+    }
+}
@@ -0,0 +1,173 @@
+//! Checkpoint integrity: checksums recorded at checkpoint time and verified
+//! before restore, so a partially written checkpoint fails with a clear error
+//! instead of an opaque Firecracker snapshot-load failure.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::Read;
+use std::path::Path;
+
+/// Files that make up a restorable checkpoint.
+pub const CHECKPOINT_FILES: &[&str] = &["memory.snap", "vmstate.snap", "rootfs.ext4"];
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileChecksum {
+    pub file: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+/// Compute size and SHA-256 for every checkpoint file in `dir`.
+pub fn compute_checksums(dir: &Path) -> Result<Vec<FileChecksum>> {
+    CHECKPOINT_FILES
+        .iter()
+        .map(|file| {
+            let path = dir.join(file);
+            let size = std::fs::metadata(&path)
+                .with_context(|| format!("failed to stat {}", path.display()))?
+                .len();
+            Ok(FileChecksum {
+                file: file.to_string(),
+                size,
+                sha256: sha256_file(&path)?,
+            })
+        })
+        .collect()
+}
+
+/// Check the checkpoint files in `dir`. Always checks that each file exists
+/// and is non-empty; when `expected` checksums are available, also compares
+/// size and SHA-256. Returns a list of problems (empty means intact).
+pub fn verify_checksums(dir: &Path, expected: Option<&[FileChecksum]>) -> Result<Vec<String>> {
+    check(dir, expected, true)
+}
+
+/// Like [`verify_checksums`], but only checks presence and sizes, without
+/// reading the files. Catches truncated copies, the common failure.
+pub fn verify_sizes(dir: &Path, expected: Option<&[FileChecksum]>) -> Result<Vec<String>> {
+    check(dir, expected, false)
+}
+
+fn check(dir: &Path, expected: Option<&[FileChecksum]>, hash: bool) -> Result<Vec<String>> {
+    let mut problems = Vec::new();
+    for file in CHECKPOINT_FILES {
+        let path = dir.join(file);
+        let size = match std::fs::metadata(&path) {
+            Ok(m) => m.len(),
+            Err(_) => {
+                problems.push(format!("{file}: missing"));
+                continue;
+            }
+        };
+        if size == 0 {
+            problems.push(format!("{file}: empty"));
+            continue;
+        }
+        let Some(want) = expected.and_then(|e| e.iter().find(|c| c.file == *file)) else {
+            continue;
+        };
+        // Size check first: a truncated copy is the common failure and
+        // is detected without reading the whole file.
+        if size != want.size {
+            problems.push(format!(
+                "{file}: size mismatch (expected {} bytes, found {size})",
+                want.size
+            ));
+            continue;
+        }
+        if hash && sha256_file(&path)? != want.sha256 {
+            problems.push(format!("{file}: checksum mismatch"));
+        }
+    }
+    Ok(problems)
+}
+
+fn sha256_file(path: &Path) -> Result<String> {
+    let mut f =
+        std::fs::File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 1024 * 1024];
+    loop {
+        let n = f
+            .read(&mut buf)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(crate::auth::hex_encode(&hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::tempdir;
+
+    fn make_checkpoint(tag: &str) -> std::path::PathBuf {
+        let dir = tempdir(&format!("integrity-{tag}"));
+        std::fs::write(dir.join("memory.snap"), b"memory contents").unwrap();
+        std::fs::write(dir.join("vmstate.snap"), b"vmstate").unwrap();
+        std::fs::write(dir.join("rootfs.ext4"), b"rootfs data").unwrap();
+        dir
+    }
+
+    #[test]
+    fn verify_accepts_intact_checkpoint() {
+        let dir = make_checkpoint("intact");
+        let sums = compute_checksums(&dir).unwrap();
+        assert_eq!(sums.len(), CHECKPOINT_FILES.len());
+        assert!(verify_checksums(&dir, Some(&sums)).unwrap().is_empty());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn verify_detects_truncation() {
+        let dir = make_checkpoint("truncated");
+        let sums = compute_checksums(&dir).unwrap();
+        std::fs::write(dir.join("memory.snap"), b"memory").unwrap();
+        let problems = verify_checksums(&dir, Some(&sums)).unwrap();
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("memory.snap: size mismatch"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn verify_detects_modified_content() {
+        let dir = make_checkpoint("modified");
+        let sums = compute_checksums(&dir).unwrap();
+        std::fs::write(dir.join("vmstate.snap"), b"VMSTATE").unwrap();
+        let problems = verify_checksums(&dir, Some(&sums)).unwrap();
+        assert_eq!(
+            problems,
+            vec!["vmstate.snap: checksum mismatch".to_string()]
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn size_check_skips_hashing() {
+        let dir = make_checkpoint("sizes");
+        let sums = compute_checksums(&dir).unwrap();
+        std::fs::write(dir.join("vmstate.snap"), b"VMSTATE").unwrap();
+        assert!(verify_sizes(&dir, Some(&sums)).unwrap().is_empty());
+        std::fs::write(dir.join("memory.snap"), b"memory").unwrap();
+        let problems = verify_sizes(&dir, Some(&sums)).unwrap();
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("memory.snap: size mismatch"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn verify_without_checksums_checks_presence() {
+        let dir = make_checkpoint("legacy");
+        assert!(verify_checksums(&dir, None).unwrap().is_empty());
+        std::fs::remove_file(dir.join("rootfs.ext4")).unwrap();
+        std::fs::write(dir.join("memory.snap"), b"").unwrap();
+        let problems = verify_checksums(&dir, None).unwrap();
+        assert!(problems.contains(&"rootfs.ext4: missing".to_string()));
+        assert!(problems.contains(&"memory.snap: empty".to_string()));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,410 @@
+//! Running Firecracker under its `jailer`.
+//!
+//! Each jailed VM gets a chroot at `<storage>/jails/firecracker/<id>/root`,
+//! runs as its user's UID/GID inside a cgroup sized from the VM's shape, and
+//! optionally in its own network namespace. The VM directory (and any other
+//! host path Firecracker opens) is bind-mounted into the chroot at the same
+//! path it has on the host, so socket, drive and snapshot paths given to
+//! Firecracker mean the same thing inside and outside the jail.
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Where cgroup v2 is mounted.
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// Memory a VM's cgroup allows beyond the guest's own, for Firecracker
+/// itself.
+const MEMORY_OVERHEAD_MIB: u64 = 128;
+
+/// CFS period the CPU quota is expressed in.
+const CPU_PERIOD_US: u64 = 100_000;
+
+/// `[jailer]` in the server config. Its presence turns the jailer on.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JailerConfig {
+    /// The `jailer` binary shipped with Firecracker.
+    #[serde(default = "default_binary")]
+    pub binary: PathBuf,
+    /// First UID (and GID) used for jailed VMs. Internal VMs run as
+    /// `uid_base`; each user gets `uid_base + 1 + n`.
+    #[serde(default = "default_uid_base")]
+    pub uid_base: u32,
+    /// Cgroup, below the cgroup v2 root, that VM cgroups are created in.
+    #[serde(default = "default_parent_cgroup")]
+    pub parent_cgroup: String,
+    /// Give each VM its own network namespace.
+    #[serde(default = "default_netns")]
+    pub netns: bool,
+}
+
+fn default_binary() -> PathBuf {
+    PathBuf::from("/usr/local/bin/jailer")
+}
+
+fn default_uid_base() -> u32 {
+    200_000
+}
+
+fn default_parent_cgroup() -> String {
+    "noid".to_string()
+}
+
+fn default_netns() -> bool {
+    true
+}
+
+impl Default for JailerConfig {
+    fn default() -> Self {
+        Self {
+            binary: default_binary(),
+            uid_base: default_uid_base(),
+            parent_cgroup: default_parent_cgroup(),
+            netns: default_netns(),
+        }
+    }
+}
+
+/// How one VM process is jailed.
+#[derive(Debug, Clone)]
+pub struct JailSpec {
+    pub config: JailerConfig,
+    /// UID and GID the VM runs as.
+    pub uid: u32,
+    pub cpus: u32,
+    pub mem_mib: u32,
+    /// Network namespace to run in, e.g. `/var/run/netns/noid3`.
+    pub netns: Option<String>,
+    /// Host paths Firecracker opens outside the VM directory, such as the
+    /// kernel.
+    pub expose: Vec<PathBuf>,
+}
+
+impl JailSpec {
+    /// `--cgroup` values limiting the VM to its shape.
+    fn cgroup_limits(&self) -> Vec<String> {
+        let cpus = u64::from(self.cpus.max(1));
+        let memory = (u64::from(self.mem_mib) + MEMORY_OVERHEAD_MIB) * 1024 * 1024;
+        vec![
+            format!("cpu.max={} {CPU_PERIOD_US}", cpus * CPU_PERIOD_US),
+            format!("memory.max={memory}"),
+            format!("io.weight={}", (cpus * 100).min(10_000)),
+        ]
+    }
+
+    /// Jailer arguments running `exec_file` as jail `id`, up to (not
+    /// including) the `--` before Firecracker's own arguments.
+    pub fn jailer_args(&self, id: &str, exec_file: &str) -> Vec<String> {
+        let mut args = vec![
+            "--id".to_string(),
+            id.to_string(),
+            "--exec-file".to_string(),
+            exec_file.to_string(),
+            "--uid".to_string(),
+            self.uid.to_string(),
+            "--gid".to_string(),
+            self.uid.to_string(),
+            "--chroot-base-dir".to_string(),
+            chroot_base().to_string_lossy().to_string(),
+            "--cgroup-version".to_string(),
+            "2".to_string(),
+            "--parent-cgroup".to_string(),
+            self.config.parent_cgroup.clone(),
+        ];
+        for limit in self.cgroup_limits() {
+            args.push("--cgroup".to_string());
+            args.push(limit);
+        }
+        if let Some(ref netns) = self.netns {
+            args.push("--netns".to_string());
+            args.push(netns.clone());
+        }
+        args
+    }
+}
+
+/// Check that VMs can be jailed with `config` on this host.
+pub fn check_available(config: &JailerConfig, firecracker: &Path) -> Result<()> {
+    if !nix::unistd::geteuid().is_root() {
+        bail!("the jailer needs noid-server to run as root");
+    }
+    if !config.binary.exists() {
+        bail!("jailer not found: {}", config.binary.display());
+    }
+    if !firecracker.exists() {
+        bail!("firecracker not found: {}", firecracker.display());
+    }
+    if !Path::new(CGROUP_ROOT).join("cgroup.controllers").exists() {
+        bail!("the jailer needs cgroup v2 mounted at {CGROUP_ROOT}");
+    }
+    Ok(())
+}
+
+/// Directory the jailer creates chroots in.
+pub fn chroot_base() -> PathBuf {
+    crate::storage::storage_dir().join("jails")
+}
+
+/// The jailer keeps the chroots of `exec_file` in a directory named after it.
+fn exec_dir(exec_file: &Path) -> PathBuf {
+    chroot_base().join(exec_file.file_name().unwrap_or_default())
+}
+
+/// A jail set up for one Firecracker process.
+#[derive(Debug)]
+pub struct Jail {
+    pub id: String,
+    /// `<chroot base>/<exec file>/<id>`, holding the chroot.
+    dir: PathBuf,
+    cgroup: Option<PathBuf>,
+}
+
+impl Jail {
+    /// Create the chroot for a new jail of `exec_file` and mirror `subvol`
+    /// and the spec's other paths into it. `subvol` is handed to the VM's
+    /// UID.
+    pub fn prepare(spec: &JailSpec, exec_file: &Path, subvol: &Path) -> Result<Self> {
+        let id = uuid::Uuid::new_v4().simple().to_string();
+        let jail = Self {
+            dir: exec_dir(exec_file).join(&id),
+            cgroup: Some(
+                Path::new(CGROUP_ROOT)
+                    .join(&spec.config.parent_cgroup)
+                    .join(&id),
+            ),
+            id,
+        };
+        let result = (|| -> Result<()> {
+            std::fs::create_dir_all(jail.root())?;
+            chown_tree(subvol, spec.uid)?;
+            for path in std::iter::once(subvol).chain(spec.expose.iter().map(PathBuf::as_path)) {
+                jail.bind(path)?;
+            }
+            Ok(())
+        })();
+        if let Err(e) = result {
+            jail.cleanup();
+            return Err(e.context("failed to prepare jail"));
+        }
+        Ok(jail)
+    }
+
+    /// The jail of running process `pid`, if it was jailed by noid.
+    pub fn of_process(pid: u32) -> Option<Self> {
+        let root = std::fs::read_link(format!("/proc/{pid}/root")).ok()?;
+        let dir = root.parent()?;
+        if root.file_name()? != "root" || !dir.starts_with(chroot_base()) {
+            return None;
+        }
+        let id = dir.file_name()?.to_string_lossy().to_string();
+        let cgroup = std::fs::read_to_string(format!("/proc/{pid}/cgroup"))
+            .ok()
+            .and_then(|c| cgroup_path(&c));
+        Some(Self {
+            id,
+            dir: dir.to_path_buf(),
+            cgroup,
+        })
+    }
+
+    /// The chroot directory.
+    pub fn root(&self) -> PathBuf {
+        self.dir.join("root")
+    }
+
+    /// Where host path `path` is inside the chroot, seen from the host.
+    pub fn host_path(&self, path: &Path) -> PathBuf {
+        self.root().join(path.strip_prefix("/").unwrap_or(path))
+    }
+
+    /// Bind-mount host path `path` at the same path inside the chroot.
+    fn bind(&self, path: &Path) -> Result<()> {
+        let target = self.host_path(path);
+        if path.is_dir() {
+            std::fs::create_dir_all(&target)?;
+        } else {
+            if let Some(parent) = target.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::File::create(&target)?;
+        }
+        nix::mount::mount(
+            Some(path),
+            &target,
+            None::<&str>,
+            nix::mount::MsFlags::MS_BIND,
+            None::<&str>,
+        )
+        .with_context(|| format!("failed to bind-mount {} into the jail", path.display()))
+    }
+
+    /// Unmount what is mounted in the jail, then remove it and its cgroup.
+    /// The jail directory is kept if anything is still mounted in it, so a
+    /// VM's files are never deleted through it.
+    pub fn cleanup(&self) {
+        let mut mounts = mounts_under(&self.dir);
+        // Deepest first
+        mounts.sort_by_key(|m| std::cmp::Reverse(m.components().count()));
+        for mount in &mounts {
+            let _ = nix::mount::umount2(mount, nix::mount::MntFlags::MNT_DETACH);
+        }
+        if mounts_under(&self.dir).is_empty() {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        } else {
+            eprintln!("warning: jail {} is still mounted, keeping it", self.id);
+        }
+        if let Some(ref cgroup) = self.cgroup {
+            let _ = std::fs::remove_dir(cgroup);
+        }
+    }
+}
+
+/// Remove jails of `exec_file` whose process is gone, left behind by a
+/// server restart.
+pub fn sweep(config: &JailerConfig, exec_file: &Path) {
+    let Ok(entries) = std::fs::read_dir(exec_dir(exec_file)) else {
+        return;
+    };
+    let live: Vec<PathBuf> = std::fs::read_dir("/proc")
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|e| std::fs::read_link(e.path().join("root")).ok())
+        .collect();
+    for entry in entries.flatten() {
+        let jail_dir = entry.path();
+        if live.contains(&jail_dir.join("root")) {
+            continue;
+        }
+        let id = entry.file_name().to_string_lossy().to_string();
+        eprintln!("removing stale jail {id}");
+        Jail {
+            cgroup: Some(Path::new(CGROUP_ROOT).join(&config.parent_cgroup).join(&id)),
+            id,
+            dir: jail_dir,
+        }
+        .cleanup();
+    }
+}
+
+/// Give `path` and everything below it to `uid` (as UID and GID).
+fn chown_tree(path: &Path, uid: u32) -> Result<()> {
+    std::os::unix::fs::lchown(path, Some(uid), Some(uid))
+        .with_context(|| format!("failed to chown {}", path.display()))?;
+    if path.is_dir() && !path.is_symlink() {
+        for entry in std::fs::read_dir(path)? {
+            chown_tree(&entry?.path(), uid)?;
+        }
+    }
+    Ok(())
+}
+
+/// The cgroup directory of a process, from its `/proc/<pid>/cgroup`.
+fn cgroup_path(proc_cgroup: &str) -> Option<PathBuf> {
+    let path = proc_cgroup
+        .lines()
+        .find_map(|l| l.strip_prefix("0::"))?
+        .trim_start_matches('/');
+    Some(Path::new(CGROUP_ROOT).join(path))
+}
+
+/// Mount points at or below `dir`, from `/proc/self/mountinfo`.
+fn mounts_under(dir: &Path) -> Vec<PathBuf> {
+    let mountinfo = std::fs::read_to_string("/proc/self/mountinfo").unwrap_or_default();
+    parse_mount_points(&mountinfo)
+        .into_iter()
+        .filter(|m| m.starts_with(dir))
+        .collect()
+}
+
+/// Mount points (the fifth field) of a mountinfo table, with its octal
+/// escapes decoded.
+fn parse_mount_points(mountinfo: &str) -> Vec<PathBuf> {
+    mountinfo
+        .lines()
+        .filter_map(|l| l.split(' ').nth(4))
+        .map(|m| {
+            PathBuf::from(
+                m.replace("\\040", " ")
+                    .replace("\\011", "\t")
+                    .replace("\\012", "\n")
+                    .replace("\\134", "\\"),
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(cpus: u32, mem_mib: u32, netns: Option<&str>) -> JailSpec {
+        JailSpec {
+            config: JailerConfig::default(),
+            uid: 200_003,
+            cpus,
+            mem_mib,
+            netns: netns.map(str::to_string),
+            expose: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn cgroup_limits_follow_the_shape() {
+        assert_eq!(
+            spec(2, 1024, None).cgroup_limits(),
+            vec![
+                "cpu.max=200000 100000".to_string(),
+                format!("memory.max={}", 1152 * 1024 * 1024),
+                "io.weight=200".to_string(),
+            ]
+        );
+        assert_eq!(spec(128, 256, None).cgroup_limits()[2], "io.weight=10000");
+    }
+
+    #[test]
+    fn jailer_args() {
+        let args = spec(1, 512, Some("/var/run/netns/noid3"))
+            .jailer_args("abc", "/usr/local/bin/firecracker");
+        let value = |flag: &str| {
+            let i = args.iter().position(|a| a == flag).unwrap();
+            args[i + 1].clone()
+        };
+        assert_eq!(value("--id"), "abc");
+        assert_eq!(value("--exec-file"), "/usr/local/bin/firecracker");
+        assert_eq!(value("--uid"), "200003");
+        assert_eq!(value("--gid"), "200003");
+        assert_eq!(value("--cgroup-version"), "2");
+        assert_eq!(value("--parent-cgroup"), "noid");
+        assert_eq!(value("--netns"), "/var/run/netns/noid3");
+        assert_eq!(args.iter().filter(|a| *a == "--cgroup").count(), 3);
+
+        let args = spec(1, 512, None).jailer_args("abc", "/usr/local/bin/firecracker");
+        assert!(!args.contains(&"--netns".to_string()));
+    }
+
+    #[test]
+    fn cgroup_path_is_read_from_the_unified_hierarchy() {
+        assert_eq!(
+            cgroup_path("0::/noid/abc\n"),
+            Some(PathBuf::from("/sys/fs/cgroup/noid/abc"))
+        );
+        assert_eq!(cgroup_path("1:name=systemd:/x\n"), None);
+    }
+
+    #[test]
+    fn mount_points_are_unescaped() {
+        let mountinfo = "\
+22 1 0:21 / /proc rw,nosuid - proc proc rw
+90 22 0:40 /vms/a /home/x/.noid/storage/jails/firecracker/id/root/my\\040vm rw - btrfs /dev/loop0 rw
+";
+        assert_eq!(
+            parse_mount_points(mountinfo),
+            vec![
+                PathBuf::from("/proc"),
+                PathBuf::from("/home/x/.noid/storage/jails/firecracker/id/root/my vm"),
+            ]
+        );
+    }
+}
@@ -1,8 +1,23 @@
 pub mod auth;
 pub mod backend;
+pub mod boot;
 pub mod config;
 pub mod db;
 pub mod exec;
+pub mod firecracker;
+pub mod golden;
+pub mod idle;
+pub mod image;
+pub mod init_script;
+pub mod integrity;
+pub mod jail;
+pub mod lockfile;
 pub mod network;
+pub mod pool;
+pub mod reconcile;
+pub mod registry;
 pub mod storage;
+#[cfg(test)]
+mod test_util;
 pub mod vm;
+pub mod watchdog;
@@ -0,0 +1,84 @@
+//! Advisory locks on files, held across processes (`flock`). A lock goes
+//! away with the process holding it, so a crash never leaves one behind.
+
+use anyhow::{Context, Result};
+use nix::errno::Errno;
+use nix::fcntl::{Flock, FlockArg};
+use std::fs::File;
+use std::io::{Read, Seek, Write};
+use std::path::Path;
+
+/// An exclusive lock on a file, released on drop.
+#[derive(Debug)]
+pub struct LockFile(Flock<File>);
+
+fn open(path: &Path) -> Result<File> {
+    std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+        .with_context(|| format!("failed to open lock file {}", path.display()))
+}
+
+impl LockFile {
+    /// Lock `path`, creating it if needed, and wait for any other holder.
+    pub fn lock(path: &Path) -> Result<Self> {
+        Flock::lock(open(path)?, FlockArg::LockExclusive)
+            .map(Self)
+            .map_err(|(_, e)| e)
+            .with_context(|| format!("failed to lock {}", path.display()))
+    }
+
+    /// Lock `path`, creating it if needed, unless another process holds it.
+    pub fn try_lock(path: &Path) -> Result<Option<Self>> {
+        match Flock::lock(open(path)?, FlockArg::LockExclusiveNonblock) {
+            Ok(lock) => Ok(Some(Self(lock))),
+            Err((_, Errno::EWOULDBLOCK)) => Ok(None),
+            Err((_, e)) => Err(e).with_context(|| format!("failed to lock {}", path.display())),
+        }
+    }
+
+    /// Replace the file's contents, e.g. with the holder's pid.
+    pub fn write(&mut self, contents: &str) -> Result<()> {
+        let file: &mut File = &mut self.0;
+        file.set_len(0)?;
+        file.rewind()?;
+        file.write_all(contents.as_bytes())?;
+        Ok(())
+    }
+}
+
+/// What the holder of the lock on `path` wrote into it, if it is held.
+pub fn holder(path: &Path) -> Result<Option<String>> {
+    if !path.exists() || LockFile::try_lock(path)?.is_some() {
+        return Ok(None);
+    }
+    let mut contents = String::new();
+    File::open(path)?.read_to_string(&mut contents)?;
+    Ok(Some(contents.trim().to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_lock_is_held_until_dropped() {
+        let path = crate::test_util::tempdir("lock").join("lock");
+        assert_eq!(holder(&path).unwrap(), None);
+
+        let mut lock = LockFile::try_lock(&path).unwrap().unwrap();
+        lock.write("1234\n").unwrap();
+        // flock locks belong to the open file, so a second open conflicts
+        // even within one process.
+        assert!(LockFile::try_lock(&path).unwrap().is_none());
+        assert_eq!(holder(&path).unwrap().as_deref(), Some("1234"));
+
+        drop(lock);
+        assert!(LockFile::try_lock(&path).unwrap().is_some());
+        assert_eq!(holder(&path).unwrap(), None);
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -15,11 +15,27 @@ pub struct NetworkConfig {
     pub guest_ip: String,
     pub guest_mac: String,
     pub index: u32,
+    /// Network namespace holding the TAP device, for jailed VMs.
+    pub netns: Option<String>,
+}
+
+/// What a jailed VM's network needs beyond a plain TAP device.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NetworkIsolation {
+    /// UID (and GID) allowed to open the TAP device.
+    pub owner: Option<u32>,
+    /// Put the TAP device in a network namespace of its own.
+    pub netns: bool,
 }
 
 /// Ask noid-netd to set up a TAP device for the given index.
-pub fn setup_vm_network(index: u32) -> Result<NetworkConfig> {
-    let request = serde_json::json!({ "op": "setup", "index": index });
+pub fn setup_vm_network(index: u32, isolation: NetworkIsolation) -> Result<NetworkConfig> {
+    let request = serde_json::json!({
+        "op": "setup",
+        "index": index,
+        "owner": isolation.owner,
+        "netns": isolation.netns,
+    });
     let response = netd_request(&request).context("failed to setup VM network via noid-netd")?;
 
     if response.get("ok") != Some(&serde_json::Value::Bool(true)) {
@@ -47,6 +63,7 @@ pub fn setup_vm_network(index: u32) -> Result<NetworkConfig> {
             .context("missing guest_mac in response")?
             .to_string(),
         index,
+        netns: response["netns"].as_str().map(str::to_string),
     })
 }
 
@@ -65,6 +82,27 @@ pub fn teardown_vm_network(tap_name: &str) -> Result<()> {
     Ok(())
 }
 
+/// Ask noid-netd which noid TAP devices exist on the host.
+pub fn active_taps() -> Result<Vec<String>> {
+    let request = serde_json::json!({ "op": "status" });
+    let response = netd_request(&request).context("failed to list TAPs via noid-netd")?;
+
+    if response.get("ok") != Some(&serde_json::Value::Bool(true)) {
+        let err = response["error"]
+            .as_str()
+            .unwrap_or("unknown error from noid-netd");
+        bail!("noid-netd status failed: {err}");
+    }
+
+    let active = response["active"]
+        .as_array()
+        .context("missing active in response")?;
+    Ok(active
+        .iter()
+        .filter_map(|t| t.as_str().map(str::to_string))
+        .collect())
+}
+
 /// Find the lowest unused network index.
 /// Max 16384 VMs (172.16.0.0/16 divided into /30 subnets).
 const MAX_NET_INDEX: u32 = 16383;
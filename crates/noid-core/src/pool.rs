@@ -0,0 +1,277 @@
+//! Pool of pre-warmed VMs: already resumed from a golden snapshot with the
+//! guest network configured, so `create` only has to move the VM directory
+//! into the user's storage and record it.
+//!
+//! Warm VMs live under the `_pool` storage namespace and have no `vms` row.
+//! Each one leaves a `pool.json` in its directory so VMs orphaned by a server
+//! restart can be found and stopped at startup.
+
+use anyhow::{Context, Result};
+use noid_types::VmShape;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::network::NetworkConfig;
+use crate::{network, storage, vm};
+
+/// Storage namespace for warm VMs. Not a real user.
+pub const POOL_USER: &str = "_pool";
+
+/// One `[[warm_pool]]` entry in `server.toml`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PoolSpec {
+    /// Named image; the default golden image if unset.
+    #[serde(default)]
+    pub image: Option<String>,
+    #[serde(default = "default_cpus")]
+    pub cpus: u32,
+    #[serde(default = "default_mem_mib")]
+    pub mem_mib: u32,
+    /// Number of warm VMs to keep ready.
+    pub size: usize,
+}
+
+fn default_cpus() -> u32 {
+    1
+}
+
+fn default_mem_mib() -> u32 {
+    2048
+}
+
+impl PoolSpec {
+    pub fn key(&self) -> PoolKey {
+        PoolKey {
+            image: self.image.clone(),
+            shape: VmShape {
+                cpus: self.cpus,
+                mem_mib: self.mem_mib,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PoolKey {
+    pub image: Option<String>,
+    pub shape: VmShape,
+}
+
+/// A running VM waiting to be handed out.
+#[derive(Debug)]
+pub struct WarmVm {
+    /// Name under `POOL_USER`.
+    pub name: String,
+    pub pid: u32,
+    pub net_config: Option<NetworkConfig>,
+    pub kernel: String,
+    pub base_rootfs: String,
+    /// Version of the Firecracker it runs on, if known.
+    pub firecracker_version: Option<String>,
+}
+
+impl WarmVm {
+    pub fn dir(&self) -> PathBuf {
+        storage::vm_dir(POOL_USER, &self.name)
+    }
+}
+
+/// Contents of `pool.json`.
+#[derive(Debug, Serialize, Deserialize)]
+struct Marker {
+    pid: u32,
+    tap_name: Option<String>,
+}
+
+/// Record what is needed to stop a warm VM after a server restart.
+pub fn write_marker(warm: &WarmVm) -> Result<()> {
+    let marker = Marker {
+        pid: warm.pid,
+        tap_name: warm.net_config.as_ref().map(|nc| nc.tap_name.clone()),
+    };
+    let path = warm.dir().join("pool.json");
+    std::fs::write(&path, serde_json::to_string(&marker)?)
+        .with_context(|| format!("failed to write {}", path.display()))
+}
+
+/// Stop a warm VM and remove its storage.
+pub fn discard(driver: &dyn storage::StorageDriver, warm: &WarmVm) {
+    vm::kill_vm_process(warm.pid as i64);
+    if let Some(nc) = &warm.net_config {
+        let _ = network::teardown_vm_network(&nc.tap_name);
+    }
+    let _ = storage::delete_subvolume(driver, POOL_USER, &warm.name);
+}
+
+/// Stop warm VMs left behind by a previous server process.
+pub fn cleanup_stale(driver: &dyn storage::StorageDriver) {
+    let dir = storage::user_storage_dir(POOL_USER).join("vms");
+    let Ok(entries) = std::fs::read_dir(&dir) else {
+        return;
+    };
+    for entry in entries.filter_map(|e| e.ok()) {
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        if let Some(marker) = read_marker(&entry.path()) {
+            if vm::is_process_alive(marker.pid as i32) {
+                vm::kill_vm_process(marker.pid as i64);
+            }
+            if let Some(tap) = marker.tap_name {
+                let _ = network::teardown_vm_network(&tap);
+            }
+        }
+        if let Err(e) = storage::delete_subvolume(driver, POOL_USER, &name) {
+            eprintln!("warning: failed to remove stale warm VM {name}: {e:#}");
+        }
+    }
+}
+
+fn read_marker(dir: &Path) -> Option<Marker> {
+    let data = std::fs::read_to_string(dir.join("pool.json")).ok()?;
+    serde_json::from_str(&data).ok()
+}
+
+/// Warm VMs by key, plus the keys currently being refilled.
+#[derive(Default)]
+pub struct WarmPool {
+    specs: Vec<PoolSpec>,
+    ready: Mutex<HashMap<PoolKey, Vec<WarmVm>>>,
+    filling: Mutex<HashSet<PoolKey>>,
+}
+
+impl WarmPool {
+    pub fn new(specs: Vec<PoolSpec>) -> Self {
+        WarmPool {
+            specs: specs.into_iter().filter(|s| s.size > 0).collect(),
+            ..Default::default()
+        }
+    }
+
+    pub fn specs(&self) -> &[PoolSpec] {
+        &self.specs
+    }
+
+    pub fn is_pooled(&self, key: &PoolKey) -> bool {
+        self.specs.iter().any(|s| &s.key() == key)
+    }
+
+    /// Take the oldest warm VM for `key`, if any.
+    pub fn take(&self, key: &PoolKey) -> Option<WarmVm> {
+        let mut ready = self.ready.lock().unwrap_or_else(|e| e.into_inner());
+        let vms = ready.get_mut(key)?;
+        if vms.is_empty() {
+            None
+        } else {
+            Some(vms.remove(0))
+        }
+    }
+
+    pub fn add(&self, key: PoolKey, warm: WarmVm) {
+        self.ready
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(key)
+            .or_default()
+            .push(warm);
+    }
+
+    /// How many warm VMs `spec` is short of.
+    pub fn deficit(&self, spec: &PoolSpec) -> usize {
+        let ready = self.ready.lock().unwrap_or_else(|e| e.into_inner());
+        let have = ready.get(&spec.key()).map_or(0, |v| v.len());
+        spec.size.saturating_sub(have)
+    }
+
+    /// Claim the right to refill `key`. Returns false if another thread
+    /// already is.
+    pub fn start_filling(&self, key: &PoolKey) -> bool {
+        self.filling
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(key.clone())
+    }
+
+    pub fn done_filling(&self, key: &PoolKey) {
+        self.filling
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(key);
+    }
+
+    /// Network indices held by warm VMs.
+    pub fn net_indices(&self) -> Vec<u32> {
+        self.ready
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .values()
+            .flatten()
+            .filter_map(|w| w.net_config.as_ref().map(|nc| nc.index))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn warm(name: &str) -> WarmVm {
+        WarmVm {
+            name: name.into(),
+            pid: 0,
+            net_config: None,
+            kernel: "/vmlinux".into(),
+            base_rootfs: "/rootfs.ext4".into(),
+            firecracker_version: None,
+        }
+    }
+
+    fn spec(size: usize) -> PoolSpec {
+        PoolSpec {
+            image: None,
+            cpus: 2,
+            mem_mib: 4096,
+            size,
+        }
+    }
+
+    #[test]
+    fn take_hands_out_oldest_first() {
+        let pool = WarmPool::new(vec![spec(2)]);
+        let key = spec(2).key();
+        assert!(pool.is_pooled(&key));
+        assert_eq!(pool.deficit(&spec(2)), 2);
+        pool.add(key.clone(), warm("a"));
+        pool.add(key.clone(), warm("b"));
+        assert_eq!(pool.deficit(&spec(2)), 0);
+        assert_eq!(pool.take(&key).unwrap().name, "a");
+        assert_eq!(pool.take(&key).unwrap().name, "b");
+        assert!(pool.take(&key).is_none());
+
+        let other = PoolKey {
+            image: Some("node20".into()),
+            shape: key.shape,
+        };
+        assert!(!pool.is_pooled(&other));
+        assert!(pool.take(&other).is_none());
+    }
+
+    #[test]
+    fn only_one_filler_per_key() {
+        let pool = WarmPool::new(vec![spec(1)]);
+        let key = spec(1).key();
+        assert!(pool.start_filling(&key));
+        assert!(!pool.start_filling(&key));
+        pool.done_filling(&key);
+        assert!(pool.start_filling(&key));
+    }
+
+    #[test]
+    fn empty_pools_are_ignored() {
+        let pool = WarmPool::new(vec![spec(0)]);
+        assert!(pool.specs().is_empty());
+        assert!(!pool.is_pooled(&spec(0).key()));
+    }
+}
@@ -0,0 +1,679 @@
+//! Reconciliation of the database with what is actually on the host:
+//! Firecracker processes, TAP devices and storage directories.
+//!
+//! After a host reboot or server crash, `vms` rows can point at PIDs that
+//! exited long ago, TAPs can be gone, and VM or checkpoint directories can
+//! outlive their rows (or the other way around). [`check`] finds these
+//! problems and [`repair`] fixes one. The server runs the safe repairs at
+//! startup ([`reconcile_at_startup`]); the rest are left to
+//! `noid-server fsck --repair`.
+
+use anyhow::{bail, Result};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::storage::StorageDriver;
+use crate::{db, network, storage, vm};
+
+/// A VM as named in reports: owner's user name and VM name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VmRef {
+    pub user_id: String,
+    pub user: String,
+    pub name: String,
+}
+
+impl fmt::Display for VmRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.user, self.name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Issue {
+    /// A VM recorded as running that has no usable Firecracker process.
+    /// `kill` is set when the process is still alive but unreachable.
+    StaleVm {
+        vm: VmRef,
+        reason: String,
+        kill: Option<i64>,
+        tap_name: Option<String>,
+    },
+    /// A running VM whose TAP device has disappeared.
+    MissingTap { vm: VmRef, tap_name: String },
+    /// A Firecracker process under noid's storage that no VM owns.
+    OrphanProcess { pid: i32, socket_path: String },
+    /// A noid TAP device that no VM uses.
+    OrphanTap { tap_name: String },
+    /// A VM directory without a `vms` row.
+    OrphanVmDir { vm: VmRef, path: PathBuf },
+    /// A `vms` row whose directory is gone.
+    MissingVmDir { vm: VmRef },
+    /// A checkpoint directory without a `checkpoints` row.
+    OrphanCheckpointDir { user_id: String, path: PathBuf },
+    /// A `checkpoints` row whose directory is gone.
+    MissingCheckpointDir {
+        vm: VmRef,
+        checkpoint_id: String,
+        path: String,
+    },
+}
+
+impl Issue {
+    /// Whether the repair only touches runtime state (processes, TAPs, the
+    /// `state` column) and is safe to apply unattended at startup. The other
+    /// repairs delete directories or rows.
+    pub fn is_safe_to_repair(&self) -> bool {
+        matches!(
+            self,
+            Issue::StaleVm { .. } | Issue::OrphanProcess { .. } | Issue::OrphanTap { .. }
+        )
+    }
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Issue::StaleVm { vm, reason, .. } => write!(f, "VM {vm}: {reason}"),
+            Issue::MissingTap { vm, tap_name } => {
+                write!(f, "VM {vm}: TAP device {tap_name} is missing")
+            }
+            Issue::OrphanProcess { pid, socket_path } => write!(
+                f,
+                "Firecracker process {pid} ({socket_path}) belongs to no VM"
+            ),
+            Issue::OrphanTap { tap_name } => write!(f, "TAP device {tap_name} belongs to no VM"),
+            Issue::OrphanVmDir { vm, path } => {
+                write!(f, "VM {vm}: {} has no database row", path.display())
+            }
+            Issue::MissingVmDir { vm } => write!(f, "VM {vm}: storage directory is missing"),
+            Issue::OrphanCheckpointDir { path, .. } => {
+                write!(f, "checkpoint {} has no database row", path.display())
+            }
+            Issue::MissingCheckpointDir {
+                vm,
+                checkpoint_id,
+                path,
+            } => write!(
+                f,
+                "VM {vm}: checkpoint {checkpoint_id} directory {path} is missing"
+            ),
+        }
+    }
+}
+
+/// A running Firecracker process and the API socket it was started with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct FcProcess {
+    pid: i32,
+    socket_path: String,
+}
+
+/// Everything the database records, loaded once per check.
+struct Known {
+    vms: Vec<(VmRef, db::VmRecord)>,
+    checkpoints: Vec<(VmRef, db::CheckpointRecord)>,
+    user_names: HashMap<String, String>,
+}
+
+impl Known {
+    fn load(db: &db::Db) -> Result<Self> {
+        let mut known = Known {
+            vms: Vec::new(),
+            checkpoints: Vec::new(),
+            user_names: HashMap::new(),
+        };
+        for user in db.list_users()? {
+            let vm_ref = |name: &str| VmRef {
+                user_id: user.id.clone(),
+                user: user.name.clone(),
+                name: name.to_string(),
+            };
+            for rec in db.list_vms(&user.id)? {
+                known.vms.push((vm_ref(&rec.name), rec));
+            }
+            for cp in db.list_all_checkpoints(&user.id)? {
+                known.checkpoints.push((vm_ref(&cp.vm_name), cp));
+            }
+            known.user_names.insert(user.id, user.name);
+        }
+        Ok(known)
+    }
+}
+
+/// Compare the database with the host. TAP checks are skipped, with a
+/// warning, when noid-netd cannot be reached.
+pub fn check(db: &db::Db) -> Result<Vec<Issue>> {
+    let known = Known::load(db)?;
+    let procs = firecracker_processes();
+    let taps = match network::active_taps() {
+        Ok(taps) => Some(taps),
+        Err(e) => {
+            eprintln!("warning: skipping TAP checks: {e:#}");
+            None
+        }
+    };
+    let users_root = storage::storage_dir().join("users");
+
+    let mut issues = check_vms(&known, &procs, taps.as_deref(), &users_root);
+    issues.extend(check_processes(&known, &procs, &users_root));
+    if let Some(taps) = &taps {
+        issues.extend(check_taps(&known, taps));
+    }
+    issues.extend(check_storage(&known, &users_root)?);
+    Ok(issues)
+}
+
+/// Fix one issue.
+pub fn repair(db: &db::Db, driver: &dyn StorageDriver, issue: &Issue) -> Result<()> {
+    match issue {
+        Issue::StaleVm {
+            vm: vm_ref,
+            kill,
+            tap_name,
+            ..
+        } => {
+            if let Some(pid) = kill {
+                vm::kill_vm_process(*pid);
+            }
+            if let Some(tap) = tap_name {
+                // Usually already gone along with the process
+                let _ = network::teardown_vm_network(tap);
+            }
+            db.set_vm_stopped(&vm_ref.user_id, &vm_ref.name)
+        }
+        Issue::MissingTap { vm, .. } => {
+            bail!(
+                "not repaired automatically; `noid reboot {}` (as {}) gives it a new TAP",
+                vm.name,
+                vm.user
+            )
+        }
+        Issue::OrphanProcess { pid, .. } => {
+            vm::kill_vm_process(*pid as i64);
+            Ok(())
+        }
+        Issue::OrphanTap { tap_name } => network::teardown_vm_network(tap_name),
+        Issue::OrphanVmDir { path, .. } | Issue::OrphanCheckpointDir { path, .. } => {
+            driver.delete_volume(path)
+        }
+        Issue::MissingVmDir { vm } => db.delete_vm(&vm.user_id, &vm.name),
+        Issue::MissingCheckpointDir {
+            vm, checkpoint_id, ..
+        } => db.delete_checkpoint(&vm.user_id, checkpoint_id),
+    }
+}
+
+/// Run at server startup, before any VM is booted: apply the safe repairs
+/// and report everything else.
+pub fn reconcile_at_startup(db: &db::Db, driver: &dyn StorageDriver) -> Result<()> {
+    for issue in check(db)? {
+        if !issue.is_safe_to_repair() {
+            eprintln!("warning: {issue} (run `noid-server fsck --repair` to fix)");
+            continue;
+        }
+        match repair(db, driver, &issue) {
+            Ok(()) => eprintln!("reconcile: repaired: {issue}"),
+            Err(e) => eprintln!("warning: failed to repair: {issue}: {e:#}"),
+        }
+    }
+    Ok(())
+}
+
+fn vm_path(users_root: &Path, user_id: &str, name: &str) -> PathBuf {
+    users_root.join(user_id).join("vms").join(name)
+}
+
+fn check_vms(
+    known: &Known,
+    procs: &[FcProcess],
+    taps: Option<&[String]>,
+    users_root: &Path,
+) -> Vec<Issue> {
+    let mut issues = Vec::new();
+    for (vm_ref, rec) in &known.vms {
+        if !vm_path(users_root, &vm_ref.user_id, &vm_ref.name).exists() {
+            issues.push(Issue::MissingVmDir { vm: vm_ref.clone() });
+        }
+        // Stopped and dead VMs have no process or TAP to check
+        if rec.state != "running" {
+            continue;
+        }
+
+        let stale = |reason: String, kill: Option<i64>| Issue::StaleVm {
+            vm: vm_ref.clone(),
+            reason,
+            kill,
+            tap_name: rec.tap_name.clone(),
+        };
+        let Some(pid) = rec.pid else {
+            issues.push(stale("no process recorded".into(), None));
+            continue;
+        };
+        let Some(proc) = procs.iter().find(|p| p.pid as i64 == pid) else {
+            issues.push(stale(format!("process {pid} has exited"), None));
+            continue;
+        };
+        if proc.socket_path != rec.socket_path {
+            // The PID was reused, typically after a host reboot
+            issues.push(stale(
+                format!("PID {pid} is no longer its Firecracker process"),
+                None,
+            ));
+            continue;
+        }
+        if !Path::new(&rec.socket_path).exists() {
+            issues.push(stale(
+                format!("API socket {} is missing", rec.socket_path),
+                Some(pid),
+            ));
+            continue;
+        }
+
+        if let (Some(taps), Some(tap)) = (taps, &rec.tap_name) {
+            if !taps.contains(tap) {
+                issues.push(Issue::MissingTap {
+                    vm: vm_ref.clone(),
+                    tap_name: tap.clone(),
+                });
+            }
+        }
+    }
+    issues
+}
+
+/// Firecracker processes whose socket is under noid's storage but that no
+/// VM row accounts for. VMs of the server's own `_`-prefixed namespaces,
+/// such as warm VMs and golden snapshot builds, have no rows and are left
+/// alone.
+fn check_processes(known: &Known, procs: &[FcProcess], users_root: &Path) -> Vec<Issue> {
+    procs
+        .iter()
+        .filter(|p| {
+            Path::new(&p.socket_path)
+                .strip_prefix(users_root)
+                .ok()
+                .and_then(|rest| rest.components().next())
+                .is_some_and(|user| !user.as_os_str().to_string_lossy().starts_with('_'))
+        })
+        .filter(|p| {
+            !known.vms.iter().any(|(_, rec)| {
+                rec.state == "running"
+                    && rec.pid == Some(p.pid as i64)
+                    && rec.socket_path == p.socket_path
+            })
+        })
+        .map(|p| Issue::OrphanProcess {
+            pid: p.pid,
+            socket_path: p.socket_path.clone(),
+        })
+        .collect()
+}
+
+fn check_taps(known: &Known, taps: &[String]) -> Vec<Issue> {
+    taps.iter()
+        .filter(|tap| {
+            !known
+                .vms
+                .iter()
+                .any(|(_, rec)| rec.state == "running" && rec.tap_name.as_ref() == Some(*tap))
+        })
+        .map(|tap| Issue::OrphanTap {
+            tap_name: tap.clone(),
+        })
+        .collect()
+}
+
+/// VM and checkpoint directories without rows, and checkpoint rows without
+/// directories. Namespaces starting with `_` (warm pool, golden templates)
+/// have no rows and are skipped.
+fn check_storage(known: &Known, users_root: &Path) -> Result<Vec<Issue>> {
+    let mut issues = Vec::new();
+    for user_dir in sorted_dirs(users_root)? {
+        let Some(user_id) = user_dir.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+        if user_id.starts_with('_') {
+            continue;
+        }
+        let user = known
+            .user_names
+            .get(user_id)
+            .cloned()
+            .unwrap_or_else(|| user_id.to_string());
+
+        for path in sorted_dirs(&user_dir.join("vms"))? {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            let has_row = known
+                .vms
+                .iter()
+                .any(|(r, _)| r.user_id == user_id && r.name == name);
+            if !has_row {
+                issues.push(Issue::OrphanVmDir {
+                    vm: VmRef {
+                        user_id: user_id.to_string(),
+                        user: user.clone(),
+                        name: name.to_string(),
+                    },
+                    path: path.clone(),
+                });
+            }
+        }
+
+        for vm_dir in sorted_dirs(&user_dir.join("checkpoints"))? {
+            for path in sorted_dirs(&vm_dir)? {
+                let has_row = known
+                    .checkpoints
+                    .iter()
+                    .any(|(_, cp)| Path::new(&cp.snapshot_path) == path);
+                if !has_row {
+                    issues.push(Issue::OrphanCheckpointDir {
+                        user_id: user_id.to_string(),
+                        path,
+                    });
+                }
+            }
+        }
+    }
+
+    for (vm_ref, cp) in &known.checkpoints {
+        if !Path::new(&cp.snapshot_path).exists() {
+            issues.push(Issue::MissingCheckpointDir {
+                vm: vm_ref.clone(),
+                checkpoint_id: cp.id.clone(),
+                path: cp.snapshot_path.clone(),
+            });
+        }
+    }
+    Ok(issues)
+}
+
+fn sorted_dirs(dir: &Path) -> Result<Vec<PathBuf>> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut dirs: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.is_dir())
+        .collect();
+    dirs.sort();
+    Ok(dirs)
+}
+
+/// All Firecracker processes on the host, found through `/proc`.
+fn firecracker_processes() -> Vec<FcProcess> {
+    let Ok(entries) = std::fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(|e| e.ok())
+        .filter_map(|e| e.file_name().to_str()?.parse::<i32>().ok())
+        .filter_map(|pid| {
+            let cmdline = std::fs::read(format!("/proc/{pid}/cmdline")).ok()?;
+            let socket_path = firecracker_api_sock(&cmdline)?;
+            Some(FcProcess { pid, socket_path })
+        })
+        .collect()
+}
+
+/// The `--api-sock` argument of a Firecracker command line (NUL-separated,
+/// as in `/proc/<pid>/cmdline`).
+fn firecracker_api_sock(cmdline: &[u8]) -> Option<String> {
+    let mut args = cmdline
+        .split(|b| *b == 0)
+        .map(|a| String::from_utf8_lossy(a).into_owned());
+    let exe = args.next()?;
+    if !Path::new(&exe)
+        .file_name()?
+        .to_string_lossy()
+        .starts_with("firecracker")
+    {
+        return None;
+    }
+    args.skip_while(|a| a != "--api-sock").nth(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pool::POOL_USER;
+    use crate::test_util::tempdir;
+
+    fn vm_ref(name: &str) -> VmRef {
+        VmRef {
+            user_id: "u1".into(),
+            user: "alice".into(),
+            name: name.into(),
+        }
+    }
+
+    fn record(name: &str, state: &str, pid: Option<i64>, socket: &str) -> db::VmRecord {
+        db::VmRecord {
+            id: 0,
+            user_id: "u1".into(),
+            name: name.into(),
+            pid,
+            socket_path: socket.into(),
+            kernel: "/vmlinux".into(),
+            rootfs: "/rootfs.ext4".into(),
+            cpus: 1,
+            mem_mib: 512,
+            state: state.into(),
+            created_at: String::new(),
+            net_index: Some(0),
+            tap_name: Some(format!("noid-{name}")),
+            guest_ip: None,
+            base_rootfs: None,
+            boot_args: None,
+            init: None,
+            restart: Default::default(),
+            restart_from: Default::default(),
+            expires_at: None,
+            stop_on_expiry: false,
+            labels: Default::default(),
+            parent_checkpoint: None,
+            init_script_state: None,
+            init_script_exit_code: None,
+            init_script_finished_at: None,
+            rate_limits: Default::default(),
+            firecracker_version: None,
+        }
+    }
+
+    #[test]
+    fn api_sock_is_read_from_firecracker_cmdlines_only() {
+        let cmdline = b"/usr/local/bin/firecracker\0--api-sock\0/s/vms/a/firecracker.sock\0--level\0Warning\0";
+        assert_eq!(
+            firecracker_api_sock(cmdline).as_deref(),
+            Some("/s/vms/a/firecracker.sock")
+        );
+        assert_eq!(firecracker_api_sock(b"/bin/sleep\0--api-sock\0/x\0"), None);
+        assert_eq!(firecracker_api_sock(b"firecracker\0--version\0"), None);
+    }
+
+    #[test]
+    fn stale_vms_are_classified() {
+        let root = tempdir("reconcile-stale");
+        let socket = root.join("live.sock");
+        std::fs::write(&socket, b"").unwrap();
+        let socket = socket.to_string_lossy().to_string();
+        for name in ["live", "exited", "reused", "nosock", "off"] {
+            std::fs::create_dir_all(vm_path(&root, "u1", name)).unwrap();
+        }
+
+        let known = Known {
+            vms: vec![
+                (vm_ref("live"), record("live", "running", Some(10), &socket)),
+                (
+                    vm_ref("exited"),
+                    record("exited", "running", Some(11), "/x"),
+                ),
+                (
+                    vm_ref("reused"),
+                    record("reused", "running", Some(12), "/y"),
+                ),
+                (
+                    vm_ref("nosock"),
+                    record("nosock", "running", Some(13), "/gone.sock"),
+                ),
+                (vm_ref("off"), record("off", "stopped", None, "/z")),
+                (vm_ref("nodir"), record("nodir", "stopped", None, "/z")),
+            ],
+            checkpoints: Vec::new(),
+            user_names: HashMap::new(),
+        };
+        let procs = [
+            FcProcess {
+                pid: 10,
+                socket_path: socket.clone(),
+            },
+            FcProcess {
+                pid: 12,
+                socket_path: "/other.sock".into(),
+            },
+            FcProcess {
+                pid: 13,
+                socket_path: "/gone.sock".into(),
+            },
+        ];
+        let taps = ["noid-exited".to_string()];
+        let issues = check_vms(&known, &procs, Some(&taps), &root);
+
+        let kills: Vec<(&str, Option<i64>)> = issues
+            .iter()
+            .filter_map(|i| match i {
+                Issue::StaleVm { vm, kill, .. } => Some((vm.name.as_str(), *kill)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            kills,
+            vec![("exited", None), ("reused", None), ("nosock", Some(13))]
+        );
+        assert!(issues.contains(&Issue::MissingTap {
+            vm: vm_ref("live"),
+            tap_name: "noid-live".into(),
+        }));
+        assert!(issues.contains(&Issue::MissingVmDir {
+            vm: vm_ref("nodir")
+        }));
+        assert_eq!(issues.len(), 5);
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn orphans_are_found() {
+        let root = tempdir("reconcile-orphans");
+        let live_sock = vm_path(&root, "u1", "live")
+            .join("firecracker.sock")
+            .to_string_lossy()
+            .to_string();
+        std::fs::create_dir_all(vm_path(&root, "u1", "live")).unwrap();
+        std::fs::create_dir_all(vm_path(&root, "u1", "leftover")).unwrap();
+        std::fs::create_dir_all(vm_path(&root, POOL_USER, "warm-1")).unwrap();
+        let kept = root.join("u1/checkpoints/live/aaaa");
+        let dropped = root.join("u1/checkpoints/gone/bbbb");
+        std::fs::create_dir_all(&kept).unwrap();
+        std::fs::create_dir_all(&dropped).unwrap();
+
+        let checkpoint = |id: &str, path: &Path| {
+            (
+                vm_ref("live"),
+                db::CheckpointRecord {
+                    id: id.into(),
+                    vm_name: "live".into(),
+                    user_id: "u1".into(),
+                    label: None,
+                    snapshot_path: path.to_string_lossy().to_string(),
+                    created_at: String::new(),
+                    checksums: None,
+                    corrupt: false,
+                },
+            )
+        };
+        let known = Known {
+            vms: vec![(
+                vm_ref("live"),
+                record("live", "running", Some(10), &live_sock),
+            )],
+            checkpoints: vec![
+                checkpoint("aaaa", &kept),
+                checkpoint("cccc", Path::new("/nowhere")),
+            ],
+            user_names: HashMap::from([("u1".to_string(), "alice".to_string())]),
+        };
+
+        let issues = check_storage(&known, &root).unwrap();
+        assert_eq!(
+            issues,
+            vec![
+                Issue::OrphanVmDir {
+                    vm: vm_ref("leftover"),
+                    path: vm_path(&root, "u1", "leftover"),
+                },
+                Issue::OrphanCheckpointDir {
+                    user_id: "u1".into(),
+                    path: dropped,
+                },
+                Issue::MissingCheckpointDir {
+                    vm: vm_ref("live"),
+                    checkpoint_id: "cccc".into(),
+                    path: "/nowhere".into(),
+                },
+            ]
+        );
+
+        let procs = [
+            FcProcess {
+                pid: 10,
+                socket_path: live_sock,
+            },
+            FcProcess {
+                pid: 20,
+                socket_path: vm_path(&root, "u1", "leftover")
+                    .join("firecracker.sock")
+                    .to_string_lossy()
+                    .to_string(),
+            },
+            FcProcess {
+                pid: 21,
+                socket_path: vm_path(&root, POOL_USER, "warm-1")
+                    .join("firecracker.sock")
+                    .to_string_lossy()
+                    .to_string(),
+            },
+            FcProcess {
+                pid: 22,
+                socket_path: "/elsewhere/firecracker.sock".into(),
+            },
+            FcProcess {
+                pid: 23,
+                socket_path: vm_path(&root, "_templates", "_golden-2x1024")
+                    .join("firecracker.sock")
+                    .to_string_lossy()
+                    .to_string(),
+            },
+        ];
+        let pids: Vec<i32> = check_processes(&known, &procs, &root)
+            .iter()
+            .filter_map(|i| match i {
+                Issue::OrphanProcess { pid, .. } => Some(*pid),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(pids, vec![20]);
+
+        let taps = ["noid-live".to_string(), "noid7".to_string()];
+        assert_eq!(
+            check_taps(&known, &taps),
+            vec![Issue::OrphanTap {
+                tap_name: "noid7".into()
+            }]
+        );
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
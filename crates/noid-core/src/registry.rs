@@ -0,0 +1,298 @@
+//! Image registry: kernels and rootfs images uploaded through the admin API
+//! and selected by name at create time.
+//!
+//! ```text
+//! ~/.noid/kernels/<name>/vmlinux
+//! ~/.noid/kernels/<name>/meta.json    {"size_bytes": ..., "sha256": "...", "uploaded_at": "..."}
+//! ~/.noid/rootfs/<name>/rootfs.ext4
+//! ~/.noid/rootfs/<name>/meta.json
+//! ```
+//!
+//! Uploads are written to a hidden staging directory and renamed into place
+//! once the checksum has been checked, so a half-uploaded file is never
+//! offered to `create`.
+
+use anyhow::{bail, Context, Result};
+use noid_types::{ArtifactInfo, ArtifactKind};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::{auth, config, db, storage};
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Meta {
+    size_bytes: u64,
+    sha256: String,
+    uploaded_at: String,
+}
+
+/// Directory holding all artifacts of `kind`.
+pub fn registry_dir(kind: ArtifactKind) -> PathBuf {
+    config::noid_dir().join(kind.collection())
+}
+
+/// Label used in name validation errors.
+fn noun(kind: ArtifactKind) -> &'static str {
+    match kind {
+        ArtifactKind::Kernel => "Kernel",
+        ArtifactKind::Rootfs => "Rootfs",
+    }
+}
+
+fn file_name(kind: ArtifactKind) -> &'static str {
+    match kind {
+        ArtifactKind::Kernel => "vmlinux",
+        ArtifactKind::Rootfs => "rootfs.ext4",
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Artifact {
+    pub kind: ArtifactKind,
+    pub name: String,
+    pub dir: PathBuf,
+}
+
+impl Artifact {
+    /// Open an uploaded artifact. Fails if it does not exist.
+    pub fn open(kind: ArtifactKind, name: &str) -> Result<Self> {
+        Self::open_in(&registry_dir(kind), kind, name)
+    }
+
+    fn open_in(root: &Path, kind: ArtifactKind, name: &str) -> Result<Self> {
+        storage::validate_name(name, noun(kind))?;
+        let artifact = Artifact {
+            kind,
+            name: name.to_string(),
+            dir: root.join(name),
+        };
+        if !artifact.path().exists() || !artifact.dir.join("meta.json").exists() {
+            bail!("{kind} '{name}' not found");
+        }
+        Ok(artifact)
+    }
+
+    /// The kernel or rootfs file itself.
+    pub fn path(&self) -> PathBuf {
+        self.dir.join(file_name(self.kind))
+    }
+
+    pub fn info(&self) -> Result<ArtifactInfo> {
+        let path = self.dir.join("meta.json");
+        let data = std::fs::read_to_string(&path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let meta: Meta = serde_json::from_str(&data)
+            .with_context(|| format!("failed to parse {}", path.display()))?;
+        Ok(ArtifactInfo {
+            kind: self.kind,
+            name: self.name.clone(),
+            size_bytes: meta.size_bytes,
+            sha256: meta.sha256,
+            uploaded_at: meta.uploaded_at,
+        })
+    }
+}
+
+/// All artifacts of `kind`, sorted by name.
+pub fn list(kind: ArtifactKind) -> Result<Vec<ArtifactInfo>> {
+    list_in(&registry_dir(kind), kind)
+}
+
+fn list_in(root: &Path, kind: ArtifactKind) -> Result<Vec<ArtifactInfo>> {
+    let entries = match std::fs::read_dir(root) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("failed to read {}", root.display())),
+    };
+    let mut infos: Vec<ArtifactInfo> = entries
+        .filter_map(|e| e.ok())
+        .filter_map(|e| e.file_name().into_string().ok())
+        .filter_map(|name| Artifact::open_in(root, kind, &name).ok())
+        .filter_map(|artifact| artifact.info().ok())
+        .collect();
+    infos.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(infos)
+}
+
+/// Store the contents of `reader` as a new artifact. When `expected_sha256`
+/// is given the upload is rejected unless it matches. Existing artifacts are
+/// never overwritten; delete them first.
+pub fn upload(
+    kind: ArtifactKind,
+    name: &str,
+    reader: &mut dyn Read,
+    expected_sha256: Option<&str>,
+) -> Result<ArtifactInfo> {
+    upload_in(&registry_dir(kind), kind, name, reader, expected_sha256)
+}
+
+fn upload_in(
+    root: &Path,
+    kind: ArtifactKind,
+    name: &str,
+    reader: &mut dyn Read,
+    expected_sha256: Option<&str>,
+) -> Result<ArtifactInfo> {
+    storage::validate_name(name, noun(kind))?;
+    let dir = root.join(name);
+    if dir.exists() {
+        bail!("{kind} '{name}' already exists");
+    }
+
+    let staging = root.join(format!(".{name}.upload-{}", uuid::Uuid::new_v4()));
+    let result = write_staging(&staging, kind, name, reader, expected_sha256).and_then(|()| {
+        match std::fs::rename(&staging, &dir) {
+            Ok(()) => Ok(()),
+            // Another upload of the same name finished first
+            Err(_) if dir.exists() => bail!("{kind} '{name}' already exists"),
+            Err(e) => Err(e).with_context(|| format!("failed to install {}", dir.display())),
+        }
+    });
+    if let Err(e) = result {
+        let _ = std::fs::remove_dir_all(&staging);
+        return Err(e);
+    }
+    Artifact::open_in(root, kind, name)?.info()
+}
+
+fn write_staging(
+    staging: &Path,
+    kind: ArtifactKind,
+    name: &str,
+    reader: &mut dyn Read,
+    expected_sha256: Option<&str>,
+) -> Result<()> {
+    std::fs::create_dir_all(staging)
+        .with_context(|| format!("failed to create {}", staging.display()))?;
+    let path = staging.join(file_name(kind));
+    let file = std::fs::File::create(&path)
+        .with_context(|| format!("failed to create {}", path.display()))?;
+    let mut out = std::io::BufWriter::new(file);
+    let mut hasher = Sha256::new();
+    let mut size_bytes = 0u64;
+    let mut buf = vec![0u8; 1024 * 1024];
+    loop {
+        let n = reader
+            .read(&mut buf)
+            .with_context(|| format!("failed to read upload of {kind} '{name}'"))?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        out.write_all(&buf[..n])
+            .with_context(|| format!("failed to write {}", path.display()))?;
+        size_bytes += n as u64;
+    }
+    out.into_inner()
+        .map_err(|e| e.into_error())
+        .and_then(|f| f.sync_all())
+        .with_context(|| format!("failed to write {}", path.display()))?;
+
+    let sha256 = auth::hex_encode(&hasher.finalize());
+    if size_bytes == 0 {
+        bail!("upload of {kind} '{name}' failed integrity verification: no data received");
+    }
+    if let Some(expected) = expected_sha256 {
+        if !expected.eq_ignore_ascii_case(&sha256) {
+            bail!(
+                "upload of {kind} '{name}' failed integrity verification: \
+                 sha256 is {sha256}, expected {expected}"
+            );
+        }
+    }
+
+    let meta = Meta {
+        size_bytes,
+        sha256,
+        uploaded_at: chrono::Utc::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+    };
+    std::fs::write(staging.join("meta.json"), serde_json::to_string(&meta)?)?;
+    Ok(())
+}
+
+/// Remove an artifact. VMs created from a rootfs keep their own copy of it,
+/// but a kernel is refused while any VM still records it.
+pub fn delete(db: &db::Db, kind: ArtifactKind, name: &str) -> Result<()> {
+    let artifact = Artifact::open(kind, name)?;
+    if kind == ArtifactKind::Kernel {
+        let in_use = db.count_vms_with_kernel(&artifact.path().to_string_lossy())?;
+        if in_use > 0 {
+            bail!("kernel '{name}' is in use by {in_use} VM(s)");
+        }
+    }
+    std::fs::remove_dir_all(&artifact.dir)
+        .with_context(|| format!("failed to remove {}", artifact.dir.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::tempdir;
+
+    fn sha256_hex(data: &[u8]) -> String {
+        auth::hex_encode(&Sha256::digest(data))
+    }
+
+    #[test]
+    fn upload_then_open_and_list() {
+        let root = tempdir("registry-upload");
+        let data = b"kernel image".to_vec();
+        let checksum = sha256_hex(&data);
+        let info = upload_in(
+            &root,
+            ArtifactKind::Kernel,
+            "6.12",
+            &mut data.as_slice(),
+            Some(&checksum),
+        )
+        .unwrap();
+        assert_eq!(info.size_bytes, data.len() as u64);
+        assert_eq!(info.sha256, checksum);
+
+        let artifact = Artifact::open_in(&root, ArtifactKind::Kernel, "6.12").unwrap();
+        assert_eq!(std::fs::read(artifact.path()).unwrap(), data);
+        assert!(artifact.path().ends_with("vmlinux"));
+
+        let names: Vec<_> = list_in(&root, ArtifactKind::Kernel)
+            .unwrap()
+            .into_iter()
+            .map(|i| i.name)
+            .collect();
+        assert_eq!(names, vec!["6.12"]);
+
+        let err = upload_in(&root, ArtifactKind::Kernel, "6.12", &mut &b"x"[..], None).unwrap_err();
+        assert!(err.to_string().contains("already exists"));
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn checksum_mismatch_leaves_nothing_behind() {
+        let root = tempdir("registry-mismatch");
+        let err = upload_in(
+            &root,
+            ArtifactKind::Rootfs,
+            "ubuntu-25.04",
+            &mut &b"truncated"[..],
+            Some(&sha256_hex(b"truncated rootfs")),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("failed integrity verification"));
+        assert_eq!(std::fs::read_dir(&root).unwrap().count(), 0);
+
+        let err = upload_in(&root, ArtifactKind::Rootfs, "empty", &mut &b""[..], None).unwrap_err();
+        assert!(err.to_string().contains("no data received"));
+        assert_eq!(std::fs::read_dir(&root).unwrap().count(), 0);
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn open_missing_artifact_is_not_found() {
+        let root = tempdir("registry-missing");
+        let err = Artifact::open_in(&root, ArtifactKind::Rootfs, "alpine").unwrap_err();
+        assert_eq!(err.to_string(), "rootfs 'alpine' not found");
+        assert!(Artifact::open_in(&root, ArtifactKind::Kernel, "../vmlinux").is_err());
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
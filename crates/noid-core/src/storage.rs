@@ -4,6 +4,10 @@ use std::process::Command;
 
 use crate::config;
 
+pub mod driver;
+
+pub use driver::{StorageDriver, StorageDriverKind};
+
 const LOOPBACK_SIZE_MB: u64 = 4096;
 const LOOPBACK_FILE: &str = "storage.img";
 
@@ -53,7 +57,7 @@ fn btrfs_available() -> bool {
         .unwrap_or(false)
 }
 
-fn is_btrfs_mounted(path: &Path) -> bool {
+pub(crate) fn is_btrfs_mounted(path: &Path) -> bool {
     if !path.exists() {
         return false;
     }
@@ -70,6 +74,12 @@ fn is_btrfs_mounted(path: &Path) -> bool {
     }
 }
 
+/// Prepare the storage root and pick the driver for it.
+pub fn open_driver(kind: StorageDriverKind) -> Result<Box<dyn StorageDriver>> {
+    ensure_storage()?;
+    driver::select_driver(kind, &storage_dir())
+}
+
 pub fn ensure_storage() -> Result<()> {
     let storage = storage_dir();
 
@@ -133,8 +143,12 @@ pub fn vm_dir(user_id: &str, vm_name: &str) -> PathBuf {
     user_storage_dir(user_id).join("vms").join(vm_name)
 }
 
-/// Create a directory for a VM (user-namespaced)
-pub fn create_vm_subvolume(user_id: &str, vm_name: &str) -> Result<PathBuf> {
+/// Create a volume for a VM (user-namespaced)
+pub fn create_vm_subvolume(
+    driver: &dyn StorageDriver,
+    user_id: &str,
+    vm_name: &str,
+) -> Result<PathBuf> {
     validate_name(vm_name, "VM")?;
     ensure_storage()?;
     let dir = vm_dir(user_id, vm_name);
@@ -144,58 +158,47 @@ pub fn create_vm_subvolume(user_id: &str, vm_name: &str) -> Result<PathBuf> {
     if let Some(parent) = dir.parent() {
         std::fs::create_dir_all(parent)?;
     }
-
-    if is_btrfs_mounted(&storage_dir()) {
-        run_cmd("btrfs", &["subvolume", "create", &dir.to_string_lossy()])?;
-    } else {
-        std::fs::create_dir_all(&dir)?;
-    }
+    driver.create_volume(&dir)?;
     Ok(dir)
 }
 
-/// Copy rootfs into VM's directory using reflink
-pub fn reflink_rootfs(user_id: &str, vm_name: &str, rootfs_src: &str) -> Result<PathBuf> {
+/// Copy the base rootfs into a VM's volume, sharing extents where the driver can
+pub fn copy_rootfs(
+    driver: &dyn StorageDriver,
+    user_id: &str,
+    vm_name: &str,
+    rootfs_src: &str,
+) -> Result<PathBuf> {
     validate_name(vm_name, "VM")?;
-    let dir = vm_dir(user_id, vm_name);
-    let dest = dir.join("rootfs.ext4");
-    run_cmd(
-        "cp",
-        &["--reflink=auto", rootfs_src, &dest.to_string_lossy()],
-    )?;
+    let dest = vm_dir(user_id, vm_name).join("rootfs.ext4");
+    driver.copy_file(Path::new(rootfs_src), &dest)?;
     Ok(dest)
 }
 
 /// Create a snapshot (checkpoint) — user-namespaced
-pub fn create_snapshot(user_id: &str, vm_name: &str, checkpoint_id: &str) -> Result<PathBuf> {
+pub fn create_snapshot(
+    driver: &dyn StorageDriver,
+    user_id: &str,
+    vm_name: &str,
+    checkpoint_id: &str,
+) -> Result<PathBuf> {
     validate_name(vm_name, "VM")?;
     validate_name(checkpoint_id, "Checkpoint")?;
     let src = vm_dir(user_id, vm_name);
     let snap_dir = user_storage_dir(user_id).join("checkpoints").join(vm_name);
     std::fs::create_dir_all(&snap_dir)?;
     let snap = snap_dir.join(checkpoint_id);
-
-    if is_btrfs_mounted(&storage_dir()) {
-        run_cmd(
-            "btrfs",
-            &[
-                "subvolume",
-                "snapshot",
-                "-r",
-                &src.to_string_lossy(),
-                &snap.to_string_lossy(),
-            ],
-        )?;
-    } else {
-        run_cmd(
-            "cp",
-            &["-a", &src.to_string_lossy(), &snap.to_string_lossy()],
-        )?;
-    }
+    driver.snapshot(&src, &snap)?;
     Ok(snap)
 }
 
 /// Clone a checkpoint to a new VM — user-namespaced
-pub fn clone_snapshot(user_id: &str, checkpoint_path: &str, new_vm_name: &str) -> Result<PathBuf> {
+pub fn clone_snapshot(
+    driver: &dyn StorageDriver,
+    user_id: &str,
+    checkpoint_path: &str,
+    new_vm_name: &str,
+) -> Result<PathBuf> {
     validate_name(new_vm_name, "VM")?;
     let dest = vm_dir(user_id, new_vm_name);
     if dest.exists() {
@@ -204,68 +207,55 @@ pub fn clone_snapshot(user_id: &str, checkpoint_path: &str, new_vm_name: &str) -
     if let Some(parent) = dest.parent() {
         std::fs::create_dir_all(parent)?;
     }
-
-    if is_btrfs_mounted(&storage_dir()) {
-        run_cmd(
-            "btrfs",
-            &[
-                "subvolume",
-                "snapshot",
-                checkpoint_path,
-                &dest.to_string_lossy(),
-            ],
-        )?;
-    } else {
-        run_cmd("cp", &["-a", checkpoint_path, &dest.to_string_lossy()])?;
-    }
+    driver.clone_volume(Path::new(checkpoint_path), &dest)?;
     Ok(dest)
 }
 
-/// Path to the golden snapshot directory.
-pub fn golden_dir() -> PathBuf {
-    config::noid_dir().join("golden")
-}
-
-/// Check if a golden snapshot exists (has memory.snap).
-pub fn golden_snapshot_exists() -> bool {
-    golden_dir().join("memory.snap").exists()
+/// Every top-level item in a user's storage: `vms/<name>`,
+/// `checkpoints/<vm>/<id>`, and any other entry directly under the user's
+/// directory.
+pub fn user_volume_paths(user_id: &str) -> Result<Vec<PathBuf>> {
+    let root = user_storage_dir(user_id);
+    let mut paths = Vec::new();
+    for entry in read_dir_paths(&root)? {
+        match entry.file_name().and_then(|n| n.to_str()) {
+            Some("vms") => paths.extend(read_dir_paths(&entry)?),
+            Some("checkpoints") => {
+                for vm in read_dir_paths(&entry)? {
+                    paths.extend(read_dir_paths(&vm)?);
+                }
+            }
+            _ => paths.push(entry),
+        }
+    }
+    Ok(paths)
 }
 
-/// Read the golden snapshot's template config (cpus, mem_mib).
-pub fn golden_config() -> Result<(u32, u32)> {
-    let config_path = golden_dir().join("config.json");
-    let data = std::fs::read_to_string(&config_path)
-        .with_context(|| format!("failed to read golden config: {}", config_path.display()))?;
-    let v: serde_json::Value =
-        serde_json::from_str(&data).context("failed to parse golden config.json")?;
-    let cpus = v["cpus"]
-        .as_u64()
-        .context("missing cpus in golden config")? as u32;
-    let mem_mib = v["mem_mib"]
-        .as_u64()
-        .context("missing mem_mib in golden config")? as u32;
-    Ok((cpus, mem_mib))
+fn read_dir_paths(dir: &Path) -> Result<Vec<PathBuf>> {
+    match std::fs::read_dir(dir) {
+        Ok(entries) => Ok(entries.filter_map(|e| e.ok()).map(|e| e.path()).collect()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e).with_context(|| format!("failed to read {}", dir.display())),
+    }
 }
 
-/// Read optional source rootfs path embedded in golden config.
-/// This is the backing file path originally captured in vmstate.snap.
-pub fn golden_snapshot_rootfs_path() -> Result<Option<String>> {
-    let config_path = golden_dir().join("config.json");
-    let data = std::fs::read_to_string(&config_path)
-        .with_context(|| format!("failed to read golden config: {}", config_path.display()))?;
-    let v: serde_json::Value =
-        serde_json::from_str(&data).context("failed to parse golden config.json")?;
-    Ok(v["snapshot_rootfs_path"].as_str().map(|s| s.to_string()))
+/// Path to the default image's golden snapshot directory.
+pub fn golden_dir() -> PathBuf {
+    config::noid_dir().join("golden")
 }
 
-/// Clone golden snapshot files into a new VM directory.
-/// Creates the VM dir, copies rootfs.ext4 (reflink), memory.snap, and vmstate.snap.
-pub fn clone_golden(user_id: &str, vm_name: &str) -> Result<PathBuf> {
+/// Clone an image's golden snapshot files into a new VM volume.
+/// Creates the VM volume and copies rootfs.ext4, memory.snap, and vmstate.snap
+/// through the driver (reflinked where supported).
+pub fn clone_golden(
+    driver: &dyn StorageDriver,
+    golden: &Path,
+    user_id: &str,
+    vm_name: &str,
+) -> Result<PathBuf> {
     validate_name(vm_name, "VM")?;
     ensure_storage()?;
 
-    let golden = golden_dir();
-
     // Validate all required files exist and are non-empty
     for file in &["rootfs.ext4", "memory.snap", "vmstate.snap"] {
         let path = golden.join(file);
@@ -284,37 +274,121 @@ pub fn clone_golden(user_id: &str, vm_name: &str) -> Result<PathBuf> {
     if let Some(parent) = dest.parent() {
         std::fs::create_dir_all(parent)?;
     }
-    std::fs::create_dir_all(&dest)?;
-    // Copy rootfs with reflink for speed
-    run_cmd(
-        "cp",
-        &[
-            "--reflink=auto",
-            &golden.join("rootfs.ext4").to_string_lossy(),
-            &dest.join("rootfs.ext4").to_string_lossy(),
-        ],
-    )?;
-    // Copy snapshot files
-    std::fs::copy(golden.join("memory.snap"), dest.join("memory.snap"))
-        .context("failed to copy memory.snap")?;
-    std::fs::copy(golden.join("vmstate.snap"), dest.join("vmstate.snap"))
-        .context("failed to copy vmstate.snap")?;
+    driver.create_volume(&dest)?;
+    for file in &["rootfs.ext4", "memory.snap", "vmstate.snap"] {
+        if let Err(e) = driver.copy_file(&golden.join(file), &dest.join(file)) {
+            let _ = driver.delete_volume(&dest);
+            return Err(e.context(format!("failed to copy golden {file}")));
+        }
+    }
 
     Ok(dest)
 }
 
 /// Delete VM storage
-pub fn delete_subvolume(user_id: &str, vm_name: &str) -> Result<()> {
+pub fn delete_subvolume(driver: &dyn StorageDriver, user_id: &str, vm_name: &str) -> Result<()> {
     validate_name(vm_name, "VM")?;
-    let dir = vm_dir(user_id, vm_name);
-    if dir.exists() {
-        if is_btrfs_mounted(&storage_dir()) {
-            run_cmd("btrfs", &["subvolume", "delete", &dir.to_string_lossy()])?;
+    driver.delete_volume(&vm_dir(user_id, vm_name))
+}
+
+/// Move a VM's volume out of the way while something replaces it. Returns
+/// where it went, for `put_back_vm_volume` or deleting once the
+/// replacement is in place, or `None` if the VM has no volume.
+pub fn set_aside_vm_volume(
+    driver: &dyn StorageDriver,
+    user_id: &str,
+    vm_name: &str,
+) -> Result<Option<PathBuf>> {
+    validate_name(vm_name, "VM")?;
+    set_aside_in(driver, &vm_dir(user_id, vm_name))
+}
+
+/// Delete whatever replaced a VM's volume and move the set-aside volume back.
+pub fn put_back_vm_volume(
+    driver: &dyn StorageDriver,
+    user_id: &str,
+    vm_name: &str,
+    aside: &Path,
+) -> Result<()> {
+    validate_name(vm_name, "VM")?;
+    put_back_in(driver, &vm_dir(user_id, vm_name), aside)
+}
+
+fn set_aside_in(driver: &dyn StorageDriver, dir: &Path) -> Result<Option<PathBuf>> {
+    if !dir.exists() {
+        return Ok(None);
+    }
+    // VM names cannot start with '.', so this never clashes with another
+    // VM's volume. One left from an interrupted replacement is stale.
+    let name = dir.file_name().unwrap_or_default().to_string_lossy();
+    let aside = dir.with_file_name(format!(".{name}.replaced"));
+    driver.delete_volume(&aside)?;
+    std::fs::rename(dir, &aside)
+        .with_context(|| format!("failed to move {} to {}", dir.display(), aside.display()))?;
+    Ok(Some(aside))
+}
+
+fn put_back_in(driver: &dyn StorageDriver, dir: &Path, aside: &Path) -> Result<()> {
+    driver.delete_volume(dir)?;
+    std::fs::rename(aside, dir).with_context(|| {
+        format!(
+            "failed to move {} back to {}",
+            aside.display(),
+            dir.display()
+        )
+    })
+}
+
+/// Move a VM's volume and checkpoint directory to a new name. Returns the
+/// (old, new) directories that were moved, for rewriting stored paths or
+/// moving them back with `undo_renames`.
+pub fn rename_vm_storage(
+    user_id: &str,
+    vm_name: &str,
+    new_name: &str,
+) -> Result<Vec<(PathBuf, PathBuf)>> {
+    validate_name(vm_name, "VM")?;
+    validate_name(new_name, "VM")?;
+    rename_in(&user_storage_dir(user_id), vm_name, new_name)
+}
+
+fn rename_in(root: &Path, vm_name: &str, new_name: &str) -> Result<Vec<(PathBuf, PathBuf)>> {
+    let mut moved = Vec::new();
+    for dir in ["vms", "checkpoints"] {
+        let (from, to) = (root.join(dir).join(vm_name), root.join(dir).join(new_name));
+        if !from.exists() {
+            continue;
+        }
+        // rename(2) would replace an empty directory, so check first
+        let result = if to.exists() {
+            Err(anyhow::anyhow!(
+                "storage already exists for VM '{new_name}': {}",
+                to.display()
+            ))
         } else {
-            std::fs::remove_dir_all(&dir)?;
+            std::fs::rename(&from, &to)
+                .with_context(|| format!("failed to move {} to {}", from.display(), to.display()))
+        };
+        if let Err(e) = result {
+            undo_renames(&moved);
+            return Err(e);
+        }
+        moved.push((from, to));
+    }
+    Ok(moved)
+}
+
+/// Move directories moved by `rename_vm_storage` back.
+pub fn undo_renames(moved: &[(PathBuf, PathBuf)]) {
+    for (from, to) in moved.iter().rev() {
+        if let Err(e) = std::fs::rename(to, from) {
+            eprintln!(
+                "warning: failed to move {} back to {}: {e}",
+                to.display(),
+                from.display()
+            );
         }
     }
-    Ok(())
 }
 
 /// Delete all storage for a user
@@ -380,6 +454,60 @@ mod tests {
         assert!(validate_name("..double", "VM").is_err());
     }
 
+    #[test]
+    fn rename_moves_volume_and_checkpoints() {
+        let root = crate::test_util::tempdir("rename");
+        for dir in ["vms/a", "vms/taken", "checkpoints/a/cp1"] {
+            std::fs::create_dir_all(root.join(dir)).unwrap();
+        }
+
+        let moved = rename_in(&root, "a", "b").unwrap();
+        assert_eq!(moved.len(), 2);
+        assert!(root.join("vms/b").exists() && !root.join("vms/a").exists());
+        assert!(root.join("checkpoints/b/cp1").exists());
+
+        // The target name's directory is never replaced
+        let err = rename_in(&root, "b", "taken").unwrap_err();
+        assert!(err.to_string().contains("already exists"));
+        assert!(root.join("vms/b").exists());
+
+        undo_renames(&moved);
+        assert!(root.join("vms/a").exists() && root.join("checkpoints/a/cp1").exists());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn set_aside_volume_can_be_put_back() {
+        let root = crate::test_util::tempdir("set-aside");
+        let dir = root.join("vms/a");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("rootfs.ext4"), "old").unwrap();
+
+        let aside = set_aside_in(&driver::CopyDriver, &dir).unwrap().unwrap();
+        assert!(!dir.exists());
+        assert_eq!(
+            std::fs::read_to_string(aside.join("rootfs.ext4")).unwrap(),
+            "old"
+        );
+
+        // The replacement is dropped and the old volume comes back
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("rootfs.ext4"), "new").unwrap();
+        put_back_in(&driver::CopyDriver, &dir, &aside).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(dir.join("rootfs.ext4")).unwrap(),
+            "old"
+        );
+        assert!(!aside.exists());
+
+        assert!(set_aside_in(&driver::CopyDriver, &root.join("vms/none"))
+            .unwrap()
+            .is_none());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
     #[test]
     fn validate_name_preserves_kind_in_error() {
         let err = validate_name("", "Checkpoint").unwrap_err();
@@ -0,0 +1,433 @@
+//! Storage drivers: how VM volumes are created, snapshotted, cloned and
+//! deleted on the host filesystem.
+//!
+//! The driver is chosen once at startup (`storage_driver` in server.toml).
+//! Path layout (users/{id}/vms/..., checkpoints/...) stays in `storage`;
+//! drivers only deal with the directories they are handed.
+
+use anyhow::{bail, Context, Result};
+use noid_types::DiskUsage;
+use serde::{Deserialize, Serialize};
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+use super::run_cmd;
+
+/// Which driver to use, as configured in server.toml.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageDriverKind {
+    /// btrfs if the storage dir is on btrfs, else reflink if supported, else copy.
+    #[default]
+    Auto,
+    Btrfs,
+    Reflink,
+    Copy,
+}
+
+/// Operations on VM and checkpoint volumes.
+pub trait StorageDriver: Send + Sync {
+    /// Short name for logs ("btrfs", "reflink", "copy").
+    fn name(&self) -> &'static str;
+    /// Create an empty writable volume at `path`.
+    fn create_volume(&self, path: &Path) -> Result<()>;
+    /// Copy a single file into a volume, sharing extents where supported.
+    fn copy_file(&self, src: &Path, dest: &Path) -> Result<()>;
+    /// Point-in-time copy of `src` at `dest`, read-only where supported.
+    fn snapshot(&self, src: &Path, dest: &Path) -> Result<()>;
+    /// Writable volume at `dest` initialized from `src`.
+    fn clone_volume(&self, src: &Path, dest: &Path) -> Result<()>;
+    /// Delete a volume created by any of the above. Missing paths are not an error.
+    fn delete_volume(&self, path: &Path) -> Result<()>;
+    /// Disk footprint of everything under `path`. The default is du-style
+    /// accounting, which cannot see shared extents.
+    fn usage(&self, path: &Path) -> Result<DiskUsage> {
+        du_usage(path)
+    }
+}
+
+/// btrfs subvolumes: create/snapshot/clone are instant and zero-copy.
+pub struct BtrfsDriver;
+
+/// Plain directories with `cp --reflink=always` (XFS, bcachefs, btrfs
+/// without subvolume permissions). Fails instead of silently doing a full copy.
+pub struct ReflinkDriver;
+
+/// Plain directories with full copies. Works on any filesystem.
+pub struct CopyDriver;
+
+impl StorageDriver for BtrfsDriver {
+    fn name(&self) -> &'static str {
+        "btrfs"
+    }
+
+    fn create_volume(&self, path: &Path) -> Result<()> {
+        run_cmd("btrfs", &["subvolume", "create", &path.to_string_lossy()])
+    }
+
+    fn copy_file(&self, src: &Path, dest: &Path) -> Result<()> {
+        cp(&["--reflink=auto"], src, dest)
+    }
+
+    fn snapshot(&self, src: &Path, dest: &Path) -> Result<()> {
+        // VMs created before volumes went through the driver may be plain
+        // directories, which btrfs cannot snapshot.
+        if !is_subvolume(src) {
+            return cp(&["-a", "--reflink=auto"], src, dest);
+        }
+        run_cmd(
+            "btrfs",
+            &[
+                "subvolume",
+                "snapshot",
+                "-r",
+                &src.to_string_lossy(),
+                &dest.to_string_lossy(),
+            ],
+        )
+    }
+
+    fn clone_volume(&self, src: &Path, dest: &Path) -> Result<()> {
+        if !is_subvolume(src) {
+            return cp(&["-a", "--reflink=auto"], src, dest);
+        }
+        run_cmd(
+            "btrfs",
+            &[
+                "subvolume",
+                "snapshot",
+                &src.to_string_lossy(),
+                &dest.to_string_lossy(),
+            ],
+        )
+    }
+
+    fn delete_volume(&self, path: &Path) -> Result<()> {
+        if !path.exists() {
+            return Ok(());
+        }
+        if is_subvolume(path) {
+            run_cmd("btrfs", &["subvolume", "delete", &path.to_string_lossy()])
+        } else {
+            std::fs::remove_dir_all(path)
+                .with_context(|| format!("failed to remove {}", path.display()))
+        }
+    }
+
+    fn usage(&self, path: &Path) -> Result<DiskUsage> {
+        let du = du_usage(path)?;
+        // qgroups only exist when quotas are enabled (`btrfs quota enable`);
+        // otherwise fall back to du-style numbers.
+        if is_subvolume(path) {
+            if let Some((referenced, exclusive)) = qgroup_usage(path) {
+                return Ok(DiskUsage {
+                    logical_bytes: du.logical_bytes,
+                    exclusive_bytes: exclusive,
+                    shared_bytes: referenced.saturating_sub(exclusive),
+                });
+            }
+        }
+        Ok(du)
+    }
+}
+
+impl StorageDriver for ReflinkDriver {
+    fn name(&self) -> &'static str {
+        "reflink"
+    }
+
+    fn create_volume(&self, path: &Path) -> Result<()> {
+        create_dir(path)
+    }
+
+    fn copy_file(&self, src: &Path, dest: &Path) -> Result<()> {
+        cp(&["--reflink=always"], src, dest)
+    }
+
+    fn snapshot(&self, src: &Path, dest: &Path) -> Result<()> {
+        cp(&["-a", "--reflink=always"], src, dest)
+    }
+
+    fn clone_volume(&self, src: &Path, dest: &Path) -> Result<()> {
+        cp(&["-a", "--reflink=always"], src, dest)
+    }
+
+    fn delete_volume(&self, path: &Path) -> Result<()> {
+        remove_dir(path)
+    }
+}
+
+impl StorageDriver for CopyDriver {
+    fn name(&self) -> &'static str {
+        "copy"
+    }
+
+    fn create_volume(&self, path: &Path) -> Result<()> {
+        create_dir(path)
+    }
+
+    fn copy_file(&self, src: &Path, dest: &Path) -> Result<()> {
+        cp(&["--reflink=auto"], src, dest)
+    }
+
+    fn snapshot(&self, src: &Path, dest: &Path) -> Result<()> {
+        cp(&["-a"], src, dest)
+    }
+
+    fn clone_volume(&self, src: &Path, dest: &Path) -> Result<()> {
+        cp(&["-a"], src, dest)
+    }
+
+    fn delete_volume(&self, path: &Path) -> Result<()> {
+        remove_dir(path)
+    }
+}
+
+/// Resolve a configured driver kind against the storage root.
+/// Explicit kinds fail if the filesystem cannot support them.
+pub fn select_driver(kind: StorageDriverKind, root: &Path) -> Result<Box<dyn StorageDriver>> {
+    match kind {
+        StorageDriverKind::Auto => {
+            if super::is_btrfs_mounted(root) {
+                Ok(Box::new(BtrfsDriver))
+            } else if supports_reflink(root) {
+                Ok(Box::new(ReflinkDriver))
+            } else {
+                Ok(Box::new(CopyDriver))
+            }
+        }
+        StorageDriverKind::Btrfs => {
+            if !super::is_btrfs_mounted(root) {
+                bail!(
+                    "storage_driver = \"btrfs\" but {} is not on a btrfs filesystem",
+                    root.display()
+                );
+            }
+            Ok(Box::new(BtrfsDriver))
+        }
+        StorageDriverKind::Reflink => {
+            if !supports_reflink(root) {
+                bail!(
+                    "storage_driver = \"reflink\" but {} does not support reflinks",
+                    root.display()
+                );
+            }
+            Ok(Box::new(ReflinkDriver))
+        }
+        StorageDriverKind::Copy => Ok(Box::new(CopyDriver)),
+    }
+}
+
+/// Probe whether `dir`'s filesystem supports reflink copies.
+fn supports_reflink(dir: &Path) -> bool {
+    let probe = dir.join(format!(".reflink-probe-{}", std::process::id()));
+    let probe_copy = dir.join(format!(".reflink-probe-{}.copy", std::process::id()));
+    let ok = std::fs::write(&probe, b"noid").is_ok()
+        && cp(&["--reflink=always"], &probe, &probe_copy).is_ok();
+    let _ = std::fs::remove_file(&probe);
+    let _ = std::fs::remove_file(&probe_copy);
+    ok
+}
+
+/// The root directory of a btrfs subvolume always has inode number 256.
+fn is_subvolume(path: &Path) -> bool {
+    std::fs::metadata(path).is_ok_and(|m| m.is_dir() && m.ino() == 256)
+}
+
+fn cp(flags: &[&str], src: &Path, dest: &Path) -> Result<()> {
+    let src = src.to_string_lossy();
+    let dest = dest.to_string_lossy();
+    let mut args = flags.to_vec();
+    args.push(&src);
+    args.push(&dest);
+    run_cmd("cp", &args)
+}
+
+fn create_dir(path: &Path) -> Result<()> {
+    std::fs::create_dir(path).with_context(|| format!("failed to create {}", path.display()))
+}
+
+fn remove_dir(path: &Path) -> Result<()> {
+    if path.exists() {
+        std::fs::remove_dir_all(path)
+            .with_context(|| format!("failed to remove {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Referenced and exclusive bytes of the subvolume at `path`, from its
+/// level-0 qgroup. `None` if quotas are disabled or the command fails.
+fn qgroup_usage(path: &Path) -> Option<(u64, u64)> {
+    let output = std::process::Command::new("btrfs")
+        .args(["qgroup", "show", "-f", "--raw"])
+        .arg(path)
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    parse_qgroup_show(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Parse `btrfs qgroup show -f --raw` output:
+///
+/// ```text
+/// qgroupid         rfer         excl
+/// --------         ----         ----
+/// 0/258      1073741824     16777216
+/// ```
+fn parse_qgroup_show(output: &str) -> Option<(u64, u64)> {
+    output.lines().find_map(|line| {
+        let mut fields = line.split_whitespace();
+        if !fields.next()?.starts_with("0/") {
+            return None;
+        }
+        let referenced = fields.next()?.parse().ok()?;
+        let exclusive = fields.next()?.parse().ok()?;
+        Some((referenced, exclusive))
+    })
+}
+
+/// du-style accounting: apparent size of regular files as logical bytes and
+/// allocated blocks as exclusive bytes, each hard-linked inode counted once.
+pub(crate) fn du_usage(path: &Path) -> Result<DiskUsage> {
+    let mut seen = std::collections::HashSet::new();
+    let mut usage = DiskUsage::default();
+    let mut stack = vec![path.to_path_buf()];
+    while let Some(p) = stack.pop() {
+        let meta = match std::fs::symlink_metadata(&p) {
+            Ok(m) => m,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e).with_context(|| format!("failed to stat {}", p.display())),
+        };
+        if !seen.insert((meta.dev(), meta.ino())) {
+            continue;
+        }
+        usage.exclusive_bytes += meta.blocks() * 512;
+        if meta.is_file() {
+            usage.logical_bytes += meta.len();
+        } else if meta.is_dir() {
+            for entry in std::fs::read_dir(&p)? {
+                stack.push(entry?.path());
+            }
+        }
+    }
+    Ok(usage)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::tempdir;
+
+    #[test]
+    fn copy_driver_volume_lifecycle() {
+        let root = tempdir("driver-lifecycle");
+        let driver = CopyDriver;
+
+        let vm = root.join("vm");
+        driver.create_volume(&vm).unwrap();
+        assert!(driver.create_volume(&vm).is_err(), "volume already exists");
+
+        let base = root.join("base.ext4");
+        std::fs::write(&base, vec![7u8; 64 * 1024]).unwrap();
+        driver.copy_file(&base, &vm.join("rootfs.ext4")).unwrap();
+        std::fs::write(vm.join("memory.snap"), b"mem").unwrap();
+
+        let snap = root.join("snap");
+        driver.snapshot(&vm, &snap).unwrap();
+        assert_eq!(std::fs::read(snap.join("memory.snap")).unwrap(), b"mem");
+
+        // Snapshot is independent of later writes to the source
+        std::fs::write(vm.join("memory.snap"), b"changed").unwrap();
+        assert_eq!(std::fs::read(snap.join("memory.snap")).unwrap(), b"mem");
+
+        let clone = root.join("clone");
+        driver.clone_volume(&snap, &clone).unwrap();
+        assert_eq!(
+            std::fs::read(clone.join("rootfs.ext4")).unwrap().len(),
+            64 * 1024
+        );
+
+        assert_eq!(driver.usage(&clone).unwrap().logical_bytes, 64 * 1024 + 3);
+
+        driver.delete_volume(&clone).unwrap();
+        assert!(!clone.exists());
+        driver.delete_volume(&clone).unwrap();
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn copy_driver_snapshot_handles_fifo() {
+        let root = tempdir("driver-fifo");
+        let driver = CopyDriver;
+        let vm = root.join("vm");
+        driver.create_volume(&vm).unwrap();
+        nix::unistd::mkfifo(
+            &vm.join("serial.in"),
+            nix::sys::stat::Mode::from_bits_truncate(0o600),
+        )
+        .unwrap();
+        driver.snapshot(&vm, &root.join("snap")).unwrap();
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn usage_counts_hard_links_once() {
+        let root = tempdir("driver-usage");
+        std::fs::write(root.join("a"), vec![1u8; 16 * 1024]).unwrap();
+        let single = du_usage(&root).unwrap();
+        assert_eq!(single.logical_bytes, 16 * 1024);
+        std::fs::hard_link(root.join("a"), root.join("b")).unwrap();
+        assert_eq!(du_usage(&root).unwrap(), single);
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn usage_of_missing_path_is_zero() {
+        let root = tempdir("driver-missing");
+        assert_eq!(du_usage(&root.join("nope")).unwrap(), DiskUsage::default());
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn usage_logical_counts_sparse_size() {
+        let root = tempdir("driver-sparse");
+        let f = std::fs::File::create(root.join("sparse.img")).unwrap();
+        f.set_len(8 * 1024 * 1024).unwrap();
+        let usage = du_usage(&root).unwrap();
+        assert_eq!(usage.logical_bytes, 8 * 1024 * 1024);
+        assert!(usage.exclusive_bytes < usage.logical_bytes);
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn parse_qgroup_show_reads_level0_row() {
+        let out = "qgroupid         rfer         excl \n\
+                   --------         ----         ---- \n\
+                   0/258      1073741824     16777216 \n";
+        assert_eq!(parse_qgroup_show(out), Some((1073741824, 16777216)));
+    }
+
+    #[test]
+    fn parse_qgroup_show_rejects_missing_row() {
+        assert_eq!(parse_qgroup_show("qgroupid rfer excl\n"), None);
+        assert_eq!(parse_qgroup_show(""), None);
+    }
+
+    #[test]
+    fn select_copy_driver_always_works() {
+        let root = tempdir("driver-select");
+        let driver = select_driver(StorageDriverKind::Copy, &root).unwrap();
+        assert_eq!(driver.name(), "copy");
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn driver_kind_deserializes_lowercase() {
+        let kind: StorageDriverKind = serde_json::from_str("\"reflink\"").unwrap();
+        assert_eq!(kind, StorageDriverKind::Reflink);
+        assert_eq!(StorageDriverKind::default(), StorageDriverKind::Auto);
+    }
+}
@@ -1,19 +1,104 @@
 use anyhow::{bail, Context, Result};
+use std::collections::{HashMap, HashSet};
 use std::io::{Read, Write};
 use std::os::unix::net::UnixStream;
 use std::path::{Path, PathBuf};
-use std::process::{Command, Stdio};
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::{Mutex, OnceLock};
 use std::time::Duration;
 
-const FIRECRACKER_BIN: &str = "/usr/local/bin/firecracker";
+use crate::jail::{Jail, JailSpec};
+use noid_types::{IoLimit, RateLimits};
 
-/// Spawn a Firecracker process with serial console I/O via files.
+/// How often the guest's balloon driver reports memory statistics.
+const BALLOON_STATS_INTERVAL_SECS: u32 = 5;
+
+/// A Firecracker process started by [`spawn_fc`] has exited.
+#[derive(Debug)]
+pub struct ProcessExit {
+    pub pid: u32,
+    /// `None` if waiting for the process failed.
+    pub status: Option<ExitStatus>,
+}
+
+type ExitHandler = Box<dyn Fn(ProcessExit) + Send + Sync>;
+
+/// Called on the reaper thread of every process spawned by [`spawn_fc`].
+static EXIT_HANDLER: OnceLock<ExitHandler> = OnceLock::new();
+
+/// PIDs of Firecracker processes this process spawned and has not reaped.
+static CHILDREN: Mutex<Option<HashSet<u32>>> = Mutex::new(None);
+
+/// Install the handler told about every Firecracker exit. Only the first
+/// call has an effect.
+pub fn set_exit_handler(handler: ExitHandler) {
+    let _ = EXIT_HANDLER.set(handler);
+}
+
+/// Whether `pid` is a Firecracker process spawned (and not yet reaped) by
+/// this process, so its exit will reach the exit handler.
+pub fn is_child(pid: u32) -> bool {
+    CHILDREN
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .as_ref()
+        .is_some_and(|c| c.contains(&pid))
+}
+
+/// Jails of Firecracker processes this process spawned, by PID, until they
+/// are cleaned up.
+static JAILS: Mutex<Option<HashMap<u32, Jail>>> = Mutex::new(None);
+
+fn set_child(pid: u32, alive: bool) {
+    let mut children = CHILDREN.lock().unwrap_or_else(|e| e.into_inner());
+    let children = children.get_or_insert_with(HashSet::new);
+    if alive {
+        children.insert(pid);
+    } else {
+        children.remove(&pid);
+    }
+}
+
+fn insert_jail(pid: u32, jail: Jail) {
+    let mut jails = JAILS.lock().unwrap_or_else(|e| e.into_inner());
+    jails.get_or_insert_with(HashMap::new).insert(pid, jail);
+}
+
+/// Take the jail of `pid` for cleanup: one this process spawned, or that of
+/// a jailed process still running from before a server restart.
+fn take_jail(pid: u32) -> Option<Jail> {
+    let mut jails = JAILS.lock().unwrap_or_else(|e| e.into_inner());
+    jails
+        .as_mut()
+        .and_then(|j| j.remove(&pid))
+        .or_else(|| Jail::of_process(pid))
+}
+
+/// Where host path `path` is seen by process `pid`: inside its chroot if
+/// it is jailed.
+fn path_seen_by(pid: u32, path: &Path) -> PathBuf {
+    let jails = JAILS.lock().unwrap_or_else(|e| e.into_inner());
+    match jails.as_ref().and_then(|j| j.get(&pid)) {
+        Some(jail) => jail.host_path(path),
+        None => match Jail::of_process(pid) {
+            Some(jail) => jail.host_path(path),
+            None => path.to_path_buf(),
+        },
+    }
+}
+
+/// Spawn Firecracker binary `firecracker` with serial console I/O via files,
+/// through the jailer if `jail` is given.
 ///
 /// stdin  = named FIFO at serial.in  (any process can write to it later)
 /// stdout = regular file at serial.log (any process can tail it)
 ///
 /// Returns (pid, socket_path).
-pub fn spawn_fc(subvol: &Path) -> Result<(u32, String)> {
+pub fn spawn_fc(
+    subvol: &Path,
+    firecracker: &Path,
+    jail: Option<&JailSpec>,
+) -> Result<(u32, String)> {
     let socket_path = subvol.join("firecracker.sock");
     let log_path = subvol.join("firecracker.log");
     let serial_out = subvol.join("serial.log");
@@ -34,11 +119,14 @@ pub fn spawn_fc(subvol: &Path) -> Result<(u32, String)> {
     // (no writer yet). We pass this as FC's stdin.
     use std::os::unix::io::FromRawFd;
 
-    let read_fd = nix::fcntl::open(
-        &serial_in,
-        nix::fcntl::OFlag::O_RDONLY | nix::fcntl::OFlag::O_NONBLOCK,
-        nix::sys::stat::Mode::empty(),
-    )
+    // The jailer closes every fd but stdio, so the sentinel writer below
+    // would not survive it: a jailed FC gets the FIFO read-write instead,
+    // which makes it a writer itself.
+    let read_flags = match jail {
+        Some(_) => nix::fcntl::OFlag::O_RDWR,
+        None => nix::fcntl::OFlag::O_RDONLY | nix::fcntl::OFlag::O_NONBLOCK,
+    };
+    let read_fd = nix::fcntl::open(&serial_in, read_flags, nix::sys::stat::Mode::empty())
     .context("failed to open serial.in FIFO for reading")?;
 
     // Clear O_NONBLOCK so FC reads block normally
@@ -59,7 +147,28 @@ pub fn spawn_fc(subvol: &Path) -> Result<(u32, String)> {
 
     let stdin_file = unsafe { std::fs::File::from_raw_fd(read_fd) };
 
-    let mut child = Command::new(FIRECRACKER_BIN)
+    // Jailed, Firecracker runs as another user and cannot create its log.
+    std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&log_path)
+        .context("failed to create firecracker.log")?;
+    let jail = match jail {
+        Some(spec) => Some((spec, Jail::prepare(spec, firecracker, subvol)?)),
+        None => None,
+    };
+    let mut command = match jail {
+        Some((spec, ref jail)) => {
+            let mut command = Command::new(&spec.config.binary);
+            command
+                .args(spec.jailer_args(&jail.id, &firecracker.to_string_lossy()))
+                .arg("--");
+            command
+        }
+        None => Command::new(firecracker),
+    };
+
+    let spawned = command
         .arg("--api-sock")
         .arg(&socket_path)
         .arg("--log-path")
@@ -70,9 +179,22 @@ pub fn spawn_fc(subvol: &Path) -> Result<(u32, String)> {
         .stdout(serial_file)
         .stderr(Stdio::null())
         .spawn()
-        .context("failed to spawn firecracker")?;
+        .context("failed to spawn firecracker");
+    let mut child = match spawned {
+        Ok(child) => child,
+        Err(e) => {
+            if let Some((_, jail)) = jail {
+                jail.cleanup();
+            }
+            unsafe { libc::close(_sentinel_fd) };
+            return Err(e);
+        }
+    };
 
     let pid = child.id();
+    if let Some((_, jail)) = jail {
+        insert_jail(pid, jail);
+    }
 
     // Close the sentinel writer fd in the parent — FC inherits a copy,
     // so the FIFO stays alive. We must close ours to avoid leaking fds.
@@ -81,8 +203,16 @@ pub fn spawn_fc(subvol: &Path) -> Result<(u32, String)> {
 
     // Spawn a background reaper thread so the child doesn't become a zombie.
     // We can't just call wait() here (it would block until FC exits).
+    set_child(pid, true);
     std::thread::spawn(move || {
-        let _ = child.wait();
+        let status = child.wait().ok();
+        if let Some(jail) = take_jail(pid) {
+            jail.cleanup();
+        }
+        if let Some(handler) = EXIT_HANDLER.get() {
+            handler(ProcessExit { pid, status });
+        }
+        set_child(pid, false);
     });
 
     wait_for_socket(&socket_path, Duration::from_secs(5))?;
@@ -107,12 +237,16 @@ pub fn write_to_serial(vm_dir: &Path, data: &[u8]) -> Result<()> {
     Ok(())
 }
 
-/// Kill a VM process (SIGTERM then SIGKILL)
+/// Kill a VM process (SIGTERM then SIGKILL) and remove its jail, if any.
 pub fn kill_vm_process(pid: i64) {
+    let jail = take_jail(pid as u32);
     let pid = nix::unistd::Pid::from_raw(pid as i32);
     let _ = nix::sys::signal::kill(pid, nix::sys::signal::Signal::SIGTERM);
     std::thread::sleep(Duration::from_millis(500));
     let _ = nix::sys::signal::kill(pid, nix::sys::signal::Signal::SIGKILL);
+    if let Some(jail) = jail {
+        jail.cleanup();
+    }
 }
 
 /// Check if a process is alive
@@ -120,14 +254,94 @@ pub fn is_process_alive(pid: i32) -> bool {
     nix::sys::signal::kill(nix::unistd::Pid::from_raw(pid), None).is_ok()
 }
 
+/// Host-side resource use of a VM's Firecracker process.
+#[derive(Debug)]
+pub struct ProcessStats {
+    /// Time since the process started.
+    pub uptime: Duration,
+    /// User plus system CPU time used so far.
+    pub cpu_secs: f64,
+    pub rss_bytes: u64,
+}
+
+/// Read a process's uptime, CPU time and resident memory from /proc.
+pub fn process_stats(pid: u32) -> Option<ProcessStats> {
+    let stat = std::fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    // The command name is in parentheses and may contain spaces; fields
+    // after it start with field 3 (state).
+    let fields: Vec<&str> = stat.rsplit_once(')')?.1.split_whitespace().collect();
+    let field = |n: usize| -> Option<u64> { fields.get(n - 3)?.parse().ok() };
+    let ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+    let ticks = if ticks > 0 { ticks as f64 } else { 100.0 };
+
+    let cpu_secs = (field(14)? + field(15)?) as f64 / ticks;
+    let started = field(22)? as f64 / ticks;
+    let host_uptime: f64 = std::fs::read_to_string("/proc/uptime")
+        .ok()?
+        .split_whitespace()
+        .next()?
+        .parse()
+        .ok()?;
+    let uptime = Duration::from_secs_f64((host_uptime - started).max(0.0));
+
+    let status = std::fs::read_to_string(format!("/proc/{pid}/status")).ok()?;
+    let rss_kib: u64 = status
+        .lines()
+        .find_map(|l| l.strip_prefix("VmRSS:"))?
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()?;
+
+    Some(ProcessStats {
+        uptime,
+        cpu_secs,
+        rss_bytes: rss_kib * 1024,
+    })
+}
+
+/// Shut a VM down cleanly: send Ctrl+Alt+Del, after which the guest reboots
+/// and Firecracker exits. Falls back to killing the process if it is still
+/// alive after `timeout`.
+pub fn shutdown_vm(pid: i64, socket_path: &str, timeout: Duration) {
+    let jail = take_jail(pid as u32);
+    let sent = fc_put(
+        socket_path,
+        "/actions",
+        &serde_json::json!({ "action_type": "SendCtrlAltDel" }),
+    );
+    if sent.is_ok() {
+        let deadline = std::time::Instant::now() + timeout;
+        while std::time::Instant::now() < deadline {
+            if !is_process_alive(pid as i32) {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(200));
+        }
+    }
+    if is_process_alive(pid as i32) {
+        kill_vm_process(pid);
+    }
+    if let Some(jail) = jail {
+        jail.cleanup();
+    }
+}
+
 // --- Firecracker API ---
 
 pub fn fc_put(socket_path: &str, path: &str, body: &serde_json::Value) -> Result<()> {
-    fc_request("PUT", socket_path, path, body)
+    fc_request("PUT", socket_path, path, Some(body)).map(drop)
 }
 
 pub fn fc_patch(socket_path: &str, path: &str, body: &serde_json::Value) -> Result<()> {
-    fc_request("PATCH", socket_path, path, body)
+    fc_request("PATCH", socket_path, path, Some(body)).map(drop)
+}
+
+pub fn fc_get(socket_path: &str, path: &str) -> Result<serde_json::Value> {
+    let body = fc_request("GET", socket_path, path, None)?;
+    serde_json::from_str(&body)
+        .with_context(|| format!("invalid Firecracker response for GET {path}"))
 }
 
 pub fn pause_vm(socket_path: &str) -> Result<()> {
@@ -148,6 +362,98 @@ pub fn resume_vm(socket_path: &str) -> Result<()> {
     .context("failed to resume VM")
 }
 
+/// MAC address of the VM's network interface, as configured in Firecracker.
+pub fn guest_mac(socket_path: &str) -> Option<String> {
+    let config = fc_get(socket_path, "/vm/config").ok()?;
+    config["network-interfaces"]
+        .as_array()?
+        .iter()
+        .find_map(|iface| iface["guest_mac"].as_str())
+        .map(str::to_string)
+}
+
+/// Whether the VM was booted with a balloon device.
+pub fn has_balloon(socket_path: &str) -> bool {
+    fc_get(socket_path, "/balloon").is_ok()
+}
+
+/// Inflate or deflate the balloon to hold `amount_mib` of guest memory.
+pub fn set_balloon(socket_path: &str, amount_mib: u32) -> Result<()> {
+    fc_patch(
+        socket_path,
+        "/balloon",
+        &serde_json::json!({ "amount_mib": amount_mib }),
+    )
+    .context("failed to resize balloon")
+}
+
+/// The balloon's size and the guest's memory statistics, as reported by
+/// Firecracker's `GET /balloon/statistics`. Sizes of guest memory are in
+/// bytes.
+pub fn balloon_stats(socket_path: &str) -> Result<serde_json::Value> {
+    fc_get(socket_path, "/balloon/statistics").context("failed to read balloon statistics")
+}
+
+/// Firecracker rate limiter for `limit`: token buckets refilled once a
+/// second. An unset limit is sent as an empty bucket, which Firecracker
+/// treats as no limit, so the same body also lifts a limit on a live VM.
+fn rate_limiter(limit: &IoLimit) -> serde_json::Value {
+    let bucket = |per_sec: Option<u64>| match per_sec {
+        Some(n) => serde_json::json!({ "size": n, "refill_time": 1000 }),
+        None => serde_json::json!({ "size": 0, "refill_time": 0 }),
+    };
+    serde_json::json!({
+        "bandwidth": bucket(limit.bandwidth),
+        "ops": bucket(limit.ops)
+    })
+}
+
+fn set_net_rate_limits(socket_path: &str, limit: &IoLimit) -> Result<()> {
+    fc_patch(
+        socket_path,
+        "/network-interfaces/eth0",
+        &serde_json::json!({
+            "iface_id": "eth0",
+            "rx_rate_limiter": rate_limiter(limit),
+            "tx_rate_limiter": rate_limiter(limit)
+        }),
+    )
+    .context("failed to set network rate limits")
+}
+
+/// Change the rate limits of a running or paused VM. `net` is whether it
+/// has a network interface.
+pub fn set_rate_limits(socket_path: &str, limits: &RateLimits, net: bool) -> Result<()> {
+    fc_patch(
+        socket_path,
+        "/drives/rootfs",
+        &serde_json::json!({
+            "drive_id": "rootfs",
+            "rate_limiter": rate_limiter(&limits.disk)
+        }),
+    )
+    .context("failed to set disk rate limits")?;
+    if net {
+        set_net_rate_limits(socket_path, &limits.net)?;
+    }
+    Ok(())
+}
+
+/// Point a running VM's root drive at `rootfs_path`, after its file moved.
+/// Firecracker keeps the file open either way, but snapshots record the
+/// drive's path.
+pub fn set_rootfs_path(socket_path: &str, rootfs_path: &str) -> Result<()> {
+    fc_patch(
+        socket_path,
+        "/drives/rootfs",
+        &serde_json::json!({
+            "drive_id": "rootfs",
+            "path_on_host": rootfs_path
+        }),
+    )
+    .context("failed to patch root drive")
+}
+
 pub fn create_fc_snapshot(socket_path: &str, snap_dir: &Path) -> Result<()> {
     let mem_path = snap_dir.join("memory.snap");
     let state_path = snap_dir.join("vmstate.snap");
@@ -166,13 +472,15 @@ pub fn create_fc_snapshot(socket_path: &str, snap_dir: &Path) -> Result<()> {
 /// Load a Firecracker snapshot, resume with new rootfs and TAP device.
 ///
 /// Networking is remapped via `network_overrides` inside the snapshot load
-/// request — Firecracker does not allow PUT/PATCH on network interfaces
-/// either before or after snapshot load as separate API calls.
+/// request — Firecracker does not allow changing a network interface's TAP
+/// device by PUT/PATCH either before or after snapshot load. Rate limits
+/// can be patched once loaded, and are set from `limits`.
 pub fn load_and_restore_snapshot(
     socket_path: &str,
     snap_dir: &Path,
     rootfs_path: &str,
     net: Option<&crate::network::NetworkConfig>,
+    limits: &RateLimits,
 ) -> Result<()> {
     let mem_path = snap_dir.join("memory.snap");
     let state_path = snap_dir.join("vmstate.snap");
@@ -196,16 +504,21 @@ pub fn load_and_restore_snapshot(
     }
     fc_put(socket_path, "/snapshot/load", &load_body).context("failed to load FC snapshot")?;
 
-    // 2. PATCH drive to point at new rootfs copy
+    // 2. PATCH drive to point at new rootfs copy. The snapshot carries the
+    //    limits of the VM it was taken from, so set this VM's either way.
     fc_patch(
         socket_path,
         "/drives/rootfs",
         &serde_json::json!({
             "drive_id": "rootfs",
-            "path_on_host": rootfs_path
+            "path_on_host": rootfs_path,
+            "rate_limiter": rate_limiter(&limits.disk)
         }),
     )
     .context("failed to patch root drive after restore")?;
+    if net.is_some() {
+        set_net_rate_limits(socket_path, &limits.net)?;
+    }
 
     // 3. Resume VM
     resume_vm(socket_path)?;
@@ -234,11 +547,12 @@ pub fn load_fc_snapshot(socket_path: &str, snap_dir: &Path) -> Result<()> {
 
 pub fn configure_and_start_vm(
     socket_path: &str,
-    kernel: &str,
+    boot: &crate::boot::BootConfig,
     rootfs_path: &str,
     cpus: u32,
     mem_mib: u32,
     net: Option<&crate::network::NetworkConfig>,
+    limits: &RateLimits,
 ) -> Result<()> {
     fc_put(
         socket_path,
@@ -250,22 +564,12 @@ pub fn configure_and_start_vm(
     )
     .context("failed to set machine config")?;
 
-    // Build boot args — append kernel ip= param if networking is configured.
-    // net.ifnames=0 biosdevname=0 keep the NIC named eth0 (reconfigure_guest_network depends on this).
-    let mut boot_args =
-        "console=ttyS0 reboot=k panic=1 pci=off root=/dev/vda rw quiet loglevel=1 systemd.show_status=auto net.ifnames=0 biosdevname=0"
-            .to_string();
-    if let Some(net_config) = net {
-        boot_args.push(' ');
-        boot_args.push_str(&crate::network::kernel_ip_param(net_config));
-    }
-
     fc_put(
         socket_path,
         "/boot-source",
         &serde_json::json!({
-            "kernel_image_path": kernel,
-            "boot_args": boot_args
+            "kernel_image_path": boot.kernel,
+            "boot_args": boot.cmdline(net)
         }),
     )
     .context("failed to set boot source")?;
@@ -277,7 +581,8 @@ pub fn configure_and_start_vm(
             "drive_id": "rootfs",
             "path_on_host": rootfs_path,
             "is_root_device": true,
-            "is_read_only": false
+            "is_read_only": false,
+            "rate_limiter": rate_limiter(&limits.disk)
         }),
     )
     .context("failed to set root drive")?;
@@ -290,12 +595,27 @@ pub fn configure_and_start_vm(
             &serde_json::json!({
                 "iface_id": "eth0",
                 "guest_mac": net_config.guest_mac,
-                "host_dev_name": net_config.tap_name
+                "host_dev_name": net_config.tap_name,
+                "rx_rate_limiter": rate_limiter(&limits.net),
+                "tx_rate_limiter": rate_limiter(&limits.net)
             }),
         )
         .context("failed to set network interface")?;
     }
 
+    // Balloon device, empty at boot. It lets memory be reclaimed from the
+    // guest later; the guest gets it back if it runs out.
+    fc_put(
+        socket_path,
+        "/balloon",
+        &serde_json::json!({
+            "amount_mib": 0,
+            "deflate_on_oom": true,
+            "stats_polling_interval_s": BALLOON_STATS_INTERVAL_SECS
+        }),
+    )
+    .context("failed to set balloon device")?;
+
     // Entropy device provides virtio-rng backed by host /dev/urandom.
     // Without this, getrandom() blocks after snapshot restore (stale entropy pool),
     // causing TLS handshakes to hang indefinitely.
@@ -325,8 +645,10 @@ pub fn configure_and_start_vm(
 /// Firecracker may need to open the original backing-file path during
 /// `/snapshot/load`, before we can patch `/drives/rootfs` to the new VM path.
 /// If that source path is missing, create a temporary symlink to `actual_rootfs`.
+/// Paths are as seen by Firecracker process `pid`, which may be jailed.
 /// Returns the symlink path if one was created.
 pub fn ensure_snapshot_rootfs_path(
+    pid: u32,
     source_rootfs_path: &str,
     actual_rootfs_path: &str,
 ) -> Result<Option<PathBuf>> {
@@ -345,6 +667,7 @@ pub fn ensure_snapshot_rootfs_path(
         );
     }
 
+    let source = &path_seen_by(pid, source);
     if source.exists() {
         return Ok(None);
     }
@@ -421,29 +744,17 @@ fn wait_for_socket(path: &Path, timeout: Duration) -> Result<()> {
     bail!("timed out waiting for socket at {}", path.display())
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn extract_rootfs_path_from_vmstate_finds_embedded_path() {
-        let dir = std::env::temp_dir().join(format!("noid-vmtest-{}", std::process::id()));
-        let _ = std::fs::remove_dir_all(&dir);
-        std::fs::create_dir_all(&dir).unwrap();
-        let vmstate = dir.join("vmstate.snap");
-        let payload = b"abc/home/firecracker/.noid/storage/users/u/vms/_golden/rootfs.ext4xyz";
-        std::fs::write(&vmstate, payload).unwrap();
-        let found = extract_rootfs_path_from_vmstate(&dir).unwrap();
-        assert_eq!(
-            found,
-            "/home/firecracker/.noid/storage/users/u/vms/_golden/rootfs.ext4"
-        );
-        let _ = std::fs::remove_dir_all(&dir);
-    }
-}
-
-fn fc_request(method: &str, socket_path: &str, path: &str, body: &serde_json::Value) -> Result<()> {
-    let body_str = serde_json::to_string(body)?;
+/// Send one request to the Firecracker API and return the response body.
+fn fc_request(
+    method: &str,
+    socket_path: &str,
+    path: &str,
+    body: Option<&serde_json::Value>,
+) -> Result<String> {
+    let body_str = match body {
+        Some(body) => serde_json::to_string(body)?,
+        None => String::new(),
+    };
     let request = format!(
         "{method} {path} HTTP/1.1\r\n\
          Host: localhost\r\n\
@@ -502,10 +813,50 @@ fn fc_request(method: &str, socket_path: &str, path: &str, body: &serde_json::Va
         .and_then(|s| s.parse().ok())
         .unwrap_or(0);
 
+    let body = response.split("\r\n\r\n").nth(1);
     if (200..300).contains(&status_code) {
-        Ok(())
+        Ok(body.unwrap_or("").to_string())
     } else {
-        let body = response.split("\r\n\r\n").nth(1).unwrap_or("unknown error");
+        let body = body.unwrap_or("unknown error");
         bail!("Firecracker API error (HTTP {status_code}): {body}")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_limiter_buckets_refill_every_second() {
+        let limiter = rate_limiter(&IoLimit {
+            bandwidth: Some(50 << 20),
+            ops: None,
+        });
+        assert_eq!(limiter["bandwidth"]["size"], 50 << 20);
+        assert_eq!(limiter["bandwidth"]["refill_time"], 1000);
+        assert_eq!(limiter["ops"]["size"], 0);
+    }
+
+    #[test]
+    fn process_stats_of_own_process() {
+        let stats = process_stats(std::process::id()).unwrap();
+        assert!(stats.rss_bytes > 0);
+        assert!(stats.cpu_secs >= 0.0);
+        assert!(stats.uptime < Duration::from_secs(24 * 60 * 60));
+        assert!(process_stats(u32::MAX).is_none());
+    }
+
+    #[test]
+    fn extract_rootfs_path_from_vmstate_finds_embedded_path() {
+        let dir = crate::test_util::tempdir("vmstate");
+        let vmstate = dir.join("vmstate.snap");
+        let payload = b"abc/home/firecracker/.noid/storage/users/u/vms/_golden/rootfs.ext4xyz";
+        std::fs::write(&vmstate, payload).unwrap();
+        let found = extract_rootfs_path_from_vmstate(&dir).unwrap();
+        assert_eq!(
+            found,
+            "/home/firecracker/.noid/storage/users/u/vms/_golden/rootfs.ext4"
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
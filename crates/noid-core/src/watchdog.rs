@@ -0,0 +1,208 @@
+//! Crash handling: what to record when a VM's Firecracker process exits on
+//! its own, and whether and when to restart it.
+//!
+//! The backend owns the watchdog threads; this module holds the policy.
+
+use noid_types::RestartPolicy;
+use std::collections::HashMap;
+use std::os::unix::process::ExitStatusExt;
+use std::path::Path;
+use std::process::ExitStatus;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Restarts attempted in a row before the watchdog gives up on a VM.
+pub const MAX_RESTARTS: u32 = 5;
+
+/// A VM that stays up this long after a restart starts over with a clean
+/// record, so a crash days later is not counted against it.
+const HEALTHY_AFTER: Duration = Duration::from_secs(10 * 60);
+
+/// Lines of the serial console and Firecracker logs kept with a crash.
+const TAIL_LINES: usize = 20;
+
+/// What happened to a VM whose process exited.
+#[derive(Debug)]
+pub struct CrashReport {
+    /// One line for event listings, e.g. "firecracker killed by signal 9".
+    pub message: String,
+    /// Exit status plus the end of the serial console and Firecracker logs.
+    pub detail: String,
+    /// Whether this counts as a failure for the `on-failure` policy.
+    pub failure: bool,
+}
+
+/// Describe the exit of the Firecracker process of the VM in `vm_dir`.
+/// `status` is `None` when it was not our child and its status is unknown.
+pub fn crash_report(vm_dir: &Path, status: Option<ExitStatus>) -> CrashReport {
+    let serial = tail_lines(&vm_dir.join("serial.log"), TAIL_LINES);
+    let panicked = serial.iter().any(|l| l.contains("Kernel panic"));
+
+    let (mut message, mut failure) = match status {
+        Some(s) if s.success() => ("firecracker exited".to_string(), false),
+        Some(s) => match (s.code(), s.signal()) {
+            (Some(code), _) => (format!("firecracker exited with status {code}"), true),
+            (None, Some(sig)) => (format!("firecracker killed by signal {sig}"), true),
+            _ => (format!("firecracker exited ({s})"), true),
+        },
+        None => ("firecracker process disappeared".to_string(), true),
+    };
+    if panicked {
+        message = format!("guest kernel panic; {message}");
+        failure = true;
+    }
+
+    let mut detail = format!("{message}\n");
+    for (title, lines) in [
+        ("serial console", serial),
+        (
+            "firecracker log",
+            tail_lines(&vm_dir.join("firecracker.log"), TAIL_LINES),
+        ),
+    ] {
+        if lines.is_empty() {
+            continue;
+        }
+        detail.push_str(&format!("--- last {} lines of {title} ---\n", lines.len()));
+        for line in lines {
+            detail.push_str(&line);
+            detail.push('\n');
+        }
+    }
+
+    CrashReport {
+        message,
+        detail,
+        failure,
+    }
+}
+
+/// The last `n` lines of a text file; empty if it can't be read.
+fn tail_lines(path: &Path, n: usize) -> Vec<String> {
+    let Ok(bytes) = std::fs::read(path) else {
+        return Vec::new();
+    };
+    let text = String::from_utf8_lossy(&bytes);
+    let lines: Vec<&str> = text
+        .lines()
+        .map(|l| l.trim_end_matches('\r'))
+        .filter(|l| !l.trim().is_empty())
+        .collect();
+    lines[lines.len().saturating_sub(n)..]
+        .iter()
+        .map(|l| l.to_string())
+        .collect()
+}
+
+/// Whether a VM with `policy` is restarted after an exit.
+pub fn should_restart(policy: RestartPolicy, failure: bool) -> bool {
+    match policy {
+        RestartPolicy::Never => false,
+        RestartPolicy::OnFailure => failure,
+        RestartPolicy::Always => true,
+    }
+}
+
+/// How long to wait before restart number `attempt` (starting at 0):
+/// 1s, 2s, 4s, ... capped at a minute.
+pub fn restart_delay(attempt: u32) -> Duration {
+    Duration::from_secs(1u64 << attempt.min(6)).min(Duration::from_secs(60))
+}
+
+/// Counts consecutive restarts per VM so a VM that keeps crashing is
+/// eventually left dead.
+#[derive(Default)]
+pub struct RestartTracker {
+    /// (user_id, vm name) → (restarts so far, time of the last one)
+    attempts: Mutex<HashMap<(String, String), (u32, Instant)>>,
+}
+
+impl RestartTracker {
+    /// Claim the next restart attempt for a VM. Returns the attempt number,
+    /// or `None` once `MAX_RESTARTS` have been used up.
+    pub fn next_attempt(&self, user_id: &str, name: &str) -> Option<u32> {
+        let mut attempts = self.attempts.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        let entry = attempts
+            .entry((user_id.to_string(), name.to_string()))
+            .or_insert((0, now));
+        if now.duration_since(entry.1) >= HEALTHY_AFTER {
+            entry.0 = 0;
+        }
+        if entry.0 >= MAX_RESTARTS {
+            return None;
+        }
+        let attempt = entry.0;
+        *entry = (attempt + 1, now);
+        Some(attempt)
+    }
+
+    /// Drop the record of a VM, e.g. when it is destroyed.
+    pub fn forget(&self, user_id: &str, name: &str) {
+        let mut attempts = self.attempts.lock().unwrap_or_else(|e| e.into_inner());
+        attempts.remove(&(user_id.to_string(), name.to_string()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crash_report_classifies_exits() {
+        let dir = crate::test_util::tempdir("watchdog");
+
+        let clean = crash_report(&dir, Some(ExitStatus::from_raw(0)));
+        assert!(!clean.failure);
+        assert_eq!(clean.message, "firecracker exited");
+
+        let killed = crash_report(&dir, Some(ExitStatus::from_raw(9)));
+        assert!(killed.failure);
+        assert_eq!(killed.message, "firecracker killed by signal 9");
+
+        let failed = crash_report(&dir, Some(ExitStatus::from_raw(1 << 8)));
+        assert_eq!(failed.message, "firecracker exited with status 1");
+        assert!(crash_report(&dir, None).failure);
+
+        // A guest that panics usually makes Firecracker exit cleanly.
+        let serial: String = (0..30).map(|i| format!("boot line {i}\n")).collect();
+        std::fs::write(
+            dir.join("serial.log"),
+            serial + "Kernel panic - not syncing: Attempted to kill init!\n",
+        )
+        .unwrap();
+        let panic = crash_report(&dir, Some(ExitStatus::from_raw(0)));
+        assert!(panic.failure);
+        assert!(panic.message.starts_with("guest kernel panic"));
+        assert!(panic.detail.contains("Attempted to kill init"));
+        assert!(panic.detail.contains("boot line 29"));
+        assert!(!panic.detail.contains("boot line 10\n"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn restart_policy_and_backoff() {
+        assert!(!should_restart(RestartPolicy::Never, true));
+        assert!(should_restart(RestartPolicy::OnFailure, true));
+        assert!(!should_restart(RestartPolicy::OnFailure, false));
+        assert!(should_restart(RestartPolicy::Always, false));
+
+        assert_eq!(restart_delay(0), Duration::from_secs(1));
+        assert_eq!(restart_delay(3), Duration::from_secs(8));
+        assert_eq!(restart_delay(10), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn restart_tracker_gives_up_after_max_restarts() {
+        let tracker = RestartTracker::default();
+        for attempt in 0..MAX_RESTARTS {
+            assert_eq!(tracker.next_attempt("u", "vm"), Some(attempt));
+        }
+        assert_eq!(tracker.next_attempt("u", "vm"), None);
+        assert_eq!(tracker.next_attempt("u", "other"), Some(0));
+
+        tracker.forget("u", "vm");
+        assert_eq!(tracker.next_attempt("u", "vm"), Some(0));
+    }
+}
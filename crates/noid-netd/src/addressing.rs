@@ -8,6 +8,8 @@
 #[derive(Debug, Clone)]
 pub struct NetConfig {
     pub tap_name: String,
+    /// The /30 in CIDR notation.
+    pub subnet: String,
     pub host_ip: String,
     pub guest_ip: String,
     pub guest_mac: String,
@@ -19,6 +21,7 @@ pub fn derive_config(index: u32) -> NetConfig {
     let hi = (offset >> 8) as u8;
     let lo = (offset & 0xFF) as u8;
 
+    let subnet = format!("172.16.{}.{}/30", hi, lo);
     let host_ip = format!("172.16.{}.{}", hi, lo.wrapping_add(1));
     let guest_ip = format!("172.16.{}.{}", hi, lo.wrapping_add(2));
     let guest_mac = format!(
@@ -30,6 +33,7 @@ pub fn derive_config(index: u32) -> NetConfig {
 
     NetConfig {
         tap_name,
+        subnet,
         host_ip,
         guest_ip,
         guest_mac,
@@ -84,6 +88,7 @@ mod tests {
     fn test_derive_config_index_64() {
         let c = derive_config(64);
         assert_eq!(c.tap_name, "noid64");
+        assert_eq!(c.subnet, "172.16.1.0/30");
         assert_eq!(c.host_ip, "172.16.1.1");
         assert_eq!(c.guest_ip, "172.16.1.2");
         assert_eq!(c.guest_mac, "AA:FC:00:00:00:40");
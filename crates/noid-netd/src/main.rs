@@ -1,6 +1,7 @@
 #[allow(dead_code)]
 mod addressing;
 mod netlink;
+mod netns;
 mod tap;
 
 use anyhow::{Context, Result};
@@ -18,6 +19,12 @@ struct Request {
     index: Option<u32>,
     #[serde(default)]
     tap_name: Option<String>,
+    /// UID allowed to open the TAP device (setup).
+    #[serde(default)]
+    owner: Option<u32>,
+    /// Put the TAP device in a network namespace of its own (setup).
+    #[serde(default)]
+    netns: bool,
 }
 
 #[derive(Serialize)]
@@ -27,6 +34,8 @@ struct SetupResponse {
     host_ip: String,
     guest_ip: String,
     guest_mac: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    netns: Option<String>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -38,13 +47,29 @@ struct OkResponse {
     active: Option<Vec<String>>,
 }
 
-fn handle_setup(index: u32) -> Result<String> {
+fn handle_setup(index: u32, owner: Option<u32>, netns: bool) -> Result<String> {
     let config = addressing::derive_config(index);
 
     // Create TAP device
-    tap::create_tap(&config.tap_name)
+    tap::create_tap(&config.tap_name, owner)
         .with_context(|| format!("failed to create TAP {}", config.tap_name))?;
 
+    if netns {
+        if let Err(e) = netns::setup(&config) {
+            let _ = tap::destroy_tap(&config.tap_name);
+            return Err(e.context("failed to set up network namespace"));
+        }
+        let resp = SetupResponse {
+            ok: true,
+            netns: Some(netns::path(&config.tap_name).to_string_lossy().to_string()),
+            tap_name: config.tap_name,
+            host_ip: config.host_ip,
+            guest_ip: config.guest_ip,
+            guest_mac: config.guest_mac,
+        };
+        return serde_json::to_string(&resp).map_err(Into::into);
+    }
+
     // Assign IP to host end
     if let Err(e) = netlink::assign_ip(&config.tap_name, &config.host_ip, 30) {
         // Rollback: destroy TAP
@@ -64,6 +89,7 @@ fn handle_setup(index: u32) -> Result<String> {
         host_ip: config.host_ip,
         guest_ip: config.guest_ip,
         guest_mac: config.guest_mac,
+        netns: None,
     };
     serde_json::to_string(&resp).map_err(Into::into)
 }
@@ -74,6 +100,8 @@ fn handle_teardown(tap_name: &str) -> Result<String> {
         anyhow::bail!("invalid tap_name '{}': must start with 'noid'", tap_name);
     }
 
+    // A namespace takes its TAP and veth pair with it
+    netns::delete(tap_name)?;
     tap::destroy_tap(tap_name)?;
 
     let resp = OkResponse {
@@ -120,7 +148,7 @@ fn handle_request(line: &str) -> String {
 
     let result = match req.op.as_str() {
         "setup" => match req.index {
-            Some(idx) => handle_setup(idx),
+            Some(idx) => handle_setup(idx, req.owner, req.netns),
             None => Err(anyhow::anyhow!("setup requires 'index' field")),
         },
         "teardown" => match req.tap_name.as_deref() {
@@ -143,6 +171,10 @@ fn handle_request(line: &str) -> String {
 }
 
 fn cleanup_orphaned_taps() {
+    for name in netns::list() {
+        eprintln!("cleaning up orphaned network namespace: {name}");
+        let _ = netns::delete(&name);
+    }
     if let Ok(entries) = std::fs::read_dir("/sys/class/net") {
         for entry in entries.flatten() {
             let name = entry.file_name().to_string_lossy().to_string();
@@ -280,13 +312,31 @@ fn ensure_iptables() -> Result<()> {
     // Prevents TLS handshake hangs when upstream network has lower MTU.
     ensure(
         &[
-            "-t", "mangle", "-C", "FORWARD", "-p", "tcp",
-            "--tcp-flags", "SYN,RST", "SYN", "-j", "TCPMSS",
+            "-t",
+            "mangle",
+            "-C",
+            "FORWARD",
+            "-p",
+            "tcp",
+            "--tcp-flags",
+            "SYN,RST",
+            "SYN",
+            "-j",
+            "TCPMSS",
             "--clamp-mss-to-pmtu",
         ],
         &[
-            "-t", "mangle", "-A", "FORWARD", "-p", "tcp",
-            "--tcp-flags", "SYN,RST", "SYN", "-j", "TCPMSS",
+            "-t",
+            "mangle",
+            "-A",
+            "FORWARD",
+            "-p",
+            "tcp",
+            "--tcp-flags",
+            "SYN,RST",
+            "SYN",
+            "-j",
+            "TCPMSS",
             "--clamp-mss-to-pmtu",
         ],
     )?;
@@ -392,6 +442,19 @@ mod tests {
         assert!(parsed.error.unwrap().contains("index"));
     }
 
+    #[test]
+    fn setup_request_for_jailed_vm() {
+        let req: Request =
+            serde_json::from_str(r#"{"op": "setup", "index": 3, "owner": 200001, "netns": true}"#)
+                .unwrap();
+        assert_eq!(req.owner, Some(200001));
+        assert!(req.netns);
+
+        let req: Request = serde_json::from_str(r#"{"op": "setup", "index": 3}"#).unwrap();
+        assert_eq!(req.owner, None);
+        assert!(!req.netns);
+    }
+
     #[test]
     fn handle_request_teardown_missing_tap_name() {
         let resp = handle_request(r#"{"op": "teardown"}"#);
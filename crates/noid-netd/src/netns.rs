@@ -0,0 +1,131 @@
+//! Per-VM network namespaces, for VMs run through Firecracker's jailer.
+//!
+//! The namespace is named after the VM's TAP device and holds it, with the
+//! host address the guest uses as its gateway. A veth pair links it to the
+//! host: the host end takes the TAP's name (so the `noid+` FORWARD rules and
+//! `status` still see it) and routes the VM's /30 to the namespace. Both
+//! ends answer ARP for the other side, so the link needs no addresses.
+//!
+//! Uses `ip` and `sysctl`, like the iptables setup.
+
+use anyhow::{bail, Context, Result};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::addressing::NetConfig;
+
+const NETNS_DIR: &str = "/var/run/netns";
+
+/// Name of the namespace end of the veth pair.
+const NS_VETH: &str = "veth0";
+
+/// Path of namespace `name`, as given to the jailer.
+pub fn path(name: &str) -> PathBuf {
+    Path::new(NETNS_DIR).join(name)
+}
+
+/// Commands moving the (existing) TAP of `config` into a new namespace and
+/// linking that to the host.
+fn setup_commands(config: &NetConfig) -> Vec<Vec<String>> {
+    let name = config.tap_name.as_str();
+    let host_addr = format!("{}/30", config.host_ip);
+    let host_proxy_arp = format!("net.ipv4.conf.{name}.proxy_arp=1");
+    let ns_proxy_arp = format!("net.ipv4.conf.{NS_VETH}.proxy_arp=1");
+    let commands: Vec<Vec<&str>> = vec![
+        vec!["ip", "netns", "add", name],
+        vec!["ip", "link", "set", name, "netns", name],
+        vec!["ip", "-n", name, "addr", "add", &host_addr, "dev", name],
+        vec!["ip", "-n", name, "link", "set", name, "up"],
+        vec!["ip", "-n", name, "link", "set", "lo", "up"],
+        vec![
+            "ip", "link", "add", name, "type", "veth", "peer", "name", NS_VETH, "netns", name,
+        ],
+        vec!["ip", "link", "set", name, "up"],
+        vec!["ip", "route", "add", &config.subnet, "dev", name],
+        vec!["sysctl", "-qw", &host_proxy_arp],
+        vec!["ip", "-n", name, "link", "set", NS_VETH, "up"],
+        vec!["ip", "-n", name, "route", "add", "default", "dev", NS_VETH],
+        vec![
+            "ip",
+            "netns",
+            "exec",
+            name,
+            "sysctl",
+            "-qw",
+            "net.ipv4.ip_forward=1",
+            &ns_proxy_arp,
+        ],
+    ];
+    commands
+        .into_iter()
+        .map(|c| c.into_iter().map(str::to_string).collect())
+        .collect()
+}
+
+/// Move the TAP of `config` into a namespace of its own. On failure the
+/// namespace, and the TAP with it, is removed.
+pub fn setup(config: &NetConfig) -> Result<()> {
+    for command in setup_commands(config) {
+        if let Err(e) = run(&command) {
+            let _ = delete(&config.tap_name);
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+/// Delete namespace `name` if it exists, with the devices in it.
+pub fn delete(name: &str) -> Result<()> {
+    if !path(name).exists() {
+        return Ok(());
+    }
+    run(&["ip", "netns", "delete", name].map(str::to_string))
+}
+
+/// noid namespaces on the host.
+pub fn list() -> Vec<String> {
+    let mut names: Vec<String> = std::fs::read_dir(NETNS_DIR)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|e| e.file_name().to_string_lossy().to_string())
+        .filter(|n| n.starts_with("noid"))
+        .collect();
+    names.sort();
+    names
+}
+
+fn run(command: &[String]) -> Result<()> {
+    let output = Command::new(&command[0])
+        .args(&command[1..])
+        .output()
+        .with_context(|| format!("failed to run {}", command[0]))?;
+    if !output.status.success() {
+        bail!(
+            "'{}' failed: {}",
+            command.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::addressing::derive_config;
+
+    #[test]
+    fn setup_moves_the_tap_and_routes_its_subnet() {
+        let commands: Vec<String> = setup_commands(&derive_config(1))
+            .iter()
+            .map(|c| c.join(" "))
+            .collect();
+        assert_eq!(commands[0], "ip netns add noid1");
+        assert_eq!(commands[1], "ip link set noid1 netns noid1");
+        assert!(commands.contains(&"ip -n noid1 addr add 172.16.0.5/30 dev noid1".to_string()));
+        assert!(commands.contains(&"ip route add 172.16.0.4/30 dev noid1".to_string()));
+        assert!(commands.contains(&"ip -n noid1 route add default dev veth0".to_string()));
+        assert_eq!(path("noid1"), PathBuf::from("/var/run/netns/noid1"));
+    }
+}
@@ -1,6 +1,7 @@
 //! TAP device lifecycle via raw ioctl.
 //!
-//! create_tap: opens /dev/net/tun, sets IFF_TAP|IFF_NO_PI, TUNSETPERSIST(1)
+//! create_tap: opens /dev/net/tun, sets IFF_TAP|IFF_NO_PI, TUNSETPERSIST(1),
+//!             and TUNSETOWNER/TUNSETGROUP for jailed VMs
 //! destroy_tap: reopens, TUNSETPERSIST(0)
 //! link_up: ioctl SIOCSIFFLAGS with IFF_UP
 
@@ -11,6 +12,8 @@ use std::os::unix::io::RawFd;
 // ioctl constants
 const TUNSETIFF: libc::c_ulong = 0x400454ca;
 const TUNSETPERSIST: libc::c_ulong = 0x400454cb;
+const TUNSETOWNER: libc::c_ulong = 0x400454cc;
+const TUNSETGROUP: libc::c_ulong = 0x400454ce;
 const IFF_TAP: libc::c_short = 0x0002;
 const IFF_NO_PI: libc::c_short = 0x1000;
 const SIOCSIFFLAGS: libc::c_ulong = 0x8914;
@@ -48,8 +51,9 @@ fn open_tun() -> Result<RawFd> {
     Ok(fd)
 }
 
-/// Create a persistent TAP device with the given name.
-pub fn create_tap(name: &str) -> Result<()> {
+/// Create a persistent TAP device with the given name. If `owner` is set,
+/// that UID (and GID) may open it without CAP_NET_ADMIN.
+pub fn create_tap(name: &str, owner: Option<u32>) -> Result<()> {
     let fd = open_tun().context("create_tap: open /dev/net/tun")?;
 
     let mut req = IfReq::new(name)?;
@@ -67,6 +71,21 @@ pub fn create_tap(name: &str) -> Result<()> {
         );
     }
 
+    if let Some(id) = owner {
+        for (request, what) in [(TUNSETOWNER, "TUNSETOWNER"), (TUNSETGROUP, "TUNSETGROUP")] {
+            let ret = unsafe { libc::ioctl(fd, request, id as libc::c_ulong) };
+            if ret < 0 {
+                unsafe { libc::close(fd) };
+                bail!(
+                    "{} failed for {}: {}",
+                    what,
+                    name,
+                    std::io::Error::last_os_error()
+                );
+            }
+        }
+    }
+
     // Make persistent
     let ret = unsafe { libc::ioctl(fd, TUNSETPERSIST, 1 as libc::c_int) };
     if ret < 0 {
@@ -1,4 +1,10 @@
+use noid_core::firecracker;
+use noid_core::jail::JailerConfig;
+use noid_core::pool::PoolSpec;
+use noid_core::storage::StorageDriverKind;
+use noid_types::{RateLimits, VmShape};
 use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServerConfig {
@@ -6,6 +12,13 @@ pub struct ServerConfig {
     pub listen: String,
     pub kernel: String,
     pub rootfs: String,
+    /// Firecracker binary new VMs run on.
+    #[serde(default = "default_firecracker")]
+    pub firecracker: PathBuf,
+    /// Other installed Firecracker binaries, used to restore snapshots taken
+    /// by releases `firecracker` cannot load.
+    #[serde(default)]
+    pub extra_firecrackers: Vec<PathBuf>,
     #[serde(default = "default_max_ws_sessions")]
     pub max_ws_sessions: usize,
     #[serde(default)]
@@ -14,12 +27,39 @@ pub struct ServerConfig {
     pub exec_timeout_secs: u64,
     #[serde(default = "default_console_timeout_secs")]
     pub console_timeout_secs: u64,
+    /// How VM volumes are stored: "auto", "btrfs", "reflink" or "copy".
+    #[serde(default)]
+    pub storage_driver: StorageDriverKind,
+    /// VM shapes to keep golden snapshots for. Each is built in the
+    /// background after the first cold boot of that shape.
+    #[serde(default)]
+    pub golden_shapes: Vec<VmShape>,
+    /// Users allowed to call the `/v1/admin/` endpoints.
+    #[serde(default)]
+    pub admins: Vec<String>,
+    /// Pre-warmed VMs to keep ready, per image and shape.
+    #[serde(default)]
+    pub warm_pool: Vec<PoolSpec>,
+    /// Hibernate VMs unused for this many seconds; never if unset.
+    #[serde(default)]
+    pub hibernate_after_secs: Option<u64>,
+    /// Run VMs through Firecracker's jailer. Off unless `[jailer]` is set.
+    #[serde(default)]
+    pub jailer: Option<JailerConfig>,
+    /// Disk and network limits on every VM (bytes and operations per
+    /// second). VMs can be given tighter ones of their own.
+    #[serde(default)]
+    pub rate_limits: RateLimits,
 }
 
 fn default_listen() -> String {
     "0.0.0.0:7654".to_string()
 }
 
+fn default_firecracker() -> PathBuf {
+    PathBuf::from(firecracker::DEFAULT_BINARY)
+}
+
 fn default_max_ws_sessions() -> usize {
     32
 }
@@ -62,10 +102,19 @@ mod tests {
         assert_eq!(cfg.listen, "0.0.0.0:7654");
         assert_eq!(cfg.kernel, "/path/to/vmlinux.bin");
         assert_eq!(cfg.rootfs, "/path/to/rootfs.ext4");
+        assert_eq!(cfg.firecracker, PathBuf::from("/usr/local/bin/firecracker"));
+        assert!(cfg.extra_firecrackers.is_empty());
         assert_eq!(cfg.max_ws_sessions, 32);
         assert!(!cfg.trust_forwarded_for);
         assert_eq!(cfg.exec_timeout_secs, 30);
         assert_eq!(cfg.console_timeout_secs, 3600);
+        assert_eq!(cfg.storage_driver, StorageDriverKind::Auto);
+        assert!(cfg.golden_shapes.is_empty());
+        assert!(cfg.admins.is_empty());
+        assert!(cfg.warm_pool.is_empty());
+        assert!(cfg.hibernate_after_secs.is_none());
+        assert!(cfg.jailer.is_none());
+        assert!(cfg.rate_limits.is_unlimited());
     }
 
     #[test]
@@ -79,6 +128,12 @@ mod tests {
             trust_forwarded_for = true
             exec_timeout_secs = 60
             console_timeout_secs = 7200
+            storage_driver = "reflink"
+            golden_shapes = [{ cpus = 2, mem_mib = 4096 }, { cpus = 4, mem_mib = 8192 }]
+            admins = ["alice"]
+            hibernate_after_secs = 1800
+            firecracker = "/opt/firecracker-1.10/firecracker"
+            extra_firecrackers = ["/opt/firecracker-1.7/firecracker"]
             "#,
         )
         .unwrap();
@@ -87,6 +142,136 @@ mod tests {
         assert!(cfg.trust_forwarded_for);
         assert_eq!(cfg.exec_timeout_secs, 60);
         assert_eq!(cfg.console_timeout_secs, 7200);
+        assert_eq!(cfg.storage_driver, StorageDriverKind::Reflink);
+        assert_eq!(cfg.admins, vec!["alice".to_string()]);
+        assert_eq!(cfg.hibernate_after_secs, Some(1800));
+        assert_eq!(
+            cfg.firecracker,
+            PathBuf::from("/opt/firecracker-1.10/firecracker")
+        );
+        assert_eq!(
+            cfg.extra_firecrackers,
+            vec![PathBuf::from("/opt/firecracker-1.7/firecracker")]
+        );
+        assert_eq!(
+            cfg.golden_shapes,
+            vec![
+                VmShape {
+                    cpus: 2,
+                    mem_mib: 4096
+                },
+                VmShape {
+                    cpus: 4,
+                    mem_mib: 8192
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_warm_pools() {
+        let cfg = ServerConfig::from_str(
+            r#"
+            kernel = "/k"
+            rootfs = "/r"
+
+            [[warm_pool]]
+            size = 8
+
+            [[warm_pool]]
+            image = "node20"
+            cpus = 2
+            mem_mib = 4096
+            size = 2
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            cfg.warm_pool,
+            vec![
+                PoolSpec {
+                    image: None,
+                    cpus: 1,
+                    mem_mib: 2048,
+                    size: 8
+                },
+                PoolSpec {
+                    image: Some("node20".into()),
+                    cpus: 2,
+                    mem_mib: 4096,
+                    size: 2
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_jailer() {
+        let cfg = ServerConfig::from_str(
+            r#"
+            kernel = "/k"
+            rootfs = "/r"
+
+            [jailer]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(cfg.jailer, Some(JailerConfig::default()));
+
+        let cfg = ServerConfig::from_str(
+            r#"
+            kernel = "/k"
+            rootfs = "/r"
+
+            [jailer]
+            binary = "/opt/firecracker/jailer"
+            uid_base = 100000
+            netns = false
+            "#,
+        )
+        .unwrap();
+        let jailer = cfg.jailer.unwrap();
+        assert_eq!(
+            jailer.binary,
+            std::path::PathBuf::from("/opt/firecracker/jailer")
+        );
+        assert_eq!(jailer.uid_base, 100000);
+        assert_eq!(jailer.parent_cgroup, "noid");
+        assert!(!jailer.netns);
+    }
+
+    #[test]
+    fn parse_rate_limits() {
+        let cfg = ServerConfig::from_str(
+            r#"
+            kernel = "/k"
+            rootfs = "/r"
+
+            [rate_limits.disk]
+            bandwidth = 104857600
+            ops = 2000
+
+            [rate_limits.net]
+            bandwidth = 12500000
+            "#,
+        )
+        .unwrap();
+        assert_eq!(cfg.rate_limits.disk.bandwidth, Some(100 << 20));
+        assert_eq!(cfg.rate_limits.disk.ops, Some(2000));
+        assert_eq!(cfg.rate_limits.net.bandwidth, Some(12_500_000));
+        assert!(cfg.rate_limits.net.ops.is_none());
+    }
+
+    #[test]
+    fn parse_unknown_storage_driver() {
+        let result = ServerConfig::from_str(
+            r#"
+            kernel = "/k"
+            rootfs = "/r"
+            storage_driver = "zfs"
+            "#,
+        );
+        assert!(result.is_err());
     }
 
     #[test]
@@ -0,0 +1,360 @@
+//! Lifecycle event stream: `GET /v1/events` as a JSON list, Server-Sent
+//! Events (`Accept: text/event-stream`) or a WebSocket.
+//!
+//! Events are rows of the `events` table, so the stream replays whatever the
+//! query asks for and then polls the database for new rows. Streams hold a
+//! thread each and count towards `max_ws_sessions`.
+
+use noid_core::db::UserRecord;
+use noid_types::VmEvent;
+use std::io::{Read, Write};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tungstenite::protocol::Message;
+
+use crate::router::{self, LogEntry};
+use crate::transport::{self, RequestContext, ResponseBuilder};
+use crate::ServerState;
+
+/// How often streams look for new events.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often an idle stream sends something, so proxies keep it open and a
+/// client that went away is noticed.
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Status line and headers of an SSE response. The body runs until the
+/// connection closes.
+const SSE_HEAD: &str = "HTTP/1.1 200 OK\r\n\
+                        Content-Type: text/event-stream\r\n\
+                        Cache-Control: no-cache\r\n\
+                        Connection: close\r\n\
+                        X-Noid-Api-Version: 1\r\n\r\n";
+
+/// Which events a request wants: `?vm=NAME`, `?since=DURATION` (e.g. `1h`)
+/// and `?after=ID`. SSE clients reconnecting send `Last-Event-ID` instead of
+/// `after`.
+#[derive(Debug, Default, PartialEq)]
+pub struct EventQuery {
+    pub vm: Option<String>,
+    pub since_secs: Option<u64>,
+    pub after_id: Option<i64>,
+}
+
+impl EventQuery {
+    pub fn parse(ctx: &RequestContext) -> Result<Self, String> {
+        let mut query = EventQuery::default();
+        for (key, value) in ctx.query_params() {
+            match key.as_str() {
+                "vm" => {
+                    noid_core::storage::validate_name(&value, "VM")
+                        .map_err(|_| format!("invalid VM name '{value}'"))?;
+                    query.vm = Some(value);
+                }
+                "since" => {
+                    query.since_secs = Some(
+                        noid_types::parse_duration_secs(&value)
+                            .map_err(|e| format!("invalid since: {e}"))?,
+                    )
+                }
+                "after" => query.after_id = Some(parse_event_id(&value)?),
+                _ => {}
+            }
+        }
+        if let Some(id) = ctx.headers.get("last-event-id") {
+            query.after_id = Some(parse_event_id(id)?);
+        }
+        Ok(query)
+    }
+
+    /// Events the query asks for up to now.
+    pub fn replay(&self, state: &ServerState, user_id: &str) -> anyhow::Result<Vec<VmEvent>> {
+        state.backend.user_events(
+            user_id,
+            self.vm.as_deref(),
+            self.after_id.unwrap_or(0),
+            self.since_secs,
+        )
+    }
+}
+
+fn parse_event_id(s: &str) -> Result<i64, String> {
+    s.trim()
+        .parse()
+        .map_err(|_| format!("invalid event id '{s}'"))
+}
+
+/// Yields the events of a stream: the replay, then new ones as they are
+/// recorded. A query without `since` or `after` starts from now.
+struct Follower {
+    vm: Option<String>,
+    last_id: i64,
+}
+
+impl Follower {
+    fn start(
+        query: &EventQuery,
+        state: &ServerState,
+        user_id: &str,
+    ) -> anyhow::Result<(Self, Vec<VmEvent>)> {
+        let (last_id, replay) = if query.after_id.is_none() && query.since_secs.is_none() {
+            (state.backend.latest_event_id()?, Vec::new())
+        } else {
+            let replay = query.replay(state, user_id)?;
+            let last = replay.last().map_or(query.after_id.unwrap_or(0), |e| e.id);
+            (last, replay)
+        };
+        let follower = Follower {
+            vm: query.vm.clone(),
+            last_id,
+        };
+        Ok((follower, replay))
+    }
+
+    fn poll(&mut self, state: &ServerState, user_id: &str) -> anyhow::Result<Vec<VmEvent>> {
+        let events = state
+            .backend
+            .user_events(user_id, self.vm.as_deref(), self.last_id, None)?;
+        if let Some(last) = events.last() {
+            self.last_id = last.id;
+        }
+        Ok(events)
+    }
+}
+
+/// One event in SSE framing. `data` is a single line of JSON.
+fn sse_frame(event: &VmEvent) -> String {
+    let data = serde_json::to_string(event).unwrap_or_default();
+    format!("id: {}\nevent: {}\ndata: {data}\n\n", event.id, event.kind)
+}
+
+pub fn is_event_stream(request: &tiny_http::Request) -> bool {
+    *request.method() == tiny_http::Method::Get
+        && request.url().split('?').next() == Some("/v1/events")
+        && request.headers().iter().any(|h| {
+            h.field.equiv("Accept")
+                && h.value
+                    .as_str()
+                    .to_ascii_lowercase()
+                    .contains("text/event-stream")
+        })
+}
+
+/// Serve `GET /v1/events` as Server-Sent Events until the client goes away.
+pub fn handle_event_stream(request: tiny_http::Request, state: Arc<ServerState>, trust_fwd: bool) {
+    let start = Instant::now();
+    let request_id = uuid::Uuid::new_v4().to_string()[..8].to_string();
+    let ctx = transport::headers_only(&request, trust_fwd);
+
+    let log = |user: Option<&str>, status: u16| {
+        router::log_request(&LogEntry {
+            request_id: &request_id,
+            user,
+            method: &ctx.method,
+            path: &ctx.path,
+            status,
+            start,
+            remote_addr: &ctx.remote_addr,
+            forwarded_for: &ctx.forwarded_for,
+        })
+    };
+
+    let user = match router::authenticate(&ctx, &state.db, &state.rate_limiter) {
+        Ok(user) => user,
+        Err(resp) => {
+            log(None, resp.status);
+            let _ = request.respond(transport::to_tiny_http_response(resp));
+            return;
+        }
+    };
+    let (follower, replay) = match open_stream(&ctx, &state, &user) {
+        Ok(opened) => {
+            log(Some(&user.name), 200);
+            opened
+        }
+        Err(resp) => {
+            log(Some(&user.name), resp.status);
+            let _ = request.respond(transport::to_tiny_http_response(resp));
+            return;
+        }
+    };
+
+    // Written by hand rather than through a tiny_http Response, whose
+    // chunked encoder holds small writes back until its buffer fills.
+    let mut out = request.into_writer();
+    let _ = stream_events(&mut out, &state, &user, follower, replay);
+    state.ws_session_count.fetch_sub(1, Ordering::SeqCst);
+}
+
+/// Check the query and take a stream slot. On success the caller must give
+/// the slot back when the stream ends.
+fn open_stream(
+    ctx: &RequestContext,
+    state: &ServerState,
+    user: &UserRecord,
+) -> Result<(Follower, Vec<VmEvent>), ResponseBuilder> {
+    let query = EventQuery::parse(ctx).map_err(|e| ResponseBuilder::error(400, &e))?;
+    let prev = state.ws_session_count.fetch_add(1, Ordering::SeqCst);
+    if prev >= state.config.max_ws_sessions {
+        state.ws_session_count.fetch_sub(1, Ordering::SeqCst);
+        return Err(ResponseBuilder::error(503, "too many streaming sessions"));
+    }
+    Follower::start(&query, state, &user.id).map_err(|e| {
+        state.ws_session_count.fetch_sub(1, Ordering::SeqCst);
+        crate::handlers::map_backend_error(&e)
+    })
+}
+
+fn stream_events(
+    out: &mut impl Write,
+    state: &ServerState,
+    user: &UserRecord,
+    mut follower: Follower,
+    replay: Vec<VmEvent>,
+) -> std::io::Result<()> {
+    out.write_all(SSE_HEAD.as_bytes())?;
+    for event in &replay {
+        out.write_all(sse_frame(event).as_bytes())?;
+    }
+    out.flush()?;
+    let mut last_write = Instant::now();
+    loop {
+        std::thread::sleep(POLL_INTERVAL);
+        let events = match follower.poll(state, &user.id) {
+            Ok(events) => events,
+            Err(e) => {
+                eprintln!("warning: event stream of {} failed: {e:#}", user.name);
+                return Ok(());
+            }
+        };
+        if events.is_empty() {
+            if last_write.elapsed() < KEEPALIVE_INTERVAL {
+                continue;
+            }
+            out.write_all(b": keepalive\n\n")?;
+        }
+        for event in &events {
+            out.write_all(sse_frame(event).as_bytes())?;
+        }
+        out.flush()?;
+        last_write = Instant::now();
+    }
+}
+
+/// Serve `GET /v1/events` over an upgraded WebSocket: one text frame of
+/// JSON per event. The caller holds the stream slot.
+pub fn handle_events_ws<S: Read + Write>(
+    stream: S,
+    state: &Arc<ServerState>,
+    user: &UserRecord,
+    query: &EventQuery,
+) {
+    let mut ws =
+        tungstenite::WebSocket::from_raw_socket(stream, tungstenite::protocol::Role::Server, None);
+    // Whether the event went out; a failure means the client is gone.
+    let send = |ws: &mut tungstenite::WebSocket<S>, event: &VmEvent| {
+        ws.send(Message::Text(
+            serde_json::to_string(event).unwrap_or_default(),
+        ))
+        .is_ok()
+    };
+
+    let (mut follower, replay) = match Follower::start(query, state, &user.id) {
+        Ok(started) => started,
+        Err(e) => {
+            let _ = ws.send(Message::Text(
+                serde_json::to_string(&noid_types::ErrorResponse {
+                    error: e.to_string(),
+                })
+                .unwrap(),
+            ));
+            let _ = ws.close(None);
+            return;
+        }
+    };
+    if !replay.iter().all(|event| send(&mut ws, event)) {
+        return;
+    }
+    let mut last_write = Instant::now();
+    loop {
+        std::thread::sleep(POLL_INTERVAL);
+        let events = match follower.poll(state, &user.id) {
+            Ok(events) => events,
+            Err(e) => {
+                eprintln!("warning: event stream of {} failed: {e:#}", user.name);
+                let _ = ws.close(None);
+                return;
+            }
+        };
+        let sent = if events.is_empty() {
+            if last_write.elapsed() < KEEPALIVE_INTERVAL {
+                continue;
+            }
+            ws.send(Message::Ping(Vec::new())).is_ok()
+        } else {
+            events.iter().all(|event| send(&mut ws, event))
+        };
+        if !sent {
+            return;
+        }
+        last_write = Instant::now();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn ctx(path: &str, headers: &[(&str, &str)]) -> RequestContext {
+        RequestContext {
+            method: "GET".into(),
+            path: path.into(),
+            headers: headers
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<HashMap<_, _>>(),
+            body: Vec::new(),
+            remote_addr: String::new(),
+            forwarded_for: None,
+        }
+    }
+
+    #[test]
+    fn event_query_parsing() {
+        assert_eq!(
+            EventQuery::parse(&ctx("/v1/events", &[])),
+            Ok(EventQuery::default())
+        );
+        assert_eq!(
+            EventQuery::parse(&ctx("/v1/events?vm=dev&since=1h&after=7", &[])),
+            Ok(EventQuery {
+                vm: Some("dev".into()),
+                since_secs: Some(3600),
+                after_id: Some(7),
+            })
+        );
+        let resumed = EventQuery::parse(&ctx("/v1/events?after=7", &[("last-event-id", "42")]));
+        assert_eq!(resumed.unwrap().after_id, Some(42));
+        assert!(EventQuery::parse(&ctx("/v1/events?since=soon", &[])).is_err());
+        assert!(EventQuery::parse(&ctx("/v1/events?after=x", &[])).is_err());
+        assert!(EventQuery::parse(&ctx("/v1/events?vm=../x", &[])).is_err());
+    }
+
+    #[test]
+    fn sse_frame_is_one_data_line() {
+        let event = VmEvent {
+            id: 3,
+            vm_name: "dev".into(),
+            kind: "vm.died".into(),
+            message: "exited".into(),
+            detail: Some("line 1\nline 2".into()),
+            created_at: "2026-01-01 00:00:00".into(),
+        };
+        let frame = sse_frame(&event);
+        assert!(frame.starts_with("id: 3\nevent: vm.died\ndata: {"));
+        assert!(frame.ends_with("}\n\n"));
+        assert_eq!(frame.lines().count(), 4);
+    }
+}
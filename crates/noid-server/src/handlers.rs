@@ -1,19 +1,38 @@
+use noid_core::backend::VmBackend;
+use noid_core::firecracker::Firecrackers;
+use noid_core::image::Image;
+use noid_core::{golden, registry};
 use noid_types::*;
 use std::sync::Arc;
 
+use crate::operations;
 use crate::router::AuthenticatedRequest;
 use crate::transport::ResponseBuilder;
 use crate::ServerState;
 
+/// Longest TTL a VM can be given, in seconds (a year).
+const MAX_TTL_SECS: u64 = 365 * 24 * 60 * 60;
+
 /// Map a backend error to an HTTP response. Known error patterns (not found,
 /// already exists) get specific status codes; all others become 500s.
 /// The error message is always passed through to the client.
-fn map_backend_error(e: &anyhow::Error) -> ResponseBuilder {
+pub fn map_backend_error(e: &anyhow::Error) -> ResponseBuilder {
     let msg = e.to_string();
-    if msg.contains("not found") {
+    if msg.contains("not found") || msg.contains("has no init script") {
         ResponseBuilder::error(404, &msg)
-    } else if msg.contains("already exists") {
+    } else if msg.contains("already exists")
+        || msg.contains("is in use")
+        || msg.contains("is not running")
+        || msg.contains("is already running")
+        || msg.contains("has no balloon device")
+        || msg.contains("cannot load")
+        || msg.contains("cannot be renamed while running")
+    {
         ResponseBuilder::error(409, &msg)
+    } else if msg.contains("is out of range") || msg.contains("too many labels") {
+        ResponseBuilder::error(400, &msg)
+    } else if msg.contains("failed integrity verification") {
+        ResponseBuilder::error(422, &msg)
     } else {
         eprintln!("internal error: {e:#}");
         ResponseBuilder::error(500, &msg)
@@ -24,12 +43,16 @@ pub fn healthz() -> ResponseBuilder {
     ResponseBuilder::json(200, &serde_json::json!({"status": "ok"}))
 }
 
-pub fn version() -> ResponseBuilder {
+pub fn version(firecracker: &Firecrackers) -> ResponseBuilder {
+    let mut binaries = firecracker.all();
+    let main = binaries.next().and_then(|b| b.version_string());
     ResponseBuilder::json(
         200,
         &VersionInfo {
             version: env!("CARGO_PKG_VERSION").to_string(),
             api_version: 1,
+            firecracker_version: main,
+            extra_firecracker_versions: binaries.filter_map(|b| b.version_string()).collect(),
         },
     )
 }
@@ -55,6 +78,7 @@ pub fn capabilities(state: &Arc<ServerState>) -> ResponseBuilder {
             max_vm_name_length: 64,
             default_cpus: 1,
             default_mem_mib: 2048,
+            fast_path_shapes: state.backend.fast_path_shapes(),
         },
     )
 }
@@ -64,19 +88,170 @@ pub fn create_vm(req: AuthenticatedRequest, state: &Arc<ServerState>) -> Respons
         Ok(b) => b,
         Err(e) => return ResponseBuilder::error(400, &format!("invalid request body: {e}")),
     };
-
-    match state
-        .backend
-        .create(&req.user.id, &body.name, body.cpus, body.mem_mib)
+    if body.rootfs.is_some() && body.image.is_some() {
+        return ResponseBuilder::error(400, "rootfs cannot be combined with image");
+    }
+    if let Some(args) = body.boot_args.as_deref() {
+        if let Err(e) = noid_core::boot::validate_boot_args(args) {
+            return ResponseBuilder::error(400, &e.to_string());
+        }
+    }
+    if let Some(init) = body.init.as_deref() {
+        if let Err(e) = noid_core::boot::validate_init(init) {
+            return ResponseBuilder::error(400, &e.to_string());
+        }
+    }
+    match body.ttl_secs {
+        Some(0) => return ResponseBuilder::error(400, "ttl_secs must be positive"),
+        Some(ttl) if ttl > MAX_TTL_SECS => {
+            return ResponseBuilder::error(400, &format!("TTL exceeds {MAX_TTL_SECS} seconds"));
+        }
+        _ => {}
+    }
+    if let Err(e) = noid_types::validate_labels(&body.labels) {
+        return ResponseBuilder::error(400, &e);
+    }
+    if let Some(Err(e)) = body
+        .user_data
+        .as_deref()
+        .map(noid_types::validate_user_data)
     {
+        return ResponseBuilder::error(400, &e);
+    }
+
+    if operations::wants_async(&req.ctx) {
+        let backend = state.backend.clone();
+        let user_id = req.user.id.clone();
+        let vm_name = body.name.clone();
+        return operations::spawn(state, &req.user.id, "create", &vm_name, move |progress| {
+            backend.create(&user_id, &body, progress)
+        });
+    }
+    match state.backend.create(&req.user.id, &body, &|_| {}) {
         Ok(info) => ResponseBuilder::json(201, &info),
         Err(e) => map_backend_error(&e),
     }
 }
 
+/// `GET /v1/vms`, optionally filtered by `?label=key=value` selectors (all
+/// must match).
 pub fn list_vms(req: &AuthenticatedRequest, state: &Arc<ServerState>) -> ResponseBuilder {
+    let selector = match req
+        .ctx
+        .query_params()
+        .iter()
+        .filter(|(k, _)| k == "label")
+        .map(|(_, v)| noid_types::parse_label(v))
+        .collect::<Result<Vec<_>, _>>()
+    {
+        Ok(selector) => selector,
+        Err(e) => return ResponseBuilder::error(400, &e),
+    };
+
     match state.backend.list(&req.user.id) {
-        Ok(vms) => ResponseBuilder::json(200, &vms),
+        Ok(mut vms) => {
+            vms.retain(|vm| noid_types::labels_match(&vm.labels, &selector));
+            ResponseBuilder::json(200, &vms)
+        }
+        Err(e) => map_backend_error(&e),
+    }
+}
+
+pub fn list_events(req: &AuthenticatedRequest, state: &Arc<ServerState>) -> ResponseBuilder {
+    let query = match crate::events::EventQuery::parse(&req.ctx) {
+        Ok(query) => query,
+        Err(e) => return ResponseBuilder::error(400, &e),
+    };
+    match query.replay(state, &req.user.id) {
+        Ok(events) => ResponseBuilder::json(200, &events),
+        Err(e) => map_backend_error(&e),
+    }
+}
+
+pub fn usage(req: &AuthenticatedRequest, state: &Arc<ServerState>) -> ResponseBuilder {
+    match state.backend.usage(&req.user.id) {
+        Ok(summary) => ResponseBuilder::json(200, &summary),
+        Err(e) => map_backend_error(&e),
+    }
+}
+
+pub fn list_images(state: &Arc<ServerState>) -> ResponseBuilder {
+    match state.backend.list_images() {
+        Ok(images) => ResponseBuilder::json(200, &images),
+        Err(e) => map_backend_error(&e),
+    }
+}
+
+/// The golden image an admin request targets: a named image, or the
+/// default one.
+fn golden_image(name: Option<&str>) -> anyhow::Result<Image> {
+    match name {
+        Some(name) => Image::named(name),
+        None => Ok(Image::default_image()),
+    }
+}
+
+pub fn promote_golden(req: AuthenticatedRequest, state: &Arc<ServerState>) -> ResponseBuilder {
+    let body: PromoteGoldenRequest = match serde_json::from_slice(&req.ctx.body) {
+        Ok(b) => b,
+        Err(e) => return ResponseBuilder::error(400, &format!("invalid request body: {e}")),
+    };
+    let image = match golden_image(body.image.as_deref()) {
+        Ok(image) => image,
+        Err(e) => return ResponseBuilder::error(400, &e.to_string()),
+    };
+
+    // Look up under the lock, but copy the snapshot files without holding it.
+    let found = {
+        let db = state.db.lock().unwrap_or_else(|e| e.into_inner());
+        golden::find_checkpoint(&db, &body.user, &body.vm, &body.checkpoint)
+    };
+    let (vm, checkpoint) = match found {
+        Ok(found) => found,
+        Err(e) => return map_backend_error(&e),
+    };
+
+    match golden::promote(&image, &vm, &checkpoint) {
+        Ok(info) => ResponseBuilder::json(200, &info),
+        Err(e) => map_backend_error(&e),
+    }
+}
+
+pub fn rollback_golden(req: AuthenticatedRequest) -> ResponseBuilder {
+    let body: RollbackGoldenRequest = if req.ctx.body.is_empty() {
+        RollbackGoldenRequest::default()
+    } else {
+        match serde_json::from_slice(&req.ctx.body) {
+            Ok(b) => b,
+            Err(e) => return ResponseBuilder::error(400, &format!("invalid request body: {e}")),
+        }
+    };
+    let image = match golden_image(body.image.as_deref()) {
+        Ok(image) => image,
+        Err(e) => return ResponseBuilder::error(400, &e.to_string()),
+    };
+
+    match golden::rollback(&image) {
+        Ok(info) => ResponseBuilder::json(200, &info),
+        Err(e) => map_backend_error(&e),
+    }
+}
+
+pub fn list_artifacts(kind: ArtifactKind) -> ResponseBuilder {
+    match registry::list(kind) {
+        Ok(artifacts) => ResponseBuilder::json(200, &artifacts),
+        Err(e) => map_backend_error(&e),
+    }
+}
+
+pub fn delete_artifact(
+    state: &Arc<ServerState>,
+    kind: ArtifactKind,
+    name: &str,
+) -> ResponseBuilder {
+    let db = state.db.lock().unwrap_or_else(|e| e.into_inner());
+    match registry::delete(&db, kind, name) {
+        Ok(()) => ResponseBuilder::no_content(),
         Err(e) => map_backend_error(&e),
     }
 }
@@ -89,6 +264,58 @@ pub fn get_vm(req: &AuthenticatedRequest, state: &Arc<ServerState>, name: &str)
     }
 }
 
+pub fn update_vm(
+    req: AuthenticatedRequest,
+    state: &Arc<ServerState>,
+    name: &str,
+) -> ResponseBuilder {
+    let body: UpdateVmRequest = match serde_json::from_slice(&req.ctx.body) {
+        Ok(b) => b,
+        Err(e) => return ResponseBuilder::error(400, &format!("invalid request body: {e}")),
+    };
+    if body.ttl_secs.is_some() && body.extend_secs.is_some() {
+        return ResponseBuilder::error(400, "ttl_secs and extend_secs cannot be combined");
+    }
+    if body.ttl_secs.or(body.extend_secs).unwrap_or(0) > MAX_TTL_SECS {
+        return ResponseBuilder::error(400, &format!("TTL exceeds {MAX_TTL_SECS} seconds"));
+    }
+
+    if let Some(new_name) = &body.name {
+        if let Err(e) = noid_core::storage::validate_name(new_name, "VM") {
+            return ResponseBuilder::error(400, &e.to_string());
+        }
+    }
+    for (key, value) in &body.labels {
+        if let Err(e) = noid_types::validate_label(key, value.as_deref().unwrap_or("")) {
+            return ResponseBuilder::error(400, &e);
+        }
+    }
+
+    // Rename last, so invalid labels or limits don't leave the VM renamed;
+    // a name that is taken is refused before anything else is applied.
+    if let Some(new_name) = body.name.as_deref().filter(|n| *n != name) {
+        match state.backend.get(&req.user.id, new_name) {
+            Ok(Some(_)) => {
+                return ResponseBuilder::error(409, &format!("VM '{new_name}' already exists"))
+            }
+            Ok(None) => {}
+            Err(e) => return map_backend_error(&e),
+        }
+    }
+    let info = match state.backend.update(&req.user.id, name, &body) {
+        Ok(info) => info,
+        Err(e) => return map_backend_error(&e),
+    };
+    let result = match &body.name {
+        Some(new_name) => state.backend.rename(&req.user.id, name, new_name),
+        None => Ok(info),
+    };
+    match result {
+        Ok(info) => ResponseBuilder::json(200, &info),
+        Err(e) => map_backend_error(&e),
+    }
+}
+
 pub fn destroy_vm(
     req: &AuthenticatedRequest,
     state: &Arc<ServerState>,
@@ -136,6 +363,21 @@ pub fn list_checkpoints(
     }
 }
 
+pub fn verify_checkpoint(
+    req: &AuthenticatedRequest,
+    state: &Arc<ServerState>,
+    name: &str,
+    checkpoint_id: &str,
+) -> ResponseBuilder {
+    match state
+        .backend
+        .verify_checkpoint(&req.user.id, name, checkpoint_id)
+    {
+        Ok(result) => ResponseBuilder::json(200, &result),
+        Err(e) => map_backend_error(&e),
+    }
+}
+
 pub fn restore_vm(
     req: AuthenticatedRequest,
     state: &Arc<ServerState>,
@@ -145,13 +387,144 @@ pub fn restore_vm(
         Ok(b) => b,
         Err(e) => return ResponseBuilder::error(400, &format!("invalid request body: {e}")),
     };
+    if let Some(Err(e)) = body
+        .user_data
+        .as_deref()
+        .map(noid_types::validate_user_data)
+    {
+        return ResponseBuilder::error(400, &e);
+    }
+
+    if operations::wants_async(&req.ctx) {
+        let backend = state.backend.clone();
+        let user_id = req.user.id.clone();
+        let name = name.to_string();
+        let vm_name = body.new_name.clone().unwrap_or_else(|| name.clone());
+        return operations::spawn(state, &req.user.id, "restore", &vm_name, move |progress| {
+            restore(backend.as_ref(), &user_id, &name, &body, progress)
+        });
+    }
+    match restore(state.backend.as_ref(), &req.user.id, name, &body, &|_| {}) {
+        Ok(info) => ResponseBuilder::json(200, &info),
+        Err(e) => map_backend_error(&e),
+    }
+}
 
-    match state.backend.restore(
-        &req.user.id,
+/// Restore a checkpoint, then start the request's init script, if any.
+fn restore(
+    backend: &dyn VmBackend,
+    user_id: &str,
+    name: &str,
+    body: &RestoreRequest,
+    progress: &dyn Fn(OperationPhase),
+) -> anyhow::Result<VmInfo> {
+    let info = backend.restore(
+        user_id,
         name,
         &body.checkpoint_id,
         body.new_name.as_deref(),
-    ) {
+        body.verify,
+        progress,
+    )?;
+    if let Some(ref script) = body.user_data {
+        backend.start_init_script(user_id, &info.name, script)?;
+    }
+    Ok(info)
+}
+
+/// `GET /v1/operations/{id}`: progress and outcome of an operation started
+/// with `Prefer: respond-async`.
+pub fn get_operation(
+    req: &AuthenticatedRequest,
+    state: &Arc<ServerState>,
+    id: &str,
+) -> ResponseBuilder {
+    match state.operations.get(&req.user.id, id) {
+        Some(op) => ResponseBuilder::json(200, &op),
+        None => ResponseBuilder::error(404, &format!("operation '{id}' not found")),
+    }
+}
+
+pub fn stop_vm(
+    req: &AuthenticatedRequest,
+    state: &Arc<ServerState>,
+    name: &str,
+) -> ResponseBuilder {
+    match state.backend.stop(&req.user.id, name) {
+        Ok(info) => ResponseBuilder::json(200, &info),
+        Err(e) => map_backend_error(&e),
+    }
+}
+
+pub fn start_vm(
+    req: &AuthenticatedRequest,
+    state: &Arc<ServerState>,
+    name: &str,
+) -> ResponseBuilder {
+    match state.backend.start(&req.user.id, name) {
+        Ok(info) => ResponseBuilder::json(200, &info),
+        Err(e) => map_backend_error(&e),
+    }
+}
+
+pub fn reboot_vm(
+    req: &AuthenticatedRequest,
+    state: &Arc<ServerState>,
+    name: &str,
+) -> ResponseBuilder {
+    match state.backend.reboot(&req.user.id, name) {
+        Ok(info) => ResponseBuilder::json(200, &info),
+        Err(e) => map_backend_error(&e),
+    }
+}
+
+pub fn vm_events(
+    req: &AuthenticatedRequest,
+    state: &Arc<ServerState>,
+    name: &str,
+) -> ResponseBuilder {
+    match state.backend.events(&req.user.id, name) {
+        Ok(events) => ResponseBuilder::json(200, &events),
+        Err(e) => map_backend_error(&e),
+    }
+}
+
+pub fn get_init_log(
+    req: &AuthenticatedRequest,
+    state: &Arc<ServerState>,
+    name: &str,
+) -> ResponseBuilder {
+    match state.backend.init_log(&req.user.id, name) {
+        Ok(log) => ResponseBuilder::json(200, &log),
+        Err(e) => map_backend_error(&e),
+    }
+}
+
+pub fn get_balloon(
+    req: &AuthenticatedRequest,
+    state: &Arc<ServerState>,
+    name: &str,
+) -> ResponseBuilder {
+    match state.backend.balloon(&req.user.id, name) {
+        Ok(info) => ResponseBuilder::json(200, &info),
+        Err(e) => map_backend_error(&e),
+    }
+}
+
+pub fn set_balloon(
+    req: AuthenticatedRequest,
+    state: &Arc<ServerState>,
+    name: &str,
+) -> ResponseBuilder {
+    let body: BalloonRequest = match serde_json::from_slice(&req.ctx.body) {
+        Ok(b) => b,
+        Err(e) => return ResponseBuilder::error(400, &format!("invalid request body: {e}")),
+    };
+
+    match state
+        .backend
+        .set_balloon(&req.user.id, name, body.target_mib)
+    {
         Ok(info) => ResponseBuilder::json(200, &info),
         Err(e) => map_backend_error(&e),
     }
@@ -191,6 +564,7 @@ pub fn exec_vm(req: AuthenticatedRequest, state: &Arc<ServerState>, name: &str)
 #[cfg(test)]
 mod tests {
     use super::*;
+    use noid_core::firecracker::{Binary, Version};
 
     #[test]
     fn healthz_returns_200() {
@@ -202,11 +576,21 @@ mod tests {
 
     #[test]
     fn version_returns_api_version_1() {
-        let resp = version();
+        let binary = |path: &str, version: &str| Binary {
+            path: path.into(),
+            version: Version::parse(version),
+        };
+        let firecracker = Firecrackers::new(
+            binary("/usr/local/bin/firecracker", "1.10.1"),
+            vec![binary("/opt/firecracker-1.7/firecracker", "1.7.0")],
+        );
+        let resp = version(&firecracker);
         assert_eq!(resp.status, 200);
         let body: VersionInfo = serde_json::from_slice(&resp.body).unwrap();
         assert_eq!(body.api_version, 1);
         assert!(!body.version.is_empty());
+        assert_eq!(body.firecracker_version.as_deref(), Some("1.10.1"));
+        assert_eq!(body.extra_firecracker_versions, vec!["1.7.0".to_string()]);
     }
 
     #[test]
@@ -214,6 +598,8 @@ mod tests {
         let err = anyhow::anyhow!("VM 'test' not found");
         let resp = map_backend_error(&err);
         assert_eq!(resp.status, 404);
+        let err = anyhow::anyhow!("VM 'test' has no init script");
+        assert_eq!(map_backend_error(&err).status, 404);
     }
 
     #[test]
@@ -223,6 +609,49 @@ mod tests {
         assert_eq!(resp.status, 409);
     }
 
+    #[test]
+    fn map_backend_error_in_use_gives_409() {
+        let err = anyhow::anyhow!("kernel '6.12' is in use by 2 VM(s)");
+        let resp = map_backend_error(&err);
+        assert_eq!(resp.status, 409);
+    }
+
+    #[test]
+    fn map_backend_error_wrong_run_state_gives_409() {
+        let err = anyhow::anyhow!("VM 'dev' is not running");
+        assert_eq!(map_backend_error(&err).status, 409);
+        let err = anyhow::anyhow!("VM 'dev' is already running");
+        assert_eq!(map_backend_error(&err).status, 409);
+        let err = anyhow::anyhow!(
+            "cannot restore checkpoint 'abc': snapshot was taken with Firecracker 1.6.0, \
+             which Firecracker 1.10.1 cannot load"
+        );
+        assert_eq!(map_backend_error(&err).status, 409);
+        let err = anyhow::anyhow!(
+            "VM 'dev' runs in a jail and cannot be renamed while running; stop it first"
+        );
+        assert_eq!(map_backend_error(&err).status, 409);
+    }
+
+    #[test]
+    fn map_backend_error_balloon_errors() {
+        let err = anyhow::anyhow!("memory target 32 MiB is out of range (64..=512 MiB)");
+        assert_eq!(map_backend_error(&err).status, 400);
+        let err = anyhow::anyhow!("VM 'dev' has no balloon device; reboot it to add one");
+        assert_eq!(map_backend_error(&err).status, 409);
+        let err = anyhow::anyhow!("too many labels (33, max 32)");
+        assert_eq!(map_backend_error(&err).status, 400);
+    }
+
+    #[test]
+    fn map_backend_error_integrity_failure_gives_422() {
+        let err = anyhow::anyhow!(
+            "checkpoint 'abc' failed integrity verification: memory.snap: size mismatch"
+        );
+        let resp = map_backend_error(&err);
+        assert_eq!(resp.status, 422);
+    }
+
     #[test]
     fn map_backend_error_unknown_passes_message() {
         let err = anyhow::anyhow!("cp failed: No space left on device");
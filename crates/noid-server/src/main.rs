@@ -1,9 +1,12 @@
 mod config;
 mod console;
+mod events;
 mod handlers;
+mod operations;
 mod router;
 mod transport;
 mod update;
+mod upload;
 mod ws_exec;
 
 use anyhow::Result;
@@ -11,6 +14,8 @@ use clap::{Parser, Subcommand};
 use noid_core::auth;
 use noid_core::backend::{FirecrackerBackend, VmBackend};
 use noid_core::db::Db;
+use noid_core::firecracker::Firecrackers;
+use noid_core::lockfile::{self, LockFile};
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 
@@ -49,16 +54,58 @@ enum Command {
         /// Username
         name: String,
     },
+    /// Manage golden images
+    Golden {
+        #[command(subcommand)]
+        action: GoldenAction,
+    },
+    /// Check the database against processes, TAPs and storage
+    Fsck {
+        /// Path to server config file
+        #[arg(long, default_value = "server.toml")]
+        config: String,
+        /// Fix the problems found
+        #[arg(long)]
+        repair: bool,
+    },
     /// Update noid-server to the latest release
     Update,
 }
 
+#[derive(Subcommand)]
+enum GoldenAction {
+    /// Replace a golden image with a checkpoint, keeping the old one for rollback
+    Promote {
+        /// Name of the user owning the checkpoint
+        #[arg(long)]
+        user: String,
+        /// VM the checkpoint was taken from
+        #[arg(long)]
+        vm: String,
+        /// Checkpoint ID
+        #[arg(long)]
+        checkpoint: String,
+        /// Named image to write (default: the default golden image)
+        #[arg(long)]
+        image: Option<String>,
+    },
+    /// Swap a golden image with its previous version
+    Rollback {
+        /// Named image (default: the default golden image)
+        #[arg(long)]
+        image: Option<String>,
+    },
+}
+
 pub struct ServerState {
     pub backend: Arc<dyn VmBackend>,
     pub db: Mutex<Db>,
     pub config: ServerConfig,
     pub rate_limiter: auth::RateLimiter,
     pub ws_session_count: AtomicUsize,
+    pub operations: operations::Operations,
+    /// Installed Firecracker binaries and their versions.
+    pub firecracker: Firecrackers,
 }
 
 fn main() -> Result<()> {
@@ -72,6 +119,11 @@ fn main() -> Result<()> {
         Command::RotateToken { name } => cmd_rotate_token(&name),
         Command::ListUsers => cmd_list_users(),
         Command::RemoveUser { name } => cmd_remove_user(&name),
+        Command::Golden { action } => cmd_golden(action),
+        Command::Fsck {
+            config: config_path,
+            repair,
+        } => cmd_fsck(&config_path, repair),
         Command::Update => update::self_update(),
     }
 }
@@ -80,12 +132,58 @@ fn cmd_serve(config_path: &str) -> Result<()> {
     let config = ServerConfig::load(config_path)?;
 
     let db = Db::open()?;
-    let backend = Arc::new(FirecrackerBackend::new(
+    // Held until the server exits; fsck --repair checks it.
+    let pid_path = noid_core::config::server_pid_path();
+    let mut pid_lock = LockFile::try_lock(&pid_path)?.ok_or_else(|| {
+        anyhow::anyhow!(
+            "another noid-server is running (pid {})",
+            lockfile::holder(&pid_path).ok().flatten().unwrap_or_default()
+        )
+    })?;
+    pid_lock.write(&format!("{}\n", std::process::id()))?;
+    let storage = noid_core::storage::open_driver(config.storage_driver)?;
+    eprintln!("storage driver: {}", storage.name());
+    let firecracker = Firecrackers::detect(&config.firecracker, &config.extra_firecrackers)?;
+    for binary in firecracker.all() {
+        match binary.version {
+            Some(version) => eprintln!("firecracker {version}: {}", binary.path.display()),
+            None => eprintln!("firecracker: {}", binary.path.display()),
+        }
+    }
+    let mut warm_pool = config.warm_pool.clone();
+    if let Some(ref jailer) = config.jailer {
+        for binary in firecracker.all() {
+            noid_core::jail::check_available(jailer, &binary.path)?;
+            noid_core::jail::sweep(jailer, &binary.path);
+        }
+        // Warm VMs are booted before anyone owns them, so they cannot run
+        // as their user's UID.
+        if !warm_pool.is_empty() {
+            eprintln!("warning: warm pools are disabled with the jailer");
+            warm_pool.clear();
+        }
+        eprintln!("jailer: {}", jailer.binary.display());
+    }
+    noid_core::reconcile::reconcile_at_startup(&db, storage.as_ref())?;
+    let backend = FirecrackerBackend::new(
         Db::open()?,
         config.kernel.clone(),
         config.rootfs.clone(),
         config.exec_timeout_secs,
-    ));
+        storage,
+        config.golden_shapes.clone(),
+        warm_pool,
+        config.jailer.clone(),
+        config.rate_limits,
+        firecracker.clone(),
+    );
+    backend.start_warm_pools();
+    backend.start_watchdog();
+    backend.start_reaper();
+    backend.start_init_scripts();
+    if let Some(secs) = config.hibernate_after_secs {
+        backend.start_hibernator(std::time::Duration::from_secs(secs));
+    }
 
     let state = Arc::new(ServerState {
         backend,
@@ -93,6 +191,8 @@ fn cmd_serve(config_path: &str) -> Result<()> {
         config: config.clone(),
         rate_limiter: auth::RateLimiter::new(),
         ws_session_count: AtomicUsize::new(0),
+        operations: operations::Operations::default(),
+        firecracker,
     });
 
     let server = tiny_http::Server::http(&config.listen)
@@ -118,6 +218,14 @@ fn cmd_serve(config_path: &str) -> Result<()> {
             std::thread::spawn(move || {
                 handle_ws_upgrade(request, state);
             });
+        } else if events::is_event_stream(&request) {
+            std::thread::spawn(move || {
+                events::handle_event_stream(request, state, trust_fwd);
+            });
+        } else if upload::is_upload(&request) {
+            std::thread::spawn(move || {
+                upload::handle_upload(request, state, trust_fwd);
+            });
         } else {
             std::thread::spawn(move || {
                 let ctx = transport::from_tiny_http(&mut request, trust_fwd);
@@ -167,22 +275,34 @@ fn handle_ws_upgrade(request: tiny_http::Request, state: Arc<ServerState>) {
 
     let path = ctx.path.split('?').next().unwrap_or(&ctx.path).to_string();
 
-    // Parse VM name from path
-    let rest = match path.strip_prefix("/v1/vms/") {
-        Some(r) => r,
-        None => {
-            let resp = transport::ResponseBuilder::error(404, "not found");
-            let _ = request.respond(transport::to_tiny_http_response(resp));
-            return;
+    // The event stream is the one WebSocket endpoint not scoped to a VM
+    let (vm_name, endpoint, event_query) = if path == "/v1/events" {
+        match events::EventQuery::parse(&ctx) {
+            Ok(query) => ("", "events", Some(query)),
+            Err(e) => {
+                let resp = transport::ResponseBuilder::error(400, &e);
+                let _ = request.respond(transport::to_tiny_http_response(resp));
+                return;
+            }
         }
-    };
-
-    let (vm_name, endpoint) = match rest.find('/') {
-        Some(pos) => (&rest[..pos], &rest[pos + 1..]),
-        None => {
-            let resp = transport::ResponseBuilder::error(404, "not found");
-            let _ = request.respond(transport::to_tiny_http_response(resp));
-            return;
+    } else {
+        // Parse VM name from path
+        let rest = match path.strip_prefix("/v1/vms/") {
+            Some(r) => r,
+            None => {
+                let resp = transport::ResponseBuilder::error(404, "not found");
+                let _ = request.respond(transport::to_tiny_http_response(resp));
+                return;
+            }
+        };
+
+        match rest.find('/') {
+            Some(pos) => (&rest[..pos], &rest[pos + 1..], None),
+            None => {
+                let resp = transport::ResponseBuilder::error(404, "not found");
+                let _ = request.respond(transport::to_tiny_http_response(resp));
+                return;
+            }
         }
     };
 
@@ -198,10 +318,14 @@ fn handle_ws_upgrade(request: tiny_http::Request, state: Arc<ServerState>) {
 
     let vm_name = vm_name.to_string();
     let endpoint = endpoint.to_string();
+    let ws_path = match event_query {
+        Some(_) => path.clone(),
+        None => format!("/v1/vms/{vm_name}/{endpoint}"),
+    };
 
     eprintln!(
-        "[ws] {} {} WS /v1/vms/{}/{} remote={}",
-        user.name, user.id, vm_name, endpoint, ctx.remote_addr,
+        "[ws] {} {} WS {} remote={}",
+        user.name, user.id, ws_path, ctx.remote_addr,
     );
 
     // We need to compute the Sec-WebSocket-Accept header
@@ -229,11 +353,14 @@ fn handle_ws_upgrade(request: tiny_http::Request, state: Arc<ServerState>) {
     let stream = request.upgrade("websocket", response);
     let ws_start = std::time::Instant::now();
 
-    match endpoint.as_str() {
-        "console" => {
+    match (endpoint.as_str(), &event_query) {
+        (_, Some(query)) => {
+            events::handle_events_ws(stream, &state, &user, query);
+        }
+        ("console", None) => {
             console::handle_console_ws(stream, &state, &user, &vm_name, peer_addr);
         }
-        "exec" => {
+        ("exec", None) => {
             ws_exec::handle_exec_ws(stream, &state, &user, &vm_name);
         }
         _ => {
@@ -242,15 +369,126 @@ fn handle_ws_upgrade(request: tiny_http::Request, state: Arc<ServerState>) {
     }
 
     eprintln!(
-        "[ws] {} WS /v1/vms/{}/{} closed ({}s)",
+        "[ws] {} WS {} closed ({}s)",
         user.name,
-        vm_name,
-        endpoint,
+        ws_path,
         ws_start.elapsed().as_secs(),
     );
     state.ws_session_count.fetch_sub(1, Ordering::SeqCst);
 }
 
+// --- Golden image commands ---
+
+fn cmd_golden(action: GoldenAction) -> Result<()> {
+    use noid_core::golden;
+    use noid_core::image::Image;
+
+    let image_arg = match &action {
+        GoldenAction::Promote { image, .. } | GoldenAction::Rollback { image } => image.clone(),
+    };
+    let image = match image_arg.as_deref() {
+        Some(name) => Image::named(name)?,
+        None => Image::default_image(),
+    };
+    let info = match action {
+        GoldenAction::Promote {
+            user,
+            vm,
+            checkpoint,
+            ..
+        } => {
+            let db = Db::open()?;
+            let (vm_rec, cp) = golden::find_checkpoint(&db, &user, &vm, &checkpoint)?;
+            golden::promote(&image, &vm_rec, &cp)?
+        }
+        GoldenAction::Rollback { .. } => golden::rollback(&image)?,
+    };
+    eprintln!(
+        "Golden image {} is now {} vCPU / {} MiB ({})",
+        image.dir.display(),
+        info.shape.cpus,
+        info.shape.mem_mib,
+        if info.has_previous {
+            "previous version kept for rollback"
+        } else {
+            "no previous version"
+        }
+    );
+    Ok(())
+}
+
+fn cmd_fsck(config_path: &str, repair: bool) -> Result<()> {
+    use noid_core::reconcile;
+
+    let config = ServerConfig::load(config_path)?;
+    // A running server has VMs and golden snapshots in the making that look
+    // like orphans; repairing would destroy them. The lock also keeps a
+    // server from starting during the repair.
+    let _pid_lock = if repair {
+        let pid_path = noid_core::config::server_pid_path();
+        let lock = LockFile::try_lock(&pid_path)?;
+        if lock.is_none() || server_answers(&config.listen) {
+            let pid = lockfile::holder(&pid_path).ok().flatten();
+            anyhow::bail!(
+                "noid-server is running{}; stop it before fsck --repair",
+                pid.map(|pid| format!(" (pid {pid})")).unwrap_or_default()
+            );
+        }
+        lock
+    } else {
+        None
+    };
+
+    let db = Db::open()?;
+    let issues = reconcile::check(&db)?;
+    if issues.is_empty() {
+        eprintln!("No problems found");
+        return Ok(());
+    }
+    if !repair {
+        for issue in &issues {
+            println!("{issue}");
+        }
+        anyhow::bail!(
+            "{} problem(s) found; run with --repair to fix them",
+            issues.len()
+        );
+    }
+
+    let storage = noid_core::storage::open_driver(config.storage_driver)?;
+    let mut failed = 0;
+    for issue in &issues {
+        match reconcile::repair(&db, storage.as_ref(), issue) {
+            Ok(()) => println!("repaired: {issue}"),
+            Err(e) => {
+                println!("not repaired: {issue}: {e:#}");
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        anyhow::bail!("{failed} of {} problem(s) not repaired", issues.len());
+    }
+    Ok(())
+}
+
+/// Whether something accepts connections on the server's `listen` address,
+/// as a server predating the pid file would.
+fn server_answers(listen: &str) -> bool {
+    use std::net::{Ipv4Addr, Ipv6Addr, TcpStream, ToSocketAddrs};
+
+    let Some(mut addr) = listen.to_socket_addrs().ok().and_then(|mut a| a.next()) else {
+        return false;
+    };
+    if addr.ip().is_unspecified() {
+        addr.set_ip(match addr.is_ipv4() {
+            true => Ipv4Addr::LOCALHOST.into(),
+            false => Ipv6Addr::LOCALHOST.into(),
+        });
+    }
+    TcpStream::connect_timeout(&addr, std::time::Duration::from_secs(1)).is_ok()
+}
+
 // --- User management commands ---
 
 fn cmd_add_user(name: &str) -> Result<()> {
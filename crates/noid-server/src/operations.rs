@@ -0,0 +1,254 @@
+//! Long-running requests in the background: `POST /v1/vms` and
+//! `POST /v1/vms/{name}/restore` sent with `Prefer: respond-async` are
+//! answered `202 Accepted` right away, and `GET /v1/operations/{id}` reports
+//! the phase they are in and, once done, the VM or the error.
+//!
+//! Operations are only kept in memory: a server restart forgets them, and
+//! finished ones are dropped after `RETENTION`.
+
+use noid_types::{OperationInfo, OperationPhase, OperationStep, VmInfo};
+use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::transport::{RequestContext, ResponseBuilder};
+use crate::ServerState;
+
+/// How long a finished operation can still be looked up.
+const RETENTION: Duration = Duration::from_secs(60 * 60);
+
+/// Operations one user may have running at once.
+const MAX_RUNNING_PER_USER: usize = 16;
+
+const STATE_RUNNING: &str = "running";
+const STATE_SUCCEEDED: &str = "succeeded";
+const STATE_FAILED: &str = "failed";
+
+struct Operation {
+    user_id: String,
+    started: Instant,
+    finished: Option<Instant>,
+    info: OperationInfo,
+}
+
+#[derive(Default)]
+pub struct Operations {
+    ops: Mutex<HashMap<String, Operation>>,
+}
+
+impl Operations {
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, Operation>> {
+        self.ops.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Record a new running operation of `user_id`, dropping expired ones.
+    fn begin(
+        &self,
+        user_id: &str,
+        kind: &str,
+        vm_name: &str,
+    ) -> Result<OperationInfo, ResponseBuilder> {
+        let mut ops = self.lock();
+        ops.retain(|_, op| op.finished.is_none_or(|t| t.elapsed() < RETENTION));
+        let running = ops
+            .values()
+            .filter(|op| op.user_id == user_id && op.finished.is_none())
+            .count();
+        if running >= MAX_RUNNING_PER_USER {
+            return Err(ResponseBuilder::error(
+                429,
+                "too many operations in progress",
+            ));
+        }
+
+        let info = OperationInfo {
+            id: uuid::Uuid::new_v4().to_string(),
+            kind: kind.to_string(),
+            vm_name: vm_name.to_string(),
+            state: STATE_RUNNING.to_string(),
+            steps: vec![OperationStep {
+                phase: OperationPhase::Queued,
+                elapsed_ms: 0,
+            }],
+            created_at: now(),
+            finished_at: None,
+            result: None,
+            error: None,
+            error_status: None,
+        };
+        ops.insert(
+            info.id.clone(),
+            Operation {
+                user_id: user_id.to_string(),
+                started: Instant::now(),
+                finished: None,
+                info: info.clone(),
+            },
+        );
+        Ok(info)
+    }
+
+    fn advance(&self, id: &str, phase: OperationPhase) {
+        if let Some(op) = self.lock().get_mut(id) {
+            let elapsed_ms = op.started.elapsed().as_millis() as u64;
+            op.info.steps.push(OperationStep { phase, elapsed_ms });
+        }
+    }
+
+    fn finish(&self, id: &str, result: anyhow::Result<VmInfo>) {
+        let mut ops = self.lock();
+        let Some(op) = ops.get_mut(id) else {
+            return;
+        };
+        op.finished = Some(Instant::now());
+        op.info.finished_at = Some(now());
+        match result {
+            Ok(vm) => {
+                op.info.state = STATE_SUCCEEDED.to_string();
+                op.info.result = Some(vm);
+            }
+            Err(e) => {
+                op.info.state = STATE_FAILED.to_string();
+                op.info.error_status = Some(crate::handlers::map_backend_error(&e).status);
+                op.info.error = Some(e.to_string());
+            }
+        }
+    }
+
+    /// Operation `id` of `user_id`, unless it was dropped or belongs to
+    /// someone else.
+    pub fn get(&self, user_id: &str, id: &str) -> Option<OperationInfo> {
+        self.lock()
+            .get(id)
+            .filter(|op| op.user_id == user_id)
+            .map(|op| op.info.clone())
+    }
+}
+
+fn now() -> String {
+    chrono::Utc::now().format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+/// Whether the request carries `Prefer: respond-async` (RFC 7240).
+pub fn wants_async(ctx: &RequestContext) -> bool {
+    ctx.headers.get("prefer").is_some_and(|value| {
+        value.split(',').any(|pref| {
+            let token = pref.split(';').next().unwrap_or_default();
+            token.trim().eq_ignore_ascii_case("respond-async")
+        })
+    })
+}
+
+/// Run `work` on its own thread as a new operation of `user_id` and answer
+/// `202` with the operation, pointing at it in `Location`.
+pub fn spawn<F>(
+    state: &Arc<ServerState>,
+    user_id: &str,
+    kind: &str,
+    vm_name: &str,
+    work: F,
+) -> ResponseBuilder
+where
+    F: FnOnce(&dyn Fn(OperationPhase)) -> anyhow::Result<VmInfo> + Send + 'static,
+{
+    let info = match state.operations.begin(user_id, kind, vm_name) {
+        Ok(info) => info,
+        Err(resp) => return resp,
+    };
+    let (id, kind) = (info.id.clone(), info.kind.clone());
+    let state = state.clone();
+    std::thread::spawn(move || {
+        let ops = &state.operations;
+        // A panic must not leave the operation running forever.
+        let result =
+            panic::catch_unwind(AssertUnwindSafe(|| work(&|phase| ops.advance(&id, phase))))
+                .unwrap_or_else(|_| Err(anyhow::anyhow!("{kind} failed unexpectedly")));
+        ops.finish(&id, result);
+    });
+
+    let mut resp = ResponseBuilder::json(202, &info);
+    resp.headers
+        .push(("Location".into(), format!("/v1/operations/{}", info.id)));
+    resp.headers
+        .push(("Preference-Applied".into(), "respond-async".into()));
+    resp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vm(name: &str) -> VmInfo {
+        VmInfo {
+            name: name.into(),
+            state: "running".into(),
+            cpus: 1,
+            mem_mib: 256,
+            created_at: now(),
+            disk: None,
+            expires_at: None,
+            expires_in_secs: None,
+            stop_on_expiry: false,
+            balloon: None,
+            labels: Default::default(),
+            details: None,
+        }
+    }
+
+    #[test]
+    fn prefer_respond_async() {
+        let ctx = |prefer: Option<&str>| RequestContext {
+            method: "POST".into(),
+            path: "/v1/vms".into(),
+            headers: prefer
+                .map(|v| ("prefer".to_string(), v.to_string()))
+                .into_iter()
+                .collect(),
+            body: Vec::new(),
+            remote_addr: String::new(),
+            forwarded_for: None,
+        };
+        assert!(wants_async(&ctx(Some("respond-async"))));
+        assert!(wants_async(&ctx(Some(
+            "return=minimal, Respond-Async; wait=5"
+        ))));
+        assert!(!wants_async(&ctx(Some("return=minimal"))));
+        assert!(!wants_async(&ctx(None)));
+    }
+
+    #[test]
+    fn operation_lifecycle() {
+        let ops = Operations::default();
+        let op = ops.begin("alice", "create", "dev").unwrap();
+        assert_eq!(op.state, STATE_RUNNING);
+        assert_eq!(op.phase(), Some(OperationPhase::Queued));
+
+        ops.advance(&op.id, OperationPhase::CloningStorage);
+        let running = ops.get("alice", &op.id).unwrap();
+        assert_eq!(running.phase(), Some(OperationPhase::CloningStorage));
+        assert!(ops.get("bob", &op.id).is_none());
+
+        ops.finish(&op.id, Ok(vm("dev")));
+        let done = ops.get("alice", &op.id).unwrap();
+        assert!(done.is_finished());
+        assert_eq!(done.result.unwrap().name, "dev");
+
+        let failed = ops.begin("alice", "restore", "dev").unwrap();
+        ops.finish(&failed.id, Err(anyhow::anyhow!("checkpoint 'x' not found")));
+        let failed = ops.get("alice", &failed.id).unwrap();
+        assert_eq!(failed.state, STATE_FAILED);
+        assert_eq!(failed.error_status, Some(404));
+    }
+
+    #[test]
+    fn running_operations_are_limited_per_user() {
+        let ops = Operations::default();
+        for _ in 0..MAX_RUNNING_PER_USER {
+            ops.begin("alice", "create", "dev").unwrap();
+        }
+        let refused = ops.begin("alice", "create", "dev").unwrap_err();
+        assert_eq!(refused.status, 429);
+        assert!(ops.begin("bob", "create", "dev").is_ok());
+    }
+}
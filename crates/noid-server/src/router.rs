@@ -47,15 +47,15 @@ pub fn authenticate(
 }
 
 /// Fields collected for request logging.
-struct LogEntry<'a> {
-    request_id: &'a str,
-    user: Option<&'a str>,
-    method: &'a str,
-    path: &'a str,
-    status: u16,
-    start: Instant,
-    remote_addr: &'a str,
-    forwarded_for: &'a Option<String>,
+pub struct LogEntry<'a> {
+    pub request_id: &'a str,
+    pub user: Option<&'a str>,
+    pub method: &'a str,
+    pub path: &'a str,
+    pub status: u16,
+    pub start: Instant,
+    pub remote_addr: &'a str,
+    pub forwarded_for: &'a Option<String>,
 }
 
 /// Route a request to the appropriate handler. Returns (handler_name, response).
@@ -84,7 +84,7 @@ pub fn route(ctx: RequestContext, state: &Arc<crate::ServerState>) -> (String, R
             return ("healthz".into(), resp);
         }
         ("GET", "/version") => {
-            let resp = crate::handlers::version();
+            let resp = crate::handlers::version(&state.firecracker);
             log_request(&LogEntry {
                 request_id: &request_id,
                 user: None,
@@ -147,15 +147,31 @@ fn route_authenticated(
     // Strip query string for matching
     let path = path.split('?').next().unwrap_or(&path).to_string();
 
+    if path.starts_with("/v1/admin/") && !state.config.admins.contains(&req.user.name) {
+        return ResponseBuilder::error(403, "admin privileges required");
+    }
+
     match (method.as_str(), path.as_str()) {
         ("GET", "/v1/whoami") => crate::handlers::whoami(&req),
         ("GET", "/v1/capabilities") => crate::handlers::capabilities(state),
         ("POST", "/v1/vms") => crate::handlers::create_vm(req, state),
         ("GET", "/v1/vms") => crate::handlers::list_vms(&req, state),
+        ("GET", "/v1/events") => crate::handlers::list_events(&req, state),
+        ("GET", "/v1/usage") => crate::handlers::usage(&req, state),
+        ("GET", "/v1/images") => crate::handlers::list_images(state),
+        ("POST", "/v1/admin/golden/promote") => crate::handlers::promote_golden(req, state),
+        ("POST", "/v1/admin/golden/rollback") => crate::handlers::rollback_golden(req),
         _ => {
             // Try VM-scoped routes: /v1/vms/{name}...
             if let Some(rest) = path.strip_prefix("/v1/vms/") {
                 route_vm_scoped(&method, rest, req, state)
+            } else if let Some(id) = path.strip_prefix("/v1/operations/") {
+                match method.as_str() {
+                    "GET" => crate::handlers::get_operation(&req, state, id),
+                    _ => ResponseBuilder::error(404, "not found"),
+                }
+            } else if let Some(rest) = path.strip_prefix("/v1/admin/") {
+                route_registry(&method, rest, state)
             } else {
                 ResponseBuilder::error(404, "not found")
             }
@@ -186,9 +202,24 @@ fn route_vm_scoped(
     match (method, sub) {
         ("GET", "") => crate::handlers::get_vm(&req, state, vm_name),
         ("DELETE", "") => crate::handlers::destroy_vm(&req, state, vm_name),
+        ("PATCH", "") => crate::handlers::update_vm(req, state, vm_name),
         ("POST", "checkpoints") => crate::handlers::create_checkpoint(req, state, vm_name),
         ("GET", "checkpoints") => crate::handlers::list_checkpoints(&req, state, vm_name),
         ("POST", "restore") => crate::handlers::restore_vm(req, state, vm_name),
+        ("POST", "stop") => crate::handlers::stop_vm(&req, state, vm_name),
+        ("POST", "start") => crate::handlers::start_vm(&req, state, vm_name),
+        ("POST", "reboot") => crate::handlers::reboot_vm(&req, state, vm_name),
+        ("GET", "events") => crate::handlers::vm_events(&req, state, vm_name),
+        ("GET", "init-log") => crate::handlers::get_init_log(&req, state, vm_name),
+        ("GET", "balloon") => crate::handlers::get_balloon(&req, state, vm_name),
+        ("PUT", "balloon") => crate::handlers::set_balloon(req, state, vm_name),
+        ("POST", sub) if sub.starts_with("checkpoints/") && sub.ends_with("/verify") => {
+            let checkpoint_id = &sub["checkpoints/".len()..sub.len() - "/verify".len()];
+            if noid_core::storage::validate_name(checkpoint_id, "Checkpoint").is_err() {
+                return ResponseBuilder::error(400, "invalid checkpoint ID");
+            }
+            crate::handlers::verify_checkpoint(&req, state, vm_name, checkpoint_id)
+        }
         ("POST", "exec") => crate::handlers::exec_vm(req, state, vm_name),
         ("GET", "exec") => {
             // WebSocket upgrade for streaming exec
@@ -202,7 +233,29 @@ fn route_vm_scoped(
     }
 }
 
-fn log_request(entry: &LogEntry) {
+/// Image registry routes: /v1/admin/{kernels,rootfs}[/{name}]. Uploads
+/// (`PUT`) are streamed and handled by `upload::handle_upload` instead.
+fn route_registry(method: &str, rest: &str, state: &Arc<crate::ServerState>) -> ResponseBuilder {
+    let (collection, name) = match rest.split_once('/') {
+        Some((collection, name)) => (collection, Some(name)),
+        None => (rest, None),
+    };
+    let Some(kind) = noid_types::ArtifactKind::from_collection(collection) else {
+        return ResponseBuilder::error(404, "not found");
+    };
+    match (method, name) {
+        ("GET", None) => crate::handlers::list_artifacts(kind),
+        ("DELETE", Some(name)) => {
+            if noid_core::storage::validate_name(name, "Image").is_err() {
+                return ResponseBuilder::error(400, &format!("invalid {kind} name"));
+            }
+            crate::handlers::delete_artifact(state, kind, name)
+        }
+        _ => ResponseBuilder::error(404, "not found"),
+    }
+}
+
+pub fn log_request(entry: &LogEntry) {
     let duration = entry.start.elapsed().as_millis();
     let user_str = entry.user.unwrap_or("-");
     let fwd = entry.forwarded_for.as_deref().unwrap_or("-");
